@@ -0,0 +1,444 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::archive::postgres::PostgresArchive;
+use crate::config::Reader;
+use crate::secrets::Secrets;
+use crate::store::{Collection, Store};
+
+pub mod postgres;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Scores are sampled at most this often per post, 5 minutes
+const SAMPLE_INTERVAL_SECS: i64 = 5 * 60;
+
+/// Samples older than [RetentionPolicy::downsample_after_days] are thinned to one per hour
+const DOWNSAMPLED_INTERVAL_SECS: i64 = 60 * 60;
+
+/// How often the archive is compacted
+pub const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Search results are cut after this many posts
+const MAX_SEARCH_RESULTS: usize = 50;
+
+/// Search over the archived posts
+#[derive(Clone, Debug, Deserialize)]
+pub struct ArchiveQuery {
+    /// Words to look for in the titles and excerpts
+    pub q: String,
+    /// Restrict the search to the posts of this subreddit
+    pub subreddit: Option<String>,
+    #[serde(default)]
+    pub min_score: u64,
+}
+
+impl ArchiveQuery {
+    /// Whether the post contains all words of the query and matches its other conditions
+    fn matches(&self, post: &ArchivedPost) -> bool {
+        let text = format!("{} {}", post.title, post.excerpt).to_lowercase();
+        let in_subreddit = self.subreddit.as_ref().is_none_or(|subreddit| {
+            let path = format!("/r/{}/", subreddit.to_lowercase());
+            post.link.to_lowercase().contains(&path)
+        });
+        in_subreddit
+            && post.score >= self.min_score
+            && self
+                .q
+                .split_whitespace()
+                .all(|word| text.contains(&word.to_lowercase()))
+    }
+}
+
+/// How long the archive keeps posts and their full score history
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionPolicy {
+    /// Posts published earlier are dropped with their history
+    pub retention_days: u32,
+    /// Score histories are thinned after this
+    pub downsample_after_days: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            retention_days: 60,
+            downsample_after_days: 7,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Taken from `ARCHIVE_RETENTION_DAYS` and `ARCHIVE_DOWNSAMPLE_AFTER_DAYS` secrets,
+    /// missing ones fall back to the defaults
+    pub fn read(reader: &mut Reader) -> RetentionPolicy {
+        let default = RetentionPolicy::default();
+        RetentionPolicy {
+            retention_days: reader
+                .number("ARCHIVE_RETENTION_DAYS")
+                .unwrap_or(default.retention_days),
+            downsample_after_days: reader
+                .number("ARCHIVE_DOWNSAMPLE_AFTER_DAYS")
+                .unwrap_or(default.downsample_after_days),
+        }
+    }
+}
+
+/// Thins the samples taken before `before` to the first one of every hour
+fn downsample(history: &mut Vec<ScoreSample>, before: i64) {
+    let mut last_bucket = None;
+    history.retain(|sample| {
+        if sample.at >= before {
+            return true;
+        }
+        let bucket = sample.at.div_euclid(DOWNSAMPLED_INTERVAL_SECS);
+        last_bucket.replace(bucket) != Some(bucket)
+    });
+}
+
+/// Longer histories lose their oldest samples
+const MAX_SAMPLES: usize = 500;
+
+/// A post as seen in a listing, with its latest known score
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchivedPost {
+    pub title: String,
+    pub link: String,
+    pub score: u64,
+    /// Unix timestamp of the publication
+    pub published: i64,
+    pub excerpt: String,
+}
+
+/// Score of a post at a point in time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreSample {
+    /// Unix timestamp of the observation
+    pub at: i64,
+    pub score: u64,
+}
+
+/// Whether a new observation of the score is worth a sample: the score changed since the last
+/// one and the sampling interval passed
+fn needs_sample(last: Option<ScoreSample>, score: u64, now: i64) -> bool {
+    last.is_none_or(|last| last.score != score && now - last.at >= SAMPLE_INTERVAL_SECS)
+}
+
+/// Storage of the archive
+#[async_trait]
+pub trait ArchiveBackend: Send + Sync {
+    /// Adds or updates posts (by id) of the subreddit and samples their scores
+    async fn record(
+        &self,
+        subreddit: &str,
+        posts: Vec<(String, ArchivedPost)>,
+        now: i64,
+    ) -> eyre::Result<()>;
+
+    /// Drops the posts published before `cutoff` with their samples
+    /// and [downsample]s the samples taken before `downsample_before`
+    async fn compact(&self, cutoff: i64, downsample_before: i64) -> eyre::Result<()>;
+
+    /// Archived posts of the subreddit, by id
+    async fn posts(&self, subreddit: &str) -> eyre::Result<BTreeMap<String, ArchivedPost>>;
+
+    /// Score samples of the posts by id, oldest first, unknown posts are left out
+    async fn score_histories(
+        &self,
+        ids: &[String],
+    ) -> eyre::Result<HashMap<String, Vec<ScoreSample>>>;
+
+    /// Posts matching the query by id, best matches first
+    async fn search(
+        &self,
+        query: &ArchiveQuery,
+        limit: usize,
+    ) -> eyre::Result<Vec<(String, ArchivedPost)>>;
+}
+
+/// Archive of listed posts per subreddit with their score over time, so feeds can be built
+/// from posts that already left Reddit's 25 item listing.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Archive {
+    backend: Arc<dyn ArchiveBackend>,
+    retention: RetentionPolicy,
+}
+
+impl Archive {
+    /// Archive kept in the store
+    pub fn new(posts: Collection<BTreeMap<String, StoredPost>>) -> Archive {
+        Archive::with_backend(CollectionArchive { posts })
+    }
+
+    pub fn with_backend(backend: impl ArchiveBackend + 'static) -> Archive {
+        Archive {
+            backend: Arc::new(backend),
+            retention: RetentionPolicy::default(),
+        }
+    }
+
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Archive {
+        self.retention = retention;
+        self
+    }
+
+    /// Archive in Postgres with `DATABASE_URL` secret, in the store's `archive` collection without
+    pub async fn from_secrets(secrets: &dyn Secrets, store: &Store) -> eyre::Result<Archive> {
+        Ok(match secrets.get("DATABASE_URL") {
+            Some(url) => Archive::with_backend(PostgresArchive::connect(&url).await?),
+            None => Archive::new(store.collection("archive").await?),
+        })
+    }
+
+    /// Adds or updates posts (by id) of the subreddit
+    pub async fn record(
+        &self,
+        subreddit: &str,
+        posts: impl IntoIterator<Item = (String, ArchivedPost)>,
+    ) -> eyre::Result<()> {
+        let now = Utc::now().timestamp();
+        let posts = posts.into_iter().collect();
+        self.backend
+            .record(&subreddit.to_lowercase(), posts, now)
+            .await
+    }
+
+    /// Applies the retention policy, run every [COMPACTION_INTERVAL]
+    pub async fn compact(&self) -> eyre::Result<()> {
+        let now = Utc::now().timestamp();
+        let RetentionPolicy {
+            retention_days,
+            downsample_after_days,
+        } = self.retention;
+        self.backend
+            .compact(
+                now - i64::from(retention_days) * DAY_SECS,
+                now - i64::from(downsample_after_days) * DAY_SECS,
+            )
+            .await
+    }
+
+    pub async fn posts(&self, subreddit: &str) -> Vec<ArchivedPost> {
+        self.archived(subreddit).await.into_values().collect()
+    }
+
+    /// Latest known scores of the archived posts of the subreddit, by id
+    pub async fn scores(&self, subreddit: &str) -> HashMap<String, u64> {
+        self.archived(subreddit)
+            .await
+            .into_iter()
+            .map(|(id, post)| (id, post.score))
+            .collect()
+    }
+
+    /// Score samples of the post, oldest first, empty for unknown posts
+    pub async fn score_history(&self, id: &str) -> eyre::Result<Vec<ScoreSample>> {
+        let mut histories = self.backend.score_histories(&[id.to_string()]).await?;
+        Ok(histories.remove(id).unwrap_or_default())
+    }
+
+    /// Score samples of the posts by id, oldest first, fetched together
+    pub async fn score_histories(
+        &self,
+        ids: &[String],
+    ) -> eyre::Result<HashMap<String, Vec<ScoreSample>>> {
+        self.backend.score_histories(ids).await
+    }
+
+    /// Posts matching the query by id, best matches first
+    pub async fn search(&self, query: &ArchiveQuery) -> eyre::Result<Vec<(String, ArchivedPost)>> {
+        self.backend.search(query, MAX_SEARCH_RESULTS).await
+    }
+
+    async fn archived(&self, subreddit: &str) -> BTreeMap<String, ArchivedPost> {
+        self.backend
+            .posts(&subreddit.to_lowercase())
+            .await
+            .inspect_err(|e| warn!("cannot read the archive of {subreddit}: {e:?}"))
+            .unwrap_or_default()
+    }
+}
+
+/// Post in the store's archive with its score samples
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredPost {
+    #[serde(flatten)]
+    post: ArchivedPost,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<ScoreSample>,
+}
+
+/// Archive in a store collection, posts by id per subreddit
+struct CollectionArchive {
+    posts: Collection<BTreeMap<String, StoredPost>>,
+}
+
+#[async_trait]
+impl ArchiveBackend for CollectionArchive {
+    async fn record(
+        &self,
+        subreddit: &str,
+        posts: Vec<(String, ArchivedPost)>,
+        now: i64,
+    ) -> eyre::Result<()> {
+        // concurrent feeds of the subreddit keep each other's samples
+        self.posts
+            .update(subreddit, |archived| {
+                let mut archived = archived.unwrap_or_default();
+                for (id, post) in posts {
+                    let score = post.score;
+                    let stored = archived.entry(id).or_insert_with(|| StoredPost {
+                        post: post.clone(),
+                        history: vec![],
+                    });
+                    stored.post = post;
+                    if needs_sample(stored.history.last().copied(), score, now) {
+                        stored.history.push(ScoreSample { at: now, score });
+                        let excess = stored.history.len().saturating_sub(MAX_SAMPLES);
+                        stored.history.drain(..excess);
+                    }
+                }
+                Some(archived)
+            })
+            .await
+    }
+
+    async fn compact(&self, cutoff: i64, downsample_before: i64) -> eyre::Result<()> {
+        let subreddits = self
+            .posts
+            .read(|posts| posts.keys().cloned().collect_vec())
+            .await;
+        for subreddit in subreddits {
+            self.posts
+                .update(&subreddit, |archived| {
+                    let mut archived = archived?;
+                    archived.retain(|_, stored| stored.post.published >= cutoff);
+                    for stored in archived.values_mut() {
+                        downsample(&mut stored.history, downsample_before);
+                    }
+                    (!archived.is_empty()).then_some(archived)
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn posts(&self, subreddit: &str) -> eyre::Result<BTreeMap<String, ArchivedPost>> {
+        let archived = self.posts.get(subreddit).await.unwrap_or_default();
+        Ok(archived
+            .into_iter()
+            .map(|(id, stored)| (id, stored.post))
+            .collect())
+    }
+
+    async fn score_histories(
+        &self,
+        ids: &[String],
+    ) -> eyre::Result<HashMap<String, Vec<ScoreSample>>> {
+        // the same post can be listed under several subreddits, the longest history wins
+        let histories = self
+            .posts
+            .read(|subreddits| {
+                let mut histories = HashMap::<String, &Vec<ScoreSample>>::new();
+                for posts in subreddits.values() {
+                    for id in ids {
+                        let Some(stored) = posts.get(id) else {
+                            continue;
+                        };
+                        let longest = histories.entry(id.clone()).or_insert(&stored.history);
+                        if stored.history.len() > longest.len() {
+                            *longest = &stored.history;
+                        }
+                    }
+                }
+                histories
+                    .into_iter()
+                    .map(|(id, history)| (id, history.clone()))
+                    .collect()
+            })
+            .await;
+        Ok(histories)
+    }
+
+    /// Plain word matching, ranked by score
+    async fn search(
+        &self,
+        query: &ArchiveQuery,
+        limit: usize,
+    ) -> eyre::Result<Vec<(String, ArchivedPost)>> {
+        let found = self
+            .posts
+            .read(|subreddits| {
+                let mut found = BTreeMap::new();
+                for posts in subreddits.values() {
+                    for (id, stored) in posts {
+                        if query.matches(&stored.post) {
+                            found.insert(id.clone(), stored.post.clone());
+                        }
+                    }
+                }
+                found
+            })
+            .await;
+        let mut found = found.into_iter().collect::<Vec<_>>();
+        found.sort_by_key(|(_, post)| Reverse(post.score));
+        found.truncate(limit);
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_sample_test() {
+        let last = Some(ScoreSample {
+            at: 1000,
+            score: 10,
+        });
+        assert!(needs_sample(None, 10, 1000));
+        assert!(!needs_sample(last, 10, 5000));
+        assert!(!needs_sample(last, 20, 1000 + SAMPLE_INTERVAL_SECS - 1));
+        assert!(needs_sample(last, 20, 1000 + SAMPLE_INTERVAL_SECS));
+    }
+
+    #[test]
+    fn archive_query_test() {
+        let post = ArchivedPost {
+            title: "Announcing Rust 1.77".to_string(),
+            link: "https://www.reddit.com/r/rust/comments/abc/announcing_rust_177/".to_string(),
+            score: 900,
+            published: 0,
+            excerpt: "C-string literals and async recursion".to_string(),
+        };
+        let query = |q: &str, subreddit: Option<&str>, min_score| ArchiveQuery {
+            q: q.to_string(),
+            subreddit: subreddit.map(String::from),
+            min_score,
+        };
+        assert!(query("rust ASYNC", Some("Rust"), 500).matches(&post));
+        assert!(!query("rust golang", None, 0).matches(&post));
+        assert!(!query("rust", Some("golang"), 0).matches(&post));
+        assert!(!query("rust", None, 1000).matches(&post));
+    }
+
+    #[test]
+    fn downsample_test() {
+        let sample = |at| ScoreSample { at, score: 1 };
+        let mut history = [0, 600, 3600, 4000, 7300, 7400].map(sample).to_vec();
+        downsample(&mut history, 7350);
+        let kept = history.iter().map(|s| s.at).collect::<Vec<_>>();
+        assert_eq!(kept, vec![0, 3600, 7300, 7400]);
+    }
+}
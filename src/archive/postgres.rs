@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use eyre::Context;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::archive::{
+    needs_sample, ArchiveBackend, ArchiveQuery, ArchivedPost, ScoreSample,
+    DOWNSAMPLED_INTERVAL_SECS, MAX_SAMPLES,
+};
+
+const MAX_CONNECTIONS: u32 = 5;
+
+/// Posts by subreddit, as the same post can be listed under several, e.g. `r/rust+golang`.
+/// Score samples belong to the post itself
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS archived_posts (
+    subreddit TEXT NOT NULL,
+    id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    link TEXT NOT NULL,
+    score BIGINT NOT NULL,
+    published BIGINT NOT NULL,
+    excerpt TEXT NOT NULL,
+    PRIMARY KEY (subreddit, id)
+);
+CREATE INDEX IF NOT EXISTS archived_posts_published ON archived_posts (published);
+CREATE INDEX IF NOT EXISTS archived_posts_search ON archived_posts
+    USING GIN (to_tsvector('english', title || ' ' || excerpt));
+CREATE TABLE IF NOT EXISTS score_samples (
+    post_id TEXT NOT NULL,
+    at BIGINT NOT NULL,
+    score BIGINT NOT NULL,
+    PRIMARY KEY (post_id, at)
+);
+CREATE INDEX IF NOT EXISTS score_samples_at ON score_samples (at);
+";
+
+/// Archive in Postgres, shared by every instance of the service
+pub struct PostgresArchive {
+    pool: PgPool,
+}
+
+impl PostgresArchive {
+    /// Connects to the database and creates the tables if missing
+    pub async fn connect(url: &str) -> eyre::Result<PostgresArchive> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await
+            .context("cannot connect to the archive database")?;
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .context("cannot create the archive tables")?;
+        Ok(PostgresArchive { pool })
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for PostgresArchive {
+    async fn record(
+        &self,
+        subreddit: &str,
+        posts: Vec<(String, ArchivedPost)>,
+        now: i64,
+    ) -> eyre::Result<()> {
+        let ids = posts.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+        let last_samples: HashMap<String, ScoreSample> = sqlx::query_as::<_, (String, i64, i64)>(
+            "SELECT DISTINCT ON (post_id) post_id, at, score FROM score_samples
+             WHERE post_id = ANY($1) ORDER BY post_id, at DESC",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(id, at, score)| {
+            let score = score as u64;
+            (id, ScoreSample { at, score })
+        })
+        .collect();
+
+        let mut transaction = self.pool.begin().await?;
+        for (id, post) in &posts {
+            sqlx::query(
+                "INSERT INTO archived_posts (subreddit, id, title, link, score, published, excerpt)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)
+                 ON CONFLICT (subreddit, id) DO UPDATE SET title = $3, link = $4, score = $5,
+                     published = $6, excerpt = $7",
+            )
+            .bind(subreddit)
+            .bind(id)
+            .bind(&post.title)
+            .bind(&post.link)
+            .bind(post.score as i64)
+            .bind(post.published)
+            .bind(&post.excerpt)
+            .execute(&mut *transaction)
+            .await?;
+            if needs_sample(last_samples.get(id).copied(), post.score, now) {
+                sqlx::query(
+                    "INSERT INTO score_samples (post_id, at, score) VALUES ($1, $2, $3)
+                     ON CONFLICT DO NOTHING",
+                )
+                .bind(id)
+                .bind(now)
+                .bind(post.score as i64)
+                .execute(&mut *transaction)
+                .await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn compact(&self, cutoff: i64, downsample_before: i64) -> eyre::Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        let posts = sqlx::query("DELETE FROM archived_posts WHERE published < $1")
+            .bind(cutoff)
+            .execute(&mut *transaction)
+            .await?;
+        let expired = sqlx::query("DELETE FROM score_samples WHERE at < $1")
+            .bind(cutoff)
+            .execute(&mut *transaction)
+            .await?;
+        // keeps the first sample of every hour, like `downsample`
+        let thinned = sqlx::query(
+            "DELETE FROM score_samples s WHERE s.at < $1 AND EXISTS (
+                 SELECT 1 FROM score_samples o WHERE o.post_id = s.post_id AND o.at < s.at
+                     AND div(o.at, $2) = div(s.at, $2)
+             )",
+        )
+        .bind(downsample_before)
+        .bind(DOWNSAMPLED_INTERVAL_SECS)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        info!(
+            "compacted the archive: {} posts, {} samples dropped, {} thinned",
+            posts.rows_affected(),
+            expired.rows_affected(),
+            thinned.rows_affected()
+        );
+        // returns the space of the deleted rows, VACUUM cannot run in a transaction
+        sqlx::raw_sql("VACUUM (ANALYZE) archived_posts, score_samples")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn posts(&self, subreddit: &str) -> eyre::Result<BTreeMap<String, ArchivedPost>> {
+        let rows = sqlx::query_as::<_, (String, String, String, i64, i64, String)>(
+            "SELECT id, title, link, score, published, excerpt FROM archived_posts
+             WHERE subreddit = $1",
+        )
+        .bind(subreddit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(archived_post).collect())
+    }
+
+    async fn score_histories(
+        &self,
+        ids: &[String],
+    ) -> eyre::Result<HashMap<String, Vec<ScoreSample>>> {
+        let rows = sqlx::query_as::<_, (String, i64, i64)>(
+            "SELECT post_id, at, score FROM (
+                 SELECT post_id, at, score,
+                     row_number() OVER (PARTITION BY post_id ORDER BY at DESC) AS n
+                 FROM score_samples WHERE post_id = ANY($1)
+             ) latest WHERE n <= $2 ORDER BY post_id, at",
+        )
+        .bind(ids)
+        .bind(MAX_SAMPLES as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut histories = HashMap::<String, Vec<ScoreSample>>::new();
+        for (id, at, score) in rows {
+            let score = score as u64;
+            histories
+                .entry(id)
+                .or_default()
+                .push(ScoreSample { at, score });
+        }
+        Ok(histories)
+    }
+
+    /// Postgres full text search in English, ranked by relevance then score
+    async fn search(
+        &self,
+        query: &ArchiveQuery,
+        limit: usize,
+    ) -> eyre::Result<Vec<(String, ArchivedPost)>> {
+        // posts listed under several subreddits are found once
+        let rows = sqlx::query_as::<_, (String, String, String, i64, i64, String)>(
+            "SELECT id, title, link, score, published, excerpt FROM (
+                 SELECT DISTINCT ON (id) id, title, link, score, published, excerpt,
+                     ts_rank(to_tsvector('english', title || ' ' || excerpt), query) AS rank
+                 FROM archived_posts, websearch_to_tsquery('english', $1) query
+                 WHERE to_tsvector('english', title || ' ' || excerpt) @@ query
+                     AND ($2::TEXT IS NULL
+                         OR position(lower('/r/' || $2 || '/') IN lower(link)) > 0)
+                     AND score >= $3
+                 ORDER BY id, score DESC
+             ) matches ORDER BY rank DESC, score DESC LIMIT $4",
+        )
+        .bind(&query.q)
+        .bind(&query.subreddit)
+        .bind(query.min_score as i64)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(archived_post).collect())
+    }
+}
+
+fn archived_post(
+    (id, title, link, score, published, excerpt): (String, String, String, i64, i64, String),
+) -> (String, ArchivedPost) {
+    let post = ArchivedPost {
+        title,
+        link,
+        score: score as u64,
+        published,
+        excerpt,
+    };
+    (id, post)
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::audit::postgres::PostgresAudit;
+use crate::secrets::Secrets;
+
+pub mod postgres;
+
+/// How often entries past the retention are dropped
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Entries kept without a database, the oldest are dropped first
+const MEMORY_ENTRIES: usize = 10_000;
+
+/// Days the entries are kept if `AUDIT_RETENTION_DAYS` is not set
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+const DEFAULT_QUERY_LIMIT: usize = 100;
+const MAX_QUERY_LIMIT: usize = 1000;
+
+/// Query parameters that carry credentials, left out of the entries
+const SECRET_PARAMS: [&str; 2] = ["token", "signature"];
+
+/// An authorized request
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix timestamp of the response
+    pub at: i64,
+    /// Name of the client token
+    pub client: String,
+    pub method: String,
+    pub path: String,
+    /// Query without the credentials, see [redact_query]
+    pub params: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    /// First address of `X-Forwarded-For`, the service runs behind a proxy
+    pub ip: Option<String>,
+}
+
+/// Filters of the audit log, newest entries first
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub client: Option<String>,
+    /// Path prefix, e.g. `/feed/rust`
+    pub path: Option<String>,
+    pub status: Option<u16>,
+    pub ip: Option<String>,
+    /// Unix timestamp
+    pub since: Option<i64>,
+    /// Defaults to 100, at most 1000
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.client.as_ref().is_none_or(|c| *c == entry.client)
+            && self.path.as_ref().is_none_or(|p| entry.path.starts_with(p))
+            && self.status.is_none_or(|s| s == entry.status)
+            && self
+                .ip
+                .as_ref()
+                .is_none_or(|ip| entry.ip.as_ref() == Some(ip))
+            && self.since.is_none_or(|since| entry.at >= since)
+    }
+
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT)
+    }
+}
+
+/// Storage of the audit log
+#[async_trait]
+pub trait AuditBackend: Send + Sync {
+    async fn record(&self, entry: AuditEntry) -> eyre::Result<()>;
+
+    /// Drops the entries recorded before `cutoff`
+    async fn prune(&self, cutoff: i64) -> eyre::Result<()>;
+
+    /// Matching entries, newest first
+    async fn query(&self, query: &AuditQuery, limit: usize) -> eyre::Result<Vec<AuditEntry>>;
+}
+
+/// Log of the authorized requests, to debug misbehaving readers and notice leaked tokens.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct AuditLog {
+    backend: Arc<dyn AuditBackend>,
+    retention_days: u32,
+}
+
+impl AuditLog {
+    /// The latest entries kept in memory, lost on restart
+    pub fn in_memory() -> AuditLog {
+        AuditLog::with_backend(MemoryAudit::default())
+    }
+
+    pub fn with_backend(backend: impl AuditBackend + 'static) -> AuditLog {
+        AuditLog {
+            backend: Arc::new(backend),
+            retention_days: DEFAULT_RETENTION_DAYS,
+        }
+    }
+
+    /// Entries older than `days` are dropped, 30 days by default
+    pub fn with_retention_days(mut self, days: u32) -> AuditLog {
+        self.retention_days = days;
+        self
+    }
+
+    /// Log in Postgres with `DATABASE_URL` secret, in memory without
+    pub async fn from_secrets(secrets: &dyn Secrets) -> eyre::Result<AuditLog> {
+        Ok(match secrets.get("DATABASE_URL") {
+            Some(url) => AuditLog::with_backend(PostgresAudit::connect(&url).await?),
+            None => AuditLog::in_memory(),
+        })
+    }
+
+    /// Records the entry in the background, so the response is not held up by the write
+    pub fn record(&self, entry: AuditEntry) {
+        let backend = self.backend.clone();
+        tokio::spawn(async move {
+            if let Err(e) = backend.record(entry).await {
+                warn!("cannot record audit entry: {e:?}");
+            }
+        });
+    }
+
+    /// Drops the entries past the retention, run every [PRUNE_INTERVAL]
+    pub async fn prune(&self) {
+        let cutoff = Utc::now().timestamp() - i64::from(self.retention_days) * 24 * 60 * 60;
+        if let Err(e) = self.backend.prune(cutoff).await {
+            warn!("cannot prune the audit log: {e:?}");
+        }
+    }
+
+    pub async fn query(&self, query: &AuditQuery) -> eyre::Result<Vec<AuditEntry>> {
+        self.backend.query(query, query.limit()).await
+    }
+}
+
+/// Query of a request without the credentials
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|param| {
+            let name = param.split_once('=').map_or(*param, |(name, _)| name);
+            !param.is_empty() && !SECRET_PARAMS.contains(&name)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Client the request was authorized as, filled in by the authentication
+/// and read back by the audit middleware once the response is ready
+#[derive(Clone, Default)]
+pub struct AuditedClient(Arc<Mutex<Option<String>>>);
+
+impl AuditedClient {
+    pub fn set(&self, client: &str) {
+        *self.0.lock().unwrap() = Some(client.to_string());
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[derive(Default)]
+struct MemoryAudit {
+    entries: Mutex<VecDeque<AuditEntry>>,
+}
+
+#[async_trait]
+impl AuditBackend for MemoryAudit {
+    async fn record(&self, entry: AuditEntry) -> eyre::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.truncate(MEMORY_ENTRIES - 1);
+        entries.push_front(entry);
+        Ok(())
+    }
+
+    async fn prune(&self, cutoff: i64) -> eyre::Result<()> {
+        self.entries.lock().unwrap().retain(|e| e.at >= cutoff);
+        Ok(())
+    }
+
+    async fn query(&self, query: &AuditQuery, limit: usize) -> eyre::Result<Vec<AuditEntry>> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries
+            .iter()
+            .filter(|e| query.matches(e))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_query_test() {
+        assert_eq!(
+            redact_query("min_score=10&token=abc&client=bob&signature=ff&expires=5"),
+            "min_score=10&client=bob&expires=5"
+        );
+        assert_eq!(redact_query(""), "");
+    }
+
+    #[tokio::test]
+    async fn memory_audit_test() {
+        let entry = |at, client: &str, path: &str| AuditEntry {
+            at,
+            client: client.to_string(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            params: String::new(),
+            status: 200,
+            latency_ms: 12,
+            ip: None,
+        };
+        let log = MemoryAudit::default();
+        log.record(entry(1, "alice", "/feed/rust")).await.unwrap();
+        log.record(entry(2, "bob", "/feed/rust")).await.unwrap();
+        log.record(entry(3, "alice", "/feed/golang")).await.unwrap();
+
+        let query = AuditQuery {
+            client: Some("alice".to_string()),
+            ..Default::default()
+        };
+        let found = log.query(&query, 10).await.unwrap();
+        assert_eq!(found.iter().map(|e| e.at).collect::<Vec<_>>(), [3, 1]);
+
+        let query = AuditQuery {
+            path: Some("/feed/rust".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            log.query(&query, 1).await.unwrap(),
+            [entry(2, "bob", "/feed/rust")]
+        );
+
+        log.prune(2).await.unwrap();
+        let all = log.query(&AuditQuery::default(), 10).await.unwrap();
+        assert_eq!(all.len(), 2);
+    }
+}
@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use eyre::Context;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::audit::{AuditBackend, AuditEntry, AuditQuery};
+
+const MAX_CONNECTIONS: u32 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    at BIGINT NOT NULL,
+    client TEXT NOT NULL,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    params TEXT NOT NULL,
+    status SMALLINT NOT NULL,
+    latency_ms BIGINT NOT NULL,
+    ip TEXT
+);
+CREATE INDEX IF NOT EXISTS audit_log_at ON audit_log (at);
+CREATE INDEX IF NOT EXISTS audit_log_client ON audit_log (client, at);
+";
+
+/// Audit log in Postgres, shared by every instance of the service
+pub struct PostgresAudit {
+    pool: PgPool,
+}
+
+impl PostgresAudit {
+    /// Connects to the database and creates the table if missing
+    pub async fn connect(url: &str) -> eyre::Result<PostgresAudit> {
+        let pool = PgPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(url)
+            .await
+            .context("cannot connect to the audit database")?;
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .context("cannot create the audit table")?;
+        Ok(PostgresAudit { pool })
+    }
+}
+
+type AuditRow = (
+    i64,
+    String,
+    String,
+    String,
+    String,
+    i16,
+    i64,
+    Option<String>,
+);
+
+#[async_trait]
+impl AuditBackend for PostgresAudit {
+    async fn record(&self, entry: AuditEntry) -> eyre::Result<()> {
+        sqlx::query(
+            "INSERT INTO audit_log (at, client, method, path, params, status, latency_ms, ip)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(entry.at)
+        .bind(entry.client)
+        .bind(entry.method)
+        .bind(entry.path)
+        .bind(entry.params)
+        .bind(entry.status as i16)
+        .bind(entry.latency_ms as i64)
+        .bind(entry.ip)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn prune(&self, cutoff: i64) -> eyre::Result<()> {
+        let pruned = sqlx::query("DELETE FROM audit_log WHERE at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        info!("pruned {} audit entries", pruned.rows_affected());
+        Ok(())
+    }
+
+    async fn query(&self, query: &AuditQuery, limit: usize) -> eyre::Result<Vec<AuditEntry>> {
+        let rows = sqlx::query_as::<_, AuditRow>(
+            "SELECT at, client, method, path, params, status, latency_ms, ip FROM audit_log
+             WHERE ($1::TEXT IS NULL OR client = $1)
+                 AND ($2::TEXT IS NULL OR starts_with(path, $2))
+                 AND ($3::SMALLINT IS NULL OR status = $3)
+                 AND ($4::TEXT IS NULL OR ip = $4)
+                 AND ($5::BIGINT IS NULL OR at >= $5)
+             ORDER BY at DESC, id DESC LIMIT $6",
+        )
+        .bind(&query.client)
+        .bind(&query.path)
+        .bind(query.status.map(|s| s as i16))
+        .bind(&query.ip)
+        .bind(query.since)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(at, client, method, path, params, status, latency_ms, ip)| AuditEntry {
+                    at,
+                    client,
+                    method,
+                    path,
+                    params,
+                    status: status as u16,
+                    latency_ms: latency_ms as u64,
+                    ip,
+                },
+            )
+            .collect())
+    }
+}
@@ -0,0 +1,50 @@
+use axum::extract::{OriginalUri, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::Utc;
+use tokio::time::Instant;
+
+use redditrss::audit::{redact_query, AuditEntry, AuditLog, AuditedClient};
+
+use crate::rate_limit::{client_address, peer_address};
+
+/// Log the requests are recorded in and whether the service is behind a proxy
+/// appending the client address to `X-Forwarded-For`, see [client_address]
+#[derive(Clone)]
+pub struct Auditing {
+    pub log: AuditLog,
+    pub trusted_proxy: bool,
+}
+
+/// Records the authorized requests in the audit log with the status of the handler,
+/// unauthorized ones have no client and are left out
+pub async fn audit(
+    State(Auditing { log, trusted_proxy }): State<Auditing>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let audited = AuditedClient::default();
+    request.extensions_mut().insert(audited.clone());
+    // format suffixes are stripped by now, the original path is logged
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or_else(|| request.uri().clone(), |OriginalUri(uri)| uri.clone());
+    let method = request.method().to_string();
+    let ip = client_address(request.headers(), peer_address(&request), trusted_proxy);
+    let response = next.run(request).await;
+    if let Some(client) = audited.get() {
+        log.record(AuditEntry {
+            at: Utc::now().timestamp(),
+            client,
+            method,
+            path: uri.path().to_string(),
+            params: redact_query(uri.query().unwrap_or_default()),
+            status: response.status().as_u16(),
+            latency_ms: started.elapsed().as_millis() as u64,
+            ip,
+        });
+    }
+    response
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use atom_syndication::{Feed, Text};
+use moka::future::Cache;
+use moka::notification::RemovalCause;
+use serde::Serialize;
+
+use crate::config::Reader;
+
+/// Capacities and lifetimes of the in-memory caches,
+/// tunable per deployment as traffic and freshness needs differ
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheConfig {
+    /// Posts whose info (score, flair, ...) is kept
+    pub score_capacity: u64,
+    /// Info of a post is refetched after this
+    pub score_ttl: Duration,
+    /// Approximate bytes taken by generated feeds, one per feed URL,
+    /// see [feed_weight]
+    pub feed_budget: u64,
+    /// Generated feeds are regenerated after this, should be longer than the prefetch interval
+    pub feed_ttl: Duration,
+    /// OAuth token is renewed after this, Reddit's tokens are valid for 24 hours
+    pub token_ttl: Duration,
+    /// Post authors whose karma and account age are kept
+    pub author_capacity: u64,
+    /// Karma and age of an author are refetched after this, they hardly change
+    pub author_ttl: Duration,
+    /// Linked pages whose Open Graph preview is kept
+    pub preview_capacity: u64,
+    /// Previews of pages are refetched after this
+    pub preview_ttl: Duration,
+    /// Approximate bytes taken by extracted articles, see [crate::rss::fulltext]
+    pub article_budget: u64,
+    /// Extracted articles are refetched after this
+    pub article_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            score_capacity: 1000,
+            score_ttl: Duration::from_secs(60 * 60),
+            feed_budget: 16 * 1024 * 1024,
+            feed_ttl: Duration::from_secs(10 * 60),
+            token_ttl: Duration::from_secs(4 * 60 * 60),
+            author_capacity: 10_000,
+            author_ttl: Duration::from_secs(24 * 60 * 60),
+            preview_capacity: 2000,
+            preview_ttl: Duration::from_secs(24 * 60 * 60),
+            article_budget: 32 * 1024 * 1024,
+            article_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Taken from `SCORE_CACHE_CAPACITY`, `SCORE_CACHE_TTL_SECS`, `FEED_CACHE_BYTES`,
+    /// `FEED_CACHE_TTL_SECS`, `TOKEN_CACHE_TTL_SECS`, `AUTHOR_CACHE_CAPACITY`,
+    /// `AUTHOR_CACHE_TTL_SECS`, `PREVIEW_CACHE_CAPACITY`, `PREVIEW_CACHE_TTL_SECS`,
+    /// `ARTICLE_CACHE_BYTES` and `ARTICLE_CACHE_TTL_SECS` secrets,
+    /// missing or invalid ones fall back to the defaults
+    pub fn read(reader: &mut Reader) -> CacheConfig {
+        let default = CacheConfig::default();
+        CacheConfig {
+            score_capacity: reader
+                .number("SCORE_CACHE_CAPACITY")
+                .unwrap_or(default.score_capacity),
+            score_ttl: reader
+                .secs("SCORE_CACHE_TTL_SECS")
+                .unwrap_or(default.score_ttl),
+            feed_budget: reader
+                .number("FEED_CACHE_BYTES")
+                .unwrap_or(default.feed_budget),
+            feed_ttl: reader
+                .secs("FEED_CACHE_TTL_SECS")
+                .unwrap_or(default.feed_ttl),
+            token_ttl: reader
+                .secs("TOKEN_CACHE_TTL_SECS")
+                .unwrap_or(default.token_ttl),
+            author_capacity: reader
+                .number("AUTHOR_CACHE_CAPACITY")
+                .unwrap_or(default.author_capacity),
+            author_ttl: reader
+                .secs("AUTHOR_CACHE_TTL_SECS")
+                .unwrap_or(default.author_ttl),
+            preview_capacity: reader
+                .number("PREVIEW_CACHE_CAPACITY")
+                .unwrap_or(default.preview_capacity),
+            preview_ttl: reader
+                .secs("PREVIEW_CACHE_TTL_SECS")
+                .unwrap_or(default.preview_ttl),
+            article_budget: reader
+                .number("ARTICLE_CACHE_BYTES")
+                .unwrap_or(default.article_budget),
+            article_ttl: reader
+                .secs("ARTICLE_CACHE_TTL_SECS")
+                .unwrap_or(default.article_ttl),
+        }
+    }
+}
+
+/// Statistics of the caches by name, see [CacheStats]
+pub type CacheReport = BTreeMap<&'static str, CacheSnapshot>;
+
+/// Hit, miss and eviction counters of a cache, moka keeps none itself
+#[derive(Default, Debug)]
+pub struct CacheStats {
+    lookups: AtomicU64,
+    misses: AtomicU64,
+    expired: AtomicU64,
+    evicted: AtomicU64,
+    invalidated: AtomicU64,
+    replaced: AtomicU64,
+}
+
+impl CacheStats {
+    pub fn lookup(&self) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Lookups not followed by a miss are counted as hits
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Eviction listener counting the removals by their cause,
+    /// to be passed to `CacheBuilder::eviction_listener`
+    pub fn listener<K, V>(self: &Arc<Self>) -> impl Fn(Arc<K>, V, RemovalCause) + Send + Sync {
+        let stats = self.clone();
+        move |_, _, cause| {
+            let counter = match cause {
+                RemovalCause::Expired => &stats.expired,
+                RemovalCause::Size => &stats.evicted,
+                RemovalCause::Explicit => &stats.invalidated,
+                RemovalCause::Replaced => &stats.replaced,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counters so far, with the current (approximate) size of `cache`
+    pub fn snapshot<K, V>(&self, cache: &Cache<K, V>) -> CacheSnapshot
+    where
+        K: Hash + Eq + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        let lookups = self.lookups.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheSnapshot {
+            entries: cache.entry_count(),
+            weight: cache.weighted_size(),
+            hits: lookups.saturating_sub(misses),
+            misses,
+            expired: self.expired.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            invalidated: self.invalidated.load(Ordering::Relaxed),
+            replaced: self.replaced.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Approximate memory taken by a feed: its text plus a fixed overhead per entry
+pub fn feed_weight(feed: &Feed) -> u32 {
+    const ENTRY_OVERHEAD: usize = 512;
+    let text = |text: &Text| text.value.len();
+    let entries: usize = feed
+        .entries
+        .iter()
+        .map(|entry| {
+            ENTRY_OVERHEAD
+                + entry.id.len()
+                + text(&entry.title)
+                + entry.summary.as_ref().map_or(0, text)
+                + entry
+                    .content
+                    .as_ref()
+                    .and_then(|c| c.value.as_ref())
+                    .map_or(0, String::len)
+                + entry.links.iter().map(|l| l.href.len()).sum::<usize>()
+                + entry.categories.iter().map(|c| c.term.len()).sum::<usize>()
+        })
+        .sum();
+    let size = ENTRY_OVERHEAD + feed.id.len() + text(&feed.title) + entries;
+    size.try_into().unwrap_or(u32::MAX)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CacheSnapshot {
+    pub entries: u64,
+    /// Total weight of the entries, same as `entries` for caches bounded by count
+    pub weight: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Removed as their TTL passed
+    pub expired: u64,
+    /// Removed to make room, a high count means the capacity is too low
+    pub evicted: u64,
+    /// Removed explicitly
+    pub invalidated: u64,
+    /// Overwritten by a newer value
+    pub replaced: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use atom_syndication::Entry;
+
+    use super::*;
+
+    #[test]
+    fn read_test() {
+        let secrets = HashMap::from([
+            ("SCORE_CACHE_CAPACITY", "50"),
+            ("FEED_CACHE_TTL_SECS", "soon"),
+        ]);
+        let config = CacheConfig::read(&mut Reader::new(&secrets));
+        assert_eq!(config.score_capacity, 50);
+        assert_eq!(config.feed_ttl, CacheConfig::default().feed_ttl);
+    }
+
+    #[tokio::test]
+    async fn stats_test() {
+        let stats = Arc::new(CacheStats::default());
+        let cache = moka::future::CacheBuilder::new(1)
+            .eviction_listener(stats.listener())
+            .build();
+        for key in [1, 1, 2] {
+            stats.lookup();
+            if cache.get(&key).await.is_none() {
+                stats.miss();
+            }
+            cache.insert(key, ()).await;
+            cache.run_pending_tasks().await;
+        }
+        let snapshot = stats.snapshot(&cache);
+        assert_eq!((snapshot.hits, snapshot.misses), (1, 2));
+        assert_eq!(snapshot.replaced, 1);
+        assert_eq!(snapshot.entries, 1);
+    }
+
+    #[test]
+    fn feed_weight_test() {
+        let entry = Entry {
+            title: "a".repeat(1000).into(),
+            ..Default::default()
+        };
+        let feed = Feed {
+            entries: vec![entry; 10],
+            ..Default::default()
+        };
+        assert!(feed_weight(&feed) > feed_weight(&Feed::default()) + 10_000);
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use eyre::{eyre, ContextCompat};
+use redditrss::config::Config;
+use redditrss::rss::feed::{FeedOptions, RssFeedProvider, Upstream};
+use redditrss::secrets::Secrets;
+use redditrss::store::Store;
+use reqwest::Url;
+
+use crate::front::parse_query;
+
+/// Standalone server, or a one-off feed generation with `fetch`
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Address the server listens on
+    #[arg(long, default_value = "0.0.0.0:8000")]
+    pub listen: String,
+    /// TOML file with the configuration, in the format of Shuttle's `Secrets.toml`,
+    /// environment variables take precedence
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Generates a filtered feed once and prints it, e.g. for cron and static hosting
+    Fetch(FetchArgs),
+}
+
+#[derive(Args)]
+pub struct FetchArgs {
+    /// Subreddit path, e.g. `r/rust` or `r/rust+programming`
+    subreddit: String,
+    /// Defaults to the configured threshold of the subreddit
+    #[arg(long)]
+    min_score: Option<u64>,
+    /// Any other feed option, named as in the feed URL's query, e.g. `--option flair=News`
+    #[arg(long = "option", value_name = "KEY=VALUE")]
+    options: Vec<String>,
+    #[arg(long, value_enum, default_value_t = Format::Atom)]
+    format: Format,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum Format {
+    Atom,
+    Json,
+}
+
+/// Runs the feed pipeline once and prints the feed to stdout.
+///
+/// The feed state (e.g. sticky entries) is kept in the store like the server does,
+/// so consecutive runs behave like a reader polling the server
+pub async fn fetch(args: FetchArgs, secrets: Arc<dyn Secrets>) -> eyre::Result<()> {
+    let options = feed_options(&args)?;
+    let config = Config::from_secrets(secrets.as_ref())?;
+    let store = Store::new(&config.store_path);
+    let provider = RssFeedProvider::from_config(&config, secrets, &store).await?;
+    let subreddit = args.subreddit.trim_matches('/');
+    let subreddit = format!("r/{}", subreddit.strip_prefix("r/").unwrap_or(subreddit));
+    let feed = provider
+        .feed_filter(Upstream::Subreddit(subreddit), &options)
+        .await?;
+    match args.format {
+        Format::Atom => println!("{}", feed.to_string()),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&feed)?),
+    }
+    Ok(())
+}
+
+/// Options are parsed like the query of a feed URL, so both accept the same names and values
+fn feed_options(args: &FetchArgs) -> eyre::Result<FeedOptions> {
+    let mut url = Url::parse("http://localhost/").expect("valid URL");
+    if let Some(min_score) = args.min_score {
+        url.query_pairs_mut()
+            .append_pair("min_score", &min_score.to_string());
+    }
+    for option in &args.options {
+        let (key, value) = option
+            .split_once('=')
+            .with_context(|| format!("option {option} is not KEY=VALUE"))?;
+        url.query_pairs_mut().append_pair(key, value);
+    }
+    parse_query(url.query().unwrap_or_default()).map_err(|e| eyre!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_options_test() {
+        let cli = Cli::parse_from([
+            "redditrss",
+            "fetch",
+            "r/rust",
+            "--min-score",
+            "200",
+            "--option",
+            "flair=News,Release",
+        ]);
+        let Some(Command::Fetch(args)) = cli.command else {
+            panic!("expected fetch");
+        };
+        let options = feed_options(&args).unwrap();
+        assert_eq!(options.min_score, Some(200));
+        assert_eq!(options.flair, vec!["News", "Release"]);
+    }
+}
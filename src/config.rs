@@ -0,0 +1,256 @@
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use eyre::eyre;
+use reqwest::Url;
+
+use crate::archive::RetentionPolicy;
+use crate::audit::DEFAULT_RETENTION_DAYS;
+use crate::cache::CacheConfig;
+use crate::http::PoolConfig;
+use crate::proxy::Proxies;
+use crate::reddit::budget::RateBudget;
+use crate::reddit::endpoints::Endpoints;
+use crate::reddit::retry::RetryPolicy;
+use crate::reposts::DEFAULT_WINDOW_DAYS;
+use crate::rss::feed::{ScoreDefaults, DEFAULT_FEED_DEADLINE};
+use crate::rss::fulltext::FullText;
+use crate::secrets::Secrets;
+use crate::snapshots::SnapshotConfig;
+use crate::store::DEFAULT_STORE_PATH;
+use crate::usage::Quotas;
+use crate::webhooks::WebhookConfig;
+
+/// Requests a single client can make per minute, unless `RATE_LIMIT_PER_MINUTE` is set
+const DEFAULT_RATE_LIMIT_PER_MINUTE: NonZeroU32 = NonZeroU32::new(30).unwrap();
+
+/// Settings of the service, parsed and validated once on boot.
+///
+/// Reddit credentials, client tokens and `DATABASE_URL` are not part of it, they are read
+/// from the secrets on use, so they can be rotated without a restart (see [Secrets::reload])
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Base URL of the service as seen by readers from `PUBLIC_URL` secret,
+    /// e.g. `https://redditrss.shuttleapp.rs`, otherwise taken from the requests
+    pub public_url: Option<Url>,
+    /// Statically configured feed paths from `FEEDS` secret, separated by whitespace
+    pub static_feeds: Vec<String>,
+    /// Time budget of a feed generation from `FEED_DEADLINE_SECS` secret
+    pub feed_deadline: Duration,
+    /// Days linked URLs are remembered for `suppress_reposts` from `REPOST_WINDOW_DAYS` secret
+    pub repost_window_days: u32,
+    /// Directory of the store from `STORE_PATH` secret, `.redditrss` by default
+    pub store_path: PathBuf,
+    /// Requests a single client can make per minute from `RATE_LIMIT_PER_MINUTE` secret
+    pub rate_limit_per_minute: NonZeroU32,
+    /// Whether the service is behind a proxy appending the client address to
+    /// `X-Forwarded-For`, from `TRUSTED_PROXY` secret
+    pub trusted_proxy: bool,
+    /// Whether quarantined subreddits are opted into when Reddit asks to,
+    /// from `QUARANTINE_OPT_IN` secret
+    pub quarantine_opt_in: bool,
+    /// Days the audit log is kept from `AUDIT_RETENTION_DAYS` secret
+    pub audit_retention_days: u32,
+    /// Domains excluded from `full_content`, see [FullText::read_opt_out]
+    pub full_content_opt_out: Vec<String>,
+    pub archive_retention: RetentionPolicy,
+    pub snapshots: Option<SnapshotConfig>,
+    pub webhooks: WebhookConfig,
+    pub cache: CacheConfig,
+    pub pool: PoolConfig,
+    pub proxies: Proxies,
+    pub endpoints: Endpoints,
+    pub retry: RetryPolicy,
+    pub rate_budget: RateBudget,
+    pub score_defaults: ScoreDefaults,
+    pub quotas: Quotas,
+}
+
+impl Config {
+    /// Fails listing every invalid setting, missing ones fall back to the defaults
+    pub fn from_secrets(secrets: &dyn Secrets) -> eyre::Result<Config> {
+        let mut reader = Reader::new(secrets);
+        let config = Config {
+            public_url: reader.url("PUBLIC_URL"),
+            static_feeds: reader
+                .string("FEEDS")
+                .map(|feeds| feeds.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
+            feed_deadline: reader
+                .secs("FEED_DEADLINE_SECS")
+                .unwrap_or(DEFAULT_FEED_DEADLINE),
+            repost_window_days: reader
+                .number("REPOST_WINDOW_DAYS")
+                .unwrap_or(DEFAULT_WINDOW_DAYS),
+            store_path: reader
+                .string("STORE_PATH")
+                .unwrap_or_else(|| DEFAULT_STORE_PATH.to_string())
+                .into(),
+            rate_limit_per_minute: reader
+                .number("RATE_LIMIT_PER_MINUTE")
+                .unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE),
+            trusted_proxy: reader.flag("TRUSTED_PROXY").unwrap_or_default(),
+            quarantine_opt_in: reader.flag("QUARANTINE_OPT_IN").unwrap_or_default(),
+            audit_retention_days: reader
+                .number("AUDIT_RETENTION_DAYS")
+                .unwrap_or(DEFAULT_RETENTION_DAYS),
+            full_content_opt_out: FullText::read_opt_out(&mut reader),
+            archive_retention: RetentionPolicy::read(&mut reader),
+            snapshots: SnapshotConfig::read(&mut reader),
+            webhooks: WebhookConfig::read(&mut reader),
+            cache: CacheConfig::read(&mut reader),
+            pool: PoolConfig::read(&mut reader),
+            proxies: Proxies::read(&mut reader),
+            endpoints: Endpoints::read(&mut reader),
+            retry: RetryPolicy::read(&mut reader),
+            rate_budget: RateBudget::read(&mut reader),
+            score_defaults: ScoreDefaults::read(&mut reader),
+            quotas: Quotas::read(&mut reader),
+        };
+        reader.finish()?;
+        Ok(config)
+    }
+}
+
+/// Typed access to the secrets, collecting the invalid values
+/// so they are reported together
+pub struct Reader<'a> {
+    secrets: &'a dyn Secrets,
+    errors: Vec<String>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(secrets: &'a dyn Secrets) -> Reader<'a> {
+        Reader {
+            secrets,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.secrets.get(key)
+    }
+
+    /// Value converted by `parse`, `None` if missing or invalid
+    pub fn parse<T, E>(&mut self, key: &str, parse: impl FnOnce(&str) -> Result<T, E>) -> Option<T>
+    where
+        E: Into<eyre::Report>,
+    {
+        let value = self.secrets.get(key)?;
+        parse(value.trim())
+            .map_err(|e| self.errors.push(format!("invalid {key}: {:#}", e.into())))
+            .ok()
+    }
+
+    pub fn number<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.parse(key, str::parse::<T>)
+    }
+
+    pub fn secs(&mut self, key: &str) -> Option<Duration> {
+        self.number(key).map(Duration::from_secs)
+    }
+
+    pub fn url(&mut self, key: &str) -> Option<Url> {
+        self.parse(key, Url::parse)
+    }
+
+    /// `true` or `false`, in any case
+    pub fn flag(&mut self, key: &str) -> Option<bool> {
+        self.parse(key, |value| match value.to_lowercase().as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(eyre!("expected true or false: {value}")),
+        })
+    }
+
+    /// Comma separated `name:value` pairs, e.g. `rust:200,programming:500`,
+    /// invalid pairs are left out
+    pub fn pairs<T>(&mut self, key: &str) -> Vec<(String, T)>
+    where
+        T: FromStr,
+    {
+        let Some(value) = self.secrets.get(key) else {
+            return Vec::new();
+        };
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .filter_map(|item| {
+                let pair = item
+                    .split_once(':')
+                    .and_then(|(name, value)| Some((name.trim(), value.trim().parse().ok()?)));
+                if pair.is_none() {
+                    self.errors.push(format!(
+                        "invalid item in {key}, expected name:value: {item}"
+                    ));
+                }
+                pair.map(|(name, value)| (name.to_string(), value))
+            })
+            .collect()
+    }
+
+    /// Records an invalid combination of settings
+    pub fn error(&mut self, error: impl Into<String>) {
+        self.errors.push(error.into());
+    }
+
+    /// Fails listing the invalid values, if any
+    pub fn finish(self) -> eyre::Result<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(eyre!("invalid configuration:\n{}", self.errors.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn from_secrets_test() {
+        let secrets = HashMap::from([
+            ("PUBLIC_URL", "https://rss.example.com"),
+            ("FEEDS", "/feed/rust /feed/golang"),
+            ("FEED_CACHE_TTL_SECS", "900"),
+        ]);
+        let config = Config::from_secrets(&secrets).unwrap();
+        assert_eq!(config.static_feeds, ["/feed/rust", "/feed/golang"]);
+        assert_eq!(config.cache.feed_ttl, Duration::from_secs(900));
+        assert_eq!(config.feed_deadline, DEFAULT_FEED_DEADLINE);
+        assert!(!config.trusted_proxy);
+        assert!(config.snapshots.is_none());
+
+        let secrets = HashMap::from([
+            ("PUBLIC_URL", "rss.example.com"),
+            ("FEED_CACHE_TTL_SECS", "15m"),
+            ("DEFAULTS", "rust:200,golang"),
+            ("TRUSTED_PROXY", "yes"),
+            ("RATE_LIMIT_PER_MINUTE", "0"),
+            ("SNAPSHOT_S3_ENDPOINT", "https://s3.example.com"),
+            ("TELEGRAM_BOT_TOKEN", "token"),
+            ("TELEGRAM_CHAT_ID", "42"),
+        ]);
+        let error = Config::from_secrets(&secrets).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid configuration:\n\
+             invalid PUBLIC_URL: relative URL without a base\n\
+             invalid RATE_LIMIT_PER_MINUTE: number would be zero for non-zero type\n\
+             invalid TRUSTED_PROXY: expected true or false: yes\n\
+             SNAPSHOT_S3_BUCKET, SNAPSHOT_S3_ACCESS_KEY and SNAPSHOT_S3_SECRET_KEY are required with SNAPSHOT_S3_ENDPOINT\n\
+             TELEGRAM_PROFILE is required to deliver to telegram\n\
+             invalid FEED_CACHE_TTL_SECS: invalid digit found in string\n\
+             invalid item in DEFAULTS, expected name:value: golang"
+        );
+    }
+}
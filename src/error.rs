@@ -0,0 +1,237 @@
+use std::fmt::{Display, Formatter};
+
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tracing::{error, warn};
+
+use crate::authorization::AuthError;
+
+/// Failure of Reddit to serve a request, attached to the [eyre::Report]
+/// so the handlers can tell the reader what went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamError {
+    /// Subreddit, post or user does not exist
+    NotFound,
+    /// Access is forbidden, for a reason not known yet
+    Forbidden,
+    Banned,
+    Private,
+    /// Quarantined subreddits are only readable after opting in
+    Quarantined,
+    /// Reddit served its bot-block page to the anonymous request,
+    /// e.g. for the IP range of the host
+    Blocked,
+    /// Reddit is rate limiting us, `retry_after` is in seconds if known
+    RateLimited {
+        retry_after: Option<u64>,
+    },
+    /// Reddit responded with a server error
+    Unavailable,
+    /// Reddit responded with something that is not a valid feed
+    Parse,
+}
+
+impl UpstreamError {
+    /// Classifies a Reddit response with an error status
+    pub fn from_response(response: &reqwest::Response) -> UpstreamError {
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => UpstreamError::NotFound,
+            reqwest::StatusCode::FORBIDDEN => UpstreamError::Forbidden,
+            reqwest::StatusCode::TOO_MANY_REQUESTS => UpstreamError::RateLimited {
+                retry_after: response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.parse::<f64>().ok())
+                    .map(|secs| secs.ceil() as u64),
+            },
+            _ => UpstreamError::Unavailable,
+        }
+    }
+}
+
+impl Display for UpstreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::NotFound => write!(f, "not found on Reddit"),
+            UpstreamError::Forbidden => write!(f, "forbidden by Reddit"),
+            UpstreamError::Banned => write!(f, "subreddit is banned"),
+            UpstreamError::Private => write!(f, "subreddit is private"),
+            UpstreamError::Quarantined => write!(f, "subreddit is quarantined"),
+            UpstreamError::Blocked => write!(f, "blocked by Reddit"),
+            UpstreamError::RateLimited { .. } => write!(f, "rate limited by Reddit"),
+            UpstreamError::Unavailable => write!(f, "Reddit is unavailable"),
+            UpstreamError::Parse => write!(f, "cannot parse Reddit response"),
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+/// Error response of the handlers
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Auth(AuthError),
+    /// Resource of the service, e.g. a profile, does not exist
+    NotFound,
+    SubredditNotFound,
+    /// Reddit forbids access to the subreddit, for a reason not known
+    SubredditUnavailable,
+    SubredditBanned,
+    SubredditPrivate,
+    SubredditQuarantined,
+    /// `retry_after` is in seconds if known
+    UpstreamRateLimited {
+        retry_after: Option<u64>,
+    },
+    /// The client used up its daily quota, `retry_after` is in seconds until the next day
+    QuotaExceeded {
+        quota: u64,
+        retry_after: u64,
+    },
+    /// The client, or all of them, have `limit` feed jobs queued or running already
+    TooManyJobs {
+        limit: usize,
+    },
+    /// Reddit failed or responded with something unexpected
+    UpstreamFailure,
+    Internal(eyre::Report),
+}
+
+impl From<AuthError> for AppError {
+    fn from(error: AuthError) -> Self {
+        AppError::Auth(error)
+    }
+}
+
+impl From<eyre::Report> for AppError {
+    fn from(report: eyre::Report) -> Self {
+        let Some(upstream) = report.downcast_ref::<UpstreamError>() else {
+            capture(&report);
+            return AppError::Internal(report);
+        };
+        warn!("upstream error: {report:?}");
+        if matches!(
+            upstream,
+            UpstreamError::Blocked | UpstreamError::Unavailable | UpstreamError::Parse
+        ) {
+            capture(&report);
+        }
+        match upstream {
+            UpstreamError::NotFound => AppError::SubredditNotFound,
+            UpstreamError::Forbidden => AppError::SubredditUnavailable,
+            UpstreamError::Banned => AppError::SubredditBanned,
+            UpstreamError::Private => AppError::SubredditPrivate,
+            UpstreamError::Quarantined => AppError::SubredditQuarantined,
+            UpstreamError::RateLimited { retry_after } => AppError::UpstreamRateLimited {
+                retry_after: *retry_after,
+            },
+            UpstreamError::Blocked | UpstreamError::Unavailable | UpstreamError::Parse => {
+                AppError::UpstreamFailure
+            }
+        }
+    }
+}
+
+/// Reports the error to Sentry, within the scope of the current request if there is one
+fn capture(report: &eyre::Report) {
+    sentry::capture_message(&format!("{report:?}"), sentry::Level::Error);
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            AppError::Auth(AuthError::Unauthorized) => {
+                (StatusCode::UNAUTHORIZED, String::from("Unauthorized"))
+            }
+            AppError::Auth(AuthError::Forbidden) => {
+                (StatusCode::FORBIDDEN, String::from("Forbidden"))
+            }
+            AppError::Auth(AuthError::Expired) => {
+                (StatusCode::UNAUTHORIZED, String::from("Link expired"))
+            }
+            AppError::Auth(AuthError::NotConfigured) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                String::from("Authorization is not configured"),
+            ),
+            AppError::NotFound => (StatusCode::NOT_FOUND, String::from("Not found")),
+            AppError::SubredditNotFound => {
+                (StatusCode::NOT_FOUND, String::from("Subreddit not found"))
+            }
+            AppError::SubredditUnavailable => (
+                StatusCode::FORBIDDEN,
+                String::from("Reddit forbids access to the subreddit"),
+            ),
+            AppError::SubredditBanned => (StatusCode::GONE, String::from("Subreddit is banned")),
+            AppError::SubredditPrivate => {
+                (StatusCode::FORBIDDEN, String::from("Subreddit is private"))
+            }
+            AppError::SubredditQuarantined => (
+                StatusCode::FORBIDDEN,
+                String::from("Subreddit is quarantined"),
+            ),
+            AppError::UpstreamRateLimited { retry_after } => {
+                let message = "Reddit is rate limiting, try again later";
+                return match retry_after {
+                    Some(secs) => (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        [(header::RETRY_AFTER, secs.to_string())],
+                        message,
+                    )
+                        .into_response(),
+                    None => (StatusCode::TOO_MANY_REQUESTS, message).into_response(),
+                };
+            }
+            AppError::QuotaExceeded { quota, retry_after } => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    format!(
+                        "Daily quota of {quota} requests is used up, it resets at midnight UTC"
+                    ),
+                )
+                    .into_response();
+            }
+            AppError::TooManyJobs { limit } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("{limit} jobs are queued or running already, wait for them to finish"),
+            ),
+            AppError::UpstreamFailure => (
+                StatusCode::BAD_GATEWAY,
+                String::from("Reddit did not respond properly"),
+            ),
+            AppError::Internal(e) => {
+                error!("error: {e:?}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    String::from("Something went wrong"),
+                )
+            }
+        };
+        (status, message).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eyre::WrapErr;
+
+    #[test]
+    fn upstream_error_test() {
+        let report = Err::<(), _>(UpstreamError::NotFound)
+            .wrap_err("cannot load feed")
+            .wrap_err("cannot generate feed")
+            .unwrap_err();
+        assert!(matches!(
+            AppError::from(report),
+            AppError::SubredditNotFound
+        ));
+        assert!(matches!(
+            AppError::from(eyre::eyre!("oops")),
+            AppError::Internal(_)
+        ));
+    }
+}
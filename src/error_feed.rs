@@ -0,0 +1,99 @@
+use atom_syndication::{Content, Entry, Feed};
+use axum::body::to_bytes;
+use axum::extract::Request;
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+
+use redditrss::version::generator;
+
+use crate::feed_format::requested_format;
+
+/// Error bodies are short messages, anything longer is cut
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Readers show a failed fetch as a parse error at best, so failures of feed requests
+/// are served as a valid feed with one entry describing the error.
+///
+/// Only `GET` requests to feeds are affected, `error_feed=false` opts out.
+/// Missing credentials are left as is, so readers can ask for them,
+/// and so is rate limiting, so readers back off.
+pub async fn error_feed(request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .map(String::from);
+    let enabled = request.method() == Method::GET
+        && (path.starts_with("/feed/") || path.starts_with("/f/"))
+        && !request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .any(|param| param == "error_feed=false");
+    let response = next.run(request).await;
+    let status = response.status();
+    let passthrough = [StatusCode::UNAUTHORIZED, StatusCode::TOO_MANY_REQUESTS];
+    if !enabled || status.as_u16() < 400 || passthrough.contains(&status) {
+        return response;
+    }
+    let body = to_bytes(response.into_body(), MAX_ERROR_BODY)
+        .await
+        .unwrap_or_default();
+    let mut message = String::from_utf8_lossy(&body).into_owned();
+    if let Some(request_id) = request_id {
+        message.push_str(&format!(" (request id {request_id})"));
+    }
+    let mut feed = render_error(&path, status, &message, Utc::now());
+    feed.generator = Some(generator());
+    let format = requested_format();
+    (
+        [(header::CONTENT_TYPE, format.content_type())],
+        format.render(&feed),
+    )
+        .into_response()
+}
+
+/// Feed with a single entry describing the error, the entry id changes daily,
+/// so a persisting error shows up once a day instead of on every poll
+fn render_error(path: &str, status: StatusCode, message: &str, now: DateTime<Utc>) -> Feed {
+    let title = format!("Cannot load feed: {status}");
+    Feed {
+        id: format!("error:{path}"),
+        title: title.clone().into(),
+        updated: now.fixed_offset(),
+        entries: vec![Entry {
+            id: format!(
+                "error:{path}:{}:{}",
+                status.as_u16(),
+                now.format("%Y-%m-%d")
+            ),
+            title: title.into(),
+            updated: now.fixed_offset(),
+            content: Some(Content {
+                content_type: Some("text".to_string()),
+                value: Some(message.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_error_test() {
+        let now = DateTime::from_timestamp(1_711_900_000, 0).unwrap();
+        let feed = render_error("/feed/rust", StatusCode::BAD_GATEWAY, "Reddit is down", now);
+        let parsed = Feed::read_from(feed.to_string().as_bytes()).unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].id, "error:/feed/rust:502:2024-03-31");
+    }
+}
@@ -0,0 +1,61 @@
+use axum::extract::Request;
+use axum::http::uri::PathAndQuery;
+use axum::http::{Method, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use redditrss::error::AppError;
+use redditrss::rss::format::FeedFormat;
+
+tokio::task_local! {
+    pub static FORMAT: FeedFormat;
+}
+
+/// Format of the feeds served for the current request, Atom unless asked otherwise
+/// from within [feed_format]
+pub fn requested_format() -> FeedFormat {
+    FORMAT.try_with(|format| *format).unwrap_or_default()
+}
+
+/// Serves feeds as RSS or JSON Feed if asked by a `.rss` or `.json` suffix of the path,
+/// e.g. `/feed/rust.rss`, or by `format=rss` query parameter, as some readers detect
+/// the type from the URL.
+///
+/// The suffix is removed before routing, handlers and access checks see `/feed/rust`,
+/// while signatures and the `rel="self"` link use the original URL, kept by the router
+/// in [axum::extract::OriginalUri]. The feeds are rendered in the [requested_format]
+/// by the handlers.
+pub async fn feed_format(mut request: Request, next: Next) -> Response {
+    let uri = request.uri().clone();
+    let is_feed = request.method() == Method::GET
+        && (uri.path().starts_with("/feed/") || uri.path().starts_with("/f/"));
+    if !is_feed {
+        return next.run(request).await;
+    }
+    let (path, suffix) = FeedFormat::split_suffix(uri.path());
+    let parameter = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("format="));
+    let format = match (suffix, parameter) {
+        (Some(format), _) => format,
+        (None, Some(parameter)) => match parameter.parse() {
+            Ok(format) => format,
+            Err(e) => return AppError::BadRequest(e).into_response(),
+        },
+        (None, None) => FeedFormat::Atom,
+    };
+    if suffix.is_some() {
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        };
+        let mut parts = uri.into_parts();
+        parts.path_and_query = path_and_query.parse::<PathAndQuery>().ok();
+        if let Ok(stripped) = Uri::from_parts(parts) {
+            *request.uri_mut() = stripped;
+        }
+    }
+    FORMAT.scope(format, next.run(request)).await
+}
@@ -1,13 +1,67 @@
-use crate::authorization::{Authorization, QueryToken};
-use crate::reddit::client::RedditClient;
-use crate::rss::feed::RssFeedProvider;
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
-use reqwest::{header, Client};
-use serde::Deserialize;
-use shuttle_runtime::SecretStore;
+use crate::auditing::Auditing;
+use crate::feed_format::requested_format;
+use crate::logging::LogFilter;
+use crate::pages::builder::BuilderForm;
+use crate::pages::{self, CacheRow, ClientRow, Dashboard, Pages, ProfileRow};
+use crate::rate_limit::ClientRateLimit;
+use atom_syndication::{Feed, Link};
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequestParts, OriginalUri, Path, RawQuery, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use redditrss::archive::{ArchiveQuery, ScoreSample, COMPACTION_INTERVAL};
+use redditrss::audit::{
+    AuditEntry, AuditLog, AuditQuery, AuditedClient, PRUNE_INTERVAL as AUDIT_PRUNE_INTERVAL,
+};
+use redditrss::authorization::{unix_now, AuthError, Authorization, ClientToken, QueryToken};
+use redditrss::cache::CacheReport;
+use redditrss::config::Config;
+use redditrss::error::AppError;
+use redditrss::jobs::{Job, JobStatus, Jobs, PRUNE_INTERVAL as JOB_PRUNE_INTERVAL};
+use redditrss::metrics::MetricsReport;
+use redditrss::profiles::{FeedProfile, ProfileDefinition};
+use redditrss::readiness::{Readiness, SelfTest, RETRY_INTERVAL as SELF_TEST_RETRY_INTERVAL};
+use redditrss::reddit::budget::background;
+use redditrss::reddit::is_valid_name;
+use redditrss::reposts::PRUNE_INTERVAL as REPOST_PRUNE_INTERVAL;
+use redditrss::rss::api::ApiFeed;
+use redditrss::rss::comments::CommentOptions;
+use redditrss::rss::digest::DigestOptions;
+use redditrss::rss::feed::{
+    entry_fullname, FeedOptions, FilteredFeed, RssFeedProvider, Upstream, PREFETCH_INTERVAL,
+    QUALIFIED_PRUNE_INTERVAL,
+};
+use redditrss::rss::format::FeedFormat;
+use redditrss::rss::hacker_news::HnList;
+use redditrss::rss::lemmy::instance_host;
+use redditrss::rss::opml::{render_opml, OpmlFeed};
+use redditrss::rss::stream::{rewrite_head, FeedHead};
+use redditrss::scheduler::{spawn_periodic, Shutdown};
+use redditrss::secrets::Secrets;
+use redditrss::snapshots::Snapshots;
+use redditrss::store::{Collection, Store};
+use redditrss::usage::{Usage, PERSIST_INTERVAL as USAGE_PERSIST_INTERVAL};
+use redditrss::version::{build_info, generator, BuildInfo};
+use redditrss::watcher::{
+    WatchedProfile, Watcher, MIN_INTERVAL as WATCHER_MIN_INTERVAL,
+    TICK_INTERVAL as WATCHER_TICK_INTERVAL,
+};
+use redditrss::webhooks::{
+    Webhook, WebhookFormat, Webhooks, POLL_INTERVAL as WEBHOOK_POLL_INTERVAL,
+};
+use reqwest::{header, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
 use std::sync::Arc;
-use tracing::error;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn, Span};
 
 /// Application state
 /// Should be cheaply cloneable
@@ -15,59 +69,1635 @@ use tracing::error;
 pub struct ApplicationState {
     feed_provider: RssFeedProvider,
     authorization: Authorization,
+    profiles: Collection<FeedProfile>,
+    /// Base URL of the service as seen by readers, e.g. `https://redditrss.shuttleapp.rs`
+    public_url: Option<Arc<str>>,
+    /// Statically configured feed paths, listed in the OPML export
+    static_feeds: Arc<Vec<String>>,
+    log_filter: LogFilter,
+    webhooks: Webhooks,
+    snapshots: Option<Snapshots>,
+    pages: Pages,
+    usage: Usage,
+    audit: AuditLog,
+    readiness: Readiness,
+    secrets: Arc<dyn Secrets>,
+    jobs: Jobs,
+    watcher: Watcher,
+    rate_limit: ClientRateLimit,
+    /// Whether client addresses are taken from `X-Forwarded-For`, see [Config::trusted_proxy]
+    trusted_proxy: bool,
 }
 
-const USER_AGENT: &str = concat!("shuttle:reddit-rss:", env!("CARGO_PKG_VERSION"));
-
 impl ApplicationState {
-    pub fn new(secrets: Arc<SecretStore>) -> ApplicationState {
-        let client = Client::builder()
-            .default_headers({
-                let mut headers = header::HeaderMap::new();
-                headers.insert(header::USER_AGENT, USER_AGENT.parse().unwrap());
-                headers
-            })
-            .build()
-            .unwrap();
-        ApplicationState {
-            feed_provider: RssFeedProvider::new(
-                client.clone(),
-                RedditClient::new(secrets.clone(), client.clone()),
-            ),
-            authorization: Authorization::new(secrets.clone()),
+    pub async fn new(
+        secrets: Arc<dyn Secrets>,
+        log_filter: LogFilter,
+    ) -> eyre::Result<ApplicationState> {
+        let config = Config::from_secrets(secrets.as_ref())?;
+        let store = Store::new(&config.store_path);
+        let feed_provider = RssFeedProvider::from_config(&config, secrets.clone(), &store).await?;
+        let profiles = store.collection("profiles").await?;
+        let webhooks = Webhooks::new(feed_provider.clone(), &config.webhooks, &store).await?;
+        let authorization =
+            Authorization::new(secrets.clone(), store.collection("revoked_tokens").await?).await;
+        Ok(ApplicationState {
+            watcher: Watcher::new(
+                feed_provider.clone(),
+                webhooks.clone(),
+                profiles.clone(),
+                &store,
+            )
+            .await?,
+            webhooks,
+            readiness: Readiness::new(secrets.clone(), feed_provider.reddit_client().clone()),
+            jobs: Jobs::new(feed_provider.clone(), &store).await?,
+            feed_provider,
+            rate_limit: ClientRateLimit::new(&config, authorization.clone()),
+            trusted_proxy: config.trusted_proxy,
+            authorization,
+            profiles,
+            public_url: config
+                .public_url
+                .map(|url| url.as_str().trim_end_matches('/').into()),
+            static_feeds: Arc::new(config.static_feeds),
+            log_filter,
+            snapshots: config
+                .snapshots
+                .as_ref()
+                .map(Snapshots::from_config)
+                .transpose()?,
+            pages: Pages::new(),
+            usage: Usage::new(&store, config.quotas).await?,
+            audit: AuditLog::from_secrets(secrets.as_ref())
+                .await?
+                .with_retention_days(config.audit_retention_days),
+            secrets,
+        })
+    }
+
+    /// Limit of the requests per client, see [ClientRateLimit]
+    pub fn rate_limit(&self) -> ClientRateLimit {
+        self.rate_limit.clone()
+    }
+
+    /// Log the authorized requests are recorded in, see [crate::auditing]
+    pub fn auditing(&self) -> Auditing {
+        Auditing {
+            log: self.audit.clone(),
+            trusted_proxy: self.trusted_proxy,
         }
     }
+
+    pub fn start_background_tasks(&self, shutdown: &Shutdown) {
+        let readiness = self.readiness.clone();
+        shutdown.spawn(async move {
+            readiness.self_test().await;
+        });
+        let readiness = self.readiness.clone();
+        spawn_periodic(shutdown, "self-test", SELF_TEST_RETRY_INTERVAL, move || {
+            let readiness = readiness.clone();
+            async move {
+                if !readiness.is_ready() {
+                    readiness.self_test().await;
+                }
+            }
+        });
+        let feed_provider = self.feed_provider.clone();
+        spawn_periodic(shutdown, "prefetch", PREFETCH_INTERVAL, move || {
+            let feed_provider = feed_provider.clone();
+            async move { background(feed_provider.prefetch()).await }
+        });
+        let feed_provider = self.feed_provider.clone();
+        spawn_periodic(
+            shutdown,
+            "archive compaction",
+            COMPACTION_INTERVAL,
+            move || {
+                let feed_provider = feed_provider.clone();
+                async move { feed_provider.compact_archive().await }
+            },
+        );
+        let feed_provider = self.feed_provider.clone();
+        spawn_periodic(
+            shutdown,
+            "repost pruning",
+            REPOST_PRUNE_INTERVAL,
+            move || {
+                let feed_provider = feed_provider.clone();
+                async move { feed_provider.prune_reposts().await }
+            },
+        );
+        let feed_provider = self.feed_provider.clone();
+        spawn_periodic(
+            shutdown,
+            "qualified entries pruning",
+            QUALIFIED_PRUNE_INTERVAL,
+            move || {
+                let feed_provider = feed_provider.clone();
+                async move { feed_provider.prune_qualified().await }
+            },
+        );
+        let webhooks = self.webhooks.clone();
+        spawn_periodic(shutdown, "webhooks", WEBHOOK_POLL_INTERVAL, move || {
+            let webhooks = webhooks.clone();
+            async move { background(webhooks.poll()).await }
+        });
+        if let Some(snapshots) = &self.snapshots {
+            let (snapshots, feed_provider) = (snapshots.clone(), self.feed_provider.clone());
+            let profiles = self.profiles.clone();
+            spawn_periodic(shutdown, "snapshots", snapshots.interval, move || {
+                let (snapshots, feed_provider) = (snapshots.clone(), feed_provider.clone());
+                let profiles = profiles.clone();
+                async move { background(snapshots.snapshot(&feed_provider, &profiles)).await }
+            });
+        }
+        let audit = self.audit.clone();
+        spawn_periodic(shutdown, "audit pruning", AUDIT_PRUNE_INTERVAL, move || {
+            let audit = audit.clone();
+            async move { audit.prune().await }
+        });
+        let watcher = self.watcher.clone();
+        spawn_periodic(shutdown, "watcher", WATCHER_TICK_INTERVAL, move || {
+            let watcher = watcher.clone();
+            async move { background(watcher.tick()).await }
+        });
+        let (jobs, stopping) = (self.jobs.clone(), shutdown.clone());
+        shutdown.spawn(async move { jobs.run(stopping).await });
+        let jobs = self.jobs.clone();
+        spawn_periodic(shutdown, "job pruning", JOB_PRUNE_INTERVAL, move || {
+            let jobs = jobs.clone();
+            async move { jobs.prune().await }
+        });
+        let usage = self.usage.clone();
+        spawn_periodic(shutdown, "usage", USAGE_PERSIST_INTERVAL, move || {
+            let usage = usage.clone();
+            async move { usage.persist().await }
+        });
+        // in-flight requests are drained before the background tasks are stopped
+        let (feed_provider, usage) = (self.feed_provider.clone(), self.usage.clone());
+        let stopping = shutdown.clone();
+        shutdown.spawn(async move {
+            stopping.stopping().await;
+            feed_provider.flush().await;
+            usage.persist().await;
+        });
+    }
 }
 
-#[derive(Deserialize)]
-pub struct Filter {
-    min_score: u64,
+/// Absolute URL of the request without the token, used as the feed's `rel="self"` link
+pub struct SelfLink(pub String);
+
+#[async_trait]
+impl FromRequestParts<ApplicationState> for SelfLink {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ApplicationState,
+    ) -> Result<Self, Self::Rejection> {
+        let base = state.public_url(&parts.headers)?;
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or(&parts.uri, |OriginalUri(uri)| uri);
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+        let mut url = base
+            .join(path)
+            .map_err(|e| AppError::Internal(eyre::eyre!("cannot build self URL: {e}")))?;
+        let query = url
+            .query_pairs()
+            .filter(|(k, _)| k != "token")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect::<Vec<_>>();
+        url.set_query(None);
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        Ok(SelfLink(url.to_string()))
+    }
+}
+
+/// Atom feed response, with the `rel="self"` link and `updated` set at serving time.
+/// Streamed in the format asked for by the request instead, see [crate::feed_format::feed_format]
+pub struct AtomFeed {
+    feed: Feed,
+    self_link: String,
+    /// Until when readers and proxies may reuse the response
+    expires: Option<DateTime<Utc>>,
+    /// Upstream document served to Atom requests, with the feed-level elements above
+    /// rewritten, see [FeedOptions::verbatim]
+    document: Option<Bytes>,
 }
 
+impl AtomFeed {
+    fn new(feed: Feed, self_link: String) -> AtomFeed {
+        AtomFeed {
+            feed,
+            self_link,
+            expires: None,
+            document: None,
+        }
+    }
+
+    /// A cached feed, sent with `Cache-Control` and `Expires` headers
+    fn cached(filtered: FilteredFeed, self_link: String, expires: DateTime<Utc>) -> AtomFeed {
+        AtomFeed {
+            expires: Some(expires),
+            document: filtered.document,
+            ..AtomFeed::new(filtered.feed, self_link)
+        }
+    }
+}
+
+impl IntoResponse for AtomFeed {
+    fn into_response(self) -> Response {
+        let AtomFeed {
+            mut feed,
+            self_link,
+            expires,
+            document,
+        } = self;
+        feed.updated = Utc::now().fixed_offset();
+        feed.generator = Some(generator());
+        let format = requested_format();
+        let verbatim = document
+            .filter(|_| format == FeedFormat::Atom)
+            .and_then(|document| {
+                let head = FeedHead {
+                    self_link: &self_link,
+                    updated: feed.updated,
+                    generator: feed.generator.as_ref()?,
+                };
+                verbatim_body(document, &head)
+            });
+        let body = verbatim.unwrap_or_else(|| {
+            feed.links.retain(|link| link.rel != "self");
+            feed.links.push(Link {
+                href: self_link,
+                rel: "self".to_string(),
+                mime_type: Some("application/atom+xml".to_string()),
+                ..Default::default()
+            });
+            stream_feed(feed, format)
+        });
+        let mut response = ([(header::CONTENT_TYPE, format.content_type())], body).into_response();
+        if let Some(expires) = expires {
+            let max_age = (expires - Utc::now()).num_seconds().max(0);
+            let headers = response.headers_mut();
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_str(&format!("max-age={max_age}")).unwrap(),
+            );
+            headers.insert(
+                header::EXPIRES,
+                HeaderValue::from_str(&expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+                    .unwrap(),
+            );
+        }
+        response
+    }
+}
+
+/// The upstream document with the feed-level elements of `head`, the entries are sent as is.
+/// None if the document cannot be read, the parsed feed is sent instead
+fn verbatim_body(document: Bytes, head: &FeedHead) -> Option<Body> {
+    match rewrite_head(&document, head) {
+        Ok((start, rest)) => {
+            let chunks = [Bytes::from(start), document.slice(rest..)];
+            Some(Body::from_stream(futures::stream::iter(
+                chunks.map(Ok::<_, io::Error>),
+            )))
+        }
+        Err(e) => {
+            warn!("cannot serve the feed verbatim: {e:?}");
+            None
+        }
+    }
+}
+
+/// Size of the chunks the serialized feed is sent in
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Body sent while the feed is being serialized in `format`, so large feeds are never held
+/// as one string and readers can start parsing earlier
+fn stream_feed(feed: Feed, format: FeedFormat) -> Body {
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChunkWriter {
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            sender,
+        };
+        // fails only if the client went away
+        let written = format
+            .write_to(&feed, writer)
+            .and_then(|mut writer| writer.flush());
+        if let Err(e) = written {
+            info!("cannot stream feed: {e}");
+        }
+    });
+    Body::from_stream(futures::stream::unfold(receiver, |mut receiver| async {
+        let chunk = receiver.recv().await?;
+        Some((Ok::<_, io::Error>(chunk), receiver))
+    }))
+}
+
+/// Sends what is written in chunks of [CHUNK_SIZE]
+struct ChunkWriter {
+    chunk: Vec<u8>,
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(buf);
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+        self.sender
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "response dropped"))
+    }
+}
+
+/// Query parameters, rejected with a message naming the missing or malformed parameter,
+/// e.g. `Invalid query parameter min_score: must be a non-negative integer`
+pub struct QueryParams<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for QueryParams<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parse_query(parts.uri.query().unwrap_or_default())
+            .map(QueryParams)
+            .map_err(AppError::BadRequest)
+    }
+}
+
+/// Parses the query, errors name the parameter and what is expected of it
+pub fn parse_query<T: DeserializeOwned>(query: &str) -> Result<T, String> {
+    let deserializer =
+        serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let parameter = e.path().to_string();
+        let message = e.inner().to_string();
+        let problem = match message.as_str() {
+            "invalid digit found in string" | "cannot parse integer from empty string" => {
+                "must be a non-negative integer"
+            }
+            "number too large to fit in target type" => "is too large",
+            "provided string was not `true` or `false`" => "must be true or false",
+            message => message,
+        };
+        if parameter == "." {
+            // e.g. missing field `q`
+            format!("Invalid query: {problem}")
+        } else {
+            format!("Invalid query parameter {parameter}: {problem}")
+        }
+    })
+}
+
+/// Client authenticated with a token, basic auth or a signed URL.
+/// Access to particular resources is checked by the handlers.
+pub struct AuthenticatedClient(pub ClientToken);
+
+#[async_trait]
+impl FromRequestParts<ApplicationState> for AuthenticatedClient {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ApplicationState,
+    ) -> Result<Self, Self::Rejection> {
+        let QueryParams(auth) = QueryParams::<QueryToken>::from_request_parts(parts, state).await?;
+        // URLs are signed as the reader sees them, e.g. with a format suffix
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or(&parts.uri, |OriginalUri(uri)| uri);
+        let client = state
+            .authorization
+            .authenticate(auth, &parts.headers, uri)?;
+        if let Some(audited) = parts.extensions.get::<AuditedClient>() {
+            audited.set(&client.name);
+        }
+        state.usage.record(&client.name, unix_now())?;
+        Ok(AuthenticatedClient(client))
+    }
+}
+
+/// Client listed in `ADMIN_CLIENTS`, allowed to use the admin routes
+pub struct AdminClient(pub ClientToken);
+
+#[async_trait]
+impl FromRequestParts<ApplicationState> for AdminClient {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ApplicationState,
+    ) -> Result<Self, Self::Rejection> {
+        let AuthenticatedClient(client) =
+            AuthenticatedClient::from_request_parts(parts, state).await?;
+        if state.authorization.is_admin(&client) {
+            Ok(AdminClient(client))
+        } else {
+            Err(AuthError::Forbidden.into())
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
 pub async fn subreddit_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path(subreddit): Path<String>,
+    QueryParams(options): QueryParams<FeedOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    client.check_access(uri.path())?;
+    let filtered = feed_provider
+        .filtered_feed(Upstream::Subreddit(format!("r/{subreddit}")), &options)
+        .await?;
+    let expires = feed_provider.expires(&filtered);
+    Ok(AtomFeed::cached(filtered, self_link, expires))
+}
+
+/// Filtered posts of the subreddit as JSON, the same options as the Atom feed
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
+pub async fn subreddit_api(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    Path(subreddit): Path<String>,
+    QueryParams(options): QueryParams<FeedOptions>,
+) -> Result<Json<ApiFeed>, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    // allowed like the subreddit's feed, `/api/feed/rust` as `/feed/rust`
+    client.check_access(&format!("/feed/{subreddit}"))?;
+    let filtered = feed_provider
+        .filtered_feed(Upstream::Subreddit(format!("r/{subreddit}")), &options)
+        .await?;
+    Ok(Json(ApiFeed::from(&filtered)))
+}
+
+/// Filtered posts of the subreddit as an HTML list, to check the filters in a browser
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
+pub async fn preview(
     State(ApplicationState {
-        authorization,
         feed_provider,
+        pages,
         ..
     }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
     Path(subreddit): Path<String>,
-    Query(Filter { min_score }): Query<Filter>,
-    Query(auth): Query<QueryToken>,
-) -> (StatusCode, String) {
-    if !authorization.authorize(auth) {
-        return (StatusCode::UNAUTHORIZED, String::from("Unauthorized"));
-    }
-    let res = feed_provider
-        .feed_filter(&format!("r/{subreddit}"), min_score)
-        .await;
-    match res {
-        Ok(s) => (StatusCode::OK, s),
-        Err(e) => {
-            error!("error: {e:?}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                String::from("Something went wrong"),
-            )
+    QueryParams(options): QueryParams<FeedOptions>,
+) -> Result<Html<String>, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    // allowed like the subreddit's feed, `/preview/rust` as `/feed/rust`
+    client.check_access(&format!("/feed/{subreddit}"))?;
+    let filtered = feed_provider
+        .filtered_feed(Upstream::Subreddit(format!("r/{subreddit}")), &options)
+        .await?;
+    let feed_url = self_link.replacen("/preview/", "/feed/", 1);
+    Ok(pages.preview(&ApiFeed::from(&filtered), &feed_url)?)
+}
+
+/// Form building feed URLs from the subreddit and filters, for those not editing URLs by hand
+pub async fn url_builder(
+    State(state): State<ApplicationState>,
+    headers: HeaderMap,
+    uri: Uri,
+    QueryParams(form): QueryParams<BuilderForm>,
+) -> Result<Html<String>, AppError> {
+    if uri.query().is_none_or(str::is_empty) {
+        return Ok(state.pages.builder(&BuilderForm::initial(), None)?);
+    }
+    let base = state.public_url(&headers)?;
+    Ok(state.pages.builder(&form, Some(form.build(&base)))?)
+}
+
+/// Stylesheets and the like of the HTML pages
+pub async fn static_asset(Path(file): Path<String>) -> Result<Response, AppError> {
+    pages::asset(&file).ok_or(AppError::NotFound)
+}
+
+#[tracing::instrument(skip_all, fields(list = %list, client))]
+pub async fn hacker_news_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path(list): Path<String>,
+    QueryParams(options): QueryParams<FeedOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    client.check_access(uri.path())?;
+    let list = list.parse::<HnList>().map_err(AppError::BadRequest)?;
+    let filtered = feed_provider
+        .filtered_feed(Upstream::HackerNews(list), &options)
+        .await?;
+    let expires = feed_provider.expires(&filtered);
+    Ok(AtomFeed::cached(filtered, self_link, expires))
+}
+
+#[tracing::instrument(skip_all, fields(instance = %instance, community = %community, client))]
+pub async fn lemmy_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path((instance, community)): Path<(String, String)>,
+    QueryParams(options): QueryParams<FeedOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    client.check_access(uri.path())?;
+    let instance = instance_host(&instance).map_err(AppError::BadRequest)?;
+    let upstream = Upstream::Lemmy {
+        instance,
+        community,
+    };
+    let filtered = feed_provider.filtered_feed(upstream, &options).await?;
+    let expires = feed_provider.expires(&filtered);
+    Ok(AtomFeed::cached(filtered, self_link, expires))
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    /// Restrict the search to this subreddit
+    subreddit: Option<String>,
+    /// Reddit search sort, e.g. `new`, `relevance`, `top`
+    sort: Option<String>,
+}
+
+/// Rejects subreddit path segments other than names, or names joined by `+` like
+/// `rust+golang`, before they are put into upstream paths: the segment is percent-decoded,
+/// `..%2F` would reach other endpoints
+fn check_subreddit(subreddit: &str) -> Result<(), AppError> {
+    if !subreddit.split('+').all(is_valid_name) {
+        return Err(AppError::BadRequest(format!(
+            "Invalid subreddit name: {subreddit:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Feed path of the subreddit a query is restricted to, for the access check.
+/// The name goes into the upstream path too, so it is validated first
+fn subreddit_path(subreddit: &str) -> Result<String, AppError> {
+    if !is_valid_name(subreddit) {
+        return Err(AppError::BadRequest(format!(
+            "Invalid subreddit name: {subreddit:?}"
+        )));
+    }
+    Ok(format!("/feed/{subreddit}"))
+}
+
+/// Reddit search results, filtered like a subreddit feed
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn search_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    QueryParams(SearchQuery { q, subreddit, sort }): QueryParams<SearchQuery>,
+    QueryParams(options): QueryParams<FeedOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    // a search restricted to a subreddit is as good as the subreddit's feed
+    let path = match &subreddit {
+        Some(subreddit) => subreddit_path(subreddit)?,
+        None => uri.path().to_string(),
+    };
+    client.check_access(&path)?;
+    let upstream = Upstream::Search {
+        query: q,
+        subreddit,
+        sort: sort.unwrap_or_else(|| String::from("new")),
+    };
+    let filtered = feed_provider.filtered_feed(upstream, &options).await?;
+    let expires = feed_provider.expires(&filtered);
+    Ok(AtomFeed::cached(filtered, self_link, expires))
+}
+
+/// Full text search over the archived posts, `/search?q=async&subreddit=rust&min_score=100`
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn archive_search(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    QueryParams(query): QueryParams<ArchiveQuery>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    let path = match &query.subreddit {
+        Some(subreddit) => subreddit_path(subreddit)?,
+        None => uri.path().to_string(),
+    };
+    client.check_access(&path)?;
+    feed_provider
+        .search_archive(&query)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+/// One entry per period with the top posts of the subreddit
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
+pub async fn subreddit_digest(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path(subreddit): Path<String>,
+    QueryParams(options): QueryParams<DigestOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    client.check_access(uri.path())?;
+    feed_provider
+        .digest(&format!("r/{subreddit}"), options)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+/// Posts and comments waiting for review, the account needs mod rights in the subreddit
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
+pub async fn modqueue_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path(subreddit): Path<String>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    client.check_access(uri.path())?;
+    feed_provider
+        .modqueue_feed(&subreddit)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+/// Recent moderator actions, the account needs mod rights in the subreddit
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
+pub async fn modlog_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path(subreddit): Path<String>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    client.check_access(uri.path())?;
+    feed_provider
+        .modlog_feed(&subreddit)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+/// Top-level comments of a post, one entry per comment
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, post = %id, client))]
+pub async fn comments_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path((subreddit, id)): Path<(String, String)>,
+    QueryParams(options): QueryParams<CommentOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    if !is_valid_name(&subreddit) || !is_valid_name(&id) {
+        return Err(AppError::BadRequest(String::from(
+            "Invalid subreddit or post id",
+        )));
+    }
+    // allowed like the subreddit's feeds, `/feed/r/rust/...` as `/feed/rust/...`
+    client.check_access(&uri.path().replacen("/feed/r/", "/feed/", 1))?;
+    feed_provider
+        .comments_feed(&subreddit, &id, &options)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+#[derive(Serialize)]
+pub struct ScoreHistory {
+    id: String,
+    samples: Vec<ScoreSample>,
+}
+
+/// Archived score samples of a post, by its id with or without the `t3_` prefix
+#[tracing::instrument(skip_all, fields(post = %id, client))]
+pub async fn score_history(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<Json<ScoreHistory>, AppError> {
+    Span::current().record("client", &client.name);
+    client.check_access(uri.path())?;
+    let id = history_fullname(&id);
+    let samples = feed_provider.score_history(&id).await?;
+    if samples.is_empty() {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json(ScoreHistory { id, samples }))
+}
+
+/// Fullname of the post whose history is asked for by its entry id as served,
+/// e.g. `urn:reddit:t3_1bqry5x`, its fullname or its bare id
+fn history_fullname(id: &str) -> String {
+    format!("t3_{}", entry_fullname(id).trim_start_matches("t3_"))
+}
+
+/// Newest comments across the subreddit
+#[tracing::instrument(skip_all, fields(subreddit = %subreddit, client))]
+pub async fn comment_stream_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+    Path(subreddit): Path<String>,
+    QueryParams(options): QueryParams<CommentOptions>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    check_subreddit(&subreddit)?;
+    client.check_access(uri.path())?;
+    feed_provider
+        .comment_stream_feed(&subreddit, &options)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+/// Posts and comments saved by the authenticated account
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn saved_rss(
+    State(state): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+) -> Result<AtomFeed, AppError> {
+    account_rss(state, client, self_link, uri, "saved").await
+}
+
+/// Posts and comments upvoted by the authenticated account
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn upvoted_rss(
+    State(state): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+) -> Result<AtomFeed, AppError> {
+    account_rss(state, client, self_link, uri, "upvoted").await
+}
+
+/// Unread messages, comment replies and username mentions of the authenticated account
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn inbox_rss(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    uri: Uri,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    client.check_access(uri.path())?;
+    feed_provider
+        .inbox_feed()
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+async fn account_rss(
+    ApplicationState { feed_provider, .. }: ApplicationState,
+    client: ClientToken,
+    self_link: String,
+    uri: Uri,
+    listing: &str,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    client.check_access(uri.path())?;
+    feed_provider
+        .account_feed(listing)
+        .await
+        .map(|feed| AtomFeed::new(feed, self_link))
+        .map_err(AppError::from)
+}
+
+/// Default lifetime of a signed URL, 30 days
+const DEFAULT_SIGNED_TTL: u64 = 30 * 24 * 60 * 60;
+
+/// Longest lifetime of a signed URL, 5 years
+const MAX_SIGNED_TTL: u64 = 5 * 365 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+pub struct SignRequest {
+    /// Path and query of the URL to sign, e.g. `/feed/rust?min_score=100`
+    path: String,
+    /// Lifetime of the signed URL in seconds
+    ttl: Option<u64>,
+}
+
+/// Signs a feed URL with the server secret, so it can be handed out without the token
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn sign_url(
+    State(ApplicationState { authorization, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    QueryParams(SignRequest { path, ttl }): QueryParams<SignRequest>,
+) -> Result<String, AppError> {
+    Span::current().record("client", &client.name);
+    let target = path
+        .parse::<Uri>()
+        .map_err(|_| AppError::BadRequest(String::from("Invalid path")))?;
+    client.check_access(target.path())?;
+    let expires = Some(ttl.unwrap_or(DEFAULT_SIGNED_TTL))
+        .filter(|&ttl| ttl <= MAX_SIGNED_TTL)
+        .and_then(|ttl| unix_now().checked_add(ttl))
+        .ok_or_else(|| {
+            AppError::BadRequest(format!("ttl cannot be over {MAX_SIGNED_TTL} seconds"))
+        })?;
+    authorization
+        .sign(&target, &client, expires)
+        .map_err(AppError::from)
+}
+
+#[derive(Serialize)]
+pub struct ProfileResponse {
+    id: String,
+    /// Feed URL without the credentials
+    url: String,
+    #[serde(flatten)]
+    definition: ProfileDefinition,
+}
+
+impl ProfileResponse {
+    fn new(id: String, profile: FeedProfile) -> ProfileResponse {
+        ProfileResponse {
+            url: format!("/f/{id}"),
+            id,
+            definition: profile.definition,
+        }
+    }
+}
+
+/// Length of generated profile ids
+const PROFILE_ID_LENGTH: usize = 8;
+
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn create_profile(
+    State(ApplicationState { profiles, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    Json(definition): Json<ProfileDefinition>,
+) -> Result<(StatusCode, Json<ProfileResponse>), AppError> {
+    Span::current().record("client", &client.name);
+    definition.check_access(&client)?;
+    let id = loop {
+        let id = Alphanumeric.sample_string(&mut rand::thread_rng(), PROFILE_ID_LENGTH);
+        if !profiles.contains(&id).await {
+            break id;
+        }
+    };
+    let profile = FeedProfile {
+        owner: client.name,
+        definition,
+    };
+    profiles.insert(id.clone(), profile.clone()).await?;
+    info!("created profile {id}");
+    Ok((StatusCode::CREATED, Json(ProfileResponse::new(id, profile))))
+}
+
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn list_profiles(
+    State(ApplicationState { profiles, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+) -> Json<Vec<ProfileResponse>> {
+    Span::current().record("client", &client.name);
+    Json(
+        profiles
+            .list()
+            .await
+            .into_iter()
+            .filter(|(_, profile)| profile.owner == client.name)
+            .map(|(id, profile)| ProfileResponse::new(id, profile))
+            .collect(),
+    )
+}
+
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn get_profile(
+    State(ApplicationState { profiles, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    Path(id): Path<String>,
+) -> Result<Json<ProfileResponse>, AppError> {
+    Span::current().record("client", &client.name);
+    let profile = owned_profile(&profiles, &id, &client).await?;
+    Ok(Json(ProfileResponse::new(id, profile)))
+}
+
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn update_profile(
+    State(ApplicationState { profiles, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    Path(id): Path<String>,
+    Json(definition): Json<ProfileDefinition>,
+) -> Result<Json<ProfileResponse>, AppError> {
+    Span::current().record("client", &client.name);
+    owned_profile(&profiles, &id, &client).await?;
+    definition.check_access(&client)?;
+    let profile = FeedProfile {
+        owner: client.name,
+        definition,
+    };
+    profiles.insert(id.clone(), profile.clone()).await?;
+    Ok(Json(ProfileResponse::new(id, profile)))
+}
+
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn delete_profile(
+    State(ApplicationState {
+        profiles, watcher, ..
+    }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    Span::current().record("client", &client.name);
+    owned_profile(&profiles, &id, &client).await?;
+    profiles.remove(&id).await?;
+    watcher.remove_interval(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Feed of a stored profile, any client with access to its subreddits can read it
+#[tracing::instrument(skip_all, fields(profile = %id, client))]
+pub async fn profile_rss(
+    State(ApplicationState {
+        feed_provider,
+        profiles,
+        ..
+    }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    Path(id): Path<String>,
+) -> Result<AtomFeed, AppError> {
+    Span::current().record("client", &client.name);
+    let FeedProfile { definition, .. } = profiles.get(&id).await.ok_or(AppError::NotFound)?;
+    definition.check_access(&client)?;
+    let filtered = feed_provider
+        .filtered_feed(definition.upstream(), &definition.options)
+        .await?;
+    let expires = match definition.max_age {
+        Some(max_age) => Utc::now() + Duration::from_secs(max_age),
+        None => feed_provider.expires(&filtered),
+    };
+    Ok(AtomFeed::cached(filtered, self_link, expires))
+}
+
+/// Job without its feed
+#[derive(Serialize)]
+pub struct JobResponse {
+    id: String,
+    status: JobStatus,
+    created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl JobResponse {
+    fn new(id: String, job: Job) -> JobResponse {
+        JobResponse {
+            id,
+            status: job.status,
+            created_at: job.created_at,
+            finished_at: job.finished_at,
+            error: job.error,
         }
     }
 }
+
+/// Queues the feed of the profile definition for generation in the background,
+/// for clients with short timeouts or huge merged feeds. The feed is polled
+/// from the returned `Location`
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn create_feed_job(
+    State(ApplicationState { jobs, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    Json(definition): Json<ProfileDefinition>,
+) -> Result<Response, AppError> {
+    Span::current().record("client", &client.name);
+    definition.check_access(&client)?;
+    let id = jobs.submit(&client.name, definition).await?;
+    let job = jobs.get(&id).await.ok_or(AppError::NotFound)?;
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/jobs/{id}"))],
+        Json(JobResponse::new(id, job)),
+    )
+        .into_response())
+}
+
+/// Status of the client's job, `202 Accepted` while it is queued or running,
+/// the feed once it is done
+#[tracing::instrument(skip_all, fields(job = %id, client))]
+pub async fn feed_job(
+    State(ApplicationState { jobs, .. }): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    SelfLink(self_link): SelfLink,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    Span::current().record("client", &client.name);
+    let job = jobs
+        .get(&id)
+        .await
+        .filter(|job| job.owner == client.name)
+        .ok_or(AppError::NotFound)?;
+    Ok(match job.status {
+        // the feed is gone if evicted or generated before a restart
+        JobStatus::Done => {
+            let feed = jobs.feed(&id).await.ok_or(AppError::NotFound)?;
+            AtomFeed::new(feed, self_link).into_response()
+        }
+        JobStatus::Queued | JobStatus::Running => {
+            (StatusCode::ACCEPTED, Json(JobResponse::new(id, job))).into_response()
+        }
+        JobStatus::Failed => Json(JobResponse::new(id, job)).into_response(),
+    })
+}
+
+/// Lifetime of signed URLs in the OPML export, long lived as readers keep them forever
+const OPML_SIGNED_TTL: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// OPML document with the client's profiles and the statically configured feeds,
+/// so they can be imported into a reader in one step
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn opml(
+    State(state): State<ApplicationState>,
+    AuthenticatedClient(client): AuthenticatedClient,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    Span::current().record("client", &client.name);
+    let base = state.public_url(&headers)?;
+    let mut feeds = vec![];
+    for (id, profile) in state.profiles.list().await {
+        if profile.owner != client.name {
+            continue;
+        }
+        let subreddits = profile.definition.subreddits.join("+");
+        feeds.push(OpmlFeed {
+            title: match profile.definition.options.min_score {
+                Some(min_score) => format!("r/{subreddits} (min score {min_score})"),
+                None => format!("r/{subreddits}"),
+            },
+            url: state.credentialed_url(&base, &format!("/f/{id}"), &client)?,
+        });
+    }
+    for path in state.static_feeds.iter() {
+        let Ok(uri) = path.parse::<Uri>() else {
+            error!("invalid path in FEEDS secret: {path}");
+            continue;
+        };
+        if client.allows(uri.path()) {
+            feeds.push(OpmlFeed {
+                title: path.trim_start_matches("/feed/").to_string(),
+                url: state.credentialed_url(&base, path, &client)?,
+            });
+        }
+    }
+    Ok((
+        [(header::CONTENT_TYPE, "text/x-opml; charset=utf-8")],
+        render_opml("Reddit RSS feeds", &feeds),
+    )
+        .into_response())
+}
+
+impl ApplicationState {
+    /// `PUBLIC_URL` secret or, if not configured, the URL the request was made to
+    fn public_url(&self, headers: &HeaderMap) -> Result<Url, AppError> {
+        let base = match &self.public_url {
+            Some(url) => url.to_string(),
+            None => {
+                let host = headers
+                    .get(header::HOST)
+                    .and_then(|h| h.to_str().ok())
+                    .ok_or_else(|| AppError::BadRequest(String::from("Missing Host header")))?;
+                let scheme = headers
+                    .get("x-forwarded-proto")
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or("https");
+                format!("{scheme}://{host}")
+            }
+        };
+        Url::parse(&base).map_err(|e| AppError::Internal(eyre::eyre!("invalid public URL: {e}")))
+    }
+
+    /// Absolute URL of `path` that the client can use without providing credentials,
+    /// signed if possible, otherwise carrying the client's token
+    fn credentialed_url(
+        &self,
+        base: &Url,
+        path: &str,
+        client: &ClientToken,
+    ) -> Result<String, AppError> {
+        let path = if self.authorization.can_sign() {
+            let uri = path
+                .parse::<Uri>()
+                .map_err(|e| AppError::Internal(eyre::eyre!("invalid feed path {path}: {e}")))?;
+            self.authorization
+                .sign(&uri, client, unix_now() + OPML_SIGNED_TTL)?
+        } else {
+            path.to_string()
+        };
+        let mut url = base
+            .join(&path)
+            .map_err(|e| AppError::Internal(eyre::eyre!("cannot build feed URL: {e}")))?;
+        if !self.authorization.can_sign() {
+            url.query_pairs_mut().append_pair("token", client.token());
+        }
+        Ok(url.to_string())
+    }
+}
+
+/// Profiles of other clients are reported as missing, to not leak their ids
+async fn owned_profile(
+    profiles: &Collection<FeedProfile>,
+    id: &str,
+    client: &ClientToken,
+) -> Result<FeedProfile, AppError> {
+    profiles
+        .get(id)
+        .await
+        .filter(|profile| profile.owner == client.name)
+        .ok_or(AppError::NotFound)
+}
+
+/// Current log filter directives
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn get_log_level(
+    State(ApplicationState { log_filter, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+) -> Result<String, AppError> {
+    Span::current().record("client", &client.name);
+    Ok(log_filter.directives()?)
+}
+
+/// Replaces the log filter with the directives in the body, e.g. `info,redditrss::reddit=debug`,
+/// until the next restart
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn set_log_level(
+    State(ApplicationState { log_filter, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    directives: String,
+) -> Result<String, AppError> {
+    Span::current().record("client", &client.name);
+    log_filter
+        .set(directives.trim())
+        .map_err(|e| AppError::BadRequest(format!("Invalid log filter: {e}")))?;
+    Ok(log_filter.directives()?)
+}
+
+/// Entry counts, hits, misses and evictions of the in-memory caches
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn cache_stats(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+) -> Json<CacheReport> {
+    Span::current().record("client", &client.name);
+    Json(feed_provider.cache_stats())
+}
+
+/// Histograms of the score fetch durations, by cache hit or miss and by outcome
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn metrics(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+) -> Json<MetricsReport> {
+    Span::current().record("client", &client.name);
+    Json(feed_provider.metrics())
+}
+
+/// Clients with their usage, profiles, caches and recent upstream errors on one page
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn admin_dashboard(
+    State(state): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    RawQuery(query): RawQuery,
+) -> Result<Html<String>, AppError> {
+    Span::current().record("client", &client.name);
+    let usage = state.usage.snapshot();
+    let clients = state
+        .authorization
+        .clients()?
+        .into_iter()
+        .map(|client| ClientRow {
+            usage: usage.get(&client.name).cloned().unwrap_or_default(),
+            quota: state.usage.quota(&client.name),
+            client,
+        })
+        .collect();
+    let profiles = state
+        .profiles
+        .list()
+        .await
+        .into_iter()
+        .map(|(id, profile)| ProfileRow {
+            id,
+            owner: profile.owner,
+            subreddits: profile.definition.subreddits,
+        })
+        .collect();
+    let caches = state
+        .feed_provider
+        .cache_stats()
+        .into_iter()
+        .map(|(name, stats)| CacheRow { name, stats })
+        .collect();
+    let dashboard = Dashboard {
+        clients,
+        profiles,
+        caches,
+        errors: state.feed_provider.recent_errors(),
+        auth_query: query.unwrap_or_default(),
+    };
+    Ok(state.pages.dashboard(&dashboard)?)
+}
+
+/// Revokes the client's token from the dashboard
+#[tracing::instrument(skip_all, fields(client, revoked = %name))]
+pub async fn revoke_token(
+    State(ApplicationState { authorization, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    RawQuery(query): RawQuery,
+) -> Result<Redirect, AppError> {
+    Span::current().record("client", &client.name);
+    check_same_site(&headers)?;
+    authorization.revoke(&name).await?;
+    info!("revoked client {name}");
+    Ok(back_to_dashboard(query))
+}
+
+/// Drops the cached feeds and lookups from the dashboard
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn purge_caches(
+    State(ApplicationState { feed_provider, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+) -> Result<Redirect, AppError> {
+    Span::current().record("client", &client.name);
+    check_same_site(&headers)?;
+    feed_provider.purge_caches();
+    Ok(back_to_dashboard(query))
+}
+
+/// Re-reads the secrets, so rotated credentials take effect without a redeploy:
+/// client tokens, admins and revocations at once, the Reddit token is renewed and
+/// checked by the self-test. Settings read on boot, e.g. the quotas, need a restart
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn reload_secrets(
+    State(ApplicationState {
+        secrets,
+        feed_provider,
+        readiness,
+        ..
+    }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    headers: HeaderMap,
+) -> Result<Json<SelfTest>, AppError> {
+    Span::current().record("client", &client.name);
+    check_same_site(&headers)?;
+    secrets.reload()?;
+    info!("reloaded the secrets");
+    feed_provider.reddit_client().rotate_token();
+    Ok(Json(readiness.self_test().await))
+}
+
+/// Rejects form submissions from other sites, browsers send credentials given in the URL
+/// (basic auth) along with them
+fn check_same_site(headers: &HeaderMap) -> Result<(), AppError> {
+    match headers.get("sec-fetch-site").and_then(|h| h.to_str().ok()) {
+        Some("cross-site") => Err(AuthError::Forbidden.into()),
+        _ => Ok(()),
+    }
+}
+
+fn back_to_dashboard(query: Option<String>) -> Redirect {
+    match query {
+        Some(query) => Redirect::to(&format!("/admin?{query}")),
+        None => Redirect::to("/admin"),
+    }
+}
+
+/// Authorized requests matching the query, newest first
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn audit_log(
+    State(ApplicationState { audit, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    QueryParams(query): QueryParams<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, AppError> {
+    Span::current().record("client", &client.name);
+    Ok(Json(audit.query(&query).await?))
+}
+
+#[derive(Serialize)]
+pub struct WebhookResponse {
+    id: String,
+    url: String,
+    subreddits: Vec<String>,
+    format: WebhookFormat,
+    /// Whether deliveries are signed, the secret itself is not returned
+    signed: bool,
+    #[serde(flatten)]
+    options: FeedOptions,
+}
+
+impl WebhookResponse {
+    fn new(id: String, webhook: Webhook) -> WebhookResponse {
+        WebhookResponse {
+            id,
+            url: webhook.url,
+            subreddits: webhook.subreddits,
+            format: webhook.format,
+            signed: webhook.secret.is_some(),
+            options: webhook.options,
+        }
+    }
+}
+
+/// Configured and registered webhooks
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn list_webhooks(
+    State(ApplicationState { webhooks, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+) -> Json<Vec<WebhookResponse>> {
+    Span::current().record("client", &client.name);
+    let webhooks = webhooks.list().await;
+    Json(
+        webhooks
+            .into_iter()
+            .map(|(id, webhook)| WebhookResponse::new(id, webhook))
+            .collect(),
+    )
+}
+
+/// Registers a webhook, new posts of its feed are delivered from the next poll on
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn create_webhook(
+    State(ApplicationState { webhooks, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    Json(webhook): Json<Webhook>,
+) -> Result<(StatusCode, Json<WebhookResponse>), AppError> {
+    Span::current().record("client", &client.name);
+    webhook.validate().map_err(AppError::BadRequest)?;
+    let id = webhooks.register(webhook.clone()).await?;
+    Ok((StatusCode::CREATED, Json(WebhookResponse::new(id, webhook))))
+}
+
+#[tracing::instrument(skip_all, fields(webhook = %id, client))]
+pub async fn delete_webhook(
+    State(ApplicationState { webhooks, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    Span::current().record("client", &client.name);
+    if webhooks.unregister(&id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Profiles polled in the background, with their intervals and last polls
+#[tracing::instrument(skip_all, fields(client))]
+pub async fn list_watched(
+    State(ApplicationState { watcher, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+) -> Json<Vec<WatchedProfile>> {
+    Span::current().record("client", &client.name);
+    Json(watcher.list().await)
+}
+
+#[derive(Deserialize)]
+pub struct WatchInterval {
+    /// Missing to poll adaptively to the posting rate
+    interval_secs: Option<u64>,
+}
+
+/// Polls the profile in the background every `interval_secs`, adaptively without it
+#[tracing::instrument(skip_all, fields(profile = %id, client))]
+pub async fn set_watch_interval(
+    State(ApplicationState {
+        watcher, profiles, ..
+    }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    Path(id): Path<String>,
+    Json(WatchInterval { interval_secs }): Json<WatchInterval>,
+) -> Result<Json<WatchedProfile>, AppError> {
+    Span::current().record("client", &client.name);
+    if !profiles.contains(&id).await {
+        return Err(AppError::NotFound);
+    }
+    if interval_secs.is_some_and(|secs| secs < WATCHER_MIN_INTERVAL.as_secs()) {
+        return Err(AppError::BadRequest(format!(
+            "Interval must be at least {}s",
+            WATCHER_MIN_INTERVAL.as_secs()
+        )));
+    }
+    watcher
+        .set_interval(&id, interval_secs.map(Duration::from_secs))
+        .await?;
+    let watched = watcher.list().await.into_iter().find(|w| w.id == id);
+    Ok(Json(watched.ok_or(AppError::NotFound)?))
+}
+
+/// Stops polling the profile, unless it is delivered to a notification sink
+#[tracing::instrument(skip_all, fields(profile = %id, client))]
+pub async fn delete_watch_interval(
+    State(ApplicationState { watcher, .. }): State<ApplicationState>,
+    AdminClient(client): AdminClient,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    Span::current().record("client", &client.name);
+    if watcher.remove_interval(&id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Version, commit and features of the running build
+pub async fn version_info() -> Json<BuildInfo> {
+    Json(build_info())
+}
+
+/// Result of the startup self-test, `503 Service Unavailable` until it passes
+pub async fn readyz(
+    State(ApplicationState { readiness, .. }): State<ApplicationState>,
+) -> Response {
+    match readiness.last() {
+        Some(test) if test.ready => Json(test).into_response(),
+        Some(test) => (StatusCode::SERVICE_UNAVAILABLE, Json(test)).into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "Self-test is running").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_format::FORMAT;
+    use atom_syndication::Entry;
+
+    #[test]
+    fn parse_query_test() {
+        let error = |query| parse_query::<FeedOptions>(query).unwrap_err();
+        assert_eq!(
+            error("min_score=-5"),
+            "Invalid query parameter min_score: must be a non-negative integer"
+        );
+        assert_eq!(
+            error("sticky=yes"),
+            "Invalid query parameter sticky: must be true or false"
+        );
+        assert_eq!(
+            parse_query::<SearchQuery>("sort=new").err().unwrap(),
+            "Invalid query: missing field `q`"
+        );
+        let options = parse_query::<FeedOptions>("min_score=5&flair=a,b").unwrap();
+        assert_eq!(options.min_score, Some(5));
+    }
+
+    #[test]
+    fn history_fullname_test() {
+        for id in ["urn:reddit:t3_1bqry5x", "t3_1bqry5x", "1bqry5x"] {
+            assert_eq!(history_fullname(id), "t3_1bqry5x");
+        }
+    }
+
+    #[test]
+    fn check_subreddit_test() {
+        assert!(check_subreddit("rust").is_ok());
+        assert!(check_subreddit("rust+golang").is_ok());
+        assert!(check_subreddit("rust/../../api/v1/me").is_err());
+        assert!(check_subreddit("rust+").is_err());
+        assert!(check_subreddit("").is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_feed_test() {
+        let feed = Feed {
+            entries: vec![
+                Entry {
+                    content: Some(atom_syndication::Content {
+                        value: Some("x".repeat(CHUNK_SIZE)),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                3
+            ],
+            ..Default::default()
+        };
+        for format in [FeedFormat::Atom, FeedFormat::Rss, FeedFormat::Json] {
+            let body = axum::body::to_bytes(stream_feed(feed.clone(), format), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, format.render(&feed).as_bytes(), "{format:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn atom_feed_format_test() {
+        let feed = Feed {
+            title: "rust".into(),
+            ..Default::default()
+        };
+        let link = "https://example.com/feed/rust.rss".to_string();
+        let response = FORMAT
+            .scope(FeedFormat::Rss, async {
+                AtomFeed::new(feed, link).into_response()
+            })
+            .await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            FeedFormat::Rss.content_type()
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<rss"), "{body}");
+        assert!(body.contains("<title>rust</title>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn verbatim_feed_test() {
+        let document = concat!(
+            r#"<feed><title>upstream</title><updated>2024-03-29T12:00:00+00:00</updated>"#,
+            r#"<link rel="self" href="https://www.reddit.com/r/rust/.rss"/>"#,
+            r#"<entry><id>urn:reddit:t3_a</id></entry></feed>"#
+        );
+        let filtered = FilteredFeed {
+            feed: Feed {
+                title: "rust".into(),
+                ..Default::default()
+            },
+            document: Some(Bytes::from_static(document.as_bytes())),
+            ..Default::default()
+        };
+        let link = "https://example.com/feed/rust".to_string();
+        let body = |format| {
+            let filtered = filtered.clone();
+            let link = link.clone();
+            FORMAT.scope(format, async move {
+                let response = AtomFeed::cached(filtered, link, Utc::now()).into_response();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            })
+        };
+        let atom = body(FeedFormat::Atom).await;
+        assert!(atom.starts_with("<feed><title>upstream</title>"), "{atom}");
+        assert!(atom.ends_with("<entry><id>urn:reddit:t3_a</id></entry></feed>"));
+        assert!(
+            atom.contains(r#"<link rel="self" href="https://example.com/feed/rust""#),
+            "{atom}"
+        );
+        assert!(
+            !atom.contains("https://www.reddit.com/r/rust/.rss"),
+            "{atom}"
+        );
+        assert!(!atom.contains("2024-03-29T12:00:00"), "{atom}");
+        assert!(atom.contains("<generator"), "{atom}");
+        // the other formats are rendered from the parsed feed
+        assert!(body(FeedFormat::Rss).await.contains("<title>rust</title>"));
+    }
+}
@@ -0,0 +1,246 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use eyre::eyre;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, RequestBuilder, Url};
+use url::Host;
+
+use crate::config::Reader;
+
+/// Redirects followed by [PublicClient], each one checked like the first URL
+const MAX_REDIRECTS: usize = 10;
+
+/// Connection reuse of the outbound clients, so bursts of polls do not pay
+/// the TLS setup again for every request
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolConfig {
+    /// Idle connections kept per host, `None` keeps all of them
+    pub max_idle_per_host: Option<usize>,
+    /// Idle connections are closed after this
+    pub idle_timeout: Duration,
+    /// Idle HTTP/2 connections are pinged this often to keep them open, `None` sends no pings
+    pub http2_keep_alive: Option<Duration>,
+    /// Connection is closed when a ping is not answered in this time
+    pub http2_keep_alive_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_idle_per_host: None,
+            idle_timeout: Duration::from_secs(90),
+            http2_keep_alive: None,
+            http2_keep_alive_timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Taken from `HTTP_POOL_MAX_IDLE_PER_HOST`, `HTTP_POOL_IDLE_TIMEOUT_SECS`,
+    /// `HTTP2_KEEP_ALIVE_SECS` and `HTTP2_KEEP_ALIVE_TIMEOUT_SECS` secrets,
+    /// missing or invalid ones fall back to the defaults
+    pub fn read(reader: &mut Reader) -> PoolConfig {
+        let default = PoolConfig::default();
+        PoolConfig {
+            max_idle_per_host: reader
+                .number("HTTP_POOL_MAX_IDLE_PER_HOST")
+                .or(default.max_idle_per_host),
+            idle_timeout: reader
+                .secs("HTTP_POOL_IDLE_TIMEOUT_SECS")
+                .unwrap_or(default.idle_timeout),
+            http2_keep_alive: reader
+                .secs("HTTP2_KEEP_ALIVE_SECS")
+                .or(default.http2_keep_alive),
+            http2_keep_alive_timeout: reader
+                .secs("HTTP2_KEEP_ALIVE_TIMEOUT_SECS")
+                .unwrap_or(default.http2_keep_alive_timeout),
+        }
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        let mut builder = builder.pool_idle_timeout(self.idle_timeout);
+        if let Some(max) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(interval) = self.http2_keep_alive {
+            builder = builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_timeout(self.http2_keep_alive_timeout)
+                .http2_keep_alive_while_idle(true);
+        }
+        builder
+    }
+}
+
+/// Client of the pages posts link to, reaching public addresses only: otherwise any post
+/// could make the service request its own network, e.g. `http://169.254.169.254/`.
+/// The URL, the addresses its host resolves to and every redirect are checked.
+///
+/// Behind a proxy the proxy connects to the pages, a proxy given by name
+/// must resolve to a public address too.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct PublicClient {
+    client: Client,
+    allow_private: bool,
+}
+
+impl PublicClient {
+    pub fn new(builder: ClientBuilder) -> reqwest::Result<PublicClient> {
+        PublicClient::build(builder, false)
+    }
+
+    /// Client reaching private addresses too, for tests against local servers
+    pub fn allowing_private(builder: ClientBuilder) -> reqwest::Result<PublicClient> {
+        PublicClient::build(builder, true)
+    }
+
+    fn build(builder: ClientBuilder, allow_private: bool) -> reqwest::Result<PublicClient> {
+        let mut builder = builder.redirect(Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match check_url(attempt.url(), allow_private) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        }));
+        if !allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        Ok(PublicClient {
+            client: builder.build()?,
+            allow_private,
+        })
+    }
+
+    /// GET request of the URL, fails unless it is an `http` or `https` one of a public host
+    pub fn get(&self, url: &Url) -> eyre::Result<RequestBuilder> {
+        check_url(url, self.allow_private).map_err(|e| eyre!(e))?;
+        Ok(self.client.get(url.clone()))
+    }
+}
+
+/// Checks the hosts given as IP addresses, the names are checked once resolved
+fn check_url(url: &Url, allow_private: bool) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{url} is not a web page"));
+    }
+    let ip = match url.host() {
+        Some(Host::Domain(_)) => return Ok(()),
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        None => return Err(format!("{url} has no host")),
+    };
+    if allow_private || is_public(ip) {
+        Ok(())
+    } else {
+        Err(format!("{ip} is not a public address"))
+    }
+}
+
+/// Resolves the names to their public addresses, fails if there are none
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses = tokio::net::lookup_host((name.as_str(), 0)).await?;
+            let public = addresses
+                .filter(|address| is_public(address.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if public.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether the address is reachable from the internet: not loopback, private,
+/// link-local, unique local, shared (CGNAT) or unspecified
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn is_public_test() {
+        let public = ["1.1.1.1", "151.101.1.140", "2a04:4e42::396"];
+        let internal = [
+            "127.0.0.1",
+            "10.0.0.5",
+            "172.16.3.4",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+        for ip in public {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in internal {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        let url = |url| Url::parse(url).unwrap();
+        assert!(check_url(&url("http://169.254.169.254/latest"), false).is_err());
+        assert!(check_url(&url("http://[::1]:8080/"), false).is_err());
+        assert!(check_url(&url("file:///etc/passwd"), false).is_err());
+        assert!(check_url(&url("https://example.com/"), false).is_ok());
+        assert!(check_url(&url("http://127.0.0.1:8080/"), true).is_ok());
+    }
+
+    #[test]
+    fn read_test() {
+        let secrets = HashMap::from([
+            ("HTTP_POOL_MAX_IDLE_PER_HOST", "8"),
+            ("HTTP2_KEEP_ALIVE_SECS", "30"),
+            ("HTTP_POOL_IDLE_TIMEOUT_SECS", "soon"),
+        ]);
+        let mut reader = Reader::new(&secrets);
+        assert_eq!(
+            PoolConfig::read(&mut reader),
+            PoolConfig {
+                max_idle_per_host: Some(8),
+                http2_keep_alive: Some(Duration::from_secs(30)),
+                ..PoolConfig::default()
+            }
+        );
+        assert!(reader.finish().is_err());
+    }
+}
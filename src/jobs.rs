@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use atom_syndication::Feed;
+use chrono::Utc;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::cache::feed_weight;
+use crate::error::{AppError, UpstreamError};
+use crate::profiles::ProfileDefinition;
+use crate::rss::feed::RssFeedProvider;
+use crate::scheduler::Shutdown;
+use crate::store::{Collection, Store};
+
+/// Finished jobs are pruned this often
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Finished jobs are kept this long for their clients to pick up the feed, 1 hour
+const RETENTION_SECS: i64 = 60 * 60;
+
+/// Feeds generated at once, the other jobs wait in the queue
+const MAX_RUNNING: usize = 4;
+
+/// Jobs queued or running at once, per client and in total
+const MAX_PENDING_PER_OWNER: usize = 5;
+const MAX_PENDING: usize = 100;
+
+/// Memory taken by the finished feeds not picked up yet, see [feed_weight]
+const FEEDS_BUDGET: u64 = 16 * 1024 * 1024;
+
+/// Length of generated job ids
+const JOB_ID_LENGTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Feed generated in the background, for clients that cannot wait for it in one request
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Job {
+    /// Name of the client that submitted the job, the only one that can read it
+    pub owner: String,
+    pub definition: ProfileDefinition,
+    pub status: JobStatus,
+    /// Unix timestamps
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+    /// Reason of the failure, safe to show to the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Queue of the feed jobs, kept in the store so the queued ones survive a restart.
+/// The generated feeds are only kept in memory, until picked up or evicted.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Jobs {
+    provider: RssFeedProvider,
+    jobs: Collection<Job>,
+    feeds: moka::future::Cache<String, Feed>,
+    /// Jobs queued or running, per owner
+    pending: Arc<Mutex<HashMap<String, usize>>>,
+    /// Not saved, the running jobs are queued again after a restart
+    running: Arc<Mutex<HashSet<String>>>,
+    queue: mpsc::Sender<String>,
+    /// Taken by [Jobs::run]
+    receiver: Arc<Mutex<Option<mpsc::Receiver<String>>>>,
+}
+
+impl Jobs {
+    pub async fn new(provider: RssFeedProvider, store: &Store) -> eyre::Result<Jobs> {
+        let (queue, receiver) = mpsc::channel(MAX_PENDING);
+        let jobs: Collection<Job> = store.collection("jobs").await?;
+        let mut pending = HashMap::new();
+        for (_, job) in jobs.list().await {
+            if matches!(job.status, JobStatus::Queued | JobStatus::Running) {
+                *pending.entry(job.owner).or_default() += 1;
+            }
+        }
+        Ok(Jobs {
+            provider,
+            jobs,
+            feeds: moka::future::CacheBuilder::new(FEEDS_BUDGET)
+                .weigher(|_, feed: &Feed| feed_weight(feed))
+                .time_to_live(Duration::from_secs(RETENTION_SECS as u64))
+                .build(),
+            pending: Arc::new(Mutex::new(pending)),
+            running: Arc::default(),
+            queue,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+        })
+    }
+
+    /// Queues the feed of the (checked) definition, returns the job id.
+    /// Fails with [AppError::TooManyJobs] if the owner or everyone has too many
+    /// jobs queued or running
+    pub async fn submit(
+        &self,
+        owner: &str,
+        definition: ProfileDefinition,
+    ) -> Result<String, AppError> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let owned = pending.get(owner).copied().unwrap_or_default();
+            if owned >= MAX_PENDING_PER_OWNER {
+                return Err(AppError::TooManyJobs {
+                    limit: MAX_PENDING_PER_OWNER,
+                });
+            }
+            if pending.values().sum::<usize>() >= MAX_PENDING {
+                return Err(AppError::TooManyJobs { limit: MAX_PENDING });
+            }
+            *pending.entry(owner.to_string()).or_default() += 1;
+        }
+        let id = loop {
+            let id = Alphanumeric.sample_string(&mut rand::thread_rng(), JOB_ID_LENGTH);
+            if !self.jobs.contains(&id).await {
+                break id;
+            }
+        };
+        let job = Job {
+            owner: owner.to_string(),
+            definition,
+            status: JobStatus::Queued,
+            created_at: Utc::now().timestamp(),
+            finished_at: None,
+            error: None,
+        };
+        if let Err(e) = self.jobs.insert(id.clone(), job).await {
+            self.release(owner);
+            return Err(e.into());
+        }
+        // there is room as long as the pending jobs are capped
+        if let Err(e) = self.queue.try_send(id.clone()) {
+            warn!("cannot queue job {id}: {e}");
+        }
+        info!("queued job {id}");
+        Ok(id)
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Job> {
+        let mut job = self.jobs.get(id).await?;
+        if self.running.lock().unwrap().contains(id) {
+            job.status = JobStatus::Running;
+        }
+        Some(job)
+    }
+
+    /// Feed of the job once done, unless it expired or was evicted
+    pub async fn feed(&self, id: &str) -> Option<Feed> {
+        self.feeds.get(id).await
+    }
+
+    fn release(&self, owner: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(owned) = pending.get_mut(owner) {
+            *owned -= 1;
+            if *owned == 0 {
+                pending.remove(owner);
+            }
+        }
+    }
+
+    /// Runs the queued jobs until shutdown, the ones left unfinished
+    /// by the last run first. Does nothing if called again
+    pub async fn run(&self, shutdown: Shutdown) {
+        let Some(mut receiver) = self.receiver.lock().unwrap().take() else {
+            return;
+        };
+        for (id, job) in self.jobs.list().await {
+            if matches!(job.status, JobStatus::Queued | JobStatus::Running) {
+                let _ = self.queue.try_send(id);
+            }
+        }
+        let permits = Arc::new(Semaphore::new(MAX_RUNNING));
+        loop {
+            let next = async {
+                let id = receiver.recv().await?;
+                let permit = permits.clone().acquire_owned().await.ok()?;
+                Some((id, permit))
+            };
+            let (id, permit) = tokio::select! {
+                Some(next) = next => next,
+                _ = shutdown.stopping() => break,
+            };
+            let jobs = self.clone();
+            let span = info_span!("job", id = %id);
+            shutdown.spawn(
+                async move {
+                    jobs.execute(&id).await;
+                    drop(permit);
+                }
+                .instrument(span),
+            );
+        }
+    }
+
+    async fn execute(&self, id: &str) {
+        let Some(mut job) = self.jobs.get(id).await else {
+            return;
+        };
+        self.running.lock().unwrap().insert(id.to_string());
+        let feed = self
+            .provider
+            .feed_filter(job.definition.upstream(), &job.definition.options)
+            .await;
+        match feed {
+            Ok(feed) => {
+                job.status = JobStatus::Done;
+                self.feeds.insert(id.to_string(), feed).await;
+                info!("job {id} is done");
+            }
+            Err(e) => {
+                warn!("job {id} failed: {e:?}");
+                job.status = JobStatus::Failed;
+                job.error = Some(match e.downcast_ref::<UpstreamError>() {
+                    Some(upstream) => upstream.to_string(),
+                    None => "cannot generate the feed".to_string(),
+                });
+            }
+        }
+        job.finished_at = Some(Utc::now().timestamp());
+        self.save(id, &job).await;
+        self.running.lock().unwrap().remove(id);
+        self.release(&job.owner);
+    }
+
+    async fn save(&self, id: &str, job: &Job) {
+        if let Err(e) = self.jobs.insert(id.to_string(), job.clone()).await {
+            warn!("cannot save job {id}: {e:?}");
+        }
+    }
+
+    /// Forgets the jobs finished more than an hour ago
+    pub async fn prune(&self) {
+        let cutoff = Utc::now().timestamp() - RETENTION_SECS;
+        for (id, job) in self.jobs.list().await {
+            if job.finished_at.is_some_and(|finished| finished < cutoff) {
+                if let Err(e) = self.jobs.remove(&id).await {
+                    warn!("cannot prune job {id}: {e:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Reddit feeds filtered by score: fetching from Reddit ([reddit::client]),
+//! filtering and rendering Atom feeds ([rss::feed]).
+//!
+//! The web service wiring lives in the `redditrss` binary,
+//! the filtering pipeline can be embedded without it.
+
+pub mod archive;
+pub mod audit;
+pub mod authorization;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod http;
+pub mod jobs;
+pub mod metrics;
+pub mod profiles;
+pub mod proxy;
+pub mod readiness;
+pub mod reddit;
+pub mod reposts;
+pub mod rss;
+pub mod scheduler;
+pub mod secrets;
+pub mod singleflight;
+pub mod snapshots;
+pub mod store;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod usage;
+pub mod version;
+pub mod watcher;
+pub mod webhooks;
@@ -1,8 +1,31 @@
 use color_eyre::config::{EyreHook, HookBuilder, PanicHook, Theme};
-use tracing::error;
+use tracing::{error, info};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::fmt;
 use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Handle to change the log filter at runtime, e.g. to bump
+/// `redditrss::reddit=debug` while investigating rate limiting.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    /// Current filter directives, e.g. `info,shuttle=trace`
+    pub fn directives(&self) -> eyre::Result<String> {
+        Ok(self.0.with_current(|filter| filter.to_string())?)
+    }
+
+    /// Replaces the filter, directives are validated before anything is changed
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.0.reload(filter).map_err(|e| e.to_string())?;
+        info!("log filter changed to {directives}");
+        Ok(())
+    }
+}
 
 fn build_error_hooks() -> (PanicHook, EyreHook) {
     HookBuilder::new()
@@ -17,15 +40,23 @@ fn init_panic_hook() -> eyre::Result<()> {
 
     eyre_hook.install()?;
     std::panic::set_hook(Box::new(move |pi| {
-        error!("Panic caught: {}", panic_hook.panic_report(pi));
+        let report = panic_hook.panic_report(pi).to_string();
+        error!("Panic caught: {report}");
+        sentry::capture_message(&format!("Panic caught: {report}"), sentry::Level::Fatal);
     }));
     Ok(())
 }
 
-fn tracing() {
+fn tracing() -> LogFilter {
     use tracing_subscriber::prelude::*;
 
+    // let user override RUST_LOG in local run if they want to
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info,shuttle=trace"))
+        .unwrap();
+    let (filter, handle) = reload::Layer::new(filter);
     tracing_subscriber::registry()
+        .with(filter)
         .with(ErrorLayer::default())
         .with(
             fmt::layer()
@@ -34,16 +65,25 @@ fn tracing() {
                 .with_ansi(true)
                 .json(),
         )
-        .with(
-            // let user override RUST_LOG in local run if they want to
-            tracing_subscriber::filter::EnvFilter::try_from_default_env()
-                .or_else(|_| tracing_subscriber::filter::EnvFilter::try_new("info,shuttle=trace"))
-                .unwrap(),
-        )
         .init();
+    LogFilter(handle)
+}
+
+/// Reports errors to Sentry if `dsn` is set, otherwise reporting is a no-op
+pub fn init_error_reporting(dsn: Option<String>) {
+    let guard = sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            ..Default::default()
+        },
+    ));
+    // reporting lives as long as the process, dropping the guard would disable it
+    std::mem::forget(guard);
 }
 
-pub fn init_logging() {
-    tracing();
+pub fn init_logging() -> LogFilter {
+    let filter = tracing();
     init_panic_hook().unwrap();
+    filter
 }
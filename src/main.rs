@@ -1,22 +1,265 @@
 use std::sync::Arc;
 
-use crate::front::{subreddit_rss, ApplicationState};
-use axum::{routing::get, Router};
-use shuttle_runtime::SecretStore;
+use crate::auditing::audit;
+use crate::error_feed::error_feed;
+use crate::feed_format::feed_format;
+use crate::front::{
+    admin_dashboard, archive_search, audit_log, cache_stats, comment_stream_rss, comments_rss,
+    create_feed_job, create_profile, create_webhook, delete_profile, delete_watch_interval,
+    delete_webhook, feed_job, get_log_level, get_profile, hacker_news_rss, inbox_rss, lemmy_rss,
+    list_profiles, list_watched, list_webhooks, metrics, modlog_rss, modqueue_rss, opml, preview,
+    profile_rss, purge_caches, readyz, reload_secrets, revoke_token, saved_rss, score_history,
+    search_rss, set_log_level, set_watch_interval, sign_url, static_asset, subreddit_api,
+    subreddit_digest, subreddit_rss, update_profile, upvoted_rss, url_builder, version_info,
+    ApplicationState,
+};
+use crate::rate_limit::rate_limit;
+use axum::extract::Request;
+use axum::http::{HeaderName, StatusCode};
+use axum::{
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use redditrss::scheduler::Shutdown;
+#[cfg(not(feature = "shuttle"))]
+use redditrss::secrets::EnvSecrets;
+use redditrss::secrets::Secrets;
+use sentry::integrations::tower::{NewSentryLayer, SentryHttpLayer};
+use std::time::Duration;
+use tower::limit::ConcurrencyLimitLayer;
+use tower::Layer;
+use tower_http::compression::CompressionLayer;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{info_span, Level};
 
-mod authorization;
+mod auditing;
+#[cfg(not(feature = "shuttle"))]
+mod cli;
+mod error_feed;
+mod feed_format;
 mod front;
 mod logging;
-mod reddit;
-mod rss;
+mod pages;
+mod rate_limit;
 
+/// Whole request time limit, above the feed generation deadline
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request bodies are small JSON documents, e.g. feed profiles
+const MAX_REQUEST_BODY: usize = 64 * 1024;
+
+/// Requests handled at once, others wait for a slot
+const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// Header identifying the request in the logs and in the response
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+#[cfg(feature = "shuttle")]
 #[shuttle_runtime::main]
-async fn axum(#[shuttle_runtime::Secrets] secrets: SecretStore) -> shuttle_axum::ShuttleAxum {
-    logging::init_logging();
-    let application = ApplicationState::new(Arc::new(secrets));
-    let router = Router::new()
+async fn axum(
+    #[shuttle_runtime::Secrets] secrets: shuttle_runtime::SecretStore,
+) -> Result<ShuttleService, shuttle_runtime::Error> {
+    let shutdown = Shutdown::default();
+    let router = app(Arc::new(secrets), &shutdown)
+        .await
+        .map_err(|e| shuttle_runtime::CustomError::msg(format!("{e:?}")))?;
+    Ok(ShuttleService { router, shutdown })
+}
+
+/// Serves the router on the address given by Shuttle, then drains in-flight requests
+/// and stops the background tasks on the termination signal
+#[cfg(feature = "shuttle")]
+struct ShuttleService {
+    router: Router,
+    shutdown: Shutdown,
+}
+
+#[cfg(feature = "shuttle")]
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for ShuttleService {
+    async fn bind(mut self, addr: std::net::SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        use shuttle_runtime::CustomError;
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(CustomError::new)?;
+        // behind Shuttle's proxy the peer address is not the client's, see [ClientRateLimit]
+        axum::serve(listener, self.router)
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .map_err(CustomError::new)?;
+        self.shutdown.stop().await;
+        Ok(())
+    }
+}
+
+/// Plain server, configured from the environment and optionally a TOML file:
+/// `redditrss [--listen 0.0.0.0:8000] [--config Secrets.toml]`,
+/// or a one-off feed generation: `redditrss fetch r/rust --min-score 200`
+#[cfg(not(feature = "shuttle"))]
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    use clap::Parser;
+    use cli::{Cli, Command};
+    use eyre::Context;
+
+    let Cli {
+        listen,
+        config,
+        command,
+    } = Cli::parse();
+    let secrets: Arc<dyn Secrets> = Arc::new(match config {
+        Some(path) => EnvSecrets::with_file(&path)?,
+        None => EnvSecrets::new(),
+    });
+    if let Some(Command::Fetch(args)) = command {
+        return cli::fetch(args, secrets).await;
+    }
+    let shutdown = Shutdown::default();
+    let router = app(secrets, &shutdown).await?;
+    let listener = tokio::net::TcpListener::bind(&listen)
+        .await
+        .with_context(|| format!("cannot listen on {listen}"))?;
+    tracing::info!("listening on {listen}");
+    // stops accepting connections on the signal, in-flight requests are allowed to finish
+    // the peer address identifies anonymous clients, see [ClientRateLimit]
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+    shutdown.stop().await;
+    Ok(())
+}
+
+/// Completes on Ctrl+C or, on Unix, on SIGTERM sent by container runtimes
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("cannot listen for Ctrl+C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("cannot listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutting down, draining in-flight requests");
+}
+
+async fn app(secrets: Arc<dyn Secrets>, shutdown: &Shutdown) -> eyre::Result<Router> {
+    let log_filter = logging::init_logging();
+    logging::init_error_reporting(secrets.get("SENTRY_DSN"));
+    let application = ApplicationState::new(secrets.clone(), log_filter).await?;
+    let rate_limiter = application.rate_limit();
+    application.start_background_tasks(shutdown);
+    let routes = Router::new()
+        .route("/", get(url_builder))
+        .route("/feed/search", get(search_rss))
+        .route("/feed/me/saved", get(saved_rss))
+        .route("/feed/me/upvoted", get(upvoted_rss))
+        .route("/feed/me/inbox", get(inbox_rss))
+        .route("/feed/hn/:list", get(hacker_news_rss))
+        .route("/feed/lemmy/:instance/c/:community", get(lemmy_rss))
         .route("/feed/:subreddit", get(subreddit_rss))
+        .route("/feed/:subreddit/digest", get(subreddit_digest))
+        .route("/feed/:subreddit/comments", get(comment_stream_rss))
+        .route("/feed/:subreddit/modqueue", get(modqueue_rss))
+        .route("/feed/:subreddit/modlog", get(modlog_rss))
+        .route("/feed/r/:subreddit/comments/:id", get(comments_rss))
+        .route("/api/feed/:subreddit", get(subreddit_api))
+        .route("/api/post/:id/score_history", get(score_history))
+        .route("/preview/:subreddit", get(preview))
+        .route("/search", get(archive_search))
+        .route("/sign", get(sign_url))
+        .route("/profiles", get(list_profiles).post(create_profile))
+        .route(
+            "/profiles/:id",
+            get(get_profile).put(update_profile).delete(delete_profile),
+        )
+        .route("/f/:id", get(profile_rss))
+        .route("/jobs/feed", post(create_feed_job))
+        .route("/jobs/:id", get(feed_job))
+        .route("/opml", get(opml))
+        .route("/version", get(version_info))
+        .route("/readyz", get(readyz))
+        .route("/static/:file", get(static_asset))
+        .route("/admin", get(admin_dashboard))
+        .route("/admin/tokens/:name/revoke", post(revoke_token))
+        .route("/admin/caches/purge", post(purge_caches))
+        .route("/admin/secrets/reload", post(reload_secrets))
+        .route("/admin/audit", get(audit_log))
+        .route("/admin/log-level", get(get_log_level).put(set_log_level))
+        .route("/admin/caches", get(cache_stats))
+        .route("/admin/metrics", get(metrics))
+        .route("/admin/webhooks", get(list_webhooks).post(create_webhook))
+        .route("/admin/webhooks/:id", delete(delete_webhook))
+        .route("/admin/watcher", get(list_watched))
+        .route(
+            "/admin/watcher/:id",
+            put(set_watch_interval).delete(delete_watch_interval),
+        )
+        // inside the error feeds, so the status of the handler is recorded
+        .layer(middleware::from_fn_with_state(
+            application.auditing(),
+            audit,
+        ))
+        .layer(middleware::from_fn(error_feed))
         .with_state(application);
+    // format suffixes are stripped before routing, so they are handled outside the routes
+    let router = Router::new()
+        .fallback_service(middleware::from_fn(feed_format).layer(routes))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit))
+        .layer(RequestBodyLimitLayer::new(MAX_REQUEST_BODY))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::GATEWAY_TIMEOUT,
+            REQUEST_TIMEOUT,
+        ))
+        .layer(ConcurrencyLimitLayer::new(MAX_CONCURRENT_REQUESTS))
+        // feeds compress about tenfold, readers poll them every few minutes
+        .layer(CompressionLayer::new())
+        // the query is not logged, it may carry the token
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request| {
+                    let request_id = request
+                        .headers()
+                        .get(REQUEST_ID)
+                        .and_then(|id| id.to_str().ok())
+                        .unwrap_or_default();
+                    info_span!(
+                        "request",
+                        method = %request.method(),
+                        path = %request.uri().path(),
+                        request_id,
+                    )
+                })
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SentryHttpLayer::with_transaction())
+        .layer(NewSentryLayer::<Request>::new_from_top())
+        // an incoming request id is kept, so requests can be followed across services
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID))
+        .layer(SetRequestIdLayer::new(REQUEST_ID, MakeRequestUuid));
 
-    Ok(router.into())
+    Ok(router)
 }
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Upper bounds of the histogram buckets in milliseconds, the last bucket has none
+const BUCKET_BOUNDS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Histograms by name, see [Histogram]
+pub type MetricsReport = BTreeMap<&'static str, HistogramSnapshot>;
+
+/// Durations counted into fixed buckets, safe to record into from concurrent tasks
+#[derive(Default, Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS_MS.len() + 1],
+    count: AtomicU64,
+    sum_ms: AtomicU64,
+    max_ms: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        let ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        let bucket = BUCKET_BOUNDS_MS.partition_point(|&bound| bound < ms);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms, Ordering::Relaxed);
+        self.max_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    /// Counts so far, the counters are read one by one so they can be off
+    /// by the recordings in progress
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let bounds = BUCKET_BOUNDS_MS.map(Some).into_iter().chain([None]);
+        HistogramSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum_ms: self.sum_ms.load(Ordering::Relaxed),
+            max_ms: self.max_ms.load(Ordering::Relaxed),
+            buckets: bounds
+                .zip(&self.buckets)
+                .map(|(le_ms, count)| Bucket {
+                    le_ms,
+                    count: count.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum_ms: u64,
+    pub max_ms: u64,
+    /// Non-cumulative, each bucket counts the durations above the bound of the previous one
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Bucket {
+    /// Upper bound in milliseconds, `None` for the last bucket
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_test() {
+        let histogram = Histogram::default();
+        for ms in [0, 5, 6, 120, 9000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 5);
+        assert_eq!(snapshot.sum_ms, 9131);
+        assert_eq!(snapshot.max_ms, 9000);
+        let counts = snapshot
+            .buckets
+            .iter()
+            .filter(|b| b.count > 0)
+            .map(|b| (b.le_ms, b.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            [(Some(5), 2), (Some(10), 1), (Some(250), 1), (None, 1)]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use redditrss::rss::feed::FeedOptions;
+
+use crate::front::parse_query;
+
+/// Fields of the feed URL builder form at `/`, the form is submitted to itself.
+/// Empty fields are left out of the feed URL
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BuilderForm {
+    /// e.g. `rust`, `r/rust` or `rust+golang`
+    pub subreddit: String,
+    /// Search query, turns the feed into a Reddit search restricted to the subreddit
+    pub q: String,
+    /// Sort of the search, `new`, `relevance`, `top` or `comments`
+    pub sort: String,
+    /// `atom`, `rss` or `json`
+    pub format: String,
+    pub min_score: String,
+    pub min_percentile: String,
+    pub flair: String,
+    pub exclude_flair: String,
+    pub hide_removed: bool,
+    pub sticky: bool,
+    pub mask_sensitive: bool,
+    pub link_preview: bool,
+    pub suppress_reposts: bool,
+    pub token: String,
+}
+
+/// URLs generated from a submitted form
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BuiltUrls {
+    pub feed: String,
+    /// Only subreddit feeds have a preview page
+    pub preview: Option<String>,
+}
+
+impl BuilderForm {
+    /// The form as first shown, with the defaults of the feeds
+    pub fn initial() -> BuilderForm {
+        BuilderForm {
+            sort: "new".to_string(),
+            format: "atom".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Feed and preview URLs under `base`, or what is wrong with the form
+    pub fn build(&self, base: &Url) -> Result<BuiltUrls, String> {
+        let subreddit = self.subreddit.trim();
+        let subreddit = subreddit
+            .strip_prefix("/r/")
+            .or_else(|| subreddit.strip_prefix("r/"))
+            .unwrap_or(subreddit);
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '+';
+        if subreddit.is_empty() || !subreddit.chars().all(valid) {
+            return Err(format!("Invalid subreddit {subreddit:?}"));
+        }
+        let suffix = match self.format.as_str() {
+            "" | "atom" => "",
+            "rss" => ".rss",
+            "json" => ".json",
+            format => return Err(format!("Unknown format {format}")),
+        };
+
+        let mut filters = Vec::new();
+        let texts = [
+            ("min_score", &self.min_score),
+            ("min_percentile", &self.min_percentile),
+            ("flair", &self.flair),
+            ("exclude_flair", &self.exclude_flair),
+        ];
+        for (name, value) in texts {
+            if !value.trim().is_empty() {
+                filters.push((name, value.trim().to_string()));
+            }
+        }
+        // only the values differing from the defaults, to keep the URL short
+        let flags = [
+            ("hide_removed", self.hide_removed, false),
+            ("sticky", self.sticky, false),
+            ("mask_sensitive", self.mask_sensitive, false),
+            ("link_preview", self.link_preview, false),
+            ("suppress_reposts", self.suppress_reposts, false),
+        ];
+        for (name, value, default) in flags {
+            if value != default {
+                filters.push((name, value.to_string()));
+            }
+        }
+        parse_query::<FeedOptions>(&encode(&filters))?;
+
+        let url = |path: &str, mut params: Vec<(&str, String)>| {
+            if !self.token.is_empty() {
+                params.push(("token", self.token.clone()));
+            }
+            let mut url = base.join(path).map_err(|e| e.to_string())?;
+            if !params.is_empty() {
+                url.set_query(Some(&encode(&params)));
+            }
+            Ok::<_, String>(url.to_string())
+        };
+        if self.q.trim().is_empty() {
+            Ok(BuiltUrls {
+                feed: url(&format!("/feed/{subreddit}{suffix}"), filters.clone())?,
+                preview: Some(url(&format!("/preview/{subreddit}"), filters)?),
+            })
+        } else {
+            let search = [
+                ("q", self.q.trim().to_string()),
+                ("subreddit", subreddit.to_string()),
+                ("sort", self.sort.clone()),
+            ];
+            Ok(BuiltUrls {
+                feed: url(
+                    &format!("/feed/search{suffix}"),
+                    search.into_iter().chain(filters).collect(),
+                )?,
+                preview: None,
+            })
+        }
+    }
+}
+
+fn encode(params: &[(&str, String)]) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_test() {
+        let base = Url::parse("https://rss.example.com").unwrap();
+        let form = BuilderForm {
+            subreddit: "r/rust".to_string(),
+            min_score: "100".to_string(),
+            flair: "news, tools".to_string(),
+            link_preview: true,
+            token: "secret".to_string(),
+            ..BuilderForm::initial()
+        };
+        assert_eq!(
+            form.build(&base),
+            Ok(BuiltUrls {
+                feed: "https://rss.example.com/feed/rust?min_score=100&flair=news%2C+tools&link_preview=true&token=secret".to_string(),
+                preview: Some("https://rss.example.com/preview/rust?min_score=100&flair=news%2C+tools&link_preview=true&token=secret".to_string()),
+            })
+        );
+
+        let search = BuilderForm {
+            subreddit: "rust".to_string(),
+            q: "async traits".to_string(),
+            format: "rss".to_string(),
+            hide_removed: true,
+            ..BuilderForm::initial()
+        };
+        assert_eq!(
+            search.build(&base).unwrap().feed,
+            "https://rss.example.com/feed/search.rss?q=async+traits&subreddit=rust&sort=new&hide_removed=true"
+        );
+
+        let invalid = BuilderForm {
+            subreddit: "rust".to_string(),
+            min_score: "lots".to_string(),
+            ..BuilderForm::initial()
+        };
+        assert_eq!(
+            invalid.build(&base),
+            Err("Invalid query parameter min_score: must be a non-negative integer".to_string())
+        );
+        assert!(BuilderForm::initial().build(&base).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+use chrono::DateTime;
+use eyre::Context;
+use handlebars::{handlebars_helper, Handlebars};
+use serde::Serialize;
+
+use redditrss::authorization::ClientInfo;
+use redditrss::cache::CacheSnapshot;
+use redditrss::rss::api::ApiFeed;
+use redditrss::rss::feed::RecentError;
+use redditrss::usage::ClientUsage;
+
+use crate::pages::builder::{BuilderForm, BuiltUrls};
+
+pub mod builder;
+
+/// Templates of the pages, built into the binary
+const TEMPLATES: [(&str, &str); 4] = [
+    ("layout", include_str!("templates/layout.hbs")),
+    ("admin", include_str!("templates/admin.hbs")),
+    ("builder", include_str!("templates/builder.hbs")),
+    ("preview", include_str!("templates/preview.hbs")),
+];
+
+/// Assets the pages link to under `/static/`, by file name with their content type
+const ASSETS: [(&str, &str, &str); 1] = [(
+    "style.css",
+    "text/css; charset=utf-8",
+    include_str!("static/style.css"),
+)];
+
+/// State of the service shown on the admin dashboard
+#[derive(Serialize)]
+pub struct Dashboard {
+    pub clients: Vec<ClientRow>,
+    pub profiles: Vec<ProfileRow>,
+    pub caches: Vec<CacheRow>,
+    pub errors: Vec<RecentError>,
+    /// Query the dashboard was requested with, its actions carry the same credentials
+    pub auth_query: String,
+}
+
+#[derive(Serialize)]
+pub struct ClientRow {
+    #[serde(flatten)]
+    pub client: ClientInfo,
+    pub usage: ClientUsage,
+    /// Daily quota, `None` if unlimited
+    pub quota: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ProfileRow {
+    pub id: String,
+    pub owner: String,
+    pub subreddits: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct CacheRow {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub stats: CacheSnapshot,
+}
+
+// unix timestamps as dates, e.g. `{{time at}}`
+handlebars_helper!(time: |at: i64| {
+    DateTime::from_timestamp(at, 0)
+        .map_or_else(String::new, |at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+});
+
+/// Server-rendered HTML pages for browsers, readers get the feeds.
+/// Should be cheaply cloneable.
+#[derive(Clone)]
+pub struct Pages {
+    registry: Arc<Handlebars<'static>>,
+}
+
+impl Pages {
+    pub fn new() -> Pages {
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        registry.register_helper("time", Box::new(time));
+        for (name, template) in TEMPLATES {
+            registry
+                .register_template_string(name, template)
+                .unwrap_or_else(|e| panic!("invalid template {name}: {e}"));
+        }
+        Pages {
+            registry: Arc::new(registry),
+        }
+    }
+
+    /// Filtered posts of a feed as a list, to check the filters before subscribing.
+    /// `feed_url` is the feed with the same filters
+    pub fn preview(&self, feed: &ApiFeed, feed_url: &str) -> eyre::Result<Html<String>> {
+        #[derive(Serialize)]
+        struct Preview<'a> {
+            feed: &'a ApiFeed,
+            feed_url: &'a str,
+        }
+        self.render("preview", &Preview { feed, feed_url })
+    }
+
+    /// Form building feed URLs, with the URLs built from the submitted form or its problem
+    pub fn builder(
+        &self,
+        form: &BuilderForm,
+        built: Option<Result<BuiltUrls, String>>,
+    ) -> eyre::Result<Html<String>> {
+        #[derive(Serialize)]
+        struct Builder<'a> {
+            form: &'a BuilderForm,
+            sorts: [&'static str; 4],
+            formats: [&'static str; 3],
+            urls: Option<BuiltUrls>,
+            error: Option<String>,
+        }
+        let (urls, error) = match built {
+            Some(Ok(urls)) => (Some(urls), None),
+            Some(Err(error)) => (None, Some(error)),
+            None => (None, None),
+        };
+        let page = Builder {
+            form,
+            sorts: ["new", "relevance", "top", "comments"],
+            formats: ["atom", "rss", "json"],
+            urls,
+            error,
+        };
+        self.render("builder", &page)
+    }
+
+    pub fn dashboard(&self, dashboard: &Dashboard) -> eyre::Result<Html<String>> {
+        self.render("admin", dashboard)
+    }
+
+    fn render<T: Serialize>(&self, name: &str, data: &T) -> eyre::Result<Html<String>> {
+        self.registry
+            .render(name, data)
+            .map(Html)
+            .with_context(|| format!("cannot render page {name}"))
+    }
+}
+
+/// Asset by its file name, `None` if there is no such asset
+pub fn asset(name: &str) -> Option<Response> {
+    let (_, content_type, body) = ASSETS.into_iter().find(|(file, ..)| *file == name)?;
+    Some(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use redditrss::rss::api::ApiPost;
+
+    use super::*;
+
+    #[test]
+    fn preview_test() {
+        let feed = ApiFeed {
+            title: "r/rust <filtered>".to_string(),
+            posts: vec![ApiPost {
+                id: "t3_a".to_string(),
+                title: "Announcing Rust 1.77.0".to_string(),
+                url: Some("https://www.reddit.com/r/rust/comments/a/".to_string()),
+                score: Some(250),
+                comments: Some(30),
+                flair: Some("news".to_string()),
+                published: Some("2024-03-21T15:00:00+00:00".to_string()),
+                updated: "2024-03-21T15:00:00+00:00".to_string(),
+            }],
+        };
+        let Html(page) = Pages::new()
+            .preview(&feed, "https://example.com/feed/rust?min_score=100")
+            .unwrap();
+        insta::assert_snapshot!(page);
+    }
+
+    #[test]
+    fn builder_test() {
+        let pages = Pages::new();
+        let Html(page) = pages.builder(&BuilderForm::initial(), None).unwrap();
+        assert!(page.contains(r#"<option value="atom" selected>"#));
+        assert!(!page.contains(r#"name="sticky" value="true" checked"#));
+        assert!(!page.contains(r#"name="hide_removed" value="true" checked"#));
+
+        let built = Err("Invalid subreddit \"\"".to_string());
+        let Html(page) = pages.builder(&BuilderForm::default(), Some(built)).unwrap();
+        assert!(page.contains(r#"<p class="error">Invalid subreddit &quot;&quot;</p>"#));
+    }
+
+    #[test]
+    fn dashboard_test() {
+        let client = |name: &str, revoked| ClientInfo {
+            name: name.to_string(),
+            allowlist: vec![],
+            admin: false,
+            revoked,
+        };
+        let dashboard = Dashboard {
+            clients: vec![
+                ClientRow {
+                    client: client("alice", false),
+                    usage: ClientUsage {
+                        requests: 42,
+                        last_seen: 1_700_000_000,
+                        day: 19_675,
+                        today: 7,
+                    },
+                    quota: Some(100),
+                },
+                ClientRow {
+                    client: client("bob", true),
+                    usage: ClientUsage::default(),
+                    quota: None,
+                },
+            ],
+            profiles: vec![],
+            caches: vec![],
+            errors: vec![RecentError {
+                at: 1_700_000_000,
+                upstream: "r/secret".to_string(),
+                error: "subreddit is private".to_string(),
+            }],
+            auth_query: "token=abc".to_string(),
+        };
+        let Html(page) = Pages::new().dashboard(&dashboard).unwrap();
+        assert!(page.contains(r#"action="/admin/tokens/alice/revoke?token&#x3D;abc""#));
+        assert!(!page.contains("/admin/tokens/bob/revoke"));
+        assert!(page.contains("<td>7 / 100</td>"));
+        assert!(page.contains("<td>2023-11-14 22:13 UTC</td><td>r/secret</td>"));
+    }
+}
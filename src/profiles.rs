@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use crate::authorization::ClientToken;
+use crate::error::AppError;
+use crate::reddit::is_valid_name;
+use crate::rss::feed::{FeedOptions, Upstream};
+
+/// Feed configuration stored server-side, served under a short URL
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FeedProfile {
+    /// Name of the client that created the profile, only it can change the profile
+    pub owner: String,
+    #[serde(flatten)]
+    pub definition: ProfileDefinition,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProfileDefinition {
+    /// Subreddits merged into one feed
+    pub subreddits: Vec<String>,
+    /// Seconds readers may cache the feed for, the feed cache TTL by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+    #[serde(flatten)]
+    pub options: FeedOptions,
+}
+
+impl ProfileDefinition {
+    /// Profile is accessible only if the client can access all of its subreddits
+    pub fn check_access(&self, client: &ClientToken) -> Result<(), AppError> {
+        if self.subreddits.is_empty() {
+            return Err(AppError::BadRequest(String::from(
+                "At least one subreddit is required",
+            )));
+        }
+        if let Some(invalid) = self.subreddits.iter().find(|s| !is_valid_name(s)) {
+            return Err(AppError::BadRequest(format!(
+                "Invalid subreddit name: {invalid:?}"
+            )));
+        }
+        self.subreddits
+            .iter()
+            .try_for_each(|subreddit| client.check_access(&format!("/feed/{subreddit}")))
+            .map_err(AppError::from)
+    }
+
+    /// Listing of the merged subreddits
+    pub fn upstream(&self) -> Upstream {
+        Upstream::Subreddit(format!("r/{}", self.subreddits.join("+")))
+    }
+}
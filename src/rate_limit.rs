@@ -0,0 +1,147 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use redditrss::authorization::{Authorization, QueryToken};
+use redditrss::config::Config;
+use tracing::info;
+
+/// Amount of tracked clients after which idle ones are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Health and readiness probes, never limited so a noisy client cannot fail them
+const UNLIMITED_PATHS: &[&str] = &["/readyz"];
+
+/// Per-client rate limiting, so one misconfigured reader
+/// cannot exhaust the shared Reddit budget for everyone else.
+///
+/// Clients are identified by their name once authenticated, falling back to the IP address
+/// for the others, so rotating invalid credentials does not get around the limit.
+/// Anonymous clients are not limited when their address is unknown, e.g. served by Shuttle
+/// without `ConnectInfo` and `TRUSTED_PROXY`, rather than sharing a single limit.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct ClientRateLimit {
+    limiter: Arc<DefaultKeyedRateLimiter<String>>,
+    authorization: Authorization,
+    /// Whether the service is behind a proxy appending the client address
+    /// to `X-Forwarded-For`, otherwise the header is ignored
+    trusted_proxy: bool,
+}
+
+impl ClientRateLimit {
+    /// Limit of [Config::rate_limit_per_minute], with [Config::trusted_proxy]
+    /// the address of anonymous clients is taken from `X-Forwarded-For`
+    pub fn new(config: &Config, authorization: Authorization) -> ClientRateLimit {
+        ClientRateLimit {
+            limiter: Arc::new(RateLimiter::keyed(Quota::per_minute(
+                config.rate_limit_per_minute,
+            ))),
+            authorization,
+            trusted_proxy: config.trusted_proxy,
+        }
+    }
+
+    /// Name of the authenticated client or, if the request is not authenticated,
+    /// its address if known
+    fn client_key(&self, request: &Request) -> Option<String> {
+        let query_token = Query::<QueryToken>::try_from_uri(request.uri());
+        let client = query_token.ok().and_then(|Query(token)| {
+            self.authorization
+                .authenticate(token, request.headers(), request.uri())
+                .ok()
+        });
+        if let Some(client) = client {
+            return Some(format!("client:{}", client.name));
+        }
+        address_key(request.headers(), peer_address(request), self.trusted_proxy)
+    }
+}
+
+pub async fn rate_limit(
+    State(rate_limit): State<ClientRateLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if UNLIMITED_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let Some(key) = rate_limit.client_key(&request) else {
+        return next.run(request).await;
+    };
+    let limiter = &rate_limit.limiter;
+    let check = limiter.check_key(&key);
+    if limiter.len() > MAX_TRACKED_CLIENTS {
+        limiter.retain_recent();
+    }
+    match check {
+        Ok(_) => next.run(request).await,
+        Err(not_until) => {
+            let wait = not_until.wait_time_from(DefaultClock::default().now());
+            info!("client is rate limited for {wait:?}");
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, (wait.as_secs() + 1).to_string())],
+                "Too many requests",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Address the client is connected from, unknown unless served with `ConnectInfo`
+pub fn peer_address(request: &Request) -> Option<String> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+}
+
+/// Address of the client: the one the trusted proxy appended last to `X-Forwarded-For`,
+/// the earlier ones are set by the client, or the peer address
+pub fn client_address(
+    headers: &HeaderMap,
+    peer: Option<String>,
+    trusted_proxy: bool,
+) -> Option<String> {
+    let forwarded = headers
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|_| trusted_proxy);
+    forwarded.or(peer)
+}
+
+/// Key of an anonymous client, by its [client_address]
+fn address_key(headers: &HeaderMap, peer: Option<String>, trusted_proxy: bool) -> Option<String> {
+    client_address(headers, peer, trusted_proxy).map(|ip| format!("ip:{ip}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_key_test() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "10.0.0.1, 203.0.113.7".parse().unwrap());
+        let peer = Some("192.0.2.1".to_string());
+        assert_eq!(
+            address_key(&headers, peer.clone(), true).as_deref(),
+            Some("ip:203.0.113.7")
+        );
+        // the header is set by the client without a proxy
+        assert_eq!(
+            address_key(&headers, peer, false).as_deref(),
+            Some("ip:192.0.2.1")
+        );
+        assert_eq!(address_key(&headers, None, false), None);
+    }
+}
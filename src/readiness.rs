@@ -0,0 +1,121 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::reddit::client::RedditClient;
+use crate::secrets::Secrets;
+
+/// Secrets the Reddit feeds cannot be served without
+pub const REQUIRED_SECRETS: [&str; 4] = [
+    "REDDIT_CLIENT_ID",
+    "REDDIT_CLIENT_SECRET",
+    "REDDIT_USERNAME",
+    "REDDIT_PASSWORD",
+];
+
+/// How often a failed self-test is run again, e.g. after Reddit was down on boot
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Outcome of a step of the self-test
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SelfTest {
+    pub ready: bool,
+    /// Unix timestamp of the run
+    pub at: i64,
+    /// Steps in order, the ones after a failed step are not run
+    pub checks: Vec<Check>,
+}
+
+/// Self-test of the configuration run on boot: the required secrets are present,
+/// Reddit grants a token and answers an authenticated call.
+/// Misconfigured deployments fail readiness instead of the first feed request.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Readiness {
+    secrets: Arc<dyn Secrets>,
+    reddit_client: RedditClient,
+    last: Arc<RwLock<Option<SelfTest>>>,
+}
+
+impl Readiness {
+    pub fn new(secrets: Arc<dyn Secrets>, reddit_client: RedditClient) -> Readiness {
+        Readiness {
+            secrets,
+            reddit_client,
+            last: Arc::default(),
+        }
+    }
+
+    /// Result of the last self-test, `None` until the first one completes
+    pub fn last(&self) -> Option<SelfTest> {
+        self.last.read().unwrap().clone()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.last.read().unwrap().as_ref().is_some_and(|t| t.ready)
+    }
+
+    /// Runs the self-test, logging a summary of it
+    pub async fn self_test(&self) -> SelfTest {
+        let checks = self.checks().await;
+        let failed = checks
+            .iter()
+            .find_map(|c| Some((c.name, c.error.as_ref()?)));
+        match failed {
+            None => info!("self-test passed"),
+            Some((name, e)) => error!("self-test failed at {name}: {e}"),
+        }
+        let test = SelfTest {
+            ready: failed.is_none(),
+            at: Utc::now().timestamp(),
+            checks,
+        };
+        *self.last.write().unwrap() = Some(test.clone());
+        test
+    }
+
+    async fn checks(&self) -> Vec<Check> {
+        let missing = REQUIRED_SECRETS
+            .into_iter()
+            .filter(|key| self.secrets.get(key).is_none())
+            .collect::<Vec<_>>();
+        let mut checks = vec![Check {
+            name: "secrets",
+            error: (!missing.is_empty()).then(|| format!("missing {}", missing.join(", "))),
+        }];
+        if !missing.is_empty() {
+            return checks;
+        }
+
+        let token = self.reddit_client.check_token().await;
+        checks.push(Check {
+            name: "token",
+            error: token.as_ref().err().map(|e| format!("{e:#}")),
+        });
+        if token.is_err() {
+            return checks;
+        }
+
+        // the account's own profile, one cheap call needing the token
+        let api = match self.reddit_client.username() {
+            Ok(username) => self.reddit_client.get_user_info(&username).await.map(drop),
+            Err(e) => Err(e),
+        };
+        checks.push(Check {
+            name: "api",
+            error: api.err().map(|e| format!("{e:#}")),
+        });
+        checks
+    }
+}
@@ -1,9 +1,11 @@
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::cache::{CacheSnapshot, CacheStats};
+use crate::secrets::Secrets;
 use eyre::{eyre, Context, ContextCompat};
 use reqwest::Client;
 use serde::Deserialize;
-use shuttle_runtime::SecretStore;
 use tracing::debug;
 
 #[derive(Debug, Deserialize)]
@@ -15,31 +17,72 @@ struct AuthResponse {
     pub token_type: String,
 }
 
+/// OAuth scopes requested for the token:
+/// `read` for listings, `history` for saved and upvoted posts,
+/// `privatemessages` for the inbox, `modlog` for the moderation log
+const SCOPES: &str = "read history privatemessages modlog";
+
 pub struct RedditAuth {
     // TODO: maybe there is a better way to cache the token
     token_cache: moka::future::Cache<(), String>,
-    secrets: Arc<SecretStore>,
+    token_stats: Arc<CacheStats>,
+    secrets: Arc<dyn Secrets>,
+    token_url: String,
 }
 
 impl RedditAuth {
-    pub fn new(secrets: Arc<SecretStore>) -> RedditAuth {
+    /// Tokens are kept for `token_ttl`, see [crate::cache::CacheConfig::token_ttl]
+    pub fn new(secrets: Arc<dyn Secrets>, token_url: String, token_ttl: Duration) -> RedditAuth {
+        let token_stats = Arc::new(CacheStats::default());
         RedditAuth {
             token_cache: moka::future::CacheBuilder::new(1)
-                .time_to_live(std::time::Duration::from_secs(4 * 60 * 60)) // 4 hours
+                .time_to_live(token_ttl)
+                .eviction_listener(token_stats.listener())
                 .build(),
+            token_stats,
             secrets,
+            token_url,
         }
     }
 
+    /// Same credentials with the tokens kept for `token_ttl`
+    pub fn with_token_ttl(&self, token_ttl: Duration) -> RedditAuth {
+        RedditAuth::new(self.secrets.clone(), self.token_url.clone(), token_ttl)
+    }
+
+    /// Name of the account the client acts as
+    pub fn username(&self) -> eyre::Result<String> {
+        self.secrets
+            .get("REDDIT_USERNAME")
+            .context("cannot get username")
+    }
+
     pub async fn get_token(&self, client: &Client) -> eyre::Result<String> {
+        self.token_stats.lookup();
         self.token_cache
-            .try_get_with((), get_token(client, &self.secrets))
+            .try_get_with((), async {
+                self.token_stats.miss();
+                get_token(client, self.secrets.as_ref(), &self.token_url).await
+            })
             .await
             .map_err(|e| eyre!("cannot get token, {e}"))
     }
+
+    /// Drops the token, the next request gets one with the current credentials
+    pub fn rotate(&self) {
+        self.token_cache.invalidate_all();
+    }
+
+    pub fn token_cache_stats(&self) -> CacheSnapshot {
+        self.token_stats.snapshot(&self.token_cache)
+    }
 }
 
-async fn get_token(client: &Client, secrets: &SecretStore) -> eyre::Result<String> {
+async fn get_token(
+    client: &Client,
+    secrets: &dyn Secrets,
+    token_url: &str,
+) -> eyre::Result<String> {
     let client_id = secrets
         .get("REDDIT_CLIENT_ID")
         .context("cannot get client id")?;
@@ -54,12 +97,13 @@ async fn get_token(client: &Client, secrets: &SecretStore) -> eyre::Result<Strin
         .context("cannot get password")?;
 
     client
-        .post("https://oauth.reddit.com/api/v1/access_token")
+        .post(token_url)
         .basic_auth(client_id, Some(client_secret))
         .form(&[
             ("grant_type", "password"),
             ("username", &username),
             ("password", &password),
+            ("scope", SCOPES),
         ])
         .send()
         .await?
@@ -0,0 +1,177 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use eyre::ensure;
+
+use crate::config::Reader;
+use crate::error::UpstreamError;
+
+/// Share of the rate limit period kept for the interactive requests by default
+const DEFAULT_RESERVED_SHARE: f64 = 0.3;
+
+/// Class of a request to Reddit, see [RateBudget]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Sent for a reader waiting for the response
+    #[default]
+    Interactive,
+    /// Sent by the background tasks, e.g. prefetching and the watcher
+    Background,
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+impl Priority {
+    /// Class of the requests of the current task, interactive unless run by [background]
+    pub fn current() -> Priority {
+        PRIORITY.try_with(|priority| *priority).unwrap_or_default()
+    }
+}
+
+/// Runs `future` with its requests to Reddit in the [Priority::Background] class
+pub async fn background<F: Future>(future: F) -> F::Output {
+    PRIORITY.scope(Priority::Background, future).await
+}
+
+/// Partition of Reddit's rate limit period between the priority classes:
+/// background requests fail with [UpstreamError::RateLimited] instead of using
+/// the share reserved for the interactive ones, so a prefetch cycle cannot make
+/// a reader wait for the next period.
+///
+/// The remaining requests are learned from the rate limit headers and counted down
+/// locally in between, nothing is held back before the first response.
+///
+/// Cheaply cloneable, the clones share the period.
+#[derive(Clone, Debug)]
+pub struct RateBudget {
+    /// Share of the period only the interactive requests can use, from 0 to 1
+    reserved_share: f64,
+    period: Arc<Mutex<Period>>,
+}
+
+#[derive(Debug, Default)]
+struct Period {
+    /// Requests per period, `used + remaining` of the last response
+    limit: Option<f64>,
+    /// Unknown once the period is over until the next response
+    remaining: Option<f64>,
+    reset_at: Option<Instant>,
+}
+
+impl Default for RateBudget {
+    fn default() -> Self {
+        RateBudget::new(DEFAULT_RESERVED_SHARE)
+    }
+}
+
+impl RateBudget {
+    pub fn new(reserved_share: f64) -> RateBudget {
+        RateBudget {
+            reserved_share,
+            period: Arc::default(),
+        }
+    }
+
+    /// Reserved share taken from `RATE_BUDGET_INTERACTIVE_SHARE` secret, e.g. `0.3`
+    pub fn read(reader: &mut Reader) -> RateBudget {
+        let share = reader.parse("RATE_BUDGET_INTERACTIVE_SHARE", |value| {
+            let share = value.parse::<f64>()?;
+            ensure!((0.0..=1.0).contains(&share), "must be between 0 and 1");
+            Ok(share)
+        });
+        RateBudget::new(share.unwrap_or(DEFAULT_RESERVED_SHARE))
+    }
+
+    /// Takes a request out of the period, unless it would dig into
+    /// the reserved share for a background one
+    pub fn acquire(&self, priority: Priority) -> Result<(), UpstreamError> {
+        let mut period = self.period.lock().unwrap();
+        self.check(&mut period, priority)?;
+        if let Some(remaining) = &mut period.remaining {
+            *remaining -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Whether a request of the class would be sent now
+    pub fn available(&self, priority: Priority) -> bool {
+        self.check(&mut self.period.lock().unwrap(), priority)
+            .is_ok()
+    }
+
+    fn check(&self, period: &mut Period, priority: Priority) -> Result<(), UpstreamError> {
+        let now = Instant::now();
+        if period.reset_at.is_some_and(|reset| reset <= now) {
+            *period = Period {
+                limit: period.limit,
+                ..Period::default()
+            };
+        }
+        let Period {
+            limit: Some(limit),
+            remaining: Some(remaining),
+            reset_at,
+        } = *period
+        else {
+            return Ok(());
+        };
+        if priority == Priority::Background && remaining - 1.0 < limit * self.reserved_share {
+            return Err(UpstreamError::RateLimited {
+                retry_after: reset_at.map(|reset| (reset - now).as_secs() + 1),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the local count with the rate limit headers of a response,
+    /// in requests and seconds
+    pub fn update(&self, used: Option<f64>, remaining: Option<f64>, reset: Option<f64>) {
+        let Some(remaining) = remaining else {
+            return;
+        };
+        let mut period = self.period.lock().unwrap();
+        if let Some(used) = used {
+            period.limit = Some(used + remaining);
+        }
+        period.remaining = Some(remaining);
+        period.reset_at = reset
+            .and_then(|reset| Duration::try_from_secs_f64(reset).ok())
+            .map(|reset| Instant::now() + reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn acquire_test() {
+        let budget = RateBudget::new(0.3);
+        // nothing is known before the first response
+        assert!(budget.acquire(Priority::Background).is_ok());
+
+        // 3 of the 10 requests per period are reserved
+        budget.update(Some(5.0), Some(5.0), Some(60.0));
+        budget.acquire(Priority::Background).unwrap();
+        budget.acquire(Priority::Background).unwrap();
+        let refused = budget.acquire(Priority::Background).unwrap_err();
+        assert!(matches!(
+            refused,
+            UpstreamError::RateLimited {
+                retry_after: Some(60)
+            }
+        ));
+        assert!(background(async { !budget.available(Priority::current()) }).await);
+        assert!(budget.available(Priority::current()));
+        for _ in 0..3 {
+            budget.acquire(Priority::Interactive).unwrap();
+        }
+
+        // a new period frees the background share again
+        budget.update(Some(0.0), Some(10.0), Some(600.0));
+        assert!(budget.acquire(Priority::Background).is_ok());
+    }
+}
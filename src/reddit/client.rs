@@ -1,13 +1,60 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::secrets::Secrets;
 use eyre::{bail, Context, ContextCompat};
 use reqwest::{Response, StatusCode};
-use shuttle_runtime::SecretStore;
-use tokio::sync::{RwLock, RwLockReadGuard};
-use tracing::info;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{info, warn};
 
+use crate::authorization::unix_now;
+use crate::cache::{CacheConfig, CacheSnapshot};
+use crate::error::UpstreamError;
 use crate::reddit::auth::RedditAuth;
+use crate::reddit::budget::{Priority, RateBudget};
+use crate::reddit::endpoints::Endpoints;
+use crate::reddit::listing::{
+    edited, Comment, CrosspostParent, InboxItem, Listing, Message, ModAction, ModLogItem, PollData,
+    Post, Thing, UserAbout, UserInfo,
+};
+use crate::reddit::retry::{current_deadline, RetryPolicy};
+use crate::store::Collection;
+
+/// Key of the Reddit throttle state in its collection
+const THROTTLE_KEY: &str = "reddit";
+
+/// `X-Ratelimit-Remaining` at or below this is persisted, and honored after a restart
+/// by waiting for the period to reset
+const LOW_REMAINING: f64 = 10.0;
+
+/// Throttle after a 429 without retry-after
+const DEFAULT_THROTTLE_SECS: f64 = 60.0;
+
+/// Changes of the throttle state within this delay are written to the store at once
+const THROTTLE_WRITE_DELAY: Duration = Duration::from_secs(1);
+
+/// Throttle state kept across restarts, see [RedditClient::with_throttle_store]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ThrottleState {
+    /// Unix time until which requests are not sent
+    pub throttled_until: Option<u64>,
+    /// Last known `X-Ratelimit-Remaining`, only while it is low
+    pub remaining: Option<f64>,
+    /// Unix time the rate limit period of `remaining` ends
+    pub reset_at: Option<u64>,
+}
+
+impl ThrottleState {
+    /// Unix time until which requests should not be sent after a restart
+    fn resume_at(&self) -> Option<u64> {
+        let exhausted = self
+            .reset_at
+            .filter(|_| self.remaining.is_some_and(|r| r <= LOW_REMAINING));
+        self.throttled_until.max(exhausted)
+    }
+}
 
 /// A client to interact with Reddit API.
 ///
@@ -15,140 +62,588 @@ use crate::reddit::auth::RedditAuth;
 #[derive(Clone)]
 pub struct RedditClient {
     client: reqwest::Client,
+    /// Client of the token requests, can go through another proxy than the API requests
+    auth_client: reqwest::Client,
     auth: Arc<RedditAuth>,
-    /// Throttle mechanism to prevent rate limiting.
-    /// It abuses write-preferring implementation of
-    /// tokio [RwLock](RwLock) to make other requests wait if needed.
-    ///
-    /// TODO: this is a very simple throttle mechanism with many flaws
-    ///     maybe we should implement a more sophisticated one.
-    permit: Arc<RwLock<bool>>,
+    /// Throttle mechanism to prevent rate limiting: until this moment
+    /// requests fail with [UpstreamError::RateLimited] instead of being sent.
+    throttled_until: Arc<Mutex<Option<Instant>>>,
+    /// Throttle state written to `throttle_store` on change, by a single writer task
+    throttle_state: Arc<watch::Sender<ThrottleState>>,
+    throttle_store: Option<Collection<ThrottleState>>,
+    /// Held while writing the throttle state, so the writes are not reordered
+    throttle_writes: Arc<tokio::sync::Mutex<()>>,
+    /// Opt the account into quarantined subreddits when Reddit asks to,
+    /// instead of failing with [UpstreamError::Quarantined]
+    quarantine_opt_in: bool,
+    endpoints: Arc<Endpoints>,
+    /// Retries of the transient failures, shared with the feed fetches
+    retry: RetryPolicy,
+    /// Share of the rate limit period reserved for the interactive requests
+    budget: RateBudget,
 }
 
 impl RedditClient {
-    pub fn new(secret_store: Arc<SecretStore>, client: reqwest::Client) -> RedditClient {
+    pub fn new(
+        secret_store: Arc<dyn Secrets>,
+        client: reqwest::Client,
+        endpoints: Endpoints,
+    ) -> RedditClient {
         RedditClient {
+            auth_client: client.clone(),
             client,
-            auth: Arc::new(RedditAuth::new(secret_store)),
-            permit: Arc::new(RwLock::new(false)),
+            quarantine_opt_in: false,
+            auth: Arc::new(RedditAuth::new(
+                secret_store,
+                endpoints.token.clone(),
+                CacheConfig::default().token_ttl,
+            )),
+            throttled_until: Arc::new(Mutex::new(None)),
+            throttle_state: Arc::new(watch::Sender::new(ThrottleState::default())),
+            throttle_store: None,
+            throttle_writes: Arc::default(),
+            endpoints: Arc::new(endpoints),
+            retry: RetryPolicy::default(),
+            budget: RateBudget::default(),
+        }
+    }
+
+    /// Restores the throttle state persisted before the last restart and keeps it
+    /// persisted in `store`, so a redeploy does not burst into an active rate limit
+    pub async fn with_throttle_store(mut self, store: Collection<ThrottleState>) -> RedditClient {
+        if let Some(state) = store.get(THROTTLE_KEY).await {
+            let now = unix_now();
+            if let Some(until) = state.resume_at().filter(|&until| until > now) {
+                info!("restoring throttle from before restart");
+                self.throttle((until - now) as f64);
+            }
+            self.throttle_state.send_replace(state);
         }
+        let mut changes = self.throttle_state.subscribe();
+        let (writes, writer_store) = (self.throttle_writes.clone(), store.clone());
+        // ends once the clients are dropped
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                tokio::time::sleep(THROTTLE_WRITE_DELAY).await;
+                let _writing = writes.lock().await;
+                let state = changes.borrow_and_update().clone();
+                if let Err(e) = writer_store.insert(THROTTLE_KEY.to_string(), state).await {
+                    warn!("cannot persist throttle state: {e:?}");
+                }
+            }
+        });
+        self.throttle_store = Some(store);
+        self
+    }
+
+    /// Keeps the access tokens for `token_ttl` instead of [CacheConfig::token_ttl]'s default
+    pub fn with_token_ttl(mut self, token_ttl: Duration) -> RedditClient {
+        self.auth = Arc::new(self.auth.with_token_ttl(token_ttl));
+        self
+    }
+
+    /// Opts the account into quarantined subreddits when Reddit asks to
+    pub fn with_quarantine_opt_in(mut self, quarantine_opt_in: bool) -> RedditClient {
+        self.quarantine_opt_in = quarantine_opt_in;
+        self
+    }
+
+    /// Sends the token requests with `client` instead of the API client
+    pub fn with_auth_client(mut self, client: reqwest::Client) -> RedditClient {
+        self.auth_client = client;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> RedditClient {
+        self.retry = retry;
+        self
+    }
+
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn with_budget(mut self, budget: RateBudget) -> RedditClient {
+        self.budget = budget;
+        self
+    }
+
+    /// Name of the account the client acts as
+    pub fn username(&self) -> eyre::Result<String> {
+        self.auth.username()
+    }
+
+    /// Obtains a token, failing if the credentials are rejected
+    pub async fn check_token(&self) -> eyre::Result<()> {
+        self.get_token().await.map(drop)
+    }
+
+    /// Drops the token, e.g. after the credentials changed, see [RedditAuth::rotate]
+    pub fn rotate_token(&self) {
+        self.auth.rotate();
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
     }
 
     async fn get_token(&self) -> eyre::Result<String> {
-        self.auth.get_token(&self.client).await
+        self.auth.get_token(&self.auth_client).await
+    }
+
+    /// Statistics of the OAuth token cache
+    pub fn token_cache_stats(&self) -> CacheSnapshot {
+        self.auth.token_cache_stats()
     }
 
     /// ordinary_url is the URL of the post without the `https://www.reddit.com` part.
     /// e.g. `/r/rust/comments/1234/this_is_a_post/`
-    pub async fn get_article_score(&self, ordinary_url: &str) -> eyre::Result<u64> {
-        for _ in 0..3 {
-            match self._get_article_score(ordinary_url).await? {
-                Some(score) => return Ok(score),
-                None => continue,
+    pub async fn get_article_info(&self, ordinary_url: &str) -> eyre::Result<ArticleInfo> {
+        let res = self
+            .api_get::<Vec<RedditComment>>(
+                ordinary_url,
+                &[("limit", "1"), ("depth", "1"), ("raw_json", "1")],
+            )
+            .await
+            .context("Cannot get article info")?;
+        Ok(res
+            .first()
+            .context("Comments returned empty array")?
+            .data
+            .children
+            .first()
+            .context("First comment's children is empty")?
+            .data()
+            .context("First comment's first child is provided as a comment")?
+            .clone())
+    }
+
+    /// Post and its top-level comments
+    pub async fn get_comments(
+        &self,
+        subreddit: &str,
+        id: &str,
+        sort: &str,
+    ) -> eyre::Result<(Post, Vec<Comment>)> {
+        let (post, comments) = self
+            .api_get::<(Listing<Thing>, Listing<Thing>)>(
+                &format!("r/{subreddit}/comments/{id}"),
+                &[("depth", "1"), ("sort", sort), ("raw_json", "1")],
+            )
+            .await
+            .context("Cannot get comments")?;
+        let post = post
+            .into_posts()
+            .next()
+            .context("Comments listing does not contain the post")?;
+        Ok((post, comments.into_comments().collect()))
+    }
+
+    /// Hot posts of the subreddit, as seen by the account
+    pub async fn get_subreddit_posts(&self, subreddit: &str) -> eyre::Result<Vec<Post>> {
+        let posts = self
+            .api_get::<Listing<Thing>>(
+                &format!("r/{subreddit}/hot"),
+                &[("limit", "25"), ("raw_json", "1")],
+            )
+            .await
+            .context("Cannot get subreddit posts")?;
+        Ok(posts.into_posts().collect())
+    }
+
+    /// Search results, restricted to the subreddit if there is one
+    pub async fn search_posts(
+        &self,
+        query: &str,
+        subreddit: Option<&str>,
+        sort: &str,
+    ) -> eyre::Result<Vec<Post>> {
+        let (path, restrict_sr) = match subreddit {
+            Some(subreddit) => (format!("r/{subreddit}/search"), "1"),
+            None => ("search".to_string(), "0"),
+        };
+        let posts = self
+            .api_get::<Listing<Thing>>(
+                &path,
+                &[
+                    ("q", query),
+                    ("sort", sort),
+                    ("restrict_sr", restrict_sr),
+                    ("limit", "25"),
+                    ("raw_json", "1"),
+                ],
+            )
+            .await
+            .context("Cannot search posts")?;
+        Ok(posts.into_posts().collect())
+    }
+
+    /// Newest comments in the subreddit
+    pub async fn get_subreddit_comments(&self, subreddit: &str) -> eyre::Result<Vec<Comment>> {
+        let comments = self
+            .api_get::<Listing<Thing>>(
+                &format!("r/{subreddit}/comments"),
+                &[("limit", "100"), ("raw_json", "1")],
+            )
+            .await
+            .context("Cannot get subreddit comments")?;
+        Ok(comments.into_comments().collect())
+    }
+
+    /// Posts and comments of a listing of the authenticated account,
+    /// e.g. `saved` or `upvoted`
+    pub async fn get_account_listing(&self, listing: &str) -> eyre::Result<Vec<Thing>> {
+        let username = self.auth.username()?;
+        let things = self
+            .api_get::<Listing<Thing>>(
+                &format!("user/{username}/{listing}"),
+                &[("limit", "100"), ("raw_json", "1")],
+            )
+            .await
+            .with_context(|| format!("Cannot get {listing} listing"))?;
+        Ok(things.data.children)
+    }
+
+    /// Unread messages, comment replies and username mentions of the authenticated account
+    pub async fn get_unread_messages(&self) -> eyre::Result<Vec<Message>> {
+        let inbox = self
+            .api_get::<Listing<InboxItem>>("message/unread", &[("raw_json", "1")])
+            .await
+            .context("Cannot get unread messages")?;
+        Ok(inbox.data.children.into_iter().map(|i| i.data).collect())
+    }
+
+    /// Karma and age of the account, fails with [UpstreamError::NotFound]
+    /// for deleted and shadow-banned accounts
+    pub async fn get_user_info(&self, username: &str) -> eyre::Result<UserInfo> {
+        let about = self
+            .api_get::<UserAbout>(&format!("user/{username}/about"), &[])
+            .await
+            .with_context(|| format!("Cannot get info of u/{username}"))?;
+        Ok(about.data)
+    }
+
+    /// Tells why the subreddit cannot be read, if it cannot
+    pub async fn probe_subreddit(&self, subreddit: &str) -> eyre::Result<SubredditStatus> {
+        let res = self
+            .api_request(&format!("r/{subreddit}/about"), &[])
+            .await
+            .context("Cannot probe subreddit")?;
+        let status = res.status();
+        if status.is_success() {
+            return Ok(SubredditStatus::Available);
+        }
+        if status != StatusCode::NOT_FOUND && status != StatusCode::FORBIDDEN {
+            return Err(UpstreamError::from_response(&res))
+                .wrap_err(format!("Received error status code {status}"));
+        }
+        // the reason is absent for subreddits that never existed
+        let reason = res
+            .json::<ProbeResponse>()
+            .await
+            .ok()
+            .and_then(|r| r.reason);
+        Ok(match reason.as_deref() {
+            Some("banned") => SubredditStatus::Banned,
+            Some("private") => SubredditStatus::Private,
+            Some("quarantined") => SubredditStatus::Quarantined,
+            _ if status == StatusCode::NOT_FOUND => SubredditStatus::NotFound,
+            _ => SubredditStatus::Private,
+        })
+    }
+
+    /// Replaces a vague not found or forbidden error with the reason
+    /// the subreddit cannot be read, multireddits are not probed
+    pub async fn explain(&self, subreddit: &str, report: eyre::Report) -> eyre::Report {
+        let vague = matches!(
+            report.downcast_ref::<UpstreamError>(),
+            Some(UpstreamError::NotFound | UpstreamError::Forbidden)
+        );
+        if !vague || subreddit.contains('+') {
+            return report;
+        }
+        match self.probe_subreddit(subreddit).await {
+            Ok(status) => match status.error() {
+                Some(error) => eyre::Report::new(error).wrap_err(format!("{report:?}")),
+                None => report,
+            },
+            Err(e) => {
+                warn!("cannot probe r/{subreddit}: {e:?}");
+                report
             }
         }
-        bail!("Cannot get article score after 3 retries")
     }
 
-    async fn _get_article_score(&self, ordinary_url: &str) -> eyre::Result<Option<u64>> {
-        let token = self.get_token().await?;
+    /// Posts and comments waiting for moderator review, needs mod rights
+    pub async fn get_modqueue(&self, subreddit: &str) -> eyre::Result<Vec<Thing>> {
+        let queue = self
+            .api_get::<Listing<Thing>>(
+                &format!("r/{subreddit}/about/modqueue"),
+                &[("limit", "100"), ("raw_json", "1")],
+            )
+            .await
+            .context("Cannot get modqueue")?;
+        Ok(queue.data.children)
+    }
 
-        let _guard = self.check_throttle().await?;
-        let url = format!("https://oauth.reddit.com/{ordinary_url}");
+    /// Recent moderator actions, needs mod rights
+    pub async fn get_modlog(&self, subreddit: &str) -> eyre::Result<Vec<ModAction>> {
+        let log = self
+            .api_get::<Listing<ModLogItem>>(
+                &format!("r/{subreddit}/about/log"),
+                &[("limit", "100"), ("raw_json", "1")],
+            )
+            .await
+            .context("Cannot get modlog")?;
+        Ok(log.data.children.into_iter().map(|i| i.data).collect())
+    }
 
-        info!("Requesting {url}");
+    /// GET request to the OAuth API, fails right away while throttled,
+    /// so callers are not blocked for the whole throttle period.
+    ///
+    /// path is relative to `https://oauth.reddit.com/`
+    async fn api_get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> eyre::Result<T> {
+        let mut res = self.api_request(path, query).await?;
+        if res.status() == StatusCode::FORBIDDEN && self.quarantine_opt_in {
+            if let Some(subreddit) = path.strip_prefix("r/").and_then(|p| p.split('/').next()) {
+                let reason = res
+                    .json::<ProbeResponse>()
+                    .await
+                    .ok()
+                    .and_then(|r| r.reason);
+                if reason.as_deref() != Some("quarantined") {
+                    return Err(UpstreamError::Forbidden)
+                        .wrap_err(format!("Cannot get {path}, reason: {reason:?}"));
+                }
+                self.quarantine_optin(subreddit).await?;
+                res = self.api_request(path, query).await?;
+            }
+        }
+        let status = res.status();
+        if status.is_client_error() || status.is_server_error() {
+            return Err(UpstreamError::from_response(&res))
+                .wrap_err(format!("Received error status code {status}"));
+        }
+        res.json::<T>()
+            .await
+            .map_err(|_| UpstreamError::Parse)
+            .wrap_err("Cannot deserialize response")
+    }
 
+    /// Acknowledges the quarantine warning of the subreddit for the account,
+    /// afterward its content can be read like any other
+    async fn quarantine_optin(&self, subreddit: &str) -> eyre::Result<()> {
+        info!("opting into quarantined r/{subreddit}");
+        let token = self.get_token().await?;
+        self.acquire()
+            .wrap_err("Cannot opt into quarantine while throttled")?;
         let res = self
             .client
-            .get(format!("https://oauth.reddit.com/{ordinary_url}"))
-            .query(&[("limit", "1"), ("depth", "1")])
+            .post(format!("{}/api/quarantine_optin", self.endpoints.api))
+            .form(&[("sr_name", subreddit)])
             .header("Authorization", format!("Bearer {token}"))
             .send()
             .await
             .context("Cannot send request")?;
+        self.rate_limiting(&res)?;
+        if !res.status().is_success() {
+            return Err(UpstreamError::Quarantined).wrap_err(format!(
+                "Cannot opt into quarantined r/{subreddit}, status {}",
+                res.status()
+            ));
+        }
+        Ok(())
+    }
 
-        drop(_guard);
+    /// Sends the GET request, the response is returned whatever its status but 429.
+    /// Every attempt goes through the throttle and the rate budget, a 429 is not retried
+    /// but answered right away, and retries end at the deadline of
+    /// [crate::reddit::retry::with_deadline]
+    async fn api_request(&self, path: &str, query: &[(&str, &str)]) -> eyre::Result<Response> {
+        let token = self.get_token().await?;
+        let url = format!("{}/{path}", self.endpoints.api);
+        let deadline = current_deadline();
+        let mut attempt = 1;
+        loop {
+            self.acquire()
+                .wrap_err_with(|| format!("Cannot get {path} while throttled"))?;
 
-        if self.rate_limiting(&res).await? {
-            return Ok(None);
-        }
+            info!("Requesting {url}");
 
-        let res = res
-            .error_for_status()
-            .context("Received error status code")?
-            .json::<Vec<RedditComment>>()
-            .await
-            .context("Cannot deserialize article request")?;
-        Ok(Some(
-            res.first()
-                .context("Comments returned empty array")?
-                .data
-                .children
-                .first()
-                .context("First comment's children is empty")?
-                .data()
-                .context("First comment's first child is provided as a comment")?
-                .score,
-        ))
+            let result = self
+                .client
+                .get(&url)
+                .query(query)
+                .header("Authorization", format!("Bearer {token}"))
+                .send()
+                .await;
+            if let Ok(res) = &result {
+                self.rate_limiting(res)
+                    .wrap_err_with(|| format!("Cannot get {path}"))?;
+            }
+            if !self.retry.wait(attempt, &result, deadline).await {
+                return result.context("Cannot send request");
+            }
+            attempt += 1;
+        }
     }
 
     /// Rate limiting logic, uses status code and following headers
-    /// to determine if we should wait:
+    /// to determine how long we should wait:
     ///
     /// retry-after: Number of seconds to wait before retrying
     /// X-Ratelimit-Used: Approximate number of requests used in this period
     /// X-Ratelimit-Remaining: Approximate number of requests left to use
     /// X-Ratelimit-Reset: Approximate number of seconds to end of period
     ///
-    /// returns true if we should retry the request
-    async fn rate_limiting(&self, response: &Response) -> eyre::Result<bool> {
+    /// A 429 fails with [UpstreamError::RateLimited], throttling for
+    /// [DEFAULT_THROTTLE_SECS] without a retry-after. Malformed headers are ignored
+    fn rate_limiting(&self, response: &Response) -> eyre::Result<()> {
         if response.status() == StatusCode::TOO_MANY_REQUESTS {
-            let retry_after = parse_number_header(response, "retry-after")?
-                .context("Received 429, but retry-after header is absent")?;
-            self.throttle(retry_after).await;
-            return Ok(true);
-        }
-        let used = parse_number_header(response, "X-Ratelimit-Used")?;
-        let remaining = parse_number_header(response, "X-Ratelimit-Remaining")?;
-        let reset = parse_number_header(response, "X-Ratelimit-Reset")?;
+            let retry_after = parse_number_header(response, "retry-after");
+            if retry_after.is_none() {
+                warn!("received 429 without retry-after, throttling for {DEFAULT_THROTTLE_SECS}s");
+            }
+            self.throttle(retry_after.unwrap_or(DEFAULT_THROTTLE_SECS));
+            return Err(UpstreamError::RateLimited {
+                retry_after: retry_after.map(|secs| secs.ceil() as u64),
+            })
+            .wrap_err("Received 429");
+        }
+        let used = parse_number_header(response, "X-Ratelimit-Used");
+        let remaining = parse_number_header(response, "X-Ratelimit-Remaining");
+        let reset = parse_number_header(response, "X-Ratelimit-Reset");
         info!(
             "rate limiting headers X-Ratelimit-Used: {used:?}, \
                                    X-Ratelimit-Remaining: {remaining:?}, \
                                    X-Ratelimit-Reset: {reset:?}"
         );
+        self.budget.update(used, remaining, reset);
         match remaining {
             Some(f) if f <= 1f64 => {
                 // By default, we throttle for 1 second
-                self.throttle(reset.unwrap_or(1f64)).await;
-                return Ok(true);
+                self.throttle(reset.unwrap_or(1f64));
             }
             _ => {}
         }
-        Ok(false)
+        let low = remaining.filter(|&r| r <= LOW_REMAINING);
+        let was_low = self.throttle_state.borrow().remaining.is_some();
+        if low.is_some() || was_low {
+            self.persist_throttle(|state| {
+                state.remaining = low;
+                state.reset_at = low.and(reset).map(|reset| unix_now() + reset.ceil() as u64);
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the current throttle state to the store, if any,
+    /// waiting for it unlike the writes on every change
+    pub async fn flush_throttle(&self) -> eyre::Result<()> {
+        let Some(store) = &self.throttle_store else {
+            return Ok(());
+        };
+        let _writing = self.throttle_writes.lock().await;
+        let state = self.throttle_state.borrow().clone();
+        store.insert(THROTTLE_KEY.to_string(), state).await?;
+        Ok(())
+    }
+
+    /// Updates the throttle state, the writer task started by
+    /// [RedditClient::with_throttle_store] persists it shortly after
+    fn persist_throttle(&self, update: impl FnOnce(&mut ThrottleState)) {
+        self.throttle_state.send_modify(update);
+    }
+
+    /// Whether requests are throttled, the rate limit period is almost used up
+    /// or the background share of it is, background work should wait for the next period then
+    pub fn budget_low(&self) -> bool {
+        if self.check_throttle().is_err() || !self.budget.available(Priority::Background) {
+            return true;
+        }
+        let state = self.throttle_state.borrow();
+        state.remaining.is_some_and(|r| r <= LOW_REMAINING)
+            && state.reset_at.is_some_and(|reset| reset > unix_now())
+    }
+
+    /// Takes the request out of the rate budget of the current task's [Priority],
+    /// fails while throttled
+    fn acquire(&self) -> Result<(), UpstreamError> {
+        self.check_throttle()?;
+        self.budget.acquire(Priority::current())
+    }
+
+    fn check_throttle(&self) -> Result<(), UpstreamError> {
+        let throttled_until = *self.throttled_until.lock().unwrap();
+        match throttled_until {
+            Some(until) if until > Instant::now() => Err(UpstreamError::RateLimited {
+                retry_after: Some((until - Instant::now()).as_secs() + 1),
+            }),
+            _ => Ok(()),
+        }
     }
-    async fn check_throttle(&self) -> eyre::Result<RwLockReadGuard<'_, bool>> {
-        Ok(self.permit.read().await)
+
+    fn throttle(&self, throttle_time: f64) {
+        let Ok(duration) = Duration::try_from_secs_f64(throttle_time) else {
+            warn!("ignoring invalid throttle time {throttle_time}");
+            return;
+        };
+        let until = Instant::now() + duration;
+        let mut throttled_until = self.throttled_until.lock().unwrap();
+        if throttled_until.is_none_or(|current| current < until) {
+            info!("throttling for {throttle_time}s");
+            *throttled_until = Some(until);
+            let until = unix_now() + throttle_time.ceil() as u64;
+            self.persist_throttle(|state| state.throttled_until = Some(until));
+        }
     }
+}
+
+/// Whether a subreddit can be read, see [RedditClient::probe_subreddit]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubredditStatus {
+    Available,
+    NotFound,
+    Banned,
+    Private,
+    Quarantined,
+}
 
-    async fn throttle(&self, throttle_time: f64) {
-        // getting mutable reference to the make other requests wait
-        let mut_permit = self.permit.write().await;
-        tokio::time::sleep(Duration::from_secs_f64(throttle_time)).await;
-        drop(mut_permit)
+impl SubredditStatus {
+    /// Error explaining why the subreddit cannot be read
+    pub fn error(self) -> Option<UpstreamError> {
+        match self {
+            SubredditStatus::Available => None,
+            SubredditStatus::NotFound => Some(UpstreamError::NotFound),
+            SubredditStatus::Banned => Some(UpstreamError::Banned),
+            SubredditStatus::Private => Some(UpstreamError::Private),
+            SubredditStatus::Quarantined => Some(UpstreamError::Quarantined),
+        }
     }
 }
 
-fn parse_number_header(response: &Response, header: &str) -> eyre::Result<Option<f64>> {
-    response
-        .headers()
-        .get(header)
-        .map(|h| {
-            h.to_str()
-                .with_context(|| format!("Cannot parse {header} header"))?
-                .parse()
-                .with_context(|| format!("Cannot parse {header} header"))
-        })
-        .transpose()
+#[derive(serde::Deserialize)]
+struct ProbeResponse {
+    /// e.g. `banned`, `private` or `quarantined`
+    reason: Option<String>,
+}
+
+/// Negative, non-finite and malformed values are ignored, as if the header was absent
+fn parse_number_header(response: &Response, header: &str) -> Option<f64> {
+    let value = response.headers().get(header)?;
+    let number = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|value| value.is_finite() && *value >= 0.0);
+    if number.is_none() {
+        warn!("ignoring invalid {header} header: {value:?}");
+    }
+    number
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -171,7 +666,7 @@ enum RedditCommentChild {
 }
 
 impl RedditCommentChild {
-    fn data(&self) -> eyre::Result<&RedditCommentItemInfo> {
+    fn data(&self) -> eyre::Result<&ArticleInfo> {
         match self {
             RedditCommentChild::RedditCommentItem(item) => Ok(&item.data),
             RedditCommentChild::Other(v) => {
@@ -186,16 +681,103 @@ impl RedditCommentChild {
 
 #[derive(serde::Deserialize, Debug)]
 struct RedditCommentItem {
-    data: RedditCommentItemInfo,
+    data: Box<ArticleInfo>,
 }
 
-#[derive(serde::Deserialize, Debug)]
-struct RedditCommentItemInfo {
-    score: u64,
+/// Post (or comment) data the feed is filtered by
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct ArticleInfo {
+    pub score: u64,
+    #[serde(default)]
+    pub num_comments: u64,
+    pub link_flair_text: Option<String>,
+    /// Unix timestamp of the creation
+    #[serde(default)]
+    pub created_utc: f64,
+    /// e.g. `github.com`, or `self.rust` for self posts
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub over_18: bool,
+    #[serde(default)]
+    pub spoiler: bool,
+    /// Submitted URL of link posts, the post itself for self posts
+    pub url: Option<String>,
+    #[serde(default)]
+    pub is_self: bool,
+    /// Set for removed posts, e.g. `deleted` by the author or `moderator`
+    pub removed_by_category: Option<String>,
+    /// `[deleted]` if the account of the author is deleted
+    pub author: Option<String>,
+    /// Whether the self text is replaced by `[removed]` or `[deleted]`
+    #[serde(rename = "selftext", default, deserialize_with = "removed_text")]
+    pub selftext_removed: bool,
+    /// The original post if this is a crosspost, see [ArticleInfo::crosspost_parent]
+    #[serde(default)]
+    pub crosspost_parent_list: Vec<CrosspostParent>,
+    /// Set for poll posts, votes change on every fetch
+    pub poll_data: Option<PollData>,
+    /// Unix timestamp of the last edit
+    #[serde(default, deserialize_with = "edited")]
+    pub edited: Option<f64>,
+}
+
+impl From<&Post> for ArticleInfo {
+    fn from(post: &Post) -> Self {
+        ArticleInfo {
+            score: post.score.max(0) as u64,
+            num_comments: post.num_comments,
+            link_flair_text: post.link_flair_text.clone(),
+            created_utc: post.created_utc,
+            domain: post.domain.clone(),
+            over_18: post.over_18,
+            spoiler: post.spoiler,
+            url: post.url.clone(),
+            is_self: post.is_self,
+            removed_by_category: post.removed_by_category.clone(),
+            author: Some(post.author.clone()),
+            selftext_removed: false,
+            crosspost_parent_list: post.crosspost_parent_list.clone(),
+            poll_data: post.poll_data.clone(),
+            edited: post.edited,
+        }
+    }
+}
+
+/// Placeholder Reddit puts in place of removed texts and deleted authors
+const DELETED: &str = "[deleted]";
+
+fn removed_text<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let text = Option::<String>::deserialize(deserializer)?;
+    Ok(matches!(text.as_deref(), Some("[removed]" | DELETED)))
+}
+
+/// Posts younger than this are treated as this old when computing velocity,
+/// so a couple of early upvotes do not look like a trend, 15 minutes
+const MIN_VELOCITY_AGE_SECS: f64 = 15.0 * 60.0;
+
+impl ArticleInfo {
+    /// Removed by the moderators or Reddit, or deleted by the author,
+    /// possibly by deleting the account
+    pub fn is_removed(&self) -> bool {
+        self.removed_by_category.is_some()
+            || self.selftext_removed
+            || self.author.as_deref() == Some(DELETED)
+    }
+
+    pub fn crosspost_parent(&self) -> Option<&CrosspostParent> {
+        self.crosspost_parent_list.first()
+    }
+
+    /// Score per hour since creation
+    pub fn velocity(&self, now: i64) -> f64 {
+        let age = (now as f64 - self.created_utc).max(MIN_VELOCITY_AGE_SECS);
+        self.score as f64 / (age / 3600.0)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::ArticleInfo;
 
     #[test]
     fn deserialize_test() {
@@ -203,4 +785,28 @@ mod tests {
         let res: Vec<super::RedditComment> = serde_json::from_str(data).unwrap();
         insta::assert_debug_snapshot!(res);
     }
+
+    #[test]
+    fn velocity_test() {
+        let info = ArticleInfo {
+            score: 100,
+            num_comments: 0,
+            link_flair_text: None,
+            created_utc: 0.0,
+            domain: None,
+            over_18: false,
+            spoiler: false,
+            url: None,
+            is_self: false,
+            removed_by_category: None,
+            author: None,
+            selftext_removed: false,
+            crosspost_parent_list: vec![],
+            poll_data: None,
+            edited: None,
+        };
+        assert_eq!(info.velocity(2 * 3600), 50.0);
+        // fresh posts are treated as 15 minutes old
+        assert_eq!(info.velocity(60), 400.0);
+    }
 }
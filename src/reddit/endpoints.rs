@@ -0,0 +1,79 @@
+use crate::config::Reader;
+
+/// Base URLs of Reddit, without trailing slashes.
+/// Replaceable, e.g. to point the client at a mock server in tests
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoints {
+    /// OAuth API, e.g. `https://oauth.reddit.com`
+    pub api: String,
+    /// Public site serving the `.rss` feeds, e.g. `https://reddit.com`
+    pub www: String,
+    /// OAuth token endpoint
+    pub token: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            api: "https://oauth.reddit.com".to_string(),
+            www: "https://reddit.com".to_string(),
+            token: "https://oauth.reddit.com/api/v1/access_token".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Default endpoints overridden by `REDDIT_API_URL`, `REDDIT_WWW_URL` and
+    /// `REDDIT_TOKEN_URL` secrets, e.g. to go through an egress gateway or a mirror.
+    /// The token endpoint defaults to the one of the API
+    pub fn read(reader: &mut Reader) -> Endpoints {
+        let mut url = |key: &str| {
+            reader
+                .url(key)
+                .map(|url| url.as_str().trim_end_matches('/').to_string())
+        };
+        let default = Endpoints::default();
+        let api = url("REDDIT_API_URL");
+        let token = match (url("REDDIT_TOKEN_URL"), &api) {
+            (Some(token), _) => token,
+            (None, Some(api)) => format!("{api}/api/v1/access_token"),
+            (None, None) => default.token,
+        };
+        Endpoints {
+            api: api.unwrap_or(default.api),
+            www: url("REDDIT_WWW_URL").unwrap_or(default.www),
+            token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn read_test() {
+        let secrets = HashMap::from([
+            ("REDDIT_API_URL", "https://reddit-gateway.corp.example/api/"),
+            ("REDDIT_WWW_URL", "https://reddit-gateway.corp.example/www"),
+        ]);
+        assert_eq!(
+            Endpoints::read(&mut Reader::new(&secrets)),
+            Endpoints {
+                api: "https://reddit-gateway.corp.example/api".to_string(),
+                www: "https://reddit-gateway.corp.example/www".to_string(),
+                token: "https://reddit-gateway.corp.example/api/api/v1/access_token".to_string(),
+            }
+        );
+        assert_eq!(
+            Endpoints::read(&mut Reader::new(&HashMap::new())),
+            Endpoints::default()
+        );
+        let secrets = HashMap::from([("REDDIT_TOKEN_URL", "oauth.reddit.com")]);
+        let mut reader = Reader::new(&secrets);
+        assert_eq!(Endpoints::read(&mut reader), Endpoints::default());
+        assert!(reader.finish().is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use serde::{Deserialize, Deserializer};
+
+/// Reddit listing, e.g. a subreddit's posts or the comments of a post
+#[derive(Deserialize, Debug)]
+pub struct Listing<T> {
+    pub data: ListingData<T>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListingData<T> {
+    pub children: Vec<T>,
+}
+
+/// An item of a listing, tagged by its kind
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "RawThing")]
+pub enum Thing {
+    Comment(Comment),
+    Post(Post),
+    /// e.g. `more` placeholders of collapsed comments
+    Other,
+}
+
+#[derive(Deserialize)]
+struct RawThing {
+    kind: String,
+    data: serde_json::Value,
+}
+
+impl TryFrom<RawThing> for Thing {
+    type Error = serde_json::Error;
+
+    fn try_from(RawThing { kind, data }: RawThing) -> Result<Self, Self::Error> {
+        Ok(match kind.as_str() {
+            "t1" => Thing::Comment(serde_json::from_value(data)?),
+            "t3" => Thing::Post(serde_json::from_value(data)?),
+            _ => Thing::Other,
+        })
+    }
+}
+
+impl Listing<Thing> {
+    pub fn into_posts(self) -> impl Iterator<Item = Post> {
+        self.data.children.into_iter().filter_map(|t| match t {
+            Thing::Post(post) => Some(post),
+            _ => None,
+        })
+    }
+
+    pub fn into_comments(self) -> impl Iterator<Item = Comment> {
+        self.data.children.into_iter().filter_map(|t| match t {
+            Thing::Comment(comment) => Some(comment),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Post {
+    /// Fullname, e.g. `t3_1bqry5x`
+    pub name: String,
+    pub title: String,
+    pub permalink: String,
+    pub author: String,
+    pub score: i64,
+    #[serde(default)]
+    pub num_comments: u64,
+    pub created_utc: f64,
+    /// e.g. `r/rust`
+    pub subreddit_name_prefixed: String,
+    /// Link target, the post itself for self posts
+    pub url: Option<String>,
+    /// HTML rendering of the self text, unescaped if requested with `raw_json=1`
+    pub selftext_html: Option<String>,
+    pub link_flair_text: Option<String>,
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub over_18: bool,
+    #[serde(default)]
+    pub spoiler: bool,
+    #[serde(default)]
+    pub is_self: bool,
+    /// The original post if this is a crosspost, Reddit nests it in a list
+    #[serde(default)]
+    pub crosspost_parent_list: Vec<CrosspostParent>,
+    /// Set for removed posts, e.g. `deleted` by the author or `moderator`
+    pub removed_by_category: Option<String>,
+    pub poll_data: Option<PollData>,
+    /// Unix timestamp of the last edit
+    #[serde(default, deserialize_with = "edited")]
+    pub edited: Option<f64>,
+}
+
+/// Reddit gives `false` for posts that were never edited, the timestamp otherwise
+pub fn edited<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Edited {
+        At(f64),
+        Never(serde::de::IgnoredAny),
+    }
+    Ok(match Edited::deserialize(deserializer)? {
+        Edited::At(at) => Some(at),
+        Edited::Never(_) => None,
+    })
+}
+
+/// Original post of a crosspost
+#[derive(Deserialize, Debug, Clone)]
+pub struct CrosspostParent {
+    /// Fullname, e.g. `t3_1bqry5x`
+    pub name: String,
+    /// e.g. `r/rust`
+    pub subreddit_name_prefixed: String,
+    pub score: i64,
+    pub permalink: String,
+}
+
+/// Options and votes of a poll post
+#[derive(Deserialize, Debug, Clone)]
+pub struct PollData {
+    pub options: Vec<PollOption>,
+    pub total_vote_count: u64,
+    /// Unix timestamp in milliseconds
+    pub voting_end_timestamp: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PollOption {
+    pub text: String,
+    /// Hidden until the voting ends, unless the poll belongs to the reader
+    pub vote_count: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Comment {
+    /// Fullname, e.g. `t1_kx3l0ab`
+    pub name: String,
+    pub author: String,
+    pub body: String,
+    /// HTML rendering of the body, unescaped if requested with `raw_json=1`
+    pub body_html: Option<String>,
+    pub score: i64,
+    pub permalink: String,
+    pub created_utc: f64,
+    /// Title of the post, only present in comment listings
+    pub link_title: Option<String>,
+}
+
+/// Response of `user/{name}/about`
+#[derive(Deserialize, Debug)]
+pub struct UserAbout {
+    pub data: UserInfo,
+}
+
+/// Public profile of an account, suspended ones have no karma or creation time
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserInfo {
+    #[serde(default)]
+    pub total_karma: i64,
+    #[serde(default)]
+    pub created_utc: f64,
+    #[serde(default)]
+    pub is_suspended: bool,
+}
+
+/// An item of the inbox, either a private message or a comment reply or mention
+#[derive(Deserialize, Debug)]
+pub struct InboxItem {
+    pub data: Message,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Message {
+    /// Fullname, e.g. `t4_2a8b9c` for messages, `t1_kx3l0ab` for comments
+    pub name: String,
+    /// Absent for messages sent by Reddit itself
+    pub author: Option<String>,
+    /// e.g. `comment reply`, `username mention` or the subject of the message
+    pub subject: String,
+    pub body: String,
+    /// HTML rendering of the body, unescaped if requested with `raw_json=1`
+    pub body_html: Option<String>,
+    pub created_utc: f64,
+    /// Permalink of the comment with its context, empty for private messages
+    #[serde(default)]
+    pub context: String,
+    /// Title of the post, only present for comments
+    pub link_title: Option<String>,
+}
+
+/// An entry of the moderation log
+#[derive(Deserialize, Debug)]
+pub struct ModLogItem {
+    pub data: ModAction,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ModAction {
+    /// e.g. `ModAction_2b1e7a3c-...`
+    pub id: String,
+    /// e.g. `removelink`, `approvecomment`, `banuser`
+    pub action: String,
+    /// Moderator who took the action
+    #[serde(rename = "mod")]
+    pub moderator: String,
+    pub details: Option<String>,
+    pub description: Option<String>,
+    pub target_author: Option<String>,
+    pub target_title: Option<String>,
+    pub target_permalink: Option<String>,
+    pub created_utc: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_thread_test() {
+        let data = include_str!("./tests/result.json");
+        let (post, comments): (Listing<Thing>, Listing<Thing>) =
+            serde_json::from_str(data).unwrap();
+        let post = post.into_posts().next().unwrap();
+        let comments = comments.into_comments().collect::<Vec<_>>();
+        assert_eq!(post.name, "t3_1bqry5x");
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].score, 29);
+    }
+
+    #[test]
+    fn edited_test() {
+        #[derive(Deserialize)]
+        struct Data {
+            #[serde(deserialize_with = "edited")]
+            edited: Option<f64>,
+        }
+        let edited = |json| serde_json::from_str::<Data>(json).unwrap().edited;
+        assert_eq!(edited(r#"{"edited": false}"#), None);
+        assert_eq!(edited(r#"{"edited": 1711900000.0}"#), Some(1711900000.0));
+    }
+
+    #[test]
+    fn deserialize_modlog_test() {
+        let data = r#"{"kind": "Listing", "data": {"children": [{"kind": "modaction", "data": {
+            "id": "ModAction_1", "action": "removelink", "mod": "automoderator",
+            "details": "spam", "description": null, "target_author": "someone",
+            "target_title": "Buy now", "target_permalink": "/r/rust/comments/1/buy_now/",
+            "created_utc": 1711900000.0}}]}}"#;
+        let log: Listing<ModLogItem> = serde_json::from_str(data).unwrap();
+        let action = &log.data.children[0].data;
+        assert_eq!(action.moderator, "automoderator");
+        assert_eq!(action.target_title.as_deref(), Some("Buy now"));
+    }
+}
@@ -1,2 +1,12 @@
-mod auth;
+pub mod auth;
+pub mod budget;
 pub mod client;
+pub mod endpoints;
+pub mod listing;
+pub mod retry;
+
+/// Whether the subreddit name or post id can go into a Reddit path as is:
+/// letters, digits and underscores, so it cannot step into another path
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
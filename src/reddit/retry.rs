@@ -0,0 +1,217 @@
+use std::future::Future;
+use std::time::Duration;
+
+use reqwest::{Response, StatusCode};
+use tokio::time::{sleep, Instant};
+use tracing::{warn, Span};
+
+use crate::config::Reader;
+
+/// Retries of the requests to Reddit failing transiently: 429 and 5xx responses,
+/// timeouts and failed connections. Reddit's public endpoints return those
+/// for seconds at a time.
+///
+/// Waits double after every attempt, up to `max_delay`. A `retry-after` of the response
+/// is waited instead, if it is longer than `max_delay` the response is returned as is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts including the first one, `1` disables retries
+    pub attempts: u32,
+    /// Wait before the first retry
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Taken from `UPSTREAM_RETRY_ATTEMPTS` and `UPSTREAM_RETRY_MAX_DELAY_SECS` secrets,
+    /// missing or invalid ones fall back to the defaults
+    pub fn read(reader: &mut Reader) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            attempts: reader
+                .number("UPSTREAM_RETRY_ATTEMPTS")
+                .unwrap_or(default.attempts)
+                .max(1),
+            max_delay: reader
+                .secs("UPSTREAM_RETRY_MAX_DELAY_SECS")
+                .unwrap_or(default.max_delay),
+            ..default
+        }
+    }
+
+    /// Wait after the `attempt`-th failed attempt, counting from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Sends the request built by `send` until it does not fail transiently,
+    /// the attempts run out or the next one would start after `deadline`.
+    /// The last response or error is returned.
+    pub async fn send<F, Fut>(
+        &self,
+        deadline: Option<Instant>,
+        mut send: F,
+    ) -> reqwest::Result<Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = reqwest::Result<Response>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = send().await;
+            if !self.wait(attempt, &result, deadline).await {
+                return result;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Waits before the next attempt if the `attempt`-th one failed transiently
+    /// and another one is allowed, returns whether to send it
+    pub async fn wait(
+        &self,
+        attempt: u32,
+        result: &reqwest::Result<Response>,
+        deadline: Option<Instant>,
+    ) -> bool {
+        let delay = match result {
+            Ok(response) if is_transient(response.status()) => match retry_after(response) {
+                Some(delay) if delay > self.max_delay => return false,
+                Some(delay) => delay,
+                None => self.backoff(attempt),
+            },
+            Err(e) if e.is_timeout() || e.is_connect() => self.backoff(attempt),
+            _ => return false,
+        };
+        if attempt >= self.attempts || deadline.is_some_and(|d| Instant::now() + delay > d) {
+            return false;
+        }
+        match result {
+            Ok(response) => warn!(
+                "{} answered {}, retrying in {delay:?}",
+                response.url(),
+                response.status()
+            ),
+            Err(e) => warn!("request failed: {e}, retrying in {delay:?}"),
+        }
+        sleep(delay).await;
+        // seen in the span of the caller if it has the field, e.g. a score fetch
+        Span::current().record("retries", attempt);
+        true
+    }
+}
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Runs `future` with its requests to Reddit not retried past `deadline`
+pub async fn with_deadline<F: Future>(deadline: Instant, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+/// Deadline of the current task set by [with_deadline], if any
+pub fn current_deadline() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// `retry-after` in seconds, the HTTP date form is not used by Reddit
+fn retry_after(response: &Response) -> Option<Duration> {
+    let secs = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn response(status: u16, retry_after: Option<&str>) -> reqwest::Result<Response> {
+        let mut response = axum::http::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            response = response.header("retry-after", retry_after);
+        }
+        Ok(Response::from(response.body("").unwrap()))
+    }
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy::default();
+        let delays = (1..=7)
+            .map(|a| policy.backoff(a).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 10000, 10000]);
+    }
+
+    #[tokio::test]
+    async fn send_test() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let attempts = AtomicU32::new(0);
+        let statuses = [503, 429, 200];
+        let res = policy
+            .send(None, || async {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst) as usize;
+                response(statuses[attempt], Some("0.01").filter(|_| attempt == 1))
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // permanent errors, long retry-after and the deadline end the retries
+        let cases = [
+            (404, None, None),
+            (429, Some("60"), None),
+            (503, None, Some(Instant::now())),
+        ];
+        for (status, retry_after, deadline) in cases {
+            attempts.store(0, Ordering::SeqCst);
+            let res = policy
+                .send(deadline, || async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    response(status, retry_after)
+                })
+                .await
+                .unwrap();
+            assert_eq!(res.status(), status);
+            assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        }
+
+        attempts.store(0, Ordering::SeqCst);
+        let res = policy
+            .send(None, || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                response(500, None)
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 500);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
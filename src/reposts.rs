@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::rss::sanitize::strip_tracking;
+use crate::store::Collection;
+
+/// URLs are remembered this many days after their first sighting by default
+pub const DEFAULT_WINDOW_DAYS: u32 = 30;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Feeds whose URLs have all expired are forgotten this often
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// First sighting of an external URL in a feed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeenUrl {
+    /// Id of the entry that linked the URL
+    pub id: String,
+    /// Unix timestamp
+    pub seen_at: i64,
+}
+
+/// External URLs linked by the entries of each feed in the last days of the window,
+/// so reposts of the same article can be dropped.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Reposts {
+    seen: Collection<BTreeMap<String, SeenUrl>>,
+    window_secs: i64,
+}
+
+impl Reposts {
+    pub fn new(seen: Collection<BTreeMap<String, SeenUrl>>) -> Reposts {
+        Reposts {
+            seen,
+            window_secs: i64::from(DEFAULT_WINDOW_DAYS) * DAY_SECS,
+        }
+    }
+
+    /// URLs remembered for `days` after their first sighting
+    pub fn with_window_days(mut self, days: u32) -> Reposts {
+        self.window_secs = i64::from(days) * DAY_SECS;
+        self
+    }
+
+    fn cutoff(&self) -> i64 {
+        Utc::now().timestamp() - self.window_secs
+    }
+
+    /// URLs seen in the feed, by their [normalize_url] form
+    pub async fn seen(&self, feed: &str) -> BTreeMap<String, SeenUrl> {
+        self.seen.get(feed).await.unwrap_or_default()
+    }
+
+    /// Adds `(url, entry id)` pairs not seen yet and drops the expired ones
+    pub async fn record(
+        &self,
+        feed: &str,
+        urls: impl IntoIterator<Item = (String, String)>,
+    ) -> eyre::Result<()> {
+        let now = Utc::now().timestamp();
+        let mut seen = self.seen(feed).await;
+        let before = seen.len();
+        let mut changed = false;
+        for (url, id) in urls {
+            seen.entry(url).or_insert_with(|| {
+                changed = true;
+                SeenUrl { id, seen_at: now }
+            });
+        }
+        seen.retain(|_, url| url.seen_at >= now - self.window_secs);
+        if changed || seen.len() != before {
+            self.seen.insert(feed.to_string(), seen).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the entry links a URL (in [normalize_url] form) first seen in another entry,
+    /// within the window
+    pub fn is_repost(&self, seen: &BTreeMap<String, SeenUrl>, id: &str, url: &str) -> bool {
+        let cutoff = self.cutoff();
+        seen.get(url)
+            .is_some_and(|first| first.id != id && first.seen_at >= cutoff)
+    }
+
+    /// Forgets the feeds whose URLs have all expired, e.g. no longer polled
+    /// or polled with other options
+    pub async fn prune(&self) {
+        let cutoff = self.cutoff();
+        for (feed, seen) in self.seen.list().await {
+            if seen.values().all(|url| url.seen_at < cutoff) {
+                if let Err(e) = self.seen.remove(&feed).await {
+                    warn!("cannot prune the URLs of {feed}: {e:?}");
+                }
+            }
+        }
+    }
+}
+
+/// Form of the URL that ignores `www.`, fragments, tracking parameters and trailing slashes,
+/// which commonly differ between submissions of the same article
+pub fn normalize_url(url: &str) -> Option<String> {
+    let mut url = Url::parse(&strip_tracking(url)).ok()?;
+    url.set_fragment(None);
+    if url.query_pairs().any(|(key, _)| key == "ref") {
+        let query = url
+            .query_pairs()
+            .filter(|(key, _)| key != "ref")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    let host = url.host_str()?.trim_start_matches("www.").to_string();
+    let path = url.path().trim_end_matches('/').to_string();
+    Some(match url.query().filter(|q| !q.is_empty()) {
+        Some(query) => format!("{host}{path}?{query}"),
+        None => format!("{host}{path}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_url_test() {
+        assert_eq!(
+            normalize_url("https://www.example.com/post/?utm_source=reddit#top").as_deref(),
+            Some("example.com/post")
+        );
+        assert_eq!(
+            normalize_url("http://example.com/post?id=1&ref=hn").as_deref(),
+            Some("example.com/post?id=1")
+        );
+        assert_eq!(
+            normalize_url("http://example.com/post?id=1&fbclid=abc").as_deref(),
+            Some("example.com/post?id=1")
+        );
+        assert_eq!(normalize_url("not a url"), None);
+    }
+
+    #[tokio::test]
+    async fn prune_test() {
+        let store = crate::store::Store::new(
+            std::env::temp_dir().join(format!("redditrss-reposts-{}", rand::random::<u64>())),
+        );
+        let reposts = Reposts::new(store.collection("reposts").await.unwrap()).with_window_days(1);
+        let url = |id: &str, age_days: i64| SeenUrl {
+            id: id.to_string(),
+            seen_at: Utc::now().timestamp() - age_days * DAY_SECS,
+        };
+        let stale = BTreeMap::from([("example.com/a".to_string(), url("t3_a", 2))]);
+        let fresh = BTreeMap::from([
+            ("example.com/a".to_string(), url("t3_a", 2)),
+            ("example.com/b".to_string(), url("t3_b", 0)),
+        ]);
+        reposts
+            .seen
+            .insert("stale".to_string(), stale)
+            .await
+            .unwrap();
+        reposts
+            .seen
+            .insert("fresh".to_string(), fresh.clone())
+            .await
+            .unwrap();
+        assert!(!reposts.is_repost(&fresh, "t3_c", "example.com/a"));
+        assert!(reposts.is_repost(&fresh, "t3_c", "example.com/b"));
+
+        reposts.prune().await;
+        assert!(!reposts.seen.contains("stale").await);
+        assert!(reposts.seen.contains("fresh").await);
+    }
+}
@@ -0,0 +1,135 @@
+use atom_syndication::{Category, Content, Entry, Feed, Link, Person};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+
+use crate::reddit::listing::{Message, Post, Thing};
+use crate::rss::comments::{comment_entry, reddit_url};
+use crate::rss::feed::{entry_id, post_categories};
+use crate::rss::sanitize::sanitize_entry;
+
+/// Feed of a listing of the authenticated account, e.g. `saved` or `upvoted`,
+/// posts and comments are kept in the order of the listing
+pub fn render_account(listing: &str, things: Vec<Thing>) -> Feed {
+    Feed {
+        id: format!("me:{listing}"),
+        title: format!("My {listing} posts").into(),
+        updated: Utc::now().fixed_offset(),
+        links: vec![Link {
+            href: format!("https://www.reddit.com/user/me/{listing}/"),
+            ..Default::default()
+        }],
+        entries: thing_entries(things),
+        ..Default::default()
+    }
+}
+
+/// Entries of the posts and comments, in the order of the listing
+pub fn thing_entries(things: Vec<Thing>) -> Vec<Entry> {
+    things
+        .into_iter()
+        .filter_map(|thing| match thing {
+            Thing::Post(post) => Some(post_entry(post)),
+            Thing::Comment(comment) => Some(comment_entry(comment)),
+            Thing::Other => None,
+        })
+        .update(sanitize_entry)
+        .collect_vec()
+}
+
+fn post_entry(post: Post) -> Entry {
+    let created = DateTime::from_timestamp(post.created_utc as i64, 0)
+        .unwrap_or_default()
+        .fixed_offset();
+    let mut categories = vec![Category {
+        term: post.subreddit_name_prefixed,
+        ..Default::default()
+    }];
+    categories.extend(post_categories(
+        post.link_flair_text.as_deref(),
+        post.domain.as_deref(),
+        post.over_18,
+        post.spoiler,
+    ));
+    let comments = reddit_url(&post.permalink);
+    let mut links = vec![Link {
+        href: comments.clone(),
+        ..Default::default()
+    }];
+    if let Some(url) = post.url.filter(|url| *url != comments) {
+        links.push(Link {
+            href: url,
+            rel: "related".to_string(),
+            ..Default::default()
+        });
+    }
+    Entry {
+        id: entry_id(&post.name),
+        title: format!("{} ({} points)", post.title, post.score).into(),
+        updated: created,
+        published: Some(created),
+        authors: vec![Person {
+            name: post.author,
+            ..Default::default()
+        }],
+        categories,
+        links,
+        content: post.selftext_html.map(|html| Content {
+            content_type: Some("html".to_string()),
+            value: Some(html),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Feed of the unread inbox of the authenticated account
+pub fn render_inbox(messages: Vec<Message>) -> Feed {
+    Feed {
+        id: "me:inbox".to_string(),
+        title: "My inbox".into(),
+        updated: Utc::now().fixed_offset(),
+        links: vec![Link {
+            href: "https://www.reddit.com/message/unread/".to_string(),
+            ..Default::default()
+        }],
+        entries: messages.into_iter().map(message_entry).collect(),
+        ..Default::default()
+    }
+}
+
+fn message_entry(message: Message) -> Entry {
+    let created = DateTime::from_timestamp(message.created_utc as i64, 0)
+        .unwrap_or_default()
+        .fixed_offset();
+    let author = message.author.unwrap_or_else(|| "reddit".to_string());
+    let title = match &message.link_title {
+        Some(post) => format!("{} from {author} on \"{post}\"", message.subject),
+        None => format!("{} from {author}", message.subject),
+    };
+    let link = if message.context.is_empty() {
+        let id = message.name.trim_start_matches("t4_");
+        format!("https://www.reddit.com/message/messages/{id}")
+    } else {
+        reddit_url(&message.context)
+    };
+    Entry {
+        id: entry_id(&message.name),
+        title: title.into(),
+        updated: created,
+        published: Some(created),
+        authors: vec![Person {
+            name: author,
+            ..Default::default()
+        }],
+        links: vec![Link {
+            href: link,
+            ..Default::default()
+        }],
+        content: Some(Content {
+            content_type: Some("html".to_string()),
+            value: Some(message.body_html.unwrap_or(message.body)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,95 @@
+use atom_syndication::Entry;
+use serde::Serialize;
+
+use crate::rss::feed::{FilteredFeed, PostStats};
+use crate::rss::format::entry_url;
+
+/// Filtered posts of a feed, for consumers that do not parse Atom
+#[derive(Debug, Serialize)]
+pub struct ApiFeed {
+    pub title: String,
+    pub posts: Vec<ApiPost>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiPost {
+    pub id: String,
+    pub title: String,
+    pub url: Option<String>,
+    /// Counters are `null` for posts kept without fresh info, e.g. not fetched in time
+    pub score: Option<u64>,
+    pub comments: Option<u64>,
+    pub flair: Option<String>,
+    /// RFC 3339
+    pub published: Option<String>,
+    pub updated: String,
+}
+
+impl ApiPost {
+    fn new(entry: &Entry, stats: Option<&PostStats>) -> ApiPost {
+        ApiPost {
+            id: entry.id.clone(),
+            title: entry.title.value.clone(),
+            url: entry_url(entry).map(str::to_string),
+            score: stats.map(|s| s.score),
+            comments: stats.map(|s| s.comments),
+            flair: stats.and_then(|s| s.flair.clone()),
+            published: entry.published.map(|at| at.to_rfc3339()),
+            updated: entry.updated.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&FilteredFeed> for ApiFeed {
+    fn from(FilteredFeed { feed, posts, .. }: &FilteredFeed) -> Self {
+        ApiFeed {
+            title: feed.title.value.clone(),
+            posts: feed
+                .entries
+                .iter()
+                .map(|entry| ApiPost::new(entry, posts.get(&entry.id)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use atom_syndication::{Feed, Link};
+    use chrono::DateTime;
+
+    use super::*;
+
+    #[test]
+    fn api_feed_test() {
+        let updated = DateTime::parse_from_rfc3339("2024-05-01T12:00:00+00:00").unwrap();
+        let entry = |id: &str| Entry {
+            id: id.to_string(),
+            title: format!("post {id}").into(),
+            links: vec![Link {
+                href: format!("https://www.reddit.com/r/rust/comments/{id}/"),
+                ..Default::default()
+            }],
+            published: Some(updated),
+            updated,
+            ..Default::default()
+        };
+        let stats = PostStats {
+            score: 120,
+            comments: 14,
+            flair: Some("news".to_string()),
+        };
+        let filtered = FilteredFeed {
+            feed: Feed {
+                title: "rust".into(),
+                entries: vec![entry("t3_a"), entry("t3_b")],
+                ..Default::default()
+            },
+            posts: HashMap::from([("t3_a".to_string(), stats)]),
+            ..Default::default()
+        };
+        insta::assert_snapshot!(serde_json::to_string_pretty(&ApiFeed::from(&filtered)).unwrap());
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use eyre::eyre;
+use futures::future::join_all;
+use itertools::Itertools;
+use tokio::time::{timeout_at, Instant};
+use tracing::warn;
+
+use crate::cache::{CacheConfig, CacheSnapshot, CacheStats};
+use crate::error::UpstreamError;
+use crate::reddit::client::RedditClient;
+use crate::reddit::listing::UserInfo;
+
+/// Karma and age of post authors, looked up for the author filters.
+/// Accounts that cannot be found are cached as `None`.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Authors {
+    reddit_client: RedditClient,
+    cache: Arc<moka::future::Cache<String, Option<UserInfo>>>,
+    stats: Arc<CacheStats>,
+}
+
+impl Authors {
+    pub fn new(reddit_client: RedditClient, cache: &CacheConfig) -> Authors {
+        let stats = Arc::new(CacheStats::default());
+        Authors {
+            reddit_client,
+            cache: Arc::new(
+                moka::future::CacheBuilder::new(cache.author_capacity)
+                    .time_to_live(cache.author_ttl)
+                    .eviction_listener(stats.listener())
+                    .build(),
+            ),
+            stats,
+        }
+    }
+
+    /// Info of the authors resolved before the deadline, by name.
+    /// Authors whose lookup failed or timed out are missing, `None` ones do not exist
+    pub async fn lookup<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        deadline: Instant,
+    ) -> HashMap<String, Option<UserInfo>> {
+        let lookups = names.into_iter().unique().map(|name| async move {
+            let info = timeout_at(deadline, self.get(name)).await;
+            match info {
+                Ok(Ok(info)) => Some((name.to_string(), info)),
+                Ok(Err(e)) => {
+                    warn!("cannot look up u/{name}: {e:?}");
+                    None
+                }
+                Err(_) => None,
+            }
+        });
+        join_all(lookups).await.into_iter().flatten().collect()
+    }
+
+    async fn get(&self, name: &str) -> eyre::Result<Option<UserInfo>> {
+        self.stats.lookup();
+        self.cache
+            .try_get_with(name.to_string(), async {
+                self.stats.miss();
+                match self.reddit_client.get_user_info(name).await {
+                    Ok(info) => Ok(Some(info)),
+                    Err(e) => match e.downcast_ref::<UpstreamError>() {
+                        Some(UpstreamError::NotFound) => Ok(None),
+                        _ => Err(e),
+                    },
+                }
+            })
+            .await
+            .map_err(|e| eyre!("{e:?}"))
+    }
+
+    pub fn cache_stats(&self) -> CacheSnapshot {
+        self.stats.snapshot(&self.cache)
+    }
+
+    pub fn purge(&self) {
+        self.cache.invalidate_all();
+    }
+}
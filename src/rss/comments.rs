@@ -0,0 +1,134 @@
+use atom_syndication::{Content, Entry, Feed, Link, Person};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::reddit::listing::{Comment, Post};
+use crate::rss::feed::{comma_separated, entry_id};
+use crate::rss::sanitize::sanitize_entry;
+
+/// Length of the comment excerpt used as entry title
+const TITLE_LENGTH: usize = 80;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CommentOptions {
+    /// Only include comments with at least this score
+    pub min_comment_score: Option<i64>,
+    /// Only include comments of these authors (comma separated in query)
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub author: Vec<String>,
+    /// Exclude comments of these authors, e.g. bots (comma separated in query)
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub exclude_author: Vec<String>,
+    /// Reddit comment sort, e.g. `top`, `new`, `best`
+    #[serde(default = "default_sort")]
+    pub sort: String,
+}
+
+fn default_sort() -> String {
+    String::from("new")
+}
+
+impl CommentOptions {
+    fn matches(&self, comment: &Comment) -> bool {
+        let by = |authors: &[String]| {
+            authors
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(&comment.author))
+        };
+        self.min_comment_score
+            .is_none_or(|min| comment.score >= min)
+            && (self.author.is_empty() || by(&self.author))
+            && !by(&self.exclude_author)
+    }
+}
+
+/// Feed with one entry per top-level comment of the post
+pub fn render_thread(post: &Post, comments: Vec<Comment>, options: &CommentOptions) -> Feed {
+    comment_feed(
+        post.name.clone(),
+        format!("Comments on \"{}\"", post.title),
+        reddit_url(&post.permalink),
+        comments,
+        options,
+    )
+}
+
+/// Feed of the newest comments across the subreddit
+pub fn render_stream(subreddit: &str, comments: Vec<Comment>, options: &CommentOptions) -> Feed {
+    comment_feed(
+        format!("comments:{subreddit}"),
+        format!("Comments in {subreddit}"),
+        format!("https://www.reddit.com/{subreddit}/comments/"),
+        comments,
+        options,
+    )
+}
+
+fn comment_feed(
+    id: String,
+    title: String,
+    link: String,
+    comments: Vec<Comment>,
+    options: &CommentOptions,
+) -> Feed {
+    let entries = comments
+        .into_iter()
+        .filter(|c| options.matches(c))
+        .map(comment_entry)
+        .update(sanitize_entry)
+        .collect_vec();
+    Feed {
+        id,
+        title: title.into(),
+        updated: Utc::now().fixed_offset(),
+        links: vec![Link {
+            href: link,
+            ..Default::default()
+        }],
+        entries,
+        ..Default::default()
+    }
+}
+
+pub fn comment_entry(comment: Comment) -> Entry {
+    let created = DateTime::from_timestamp(comment.created_utc as i64, 0)
+        .unwrap_or_default()
+        .fixed_offset();
+    let title = comment.body.split_whitespace().join(" ");
+    let title = match title.char_indices().nth(TITLE_LENGTH) {
+        Some((i, _)) => format!("{}…", &title[..i]),
+        None => title,
+    };
+    let title = match &comment.link_title {
+        Some(post) => format!(
+            "{} on \"{post}\" ({} points): {title}",
+            comment.author, comment.score
+        ),
+        None => format!("{} ({} points): {title}", comment.author, comment.score),
+    };
+    Entry {
+        id: entry_id(&comment.name),
+        title: title.into(),
+        updated: created,
+        published: Some(created),
+        authors: vec![Person {
+            name: comment.author,
+            ..Default::default()
+        }],
+        links: vec![Link {
+            href: reddit_url(&comment.permalink),
+            ..Default::default()
+        }],
+        content: Some(Content {
+            content_type: Some("html".to_string()),
+            value: Some(comment.body_html.unwrap_or(comment.body)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+pub fn reddit_url(permalink: &str) -> String {
+    format!("https://www.reddit.com{permalink}")
+}
@@ -0,0 +1,84 @@
+use std::collections::{HashMap, HashSet};
+
+use atom_syndication::{Content, Entry};
+use serde::{Deserialize, Serialize};
+
+use crate::reddit::listing::CrosspostParent;
+use crate::rss::comments::reddit_url;
+use crate::rss::digest::escape;
+
+/// How crossposts show up in the feed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Crossposts {
+    /// As any other post
+    #[default]
+    Keep,
+    /// Only the highest-scored of a post and its crossposts is kept,
+    /// useful for multireddits where the same post shows up in several subreddits
+    Collapse,
+    /// With a "crossposted from" line linking the original post
+    Annotate,
+}
+
+/// Appends a line linking the original post to the content of the entry
+pub fn annotate_crosspost(entry: &mut Entry, parent: &CrosspostParent) {
+    let line = format!(
+        r#"<p>crossposted from <a href="{}">{}</a> ({} points)</p>"#,
+        escape(&reddit_url(&parent.permalink)),
+        escape(&parent.subreddit_name_prefixed),
+        parent.score
+    );
+    let content = entry.content.get_or_insert_with(|| Content {
+        content_type: Some("html".to_string()),
+        ..Default::default()
+    });
+    content
+        .value
+        .get_or_insert_with(String::new)
+        .push_str(&line);
+}
+
+/// Keeps only the highest-scored entry of each original post, the first one on ties.
+/// `posts` maps entry ids to the fullname of their original post and their score,
+/// entries missing from it are kept
+pub fn collapse_crossposts(entries: &mut Vec<Entry>, posts: &HashMap<String, (String, u64)>) {
+    let mut best: HashMap<&str, (&str, u64)> = HashMap::new();
+    for entry in entries.iter() {
+        let Some((original, score)) = posts.get(&entry.id) else {
+            continue;
+        };
+        best.entry(original)
+            .and_modify(|current| {
+                if *score > current.1 {
+                    *current = (&entry.id, *score);
+                }
+            })
+            .or_insert((&entry.id, *score));
+    }
+    let kept: HashSet<String> = best.into_values().map(|(id, _)| id.to_string()).collect();
+    entries.retain(|entry| !posts.contains_key(&entry.id) || kept.contains(&entry.id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapse_crossposts_test() {
+        let mut entries = ["t3_a", "t3_b", "t3_c", "t3_d"]
+            .map(|id| Entry {
+                id: id.to_string(),
+                ..Default::default()
+            })
+            .to_vec();
+        let posts = HashMap::from([
+            ("t3_a".to_string(), ("t3_a".to_string(), 10)),
+            ("t3_b".to_string(), ("t3_a".to_string(), 30)),
+            ("t3_c".to_string(), ("t3_c".to_string(), 5)),
+        ]);
+        collapse_crossposts(&mut entries, &posts);
+        let ids = entries.iter().map(|e| e.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["t3_b", "t3_c", "t3_d"]);
+    }
+}
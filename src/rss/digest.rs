@@ -0,0 +1,193 @@
+use atom_syndication::{Content, Entry, Feed, Link};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Utc};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::archive::ArchivedPost;
+
+/// Length of post excerpts in digest entries
+const EXCERPT_LENGTH: usize = 200;
+
+/// Amount of completed periods included in the digest feed
+const DIGEST_PERIODS: usize = 7;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl Period {
+    fn name(self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+        }
+    }
+
+    fn length(self) -> Duration {
+        match self {
+            Period::Daily => Duration::days(1),
+            Period::Weekly => Duration::weeks(1),
+        }
+    }
+
+    /// Start of the period containing `time`, periods start at midnight UTC,
+    /// weekly ones on Monday
+    fn start(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let date = match self {
+            Period::Daily => time.date_naive(),
+            Period::Weekly => {
+                time.date_naive() - Duration::days(time.weekday().num_days_from_monday() as i64)
+            }
+        };
+        Utc.from_utc_datetime(&date.and_time(NaiveTime::MIN))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct DigestOptions {
+    #[serde(default)]
+    pub period: Period,
+    /// Amount of posts in each digest entry
+    #[serde(default = "default_top")]
+    pub top: usize,
+}
+
+fn default_top() -> usize {
+    10
+}
+
+/// Feed with one entry per completed period, listing the top posts of that period
+pub fn render_digest(
+    subreddit: &str,
+    posts: Vec<ArchivedPost>,
+    options: DigestOptions,
+    now: DateTime<Utc>,
+) -> Feed {
+    let DigestOptions { period, top } = options;
+    let current = period.start(now);
+    let entries = (1..=DIGEST_PERIODS)
+        .filter_map(|ago| {
+            let start = current - period.length() * ago as i32;
+            let end = start + period.length();
+            let top_posts = posts
+                .iter()
+                .filter(|p| (start.timestamp()..end.timestamp()).contains(&p.published))
+                .sorted_by(|a, b| b.score.cmp(&a.score))
+                .take(top)
+                .collect_vec();
+            if top_posts.is_empty() {
+                return None;
+            }
+            let date = start.format("%Y-%m-%d");
+            Some(Entry {
+                id: format!("digest:{subreddit}:{}:{date}", period.name()),
+                title: format!("{subreddit}: top {} posts of {date}", top_posts.len()).into(),
+                updated: end.fixed_offset(),
+                content: Some(Content {
+                    content_type: Some("html".to_string()),
+                    value: Some(render_posts(&top_posts)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        })
+        .collect_vec();
+    Feed {
+        id: format!("digest:{subreddit}:{}", period.name()),
+        title: format!("{subreddit} {} digest", period.name()).into(),
+        updated: now.fixed_offset(),
+        links: vec![Link {
+            href: format!("https://www.reddit.com/{subreddit}/"),
+            ..Default::default()
+        }],
+        entries,
+        ..Default::default()
+    }
+}
+
+fn render_posts(posts: &[&ArchivedPost]) -> String {
+    let items = posts
+        .iter()
+        .map(|p| {
+            format!(
+                r#"<li><a href="{}">{}</a> ({} points)<p>{}</p></li>"#,
+                escape(&p.link),
+                escape(&p.title),
+                p.score,
+                escape(&p.excerpt)
+            )
+        })
+        .join("");
+    format!("<ol>{items}</ol>")
+}
+
+/// Plain text beginning of the HTML content
+pub fn excerpt(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = unescape(&text.split_whitespace().join(" "));
+    match text.char_indices().nth(EXCERPT_LENGTH) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text,
+    }
+}
+
+/// Reverts escaping of the common HTML entities
+pub fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Escapes text for HTML contents and attribute values
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_start_test() {
+        // Wednesday
+        let time = Utc.with_ymd_and_hms(2024, 5, 15, 13, 30, 0).unwrap();
+        assert_eq!(
+            Period::Daily.start(time),
+            Utc.with_ymd_and_hms(2024, 5, 15, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            Period::Weekly.start(time),
+            Utc.with_ymd_and_hms(2024, 5, 13, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn excerpt_test() {
+        assert_eq!(
+            excerpt("<div><p>Hello</p>\n<p>world &amp; all</p></div>"),
+            "Hello world & all"
+        );
+    }
+}
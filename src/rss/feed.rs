@@ -1,102 +1,1657 @@
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use atom_syndication::{Entry, Feed};
-use eyre::{bail, eyre, Context};
-use futures::future::try_join_all;
+use atom_syndication::{Category, Content, Entry, Feed, Link, Person, TextType};
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use eyre::{bail, eyre};
 use itertools::Itertools;
-use reqwest::Client;
-use tracing::info;
+use quick_xml::escape::escape;
+use reqwest::{header, Client, Url};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::time::Instant;
+use tracing::{info, warn};
 
-use crate::reddit::client::RedditClient;
+use crate::archive::{Archive, ArchiveQuery, ArchivedPost, ScoreSample};
+use crate::cache::{feed_weight, CacheConfig, CacheReport, CacheStats};
+use crate::config::{Config, Reader};
+use crate::error::UpstreamError;
+use crate::http::PublicClient;
+use crate::metrics::MetricsReport;
+use crate::reddit::budget::Priority;
+use crate::reddit::client::{ArticleInfo, RedditClient};
+use crate::reddit::listing::{Post, UserInfo};
+use crate::reddit::retry::with_deadline;
+use crate::reposts::{normalize_url, Reposts};
+use crate::rss::account::{render_account, render_inbox};
+use crate::rss::authors::Authors;
+use crate::rss::comments::{reddit_url, render_stream, render_thread, CommentOptions};
+use crate::rss::crosspost::{annotate_crosspost, collapse_crossposts, Crossposts};
+use crate::rss::digest::{excerpt, render_digest, unescape, DigestOptions};
+use crate::rss::filter::Filter;
+use crate::rss::fulltext::{inline_article, FullText};
+use crate::rss::hacker_news::{HackerNewsSource, HnList};
+use crate::rss::lemmy::LemmySource;
+use crate::rss::links::{LinkStyle, LinkTarget};
+use crate::rss::mask::mask_entry;
+use crate::rss::moderation::{render_modlog, render_modqueue};
+use crate::rss::poll::render_poll;
+use crate::rss::preview::Previews;
+use crate::rss::sanitize::sanitize_entry;
+use crate::rss::search::render_search;
+use crate::rss::source::{FeedSource, RedditSource, ScoredListing, Sources};
+use crate::rss::sparkline::render_sparkline;
+use crate::rss::stream::retain_entries;
+use crate::rss::template::{render_entry, Template};
+use crate::secrets::Secrets;
+use crate::singleflight::SingleFlight;
+use crate::store::{Collection, Store};
+
+/// Filtering options of a feed, provided as query parameters or stored in a profile
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FeedOptions {
+    /// Defaults to the configured threshold of the subreddit, see [ScoreDefaults]
+    pub min_score: Option<u64>,
+    /// Only include posts with one of these flairs (comma separated in query)
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub flair: Vec<String>,
+    /// Exclude posts with any of these flairs (comma separated in query)
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub exclude_flair: Vec<String>,
+    /// Keep entries that passed the filter once, even if their score dips later,
+    /// so they do not flicker in and out of the feed. Off by default, the passed
+    /// entries of every distinct set of options are stored
+    #[serde(default)]
+    pub sticky: bool,
+    /// Set entry's `updated` to the moment it first passed the filter,
+    /// so posts that rise late are not buried under an old timestamp
+    #[serde(default)]
+    pub promote_late: bool,
+    /// Only include posts scoring at least this percentile (0-100) of the posts
+    /// in the subreddit over the last week, e.g. 90 for the top 10%
+    pub min_percentile: Option<u8>,
+    /// Only include posts whose author has at least this much karma,
+    /// looked up on Reddit for each author, see [Authors]
+    pub min_author_karma: Option<i64>,
+    /// Only include posts whose author's account is at least this many days old
+    pub min_author_age_days: Option<u64>,
+    /// Also include posts gaining at least this much score per hour since creation,
+    /// even if they are below the score threshold yet
+    pub min_velocity: Option<u64>,
+    /// Frontend Reddit links point to, `www`, `old` or `redlib:<host>`
+    #[serde(default)]
+    pub link_style: LinkStyle,
+    /// Main link of link posts, `comments` or `external` for the submitted URL
+    #[serde(default)]
+    pub link_target: LinkTarget,
+    /// Expression combining conditions on the posts, applied on top of the other filters,
+    /// see [Filter]
+    pub filter: Option<Filter>,
+    /// Leave out posts removed by the moderators or deleted by their authors
+    #[serde(default)]
+    pub hide_removed: bool,
+    /// Note the time of the last edit in the content of edited posts
+    #[serde(default)]
+    pub mark_edited: bool,
+    /// Collapse the content of NSFW and spoiler posts, with images replaced by links
+    #[serde(default)]
+    pub mask_sensitive: bool,
+    /// Append the Open Graph title, description and image of the linked page to link posts,
+    /// previews of pages not fetched before the deadline are left out
+    #[serde(default)]
+    pub link_preview: bool,
+    /// Put the full text of the linked article into link posts, extracted readability-style,
+    /// articles not extracted before the deadline are left out
+    #[serde(default)]
+    pub full_content: bool,
+    /// Drop entries linking a URL that an earlier entry of the feed linked
+    /// in the last `REPOST_WINDOW_DAYS`, 30 by default
+    #[serde(default)]
+    pub suppress_reposts: bool,
+    /// How crossposts show up, `keep`, `collapse` or `annotate`
+    #[serde(default)]
+    pub crossposts: Crossposts,
+    /// Append a sparkline of the archived score history to the entries
+    #[serde(default)]
+    pub sparkline: bool,
+    /// Template of entry titles, see [Template]
+    pub title_template: Option<Template>,
+    /// Template of entry contents, see [Template]
+    pub content_template: Option<Template>,
+    /// Serve Reddit's Atom feed as is, with only the entries not passing the filters left out,
+    /// the ids made absolute and tracking parameters stripped: no categories, rendered polls
+    /// or filters in the title. The self link, `updated` and `generator` are set when served.
+    /// Ignored with the options rewriting the entries, see [FeedOptions::only_picks].
+    ///
+    /// For fidelity, not memory: the entries are still parsed for filtering and the other
+    /// formats, so the document is cached beside the parsed feed
+    #[serde(default)]
+    pub verbatim: bool,
+}
+
+impl FeedOptions {
+    /// `min_score` is the effective threshold, combining [FeedOptions::min_score]
+    /// and [FeedOptions::min_percentile]. `author` is the looked up author, if any
+    fn matches(
+        &self,
+        entry: &Entry,
+        info: &ArticleInfo,
+        author: Option<&Option<UserInfo>>,
+        min_score: u64,
+        now: i64,
+    ) -> bool {
+        let flair = info.link_flair_text.as_deref().unwrap_or_default();
+        let has_flair = |flairs: &[String]| flairs.iter().any(|f| f.eq_ignore_ascii_case(flair));
+        let trending = self
+            .min_velocity
+            .is_some_and(|v| info.velocity(now) >= v as f64);
+        (info.score >= min_score || trending)
+            && (self.flair.is_empty() || has_flair(&self.flair))
+            && !has_flair(&self.exclude_flair)
+            && self.author_matches(author, now)
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(entry, info))
+    }
+
+    /// Whether the options only pick the entries, without rewriting them,
+    /// so the upstream document can be served verbatim
+    fn only_picks(&self) -> bool {
+        !self.promote_late
+            && !self.mark_edited
+            && !self.mask_sensitive
+            && !self.link_preview
+            && !self.full_content
+            && !self.sparkline
+            && self.crossposts != Crossposts::Annotate
+            && self.link_target == LinkTarget::Comments
+            && self.link_style == LinkStyle::Www
+            && self.title_template.is_none()
+            && self.content_template.is_none()
+    }
+
+    fn has_author_filters(&self) -> bool {
+        self.min_author_karma.is_some() || self.min_author_age_days.is_some()
+    }
+
+    /// Authors that could not be looked up pass, accounts that do not exist
+    /// (`Some(None)`) or are suspended do not
+    fn author_matches(&self, author: Option<&Option<UserInfo>>, now: i64) -> bool {
+        if !self.has_author_filters() {
+            return true;
+        }
+        let Some(author) = author else {
+            return true;
+        };
+        let Some(user) = author.as_ref().filter(|user| !user.is_suspended) else {
+            return false;
+        };
+        let age_days = (now as f64 - user.created_utc) / (24.0 * 60.0 * 60.0);
+        self.min_author_karma.is_none_or(|k| user.total_karma >= k)
+            && self
+                .min_author_age_days
+                .is_none_or(|d| age_days >= d as f64)
+    }
+
+    /// Short human-readable summary of the filters, shown in the feed title
+    fn describe(&self) -> String {
+        let mut filters = vec![];
+        if let Some(min_score) = self.min_score.filter(|&s| s > 0) {
+            filters.push(format!("score ≥ {min_score}"));
+        }
+        if let Some(p) = self.min_percentile {
+            filters.push(format!("top {}%", 100u8.saturating_sub(p)));
+        }
+        if let Some(v) = self.min_velocity {
+            filters.push(format!("rising ≥ {v}/h"));
+        }
+        if let Some(karma) = self.min_author_karma {
+            filters.push(format!("author karma ≥ {karma}"));
+        }
+        if let Some(days) = self.min_author_age_days {
+            filters.push(format!("author account ≥ {days} days"));
+        }
+        if !self.flair.is_empty() {
+            filters.push(format!("flair {}", self.flair.join(", ")));
+        }
+        if !self.exclude_flair.is_empty() {
+            filters.push(format!("without {}", self.exclude_flair.join(", ")));
+        }
+        if let Some(filter) = &self.filter {
+            filters.push(filter.to_string());
+        }
+        filters.join("; ")
+    }
+}
+
+/// Nearest-rank percentile of the scores
+fn percentile(mut scores: Vec<u64>, percentile: u8) -> Option<u64> {
+    if scores.is_empty() {
+        return None;
+    }
+    scores.sort_unstable();
+    let rank = (f64::from(percentile.min(100)) / 100.0 * scores.len() as f64).ceil() as usize;
+    Some(scores[rank.saturating_sub(1)])
+}
+
+/// Accepts both a list (JSON) and a comma separated string (query parameters)
+pub fn comma_separated<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect(),
+        StringOrList::List(list) => list,
+    })
+}
+
+/// Flair, domain and content warnings of a post as Atom categories,
+/// so readers can filter on them without server-side filters
+pub fn post_categories(
+    flair: Option<&str>,
+    domain: Option<&str>,
+    over_18: bool,
+    spoiler: bool,
+) -> Vec<Category> {
+    let category = |scheme: &str, term: &str| Category {
+        term: term.to_string(),
+        scheme: Some(format!("https://www.reddit.com/#{scheme}")),
+        label: None,
+    };
+    let mut categories = vec![];
+    if let Some(flair) = flair.filter(|f| !f.is_empty()) {
+        categories.push(category("flair", flair));
+    }
+    if let Some(domain) = domain {
+        categories.push(category("domain", domain));
+    }
+    if over_18 {
+        categories.push(category("content-warning", "nsfw"));
+    }
+    if spoiler {
+        categories.push(category("content-warning", "spoiler"));
+    }
+    categories
+}
+
+/// Sets `updated` of the entry to the edit time of the post, if it was edited after
+/// the grace period, and optionally notes the edit in the content
+fn apply_edit(entry: &mut Entry, info: &ArticleInfo, mark: bool) {
+    let edited = info
+        .edited
+        .filter(|&at| at - info.created_utc > EDIT_GRACE_SECS)
+        .and_then(|at| DateTime::from_timestamp(at as i64, 0));
+    let Some(edited) = edited else {
+        return;
+    };
+    if edited > entry.updated {
+        entry.updated = edited.fixed_offset();
+    }
+    if mark {
+        let note = format!(
+            "<p><em>edited {}</em></p>",
+            edited.format("%Y-%m-%d %H:%M UTC")
+        );
+        let content = entry.content.get_or_insert_with(|| Content {
+            content_type: Some("html".to_string()),
+            ..Default::default()
+        });
+        content
+            .value
+            .get_or_insert_with(String::new)
+            .push_str(&note);
+    }
+}
+
+/// Reddit escapes titles once more than needed, which shows up as `&amp;`
+/// in some readers, so entities are unescaped once. Not more, a title
+/// about HTML entities keeps them
+fn normalize_text(text: &str) -> String {
+    unescape(text).split_whitespace().join(" ")
+}
+
+/// Unescapes plain-text title and summary of the entry, HTML ones are left as is
+pub fn normalize_entry(entry: &mut Entry) {
+    let texts = [Some(&mut entry.title), entry.summary.as_mut()];
+    for text in texts.into_iter().flatten() {
+        if text.r#type == TextType::Text {
+            text.value = normalize_text(&text.value);
+        }
+    }
+}
+
+/// Prefix turning post fullnames into the absolute IRIs Atom requires of entry ids
+const ENTRY_ID_PREFIX: &str = "urn:reddit:";
+
+/// Id of the entry of a Reddit thing in the served feeds, e.g. `urn:reddit:t3_1bqry5x`
+/// for a post or `urn:reddit:t1_kx2a9z` for a comment. Ids already absolute,
+/// e.g. `hn:39876543` of the other sources, are kept
+pub fn entry_id(id: &str) -> String {
+    if id.contains(':') {
+        id.to_string()
+    } else {
+        format!("{ENTRY_ID_PREFIX}{id}")
+    }
+}
+
+/// Fullname of the Reddit thing of a served entry, see [entry_id]
+pub fn entry_fullname(id: &str) -> &str {
+    id.strip_prefix(ENTRY_ID_PREFIX).unwrap_or(id)
+}
+
+/// Fullname of the post, e.g. `t3_1bqry5x`, the entry id is made of, so the same post
+/// has the same id in every feed, whatever listing or sort it came from
+pub fn post_fullname(entry: &Entry) -> Option<String> {
+    if entry.id.starts_with("t3_") {
+        return Some(entry.id.clone());
+    }
+    let link = &entry.links.first()?.href;
+    let (_, rest) = link.split_once("/comments/")?;
+    let id = rest.split('/').next().filter(|id| !id.is_empty())?;
+    Some(format!("t3_{id}"))
+}
+
+/// Entry of a post from the API, shaped like the entries of Reddit's own feeds
+pub fn listing_entry(post: Post) -> Entry {
+    let created = DateTime::from_timestamp(post.created_utc as i64, 0)
+        .unwrap_or_default()
+        .fixed_offset();
+    let permalink = reddit_url(&post.permalink);
+    let content = match (&post.selftext_html, &post.url) {
+        (Some(html), _) => html.clone(),
+        (None, Some(url)) if *url != permalink => {
+            // raw_json values are not escaped
+            let (url, comments) = (escape(url.as_str()), escape(permalink.as_str()));
+            format!(r#"<a href="{url}">[link]</a> <a href="{comments}">[comments]</a>"#)
+        }
+        _ => String::new(),
+    };
+    Entry {
+        id: post.name,
+        title: post.title.into(),
+        updated: created,
+        published: Some(created),
+        authors: vec![Person {
+            name: format!("/u/{}", post.author),
+            uri: Some(format!("https://www.reddit.com/user/{}", post.author)),
+            ..Default::default()
+        }],
+        links: vec![Link {
+            href: permalink,
+            ..Default::default()
+        }],
+        content: Some(Content {
+            content_type: Some("html".to_string()),
+            value: Some(content),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Upstream listing a feed is filtered from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Upstream {
+    /// Subreddit path, e.g. `r/rust` or `r/rust+programming`
+    Subreddit(String),
+    /// Reddit search, optionally restricted to a subreddit
+    Search {
+        query: String,
+        subreddit: Option<String>,
+        sort: String,
+    },
+    /// Hacker News story list
+    HackerNews(HnList),
+    /// Lemmy community, read through `instance`
+    Lemmy { instance: String, community: String },
+}
+
+impl Upstream {
+    /// `.rss` feed of the listing on the public site at `www`, e.g. `https://reddit.com`
+    pub fn rss_url(&self, www: &str) -> eyre::Result<Url> {
+        Ok(match self {
+            Upstream::Subreddit(subreddit) => Url::parse(&format!("{www}/{subreddit}/.rss"))?,
+            Upstream::Search {
+                query,
+                subreddit,
+                sort,
+            } => {
+                let mut url = match subreddit {
+                    Some(subreddit) => {
+                        let mut url = Url::parse(&format!("{www}/r/{subreddit}/search.rss"))?;
+                        url.query_pairs_mut().append_pair("restrict_sr", "1");
+                        url
+                    }
+                    None => Url::parse(&format!("{www}/search.rss"))?,
+                };
+                url.query_pairs_mut()
+                    .append_pair("q", query)
+                    .append_pair("sort", sort);
+                url
+            }
+            Upstream::HackerNews(_) | Upstream::Lemmy { .. } => {
+                bail!("{self} is not a Reddit listing")
+            }
+        })
+    }
+
+    /// Subreddit path the listing is archived under, searches and other sites are not archived
+    pub fn archive_key(&self) -> Option<&str> {
+        match self {
+            Upstream::Subreddit(subreddit) => Some(subreddit),
+            Upstream::Search { .. } | Upstream::HackerNews(_) | Upstream::Lemmy { .. } => None,
+        }
+    }
+}
+
+impl Display for Upstream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Upstream::Subreddit(subreddit) => write!(f, "{subreddit}"),
+            Upstream::Search {
+                query,
+                subreddit: Some(subreddit),
+                sort,
+            } => write!(f, "search:{sort}:r/{subreddit}:{query}"),
+            Upstream::Search { query, sort, .. } => write!(f, "search:{sort}:{query}"),
+            Upstream::HackerNews(list) => write!(f, "hn:{list}"),
+            Upstream::Lemmy {
+                instance,
+                community,
+            } => write!(f, "lemmy:{instance}/c/{community}"),
+        }
+    }
+}
+
+/// `min_score` of the feeds not setting one: per subreddit from `DEFAULTS` secret,
+/// e.g. `rust:200,programming:500`, otherwise `DEFAULT_MIN_SCORE` secret, otherwise 0
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScoreDefaults {
+    global: u64,
+    /// By lowercase subreddit name, without `r/`
+    subreddits: HashMap<String, u64>,
+}
+
+impl ScoreDefaults {
+    pub fn read(reader: &mut Reader) -> ScoreDefaults {
+        ScoreDefaults {
+            global: reader.number("DEFAULT_MIN_SCORE").unwrap_or_default(),
+            subreddits: reader
+                .pairs("DEFAULTS")
+                .into_iter()
+                .map(|(name, score)| (name.trim_start_matches("r/").to_lowercase(), score))
+                .collect(),
+        }
+    }
+
+    /// Threshold of a subreddit feed, for multireddits the lowest of the subreddits,
+    /// so the less popular ones are not silenced
+    fn min_score(&self, upstream: &Upstream) -> u64 {
+        let Upstream::Subreddit(subreddits) = upstream else {
+            return self.global;
+        };
+        subreddits
+            .trim_start_matches("r/")
+            .split('+')
+            .map(|name| {
+                self.subreddits
+                    .get(&name.to_lowercase())
+                    .copied()
+                    .unwrap_or(self.global)
+            })
+            .min()
+            .unwrap_or(self.global)
+    }
+}
+
+/// A feed as requested by a reader, key of the feed cache
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct FeedRequest {
+    upstream: Upstream,
+    options: FeedOptions,
+}
+
+impl FeedRequest {
+    /// Stable identifier of the feed, used as a key in the store
+    fn store_key(&self) -> String {
+        let options = serde_json::to_string(&self.options).unwrap_or_default();
+        format!("{} {options}", self.upstream)
+    }
+}
+
+/// Entries of a feed that passed its filters, see [FeedOptions::sticky]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QualifiedEntries {
+    /// Unix time of the last generation of the feed, refreshed at most every [TOUCH_INTERVAL]
+    /// while the entries are unchanged
+    touched: i64,
+    /// Unix time each entry first qualified, by id
+    entries: BTreeMap<String, i64>,
+}
+
+/// Reddit's counters of a post when its feed was generated
+#[derive(Clone, Debug, PartialEq)]
+pub struct PostStats {
+    pub score: u64,
+    pub comments: u64,
+    pub flair: Option<String>,
+}
+
+impl From<&ArticleInfo> for PostStats {
+    fn from(info: &ArticleInfo) -> Self {
+        PostStats {
+            score: info.score,
+            comments: info.num_comments,
+            flair: info.link_flair_text.clone(),
+        }
+    }
+}
+
+/// A filtered feed with the counters of its entries by id,
+/// entries kept without fresh info have none
+#[derive(Clone, Debug, Default)]
+pub struct FilteredFeed {
+    pub feed: Feed,
+    pub posts: HashMap<String, PostStats>,
+    /// When the feed was generated, it is cached from then on
+    pub generated: DateTime<Utc>,
+    /// Upstream Atom document with only the entries of the feed, served to Atom readers
+    /// with the feed-level elements of the response, see [FeedOptions::verbatim]
+    pub document: Option<Bytes>,
+}
+
+/// Failed feed generations kept for the admin dashboard
+const RECENT_ERRORS: usize = 50;
+
+/// Feed generation that failed upstream, e.g. on a private subreddit or Reddit being down
+#[derive(Clone, Debug, Serialize)]
+pub struct RecentError {
+    /// Unix timestamp
+    pub at: i64,
+    pub upstream: String,
+    pub error: String,
+}
+
+/// Edits this soon after the creation, e.g. typo fixes, do not count as updates, 5 minutes
+const EDIT_GRACE_SECS: f64 = 5.0 * 60.0;
+
+/// Posts from this window are used to compute the percentile threshold, 7 days
+const PERCENTILE_WINDOW_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// How often frequently requested feeds are regenerated
+pub const PREFETCH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Access counters are multiplied by this every prefetch cycle,
+/// so only recent requests count towards popularity
+const ACCESS_DECAY: f64 = 0.9;
+
+/// Feeds with decayed access counter above this are prefetched,
+/// e.g. a reader polling every 25 minutes keeps its feed warm
+const PREFETCH_THRESHOLD: f64 = 2.0;
+
+/// Counters below this are forgotten
+const ACCESS_FORGET_THRESHOLD: f64 = 0.1;
+
+/// Feeds not generated for this long forget their qualified entries, e.g. no longer polled
+/// or polled with other options
+const QUALIFIED_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Unchanged qualified entries are stored again this often, to mark the feed as still polled
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Qualified entries of the feeds no longer generated are forgotten this often
+pub const QUALIFIED_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Default time budget of a feed generation, some readers give up at 30 seconds
+pub const DEFAULT_FEED_DEADLINE: Duration = Duration::from_secs(25);
+
+const USER_AGENT: &str = concat!("shuttle:reddit-rss:", env!("CARGO_PKG_VERSION"));
+
+/// Limits of a single request to Reddit, so one slow response cannot eat the feed's deadline
+const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// A provider for RSS feed.
 /// Should be cheaply cloneable.
 #[derive(Clone)]
 pub struct RssFeedProvider {
+    source: Arc<dyn FeedSource>,
     reddit_client: RedditClient,
-    client: Client,
-    score_cache: Arc<moka::future::Cache<String, u64>>,
+    feed_cache: Arc<moka::future::Cache<FeedRequest, FilteredFeed>>,
+    feed_stats: Arc<CacheStats>,
+    /// Concurrent requests for the same feed share one generation, unless their
+    /// [Priority] differs: readers do not wait for a background one out of budget
+    in_flight: SingleFlight<(Priority, FeedRequest), FilteredFeed>,
+    /// Decaying request counters, used to pick feeds for prefetching
+    access: Arc<Mutex<HashMap<FeedRequest, f64>>>,
+    /// Entries that passed the filter with the (unix) time they first did, per feed.
+    /// See [FeedOptions::sticky] and [FeedOptions::promote_late]
+    qualified: Collection<QualifiedEntries>,
+    archive: Archive,
+    reposts: Reposts,
+    authors: Authors,
+    previews: Previews,
+    full_text: FullText,
+    /// Time budget of a feed generation, entries whose info is not resolved
+    /// in time are left out
+    deadline: Duration,
+    score_defaults: Arc<ScoreDefaults>,
+    recent_errors: Arc<Mutex<VecDeque<RecentError>>>,
+    feed_ttl: Duration,
 }
 
 impl RssFeedProvider {
-    pub fn new(client: Client, reddit_client: RedditClient) -> RssFeedProvider {
+    /// `source` is the backend of the filtered feeds,
+    /// `reddit_client` serves the other feeds, e.g. comments and the inbox,
+    /// `page_client` fetches the pages posts link to
+    pub fn new(
+        source: Arc<dyn FeedSource>,
+        reddit_client: RedditClient,
+        page_client: PublicClient,
+        qualified: Collection<QualifiedEntries>,
+        archive: Archive,
+        reposts: Reposts,
+        cache: &CacheConfig,
+    ) -> RssFeedProvider {
+        if cache.feed_ttl <= PREFETCH_INTERVAL {
+            warn!(
+                "feed cache TTL is not longer than the prefetch interval, prefetched feeds expire"
+            );
+        }
+        let feed_stats = Arc::new(CacheStats::default());
         RssFeedProvider {
+            source,
+            authors: Authors::new(reddit_client.clone(), cache),
+            previews: Previews::new(page_client.clone(), cache),
+            full_text: FullText::new(page_client, cache),
             reddit_client,
-            client,
-            score_cache: Arc::new(
-                moka::future::CacheBuilder::new(1000)
-                    .time_to_live(Duration::from_secs(60 * 60))
+            feed_cache: Arc::new(
+                moka::future::CacheBuilder::new(cache.feed_budget)
+                    .weigher(|_, filtered: &FilteredFeed| {
+                        let document = filtered.document.as_ref().map_or(0, Bytes::len);
+                        feed_weight(&filtered.feed).saturating_add(document as u32)
+                    })
+                    .time_to_live(cache.feed_ttl)
+                    .eviction_listener(feed_stats.listener())
                     .build(),
             ),
+            feed_stats,
+            in_flight: SingleFlight::default(),
+            access: Arc::new(Mutex::new(HashMap::new())),
+            qualified,
+            archive,
+            reposts,
+            deadline: DEFAULT_FEED_DEADLINE,
+            score_defaults: Arc::default(),
+            recent_errors: Arc::default(),
+            feed_ttl: cache.feed_ttl,
+        }
+    }
+
+    /// Domains excluded from `full_content`, see [FullText::read_opt_out]
+    pub fn with_full_text_opt_out(mut self, domains: Vec<String>) -> RssFeedProvider {
+        self.full_text = self.full_text.with_opt_out(domains);
+        self
+    }
+
+    /// Previews fetching the linked pages with their own client, e.g. through a proxy
+    pub fn with_previews(mut self, previews: Previews) -> RssFeedProvider {
+        self.previews = previews;
+        self
+    }
+
+    /// Time budget of a feed generation, [DEFAULT_FEED_DEADLINE] by default
+    pub fn with_deadline(mut self, deadline: Duration) -> RssFeedProvider {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Thresholds of the feeds not setting `min_score`
+    pub fn with_score_defaults(mut self, score_defaults: ScoreDefaults) -> RssFeedProvider {
+        self.score_defaults = Arc::new(score_defaults);
+        self
+    }
+
+    /// Provider set up by `config`, with the Reddit credentials from `secrets`,
+    /// keeping its state in `store`
+    pub async fn from_config(
+        config: &Config,
+        secrets: Arc<dyn Secrets>,
+        store: &Store,
+    ) -> eyre::Result<RssFeedProvider> {
+        let Config {
+            cache,
+            pool,
+            proxies,
+            ..
+        } = config;
+        let upstream_client = || {
+            pool.apply(Client::builder())
+                .default_headers({
+                    let mut headers = header::HeaderMap::new();
+                    headers.insert(header::USER_AGENT, USER_AGENT.parse().unwrap());
+                    headers
+                })
+                .connect_timeout(UPSTREAM_CONNECT_TIMEOUT)
+                .timeout(UPSTREAM_TIMEOUT)
+        };
+        // one client per route, its clones share the connection pool
+        let client = proxies.upstream(upstream_client()).build()?;
+        let auth_client = proxies
+            .auth(upstream_client())
+            .map(|b| b.build())
+            .transpose()?;
+        let page_client = PublicClient::new(
+            proxies.upstream(pool.apply(Client::builder()).user_agent(USER_AGENT)),
+        )?;
+        let archive = Archive::from_secrets(secrets.as_ref(), store)
+            .await?
+            .with_retention(config.archive_retention);
+        let mut reddit_client =
+            RedditClient::new(secrets, client.clone(), config.endpoints.clone())
+                .with_token_ttl(config.cache.token_ttl)
+                .with_quarantine_opt_in(config.quarantine_opt_in);
+        if let Some(auth_client) = auth_client {
+            reddit_client = reddit_client.with_auth_client(auth_client);
+        }
+        let reddit_client = reddit_client
+            .with_retry(config.retry.clone())
+            .with_budget(config.rate_budget.clone())
+            .with_throttle_store(store.collection("throttle").await?)
+            .await;
+        Ok(RssFeedProvider::new(
+            Arc::new(Sources::new(
+                RedditSource::new(client.clone(), reddit_client.clone(), cache),
+                HackerNewsSource::new(client.clone()),
+                LemmySource::new(PublicClient::new(proxies.upstream(upstream_client()))?),
+            )),
+            reddit_client,
+            page_client,
+            store.collection("qualified_feeds").await?,
+            archive,
+            Reposts::new(store.collection("reposts").await?)
+                .with_window_days(config.repost_window_days),
+            cache,
+        )
+        .with_deadline(config.feed_deadline)
+        .with_score_defaults(config.score_defaults.clone())
+        .with_full_text_opt_out(config.full_content_opt_out.clone()))
+    }
+
+    pub async fn feed_filter(
+        &self,
+        upstream: Upstream,
+        options: &FeedOptions,
+    ) -> eyre::Result<Feed> {
+        Ok(self.filtered_feed(upstream, options).await?.feed)
+    }
+
+    /// When the cached feed is generated anew, polling it earlier gets the same entries
+    pub fn expires(&self, filtered: &FilteredFeed) -> DateTime<Utc> {
+        filtered.generated + self.feed_ttl
+    }
+
+    /// The same feed as [RssFeedProvider::feed_filter] with the counters of the entries,
+    /// for the serializations other than Atom
+    pub async fn filtered_feed(
+        &self,
+        upstream: Upstream,
+        options: &FeedOptions,
+    ) -> eyre::Result<FilteredFeed> {
+        let request = self.feed_request(upstream, options);
+        self.track_access(&request);
+        self.feed_stats.lookup();
+        if let Some(feed) = self.feed_cache.get(&request).await {
+            info!("serving cached feed");
+            return Ok(feed);
+        }
+        self.feed_stats.miss();
+        self.refresh(request).await
+    }
+
+    /// Regenerates the feed into the cache, for background polls: they do not count
+    /// as accesses, see [RssFeedProvider::prefetch]
+    pub async fn refresh_feed(
+        &self,
+        upstream: Upstream,
+        options: &FeedOptions,
+    ) -> eyre::Result<FilteredFeed> {
+        self.refresh(self.feed_request(upstream, options)).await
+    }
+
+    fn feed_request(&self, upstream: Upstream, options: &FeedOptions) -> FeedRequest {
+        let mut options = options.clone();
+        options
+            .min_score
+            .get_or_insert_with(|| self.score_defaults.min_score(&upstream));
+        FeedRequest { upstream, options }
+    }
+
+    /// Statistics of the feed cache and the caches of the source
+    pub fn cache_stats(&self) -> CacheReport {
+        let mut report = self.source.cache_stats();
+        report.insert("feed", self.feed_stats.snapshot(&self.feed_cache));
+        report.insert("author", self.authors.cache_stats());
+        report.insert("preview", self.previews.cache_stats());
+        report.insert("article", self.full_text.cache_stats());
+        report
+    }
+
+    /// Durations of the score fetches, see [FeedSource::metrics]
+    pub fn metrics(&self) -> MetricsReport {
+        self.source.metrics()
+    }
+
+    /// Drops every cached feed and lookup, the next requests go to the upstreams
+    pub fn purge_caches(&self) {
+        self.source.purge_caches();
+        self.feed_cache.invalidate_all();
+        self.authors.purge();
+        self.previews.purge();
+        self.full_text.purge();
+        info!("purged the caches");
+    }
+
+    /// Client of the Reddit API the feeds are generated with
+    pub fn reddit_client(&self) -> &RedditClient {
+        &self.reddit_client
+    }
+
+    /// Feed generations that failed upstream, the most recent first
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.recent_errors.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Persists the state kept in memory, before shutdown
+    pub async fn flush(&self) {
+        if let Err(e) = self.reddit_client.flush_throttle().await {
+            warn!("cannot persist throttle state: {e:?}");
+        }
+    }
+
+    /// Regenerates frequently requested feeds, so their readers are served from warm cache.
+    /// Feeds are regenerated one by one, to not burst into Reddit's rate limit.
+    pub async fn prefetch(&self) {
+        for request in self.popular_requests() {
+            info!("prefetching {}", request.upstream);
+            let upstream = request.upstream.clone();
+            if let Err(e) = self.refresh(request).await {
+                warn!("cannot prefetch {upstream}: {e:?}");
+            }
         }
     }
 
-    pub async fn feed_filter(&self, subreddit: &str, min_score: u64) -> eyre::Result<String> {
-        info!("fetching feed");
-        let request = self
-            .client
-            .get(format!("https://reddit.com/{subreddit}/.rss"))
-            .send()
+    /// Generates the feed and puts it into the cache,
+    /// joining the generation already in flight if there is one
+    async fn refresh(&self, request: FeedRequest) -> eyre::Result<FilteredFeed> {
+        let generation = {
+            let provider = self.clone();
+            let request = request.clone();
+            async move { provider.generate_feed(&request).await }
+        };
+        let filtered = self
+            .in_flight
+            .run((Priority::current(), request.clone()), generation)
             .await
-            .context("cannot send feed request")?;
-        let status = request.status();
-        if status.is_client_error() || status.is_server_error() {
-            bail!(
-                "cannot load feed: \t\nstatus: {:?}\t\nbody: {:?}",
-                status,
-                request.text().await
-            );
+            .map_err(|e| match e.downcast_ref::<UpstreamError>() {
+                // keeps the cause visible to the handlers
+                Some(upstream) => {
+                    self.record_error(&request.upstream, &format!("{e:#}"));
+                    eyre::Report::new(*upstream).wrap_err(format!("{e:?}"))
+                }
+                None => eyre!("{e:?}"),
+            })?;
+        self.feed_cache.insert(request, filtered.clone()).await;
+        Ok(filtered)
+    }
+
+    fn record_error(&self, upstream: &Upstream, error: &str) {
+        let mut errors = self.recent_errors.lock().unwrap();
+        errors.truncate(RECENT_ERRORS - 1);
+        errors.push_front(RecentError {
+            at: Utc::now().timestamp(),
+            upstream: upstream.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    fn track_access(&self, request: &FeedRequest) {
+        let mut access = self.access.lock().unwrap();
+        *access.entry(request.clone()).or_default() += 1.0;
+    }
+
+    /// Returns requests above the prefetch threshold and decays all counters
+    fn popular_requests(&self) -> Vec<FeedRequest> {
+        let mut access = self.access.lock().unwrap();
+        let popular = access
+            .iter()
+            .filter(|(_, count)| **count >= PREFETCH_THRESHOLD)
+            .map(|(request, _)| request.clone())
+            .collect_vec();
+        access.retain(|_, count| {
+            *count *= ACCESS_DECAY;
+            *count >= ACCESS_FORGET_THRESHOLD
+        });
+        popular
+    }
+
+    /// Digest of the top posts per period, built from the archived listings
+    pub async fn digest(&self, subreddit: &str, options: DigestOptions) -> eyre::Result<Feed> {
+        // refreshes the archive with the current listing
+        let deadline = Instant::now() + self.deadline;
+        self.scored_listing(&Upstream::Subreddit(subreddit.to_string()), deadline)
+            .await?;
+        let posts = self.archive.posts(subreddit).await;
+        Ok(render_digest(subreddit, posts, options, Utc::now()))
+    }
+
+    /// Latest known scores of the posts listed by the upstream, by entry id.
+    /// Only subreddit listings are archived, other upstreams have none
+    pub async fn archived_scores(&self, upstream: &Upstream) -> HashMap<String, u64> {
+        match upstream.archive_key() {
+            Some(subreddit) => self.archive.scores(subreddit).await,
+            None => HashMap::new(),
         }
-        let feed = request.text().await.context("cannot parse feed")?;
-        let mut atom_feed =
-            Feed::read_from(feed.as_bytes()).map_err(|e| eyre!("Cannot parse feed: {e:?}"))?;
+    }
 
-        info!("fetching scores");
-        let score_fetch = atom_feed
+    /// Publication times of the archived posts of the upstream, none for the upstreams
+    /// that are not archived
+    pub async fn archived_publications(&self, upstream: &Upstream) -> Vec<i64> {
+        match upstream.archive_key() {
+            Some(subreddit) => {
+                let posts = self.archive.posts(subreddit).await;
+                posts.into_iter().map(|post| post.published).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Archived posts matching the query as a feed
+    pub async fn search_archive(&self, query: &ArchiveQuery) -> eyre::Result<Feed> {
+        let results = self.archive.search(query).await?;
+        Ok(render_search(query, results, Utc::now()))
+    }
+
+    /// Forgets the linked URLs of the feeds no longer polled, see [Reposts::prune]
+    pub async fn prune_reposts(&self) {
+        self.reposts.prune().await
+    }
+
+    /// Stores the qualified entries if changed, or if the feed was last marked as polled
+    /// a [TOUCH_INTERVAL] ago
+    async fn store_qualified(
+        &self,
+        store_key: String,
+        previous: BTreeMap<String, i64>,
+        entries: BTreeMap<String, i64>,
+    ) {
+        let now = Utc::now().timestamp();
+        let touched = self.qualified.get(&store_key).await.map(|q| q.touched);
+        let stale = touched.is_none_or(|t| now - t >= TOUCH_INTERVAL.as_secs() as i64);
+        if entries == previous && !stale {
+            return;
+        }
+        let qualified = QualifiedEntries {
+            touched: now,
+            entries,
+        };
+        if let Err(e) = self.qualified.insert(store_key, qualified).await {
+            warn!("cannot store qualified entries: {e:?}");
+        }
+    }
+
+    /// Forgets the qualified entries of the feeds not generated in [QUALIFIED_RETENTION]
+    pub async fn prune_qualified(&self) {
+        let cutoff = Utc::now().timestamp() - QUALIFIED_RETENTION.as_secs() as i64;
+        for (feed, qualified) in self.qualified.list().await {
+            if qualified.touched < cutoff {
+                if let Err(e) = self.qualified.remove(&feed).await {
+                    warn!("cannot prune the qualified entries of {feed}: {e:?}");
+                }
+            }
+        }
+    }
+
+    /// Applies the archive's retention policy
+    pub async fn compact_archive(&self) {
+        if let Err(e) = self.archive.compact().await {
+            warn!("cannot compact the archive: {e:?}");
+        }
+    }
+
+    /// Archived score samples of the post, by fullname such as `t3_abc123` or entry id
+    pub async fn score_history(&self, id: &str) -> eyre::Result<Vec<ScoreSample>> {
+        self.archive.score_history(entry_fullname(id)).await
+    }
+
+    /// Top-level comments of the post as a feed
+    pub async fn comments_feed(
+        &self,
+        subreddit: &str,
+        id: &str,
+        options: &CommentOptions,
+    ) -> eyre::Result<Feed> {
+        let (post, comments) = self
+            .within_deadline(
+                self.reddit_client
+                    .get_comments(subreddit, id, &options.sort),
+            )
+            .await?;
+        Ok(render_thread(&post, comments, options))
+    }
+
+    /// Newest comments across the subreddit as a feed
+    pub async fn comment_stream_feed(
+        &self,
+        subreddit: &str,
+        options: &CommentOptions,
+    ) -> eyre::Result<Feed> {
+        let comments = self.reddit_client.get_subreddit_comments(subreddit);
+        let comments = match self.within_deadline(comments).await {
+            Ok(comments) => comments,
+            Err(e) => return Err(self.reddit_client.explain(subreddit, e).await),
+        };
+        Ok(render_stream(&format!("r/{subreddit}"), comments, options))
+    }
+
+    /// Saved or upvoted posts and comments of the authenticated account as a feed
+    pub async fn account_feed(&self, listing: &str) -> eyre::Result<Feed> {
+        let things = self
+            .within_deadline(self.reddit_client.get_account_listing(listing))
+            .await?;
+        Ok(render_account(listing, things))
+    }
+
+    /// Unread messages, replies and mentions of the authenticated account as a feed
+    pub async fn inbox_feed(&self) -> eyre::Result<Feed> {
+        let messages = self
+            .within_deadline(self.reddit_client.get_unread_messages())
+            .await?;
+        Ok(render_inbox(messages))
+    }
+
+    /// Posts and comments waiting for moderator review as a feed
+    pub async fn modqueue_feed(&self, subreddit: &str) -> eyre::Result<Feed> {
+        let things = match self
+            .within_deadline(self.reddit_client.get_modqueue(subreddit))
+            .await
+        {
+            Ok(things) => things,
+            Err(e) => return Err(self.reddit_client.explain(subreddit, e).await),
+        };
+        Ok(render_modqueue(&format!("r/{subreddit}"), things))
+    }
+
+    /// Recent moderator actions as a feed
+    pub async fn modlog_feed(&self, subreddit: &str) -> eyre::Result<Feed> {
+        let actions = match self
+            .within_deadline(self.reddit_client.get_modlog(subreddit))
+            .await
+        {
+            Ok(actions) => actions,
+            Err(e) => return Err(self.reddit_client.explain(subreddit, e).await),
+        };
+        Ok(render_modlog(&format!("r/{subreddit}"), actions))
+    }
+
+    /// Runs `future` with its retries to Reddit ending at the feed deadline
+    async fn within_deadline<F: Future>(&self, future: F) -> F::Output {
+        with_deadline(Instant::now() + self.deadline, future).await
+    }
+
+    /// Upstream feed with the info of every entry, entries are recorded in the archive
+    async fn scored_listing(
+        &self,
+        upstream: &Upstream,
+        deadline: Instant,
+    ) -> eyre::Result<ScoredListing> {
+        let listing = with_deadline(deadline, self.source.listing(upstream, deadline)).await?;
+
+        let archived = listing
+            .feed
             .entries()
             .iter()
-            .map(|e| self.get_score(e))
+            .zip(&listing.scores)
+            .filter_map(|(e, info)| {
+                let info = info.as_ref()?;
+                Some((
+                    e.id.clone(),
+                    ArchivedPost {
+                        title: e.title.value.clone(),
+                        link: e.links.first()?.href.clone(),
+                        score: info.score,
+                        published: e.published.unwrap_or(e.updated).timestamp(),
+                        excerpt: excerpt(
+                            e.content
+                                .as_ref()
+                                .and_then(|c| c.value.as_deref())
+                                .unwrap_or_default(),
+                        ),
+                    },
+                ))
+            })
             .collect_vec();
-        let scores = try_join_all(score_fetch).await?;
+        if let Some(subreddit) = upstream.archive_key() {
+            if let Err(e) = self.archive.record(subreddit, archived).await {
+                warn!("cannot archive listing: {e:?}");
+            }
+        }
+
+        Ok(listing)
+    }
+
+    async fn generate_feed(&self, feed_request: &FeedRequest) -> eyre::Result<FilteredFeed> {
+        let FeedRequest { upstream, options } = feed_request;
+        let deadline = Instant::now() + self.deadline;
+        let ScoredListing {
+            feed: mut atom_feed,
+            scores,
+            document,
+        } = self.scored_listing(upstream, deadline).await?;
+        // the upstream document is not held while filtering feeds served otherwise
+        let document = document.filter(|_| options.verbatim && options.only_picks());
 
         info!("filtering feed");
+        let min_score = match options.min_percentile {
+            Some(p) => {
+                let threshold = self.percentile_score(upstream, &scores, p).await;
+                info!("{p}th percentile score is {threshold:?}");
+                threshold
+                    .unwrap_or_default()
+                    .max(options.min_score.unwrap_or_default())
+            }
+            None => options.min_score.unwrap_or_default(),
+        };
+        let track = options.sticky || options.promote_late;
+        let store_key = feed_request.store_key();
+        let previously_qualified = if track {
+            self.qualified
+                .get(&store_key)
+                .await
+                .map(|qualified| qualified.entries)
+        } else {
+            None
+        };
+        // without a previous generation, entries are taken as qualified since published,
+        // or they would all be promoted at once
+        let first_generation = previously_qualified.is_none();
+        let previously_qualified = previously_qualified.unwrap_or_default();
+        let seen_urls = if options.suppress_reposts {
+            self.reposts.seen(&store_key).await
+        } else {
+            BTreeMap::new()
+        };
+        let authors = if options.has_author_filters() {
+            let names = scores
+                .iter()
+                .flatten()
+                .filter_map(|info| info.author.as_deref())
+                .filter(|&name| name != "[deleted]");
+            self.authors.lookup(names, deadline).await
+        } else {
+            HashMap::new()
+        };
+        let now = Utc::now().timestamp();
+        let mut qualified = BTreeMap::new();
+        // original post and score of the entries, to collapse crossposts
+        let mut originals = HashMap::new();
+        // normalized external URLs of the entries, to track reposts
+        let mut linked = HashMap::new();
+        // external URLs of the entries to preview or inline
+        let mut enrich_urls = HashMap::new();
+        let mut posts = HashMap::new();
         atom_feed.entries = atom_feed
             .entries
             .into_iter()
-            .zip(scores.into_iter())
-            .filter_map(|(e, s)| match s {
-                Some(s) if s >= min_score => Some(e),
-                _ => None,
+            .zip(scores)
+            .filter_map(|(mut e, info)| {
+                let since = previously_qualified.get(&e.id).copied();
+                let Some(info) = info else {
+                    // info is unknown, e.g. not fetched in time, previous decision stands
+                    since?;
+                    qualified.insert(e.id.clone(), since.unwrap_or(now));
+                    return Some(e);
+                };
+                if options.hide_removed && info.is_removed() {
+                    return None;
+                }
+                let external_url = info.url.as_deref().filter(|_| !info.is_self);
+                let normalized_url = external_url.and_then(normalize_url);
+                if options.suppress_reposts
+                    && normalized_url.as_ref().is_some_and(|url| {
+                        self.reposts.is_repost(&seen_urls, &e.id, url)
+                            || linked.values().any(|u| u == url)
+                    })
+                {
+                    return None;
+                }
+                let author = info.author.as_ref().and_then(|name| authors.get(name));
+                if options.matches(&e, &info, author, min_score, now)
+                    || (options.sticky && since.is_some())
+                {
+                    let published = e.published.unwrap_or(e.updated).timestamp().min(now);
+                    let first_seen = if first_generation { published } else { now };
+                    qualified.insert(e.id.clone(), since.unwrap_or(first_seen));
+                } else {
+                    return None;
+                }
+                e.categories.extend(post_categories(
+                    info.link_flair_text.as_deref(),
+                    info.domain.as_deref(),
+                    info.over_18,
+                    info.spoiler,
+                ));
+                if let Some(url) = external_url {
+                    options.link_target.retarget_entry(&mut e, url);
+                    let sensitive = info.over_18 || info.spoiler;
+                    let enrich = options.link_preview || options.full_content;
+                    if enrich && !(options.mask_sensitive && sensitive) {
+                        enrich_urls.insert(e.id.clone(), url.to_string());
+                    }
+                }
+                if let Some(url) = normalized_url {
+                    linked.insert(e.id.clone(), url);
+                }
+                apply_edit(&mut e, &info, options.mark_edited);
+                if let Some(poll) = &info.poll_data {
+                    render_poll(&mut e, poll);
+                }
+                if let Some(parent) = info.crosspost_parent() {
+                    if options.crossposts == Crossposts::Annotate {
+                        annotate_crosspost(&mut e, parent);
+                    }
+                }
+                let original = info
+                    .crosspost_parent()
+                    .map(|parent| parent.name.clone())
+                    .or_else(|| post_fullname(&e));
+                if let Some(original) = original {
+                    originals.insert(e.id.clone(), (original, info.score));
+                }
+                if options.mask_sensitive && (info.over_18 || info.spoiler) {
+                    mask_entry(&mut e, if info.over_18 { "NSFW" } else { "Spoiler" });
+                }
+                render_entry(
+                    &mut e,
+                    &info,
+                    options.title_template.as_ref(),
+                    options.content_template.as_ref(),
+                );
+                posts.insert(e.id.clone(), PostStats::from(&info));
+                Some(e)
             })
             .collect_vec();
+        if options.crossposts == Crossposts::Collapse {
+            collapse_crossposts(&mut atom_feed.entries, &originals);
+        }
+        if options.suppress_reposts {
+            let urls = atom_feed
+                .entries
+                .iter()
+                .filter_map(|e| Some((linked.remove(&e.id)?, e.id.clone())))
+                .collect_vec();
+            if let Err(e) = self.reposts.record(&store_key, urls).await {
+                warn!("cannot record linked URLs: {e:?}");
+            }
+        }
+        if !enrich_urls.is_empty() {
+            self.enrich(&mut atom_feed.entries, &enrich_urls, options, deadline)
+                .await;
+        }
+        if options.sparkline {
+            let ids = atom_feed.entries.iter().map(|e| e.id.clone()).collect_vec();
+            match self.archive.score_histories(&ids).await {
+                Ok(histories) => {
+                    for entry in atom_feed.entries.iter_mut() {
+                        let history = histories.get(&entry.id).map_or(&[][..], Vec::as_slice);
+                        render_sparkline(entry, history);
+                    }
+                }
+                Err(e) => warn!("cannot read the score histories: {e:?}"),
+            }
+        }
 
-        Ok(atom_feed.to_string())
+        if options.promote_late {
+            for entry in atom_feed.entries.iter_mut() {
+                let qualified_at = qualified
+                    .get(&entry.id)
+                    .and_then(|ts| DateTime::from_timestamp(*ts, 0));
+                match qualified_at {
+                    Some(at) if at > entry.updated => entry.updated = at.fixed_offset(),
+                    _ => {}
+                }
+            }
+        }
+
+        let filters = options.describe();
+        if !filters.is_empty() {
+            atom_feed.title.value = format!("{} ({filters})", atom_feed.title.value);
+        }
+        let document = document.and_then(|document| {
+            let kept = atom_feed
+                .entries
+                .iter()
+                .map(|e| e.id.as_str())
+                .collect::<HashSet<_>>();
+            let id = |id: &str| kept.contains(id).then(|| entry_id(id));
+            match retain_entries(&document, id) {
+                Ok(document) => Some(Bytes::from(document)),
+                Err(e) => {
+                    warn!("cannot copy the feed verbatim: {e:?}");
+                    None
+                }
+            }
+        });
+        let mut served_posts = HashMap::new();
+        for entry in atom_feed.entries.iter_mut() {
+            sanitize_entry(entry);
+            options.link_style.rewrite_entry(entry);
+            let id = entry_id(&entry.id);
+            if let Some(post) = posts.remove(&entry.id) {
+                served_posts.insert(id.clone(), post);
+            }
+            entry.id = id;
+        }
+
+        // entries that left the upstream listing are forgotten
+        if track {
+            self.store_qualified(store_key, previously_qualified, qualified)
+                .await;
+        }
+
+        Ok(FilteredFeed {
+            feed: atom_feed,
+            posts: served_posts,
+            generated: Utc::now(),
+            document,
+        })
     }
 
-    async fn load_score(&self, mut url: String) -> eyre::Result<u64> {
-        url = url.replace("https://www.reddit.com/", "");
-        self.reddit_client
-            .get_article_score(&url)
-            .await
-            .context("Cannot load score from reddit")
-    }
-
-    async fn get_score(&self, entry: &Entry) -> eyre::Result<Option<u64>> {
-        match entry.links.first() {
-            Some(link) => {
-                let url = link.href.clone();
-                let score = self
-                    .score_cache
-                    .try_get_with(url.clone(), self.load_score(url))
-                    .await
-                    .map_err(|e| eyre!("cannot load score, {e:?}"))?;
-                Ok(Some(score))
+    /// Inlines the full text and appends the previews of the linked pages, as requested,
+    /// `urls` are the linked pages by entry id
+    async fn enrich(
+        &self,
+        entries: &mut [Entry],
+        urls: &HashMap<String, String>,
+        options: &FeedOptions,
+        deadline: Instant,
+    ) {
+        let linked = || {
+            entries
+                .iter()
+                .filter_map(|e| urls.get(&e.id).map(String::as_str))
+                .collect_vec()
+        };
+        let (articles, previews) = tokio::join!(
+            async {
+                match options.full_content {
+                    true => self.full_text.lookup(linked(), deadline).await,
+                    false => HashMap::new(),
+                }
+            },
+            async {
+                match options.link_preview {
+                    true => self.previews.lookup(linked(), deadline).await,
+                    false => HashMap::new(),
+                }
+            },
+        );
+        for entry in entries.iter_mut() {
+            let Some(url) = urls.get(&entry.id) else {
+                continue;
+            };
+            if let Some(article) = articles.get(url) {
+                inline_article(entry, article);
             }
-            None => {
-                info!("Cannot find link in the entry\n{entry:?}");
-                Ok(None)
+            if let Some(preview) = previews.get(url) {
+                preview.render_into(entry);
             }
         }
     }
+
+    /// Percentile of the scores of archived posts in the trailing window,
+    /// falls back to the current listing if the archive is empty
+    async fn percentile_score(
+        &self,
+        upstream: &Upstream,
+        listing: &[Option<ArticleInfo>],
+        p: u8,
+    ) -> Option<u64> {
+        let cutoff = Utc::now().timestamp() - PERCENTILE_WINDOW_SECS;
+        let archived = match upstream.archive_key() {
+            Some(subreddit) => self.archive.posts(subreddit).await,
+            None => vec![],
+        };
+        let archived = archived
+            .into_iter()
+            .filter(|post| post.published >= cutoff)
+            .map(|post| post.score)
+            .collect_vec();
+        if archived.is_empty() {
+            percentile(listing.iter().flatten().map(|i| i.score).collect(), p)
+        } else {
+            percentile(archived, p)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reddit::endpoints::Endpoints;
+    use tempfile::TempDir;
+
+    #[test]
+    fn post_fullname_test() {
+        let entry = |id: &str, link: &str| Entry {
+            id: id.to_string(),
+            links: vec![atom_syndication::Link {
+                href: link.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let link = "https://www.reddit.com/r/rust/comments/1bqry5x/a_very_rusty/";
+        assert_eq!(
+            post_fullname(&entry("t3_1bqry5x", link)).as_deref(),
+            Some("t3_1bqry5x")
+        );
+        assert_eq!(
+            post_fullname(&entry("https://example.com/1", link)).as_deref(),
+            Some("t3_1bqry5x")
+        );
+        assert_eq!(post_fullname(&entry("x", "https://example.com/")), None);
+    }
+
+    #[test]
+    fn listing_entry_test() {
+        let post: Post = serde_json::from_value(serde_json::json!({
+            "name": "t3_abc",
+            "title": "Quotes",
+            "permalink": "/r/rust/comments/abc/quotes/",
+            "author": "ferris",
+            "score": 1,
+            "created_utc": 1711706400.0,
+            "subreddit_name_prefixed": "r/rust",
+            "url": "https://example.com/?a=1&b=\"2\"",
+        }))
+        .unwrap();
+        let content = listing_entry(post).content.unwrap().value.unwrap();
+        assert!(
+            content.starts_with(r#"<a href="https://example.com/?a=1&amp;b=&quot;2&quot;">"#),
+            "{content}"
+        );
+    }
+
+    #[test]
+    fn entry_id_test() {
+        assert_eq!(entry_id("t3_1bqry5x"), "urn:reddit:t3_1bqry5x");
+        assert_eq!(entry_id("t1_kx2a9z"), "urn:reddit:t1_kx2a9z");
+        assert_eq!(entry_id("hn:39876543"), "hn:39876543");
+        assert_eq!(entry_fullname(&entry_id("t4_2b3c4d")), "t4_2b3c4d");
+    }
+
+    #[test]
+    fn normalize_text_test() {
+        assert_eq!(normalize_text("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(normalize_text("Escaping &amp;lt;"), "Escaping &lt;");
+        assert_eq!(normalize_text("Vec&lt;T&gt;\n  is  fine"), "Vec<T> is fine");
+    }
+
+    #[test]
+    fn describe_test() {
+        let options: FeedOptions =
+            serde_json::from_str(r#"{"min_score": 100, "min_percentile": 90, "flair": "News"}"#)
+                .unwrap();
+        assert_eq!(options.describe(), "score ≥ 100; top 10%; flair News");
+    }
+
+    /// Fixed listing, entry ids with their score or `None` if the info is missing
+    struct MockSource(Vec<(&'static str, Option<u64>)>);
+
+    #[async_trait::async_trait]
+    impl FeedSource for MockSource {
+        async fn listing(&self, _: &Upstream, _: Instant) -> eyre::Result<ScoredListing> {
+            let feed = Feed {
+                title: "r/rust".into(),
+                entries: self
+                    .0
+                    .iter()
+                    .map(|(id, _)| Entry {
+                        id: id.to_string(),
+                        title: id.to_string().into(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+            let scores = self
+                .0
+                .iter()
+                .map(|(_, score)| {
+                    score.map(|score| ArticleInfo {
+                        score,
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            Ok(ScoredListing::new(feed, scores))
+        }
+    }
+
+    /// Provider with its store in the directory, removed once it is dropped
+    async fn provider(source: MockSource) -> (RssFeedProvider, TempDir) {
+        let dir = TempDir::with_prefix("redditrss-test-").unwrap();
+        let store = Store::new(dir.path());
+        let secrets = Arc::new(crate::secrets::EnvSecrets::new());
+        let provider = RssFeedProvider::new(
+            Arc::new(source),
+            RedditClient::new(secrets, Client::new(), Endpoints::default()),
+            PublicClient::new(Client::builder()).unwrap(),
+            store.collection("qualified_feeds").await.unwrap(),
+            Archive::new(store.collection("archive").await.unwrap()),
+            Reposts::new(store.collection("reposts").await.unwrap()),
+            &CacheConfig::default(),
+        );
+        (provider, dir)
+    }
+
+    #[tokio::test]
+    async fn feed_filter_test() {
+        let (provider, _dir) = provider(MockSource(vec![
+            ("t3_low", Some(50)),
+            ("t3_high", Some(150)),
+            ("t3_unknown", None),
+        ]))
+        .await;
+        let options: FeedOptions = serde_json::from_str(r#"{"min_score": 100}"#).unwrap();
+        let feed = provider
+            .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options)
+            .await
+            .unwrap();
+        let ids = feed.entries.iter().map(|e| e.id.as_str()).collect_vec();
+        assert_eq!(ids, vec!["urn:reddit:t3_high"]);
+        assert_eq!(feed.title.value, "r/rust (score ≥ 100)");
+    }
+
+    #[tokio::test]
+    async fn score_defaults_test() {
+        let secrets = HashMap::from([
+            ("DEFAULT_MIN_SCORE", "100"),
+            ("DEFAULTS", "rust:200, Programming:20, broken"),
+        ]);
+        let defaults = ScoreDefaults::read(&mut Reader::new(&secrets));
+        let subreddit = |name: &str| Upstream::Subreddit(name.to_string());
+        assert_eq!(defaults.min_score(&subreddit("r/rust")), 200);
+        assert_eq!(defaults.min_score(&subreddit("r/rust+programming")), 20);
+        assert_eq!(defaults.min_score(&subreddit("r/golang")), 100);
+
+        let (provider, _dir) = provider(MockSource(vec![
+            ("t3_low", Some(150)),
+            ("t3_high", Some(250)),
+        ]))
+        .await;
+        let provider = provider.with_score_defaults(defaults);
+        let options: FeedOptions = serde_json::from_str("{}").unwrap();
+        let feed = provider
+            .feed_filter(subreddit("r/rust"), &options)
+            .await
+            .unwrap();
+        assert_eq!(feed.title.value, "r/rust (score ≥ 200)");
+        assert_eq!(feed.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prune_qualified_test() {
+        let (provider, _dir) = provider(MockSource(vec![("t3_high", Some(150))])).await;
+        let options: FeedOptions =
+            serde_json::from_str(r#"{"min_score": 100, "sticky": true}"#).unwrap();
+        let upstream = Upstream::Subreddit("r/rust".to_string());
+        provider
+            .feed_filter(upstream.clone(), &options)
+            .await
+            .unwrap();
+        let key = FeedRequest { upstream, options }.store_key();
+        let qualified = provider.qualified.get(&key).await.unwrap();
+        assert_eq!(qualified.entries.keys().collect_vec(), vec!["t3_high"]);
+
+        provider.prune_qualified().await;
+        assert!(provider.qualified.contains(&key).await);
+
+        let touched = Utc::now().timestamp() - QUALIFIED_RETENTION.as_secs() as i64 - 1;
+        let qualified = QualifiedEntries {
+            touched,
+            ..qualified
+        };
+        provider
+            .qualified
+            .insert(key.clone(), qualified)
+            .await
+            .unwrap();
+        provider.prune_qualified().await;
+        assert!(!provider.qualified.contains(&key).await);
+    }
+
+    #[test]
+    fn percentile_test() {
+        let scores = (1..=20).collect_vec();
+        assert_eq!(percentile(scores.clone(), 90), Some(18));
+        assert_eq!(percentile(scores.clone(), 100), Some(20));
+        assert_eq!(percentile(scores, 0), Some(1));
+        assert_eq!(percentile(vec![], 50), None);
+    }
 }
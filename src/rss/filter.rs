@@ -0,0 +1,480 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use atom_syndication::Entry;
+use serde::{Deserialize, Serialize};
+
+use crate::reddit::client::ArticleInfo;
+
+/// Boolean expression over the posts, given as `filter` query parameter, e.g.
+/// `score>=200 AND (flair="Showcase" OR comments>=50) AND NOT title~"weekly"`.
+///
+/// Fields:
+/// - numbers `score`, `comments`: compared with `=`, `!=`, `<`, `<=`, `>`, `>=`
+/// - texts `flair`, `domain`, `title`, `author`: `=` and `!=` compare ignoring case,
+///   `~` checks if the text contains the value, ignoring case
+/// - flags `nsfw`, `spoiler`, `self`: alone, or compared with `true` or `false`
+///
+/// `AND` binds tighter than `OR`, keywords are case-insensitive.
+/// Values without spaces or special characters can be left unquoted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Filter {
+    /// The expression as given, filters are compared and hashed by it
+    source: String,
+    expr: Expr,
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Filter {}
+
+impl Hash for Filter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.source
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+/// Longest filter accepted, in bytes
+const MAX_LENGTH: usize = 1000;
+
+/// Deepest nesting of parentheses and `NOT`s accepted,
+/// the parser and the evaluation recurse as deep
+const MAX_DEPTH: usize = 32;
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        if source.len() > MAX_LENGTH {
+            return Err(format!("filter is longer than {MAX_LENGTH} characters"));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {token} in filter"));
+        }
+        Ok(Filter {
+            source: source.to_string(),
+            expr,
+        })
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry, info: &ArticleInfo) -> bool {
+        self.expr.eval(entry, info)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Number(NumberField, Comparison, u64),
+    Text(TextField, TextComparison, String),
+    Flag(FlagField, bool),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum NumberField {
+    Score,
+    Comments,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextField {
+    Flair,
+    Domain,
+    Title,
+    Author,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum FlagField {
+    Nsfw,
+    Spoiler,
+    IsSelf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextComparison {
+    Eq,
+    Ne,
+    Contains,
+}
+
+impl Expr {
+    fn eval(&self, entry: &Entry, info: &ArticleInfo) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(entry, info) && b.eval(entry, info),
+            Expr::Or(a, b) => a.eval(entry, info) || b.eval(entry, info),
+            Expr::Not(a) => !a.eval(entry, info),
+            Expr::Number(field, comparison, value) => {
+                let actual = match field {
+                    NumberField::Score => info.score,
+                    NumberField::Comments => info.num_comments,
+                };
+                match comparison {
+                    Comparison::Eq => actual == *value,
+                    Comparison::Ne => actual != *value,
+                    Comparison::Lt => actual < *value,
+                    Comparison::Le => actual <= *value,
+                    Comparison::Gt => actual > *value,
+                    Comparison::Ge => actual >= *value,
+                }
+            }
+            Expr::Text(field, comparison, value) => {
+                let actual = match field {
+                    TextField::Flair => info.link_flair_text.as_deref().unwrap_or_default(),
+                    TextField::Domain => info.domain.as_deref().unwrap_or_default(),
+                    TextField::Title => entry.title.value.as_str(),
+                    TextField::Author => entry
+                        .authors
+                        .first()
+                        .map(|author| author.name.trim_start_matches("/u/"))
+                        .unwrap_or_default(),
+                };
+                match comparison {
+                    TextComparison::Eq => actual.eq_ignore_ascii_case(value),
+                    TextComparison::Ne => !actual.eq_ignore_ascii_case(value),
+                    // the value is lowercased when parsed
+                    TextComparison::Contains => actual.to_lowercase().contains(value.as_str()),
+                }
+            }
+            Expr::Flag(field, expected) => {
+                let actual = match field {
+                    FlagField::Nsfw => info.over_18,
+                    FlagField::Spoiler => info.spoiler,
+                    FlagField::IsSelf => info.is_self,
+                };
+                actual == *expected
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Operator(&'static str),
+    /// Quoted value
+    Text(String),
+    /// Field, keyword or unquoted value
+    Word(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => f.write_str("`(`"),
+            Token::Close => f.write_str("`)`"),
+            Token::Operator(op) => write!(f, "`{op}`"),
+            Token::Text(text) => write!(f, "\"{text}\""),
+            Token::Word(word) => write!(f, "`{word}`"),
+        }
+    }
+}
+
+/// Longer operators first, so `>=` is not read as `>`
+const OPERATORS: [&str; 7] = [">=", "<=", "!=", ">", "<", "=", "~"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Operator(op));
+            rest = &rest[op.len()..];
+        } else if c == '"' {
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| "unterminated quote in filter".to_string())?;
+            tokens.push(Token::Text(rest[1..=end].to_string()));
+            rest = &rest[end + 2..];
+        } else {
+            let end = rest
+                .find(|c: char| {
+                    c.is_whitespace()
+                        || "()\"".contains(c)
+                        || OPERATORS.iter().any(|op| op.starts_with(c))
+                })
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected `{c}` in filter"));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Parentheses and `NOT`s the parser is in
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    /// Parses with `parse` one level deeper, up to [MAX_DEPTH]
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Expr, String>) -> Result<Expr, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("filter is nested deeper than {MAX_DEPTH} levels"));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.nested(Parser::not)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.nested(Parser::or)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    Some(token) => Err(format!("expected `)` in filter, found {token}")),
+                    None => Err("missing `)` in filter".to_string()),
+                }
+            }
+            Some(Token::Word(field)) => self.condition(&field),
+            Some(token) => Err(format!("expected a field in filter, found {token}")),
+            None => Err("filter ends unexpectedly".to_string()),
+        }
+    }
+
+    fn condition(&mut self, field: &str) -> Result<Expr, String> {
+        let operator = match self.peek() {
+            Some(Token::Operator(op)) => {
+                let op = *op;
+                self.position += 1;
+                Some(op)
+            }
+            _ => None,
+        };
+        let field = field.to_lowercase();
+        let flag = match field.as_str() {
+            "nsfw" => Some(FlagField::Nsfw),
+            "spoiler" => Some(FlagField::Spoiler),
+            "self" => Some(FlagField::IsSelf),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            return match operator {
+                None => Ok(Expr::Flag(flag, true)),
+                Some(op @ ("=" | "!=")) => {
+                    let value = match self.value(&field)?.to_lowercase().as_str() {
+                        "true" => true,
+                        "false" => false,
+                        value => return Err(format!("{field} must be true or false, not {value}")),
+                    };
+                    Ok(Expr::Flag(flag, value == (op == "=")))
+                }
+                Some(op) => Err(format!("{field} cannot be compared with `{op}`")),
+            };
+        }
+        let Some(op) = operator else {
+            return Err(format!("expected a comparison after {field} in filter"));
+        };
+        let number = match field.as_str() {
+            "score" => Some(NumberField::Score),
+            "comments" => Some(NumberField::Comments),
+            _ => None,
+        };
+        if let Some(number) = number {
+            let comparison = match op {
+                "=" => Comparison::Eq,
+                "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                ">=" => Comparison::Ge,
+                op => return Err(format!("{field} cannot be compared with `{op}`")),
+            };
+            let value = self.value(&field)?;
+            let value = value.parse().map_err(|_| {
+                format!("{field} must be compared to a non-negative integer, not {value}")
+            })?;
+            return Ok(Expr::Number(number, comparison, value));
+        }
+        let text = match field.as_str() {
+            "flair" => TextField::Flair,
+            "domain" => TextField::Domain,
+            "title" => TextField::Title,
+            "author" => TextField::Author,
+            _ => {
+                return Err(format!(
+                    "unknown field {field} in filter, expected score, comments, flair, domain, \
+                     title, author, nsfw, spoiler or self"
+                ))
+            }
+        };
+        let (comparison, value) = match op {
+            "=" => (TextComparison::Eq, self.value(&field)?),
+            "!=" => (TextComparison::Ne, self.value(&field)?),
+            "~" => (TextComparison::Contains, self.value(&field)?.to_lowercase()),
+            op => return Err(format!("{field} cannot be compared with `{op}`")),
+        };
+        Ok(Expr::Text(text, comparison, value))
+    }
+
+    fn value(&mut self, field: &str) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Text(value) | Token::Word(value)) => Ok(value),
+            Some(token) => Err(format!("expected a value for {field}, found {token}")),
+            None => Err(format!("expected a value for {field}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_test() {
+        let filter: Filter =
+            r#"score>=200 AND (flair="Showcase" OR comments>=50) AND NOT title~"weekly""#
+                .parse()
+                .unwrap();
+        let post = |title: &str, score, flair: &str, num_comments| {
+            let entry = Entry {
+                title: title.to_string().into(),
+                ..Default::default()
+            };
+            let info = ArticleInfo {
+                score,
+                num_comments,
+                link_flair_text: Some(flair.to_string()),
+                ..Default::default()
+            };
+            filter.matches(&entry, &info)
+        };
+        assert!(post("My crate", 300, "showcase", 0));
+        assert!(post("Question", 300, "Help", 80));
+        assert!(!post("Question", 300, "Help", 10));
+        assert!(!post("Weekly thread", 300, "Showcase", 80));
+        assert!(!post("My crate", 100, "Showcase", 80));
+
+        assert_eq!(
+            "score >= many".parse::<Filter>().unwrap_err(),
+            "score must be compared to a non-negative integer, not many"
+        );
+        assert!("nsfw AND (score > 1".parse::<Filter>().is_err());
+        assert!("karma > 1".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn nesting_limit_test() {
+        let nested = |depth| {
+            format!(
+                "{}{}nsfw{}",
+                "NOT ".repeat(depth),
+                "(".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert!(nested(MAX_DEPTH / 2).parse::<Filter>().is_ok());
+        assert_eq!(
+            nested(MAX_DEPTH / 2 + 1).parse::<Filter>().unwrap_err(),
+            "filter is nested deeper than 32 levels"
+        );
+        let deep = "(".repeat(100_000);
+        assert_eq!(
+            deep.parse::<Filter>().unwrap_err(),
+            "filter is longer than 1000 characters"
+        );
+        let deep = "NOT ".repeat(200) + "nsfw";
+        assert!(deep.parse::<Filter>().is_err());
+    }
+}
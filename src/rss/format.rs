@@ -0,0 +1,263 @@
+use std::io;
+use std::str::FromStr;
+
+use atom_syndication::{Entry, Feed, Link};
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::Writer;
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Serialization of a feed, picked by the `format` query parameter
+/// or the suffix of the path, e.g. `/feed/rust.rss`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeedFormat {
+    #[default]
+    Atom,
+    /// RSS 2.0
+    Rss,
+    /// JSON Feed 1.1, see <https://www.jsonfeed.org/version/1.1/>
+    Json,
+}
+
+impl FromStr for FeedFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "atom" => Ok(FeedFormat::Atom),
+            "rss" => Ok(FeedFormat::Rss),
+            "json" => Ok(FeedFormat::Json),
+            _ => Err(format!("Unknown format {s}, expected atom, rss or json")),
+        }
+    }
+}
+
+impl FeedFormat {
+    /// Splits a known format suffix off the path, `/feed/rust.rss` is `/feed/rust` as RSS
+    pub fn split_suffix(path: &str) -> (&str, Option<FeedFormat>) {
+        path.rsplit_once('.')
+            .filter(|(_, suffix)| !suffix.contains('/'))
+            .and_then(|(path, suffix)| Some((path, suffix.parse().ok()?)))
+            .map_or((path, None), |(path, format)| (path, Some(format)))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+
+    pub fn render(&self, feed: &Feed) -> String {
+        let rendered = self
+            .write_to(feed, Vec::new())
+            .expect("writing to memory does not fail");
+        String::from_utf8(rendered).expect("written from strings")
+    }
+
+    /// Writes the feed into `writer` as it is serialized, entry by entry,
+    /// so it is never held as one string
+    pub fn write_to<W: io::Write>(&self, feed: &Feed, writer: W) -> io::Result<W> {
+        match self {
+            FeedFormat::Atom => feed.write_to(writer).map_err(io::Error::other),
+            FeedFormat::Rss => {
+                let mut writer = Writer::new(writer);
+                write_rss(&mut writer, feed)?;
+                Ok(writer.into_inner())
+            }
+            FeedFormat::Json => {
+                let mut writer = writer;
+                serde_json::to_writer(&mut writer, &JsonFeed(feed))?;
+                Ok(writer)
+            }
+        }
+    }
+}
+
+fn link<'a>(links: &'a [Link], rel: &str) -> Option<&'a str> {
+    links
+        .iter()
+        .find(|link| link.rel == rel)
+        .map(|link| link.href.as_str())
+}
+
+/// Main link of the entry, the first `alternate` one
+pub fn entry_url(entry: &Entry) -> Option<&str> {
+    link(&entry.links, "alternate")
+}
+
+fn entry_html(entry: &Entry) -> Option<&str> {
+    entry
+        .content
+        .as_ref()
+        .and_then(|content| content.value.as_deref())
+        .or(entry.summary.as_ref().map(|summary| summary.value.as_str()))
+}
+
+/// RSS 2.0 rendering, Atom-only details such as contributors are left out
+pub fn to_rss(feed: &Feed) -> String {
+    FeedFormat::Rss.render(feed)
+}
+
+fn write_rss<W: io::Write>(writer: &mut Writer<W>, feed: &Feed) -> io::Result<()> {
+    let text = |value: &str| BytesText::new(value).into_owned();
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("rss")
+        .with_attribute(("version", "2.0"))
+        .write_inner_content(|writer| {
+            writer
+                .create_element("channel")
+                .write_inner_content(|writer| {
+                    let description = feed.subtitle.as_ref().unwrap_or(&feed.title);
+                    writer
+                        .create_element("title")
+                        .write_text_content(text(&feed.title.value))?;
+                    writer.create_element("link").write_text_content(text(
+                        link(&feed.links, "alternate").unwrap_or_default(),
+                    ))?;
+                    writer
+                        .create_element("description")
+                        .write_text_content(text(&description.value))?;
+                    writer
+                        .create_element("lastBuildDate")
+                        .write_text_content(text(&feed.updated.to_rfc2822()))?;
+                    for entry in &feed.entries {
+                        writer
+                            .create_element("item")
+                            .write_inner_content(|writer| {
+                                writer
+                                    .create_element("title")
+                                    .write_text_content(text(&entry.title.value))?;
+                                if let Some(url) = entry_url(entry) {
+                                    writer
+                                        .create_element("link")
+                                        .write_text_content(text(url))?;
+                                }
+                                writer
+                                    .create_element("guid")
+                                    .with_attribute(("isPermaLink", "false"))
+                                    .write_text_content(text(&entry.id))?;
+                                let published = entry.published.unwrap_or(entry.updated);
+                                writer
+                                    .create_element("pubDate")
+                                    .write_text_content(text(&published.to_rfc2822()))?;
+                                for author in &entry.authors {
+                                    // RSS wants an email address, Dublin Core is not worth a namespace
+                                    writer
+                                        .create_element("author")
+                                        .write_text_content(text(&author.name))?;
+                                }
+                                for category in &entry.categories {
+                                    writer
+                                        .create_element("category")
+                                        .write_text_content(text(&category.term))?;
+                                }
+                                if let Some(html) = entry_html(entry) {
+                                    writer
+                                        .create_element("description")
+                                        .write_text_content(text(html))?;
+                                }
+                                Ok(())
+                            })?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// JSON Feed 1.1 item of the entry
+pub fn json_feed_item(entry: &Entry) -> Value {
+    let mut item = json!({
+        "id": entry.id,
+        "title": entry.title.value,
+        "date_modified": entry.updated.to_rfc3339(),
+    });
+    if let Some(url) = entry_url(entry) {
+        item["url"] = json!(url);
+    }
+    if let Some(url) = link(&entry.links, "related") {
+        item["external_url"] = json!(url);
+    }
+    if let Some(published) = entry.published {
+        item["date_published"] = json!(published.to_rfc3339());
+    }
+    match entry_html(entry) {
+        Some(html) => item["content_html"] = json!(html),
+        None => item["content_text"] = json!(""),
+    }
+    if !entry.authors.is_empty() {
+        item["authors"] = entry
+            .authors
+            .iter()
+            .map(|author| json!({ "name": author.name, "url": author.uri }))
+            .collect();
+    }
+    if !entry.categories.is_empty() {
+        item["tags"] = entry.categories.iter().map(|c| json!(c.term)).collect();
+    }
+    item
+}
+
+/// JSON Feed 1.1 rendering
+pub fn to_json_feed(feed: &Feed) -> Value {
+    serde_json::to_value(JsonFeed(feed)).expect("feeds serialize to JSON")
+}
+
+/// JSON Feed 1.1 of the feed, serialized item by item
+struct JsonFeed<'a>(&'a Feed);
+
+impl Serialize for JsonFeed<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let JsonFeed(feed) = self;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("version", "https://jsonfeed.org/version/1.1")?;
+        map.serialize_entry("title", &feed.title.value)?;
+        if let Some(url) = link(&feed.links, "alternate") {
+            map.serialize_entry("home_page_url", url)?;
+        }
+        if let Some(url) = link(&feed.links, "self") {
+            map.serialize_entry("feed_url", url)?;
+        }
+        map.serialize_entry("items", &JsonItems(&feed.entries))?;
+        map.end()
+    }
+}
+
+struct JsonItems<'a>(&'a [Entry]);
+
+impl Serialize for JsonItems<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(json_feed_item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_suffix_test() {
+        assert_eq!(
+            FeedFormat::split_suffix("/feed/rust.rss"),
+            ("/feed/rust", Some(FeedFormat::Rss))
+        );
+        assert_eq!(
+            FeedFormat::split_suffix("/feed/lemmy/lemmy.ml/c/rust"),
+            ("/feed/lemmy/lemmy.ml/c/rust", None)
+        );
+        assert_eq!(FeedFormat::split_suffix("/f/a.b"), ("/f/a.b", None));
+    }
+
+    #[test]
+    fn render_test() {
+        let feed =
+            Feed::read_from(include_str!("../../tests/fixtures/listing.rss").as_bytes()).unwrap();
+        insta::assert_snapshot!(to_rss(&feed));
+        insta::assert_snapshot!(serde_json::to_string_pretty(&to_json_feed(&feed)).unwrap());
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use atom_syndication::{Content, Entry};
+use dom_smoothie::{Config, Readability};
+use eyre::{bail, Context};
+use futures::future::join_all;
+use reqwest::{header, Url};
+use tokio::sync::Semaphore;
+use tokio::time::{timeout, timeout_at, Instant};
+use tracing::info;
+
+use crate::cache::{CacheConfig, CacheSnapshot, CacheStats};
+use crate::config::Reader;
+use crate::http::PublicClient;
+use crate::rss::preview::read_prefix;
+use crate::rss::sanitize::sanitize_html;
+
+/// Articles fetched at the same time, across all feeds
+const CONCURRENT_FETCHES: usize = 2;
+
+/// Extractions running at the same time, across all feeds. An extraction past
+/// its timeout keeps its blocking thread and its permit until it ends
+const CONCURRENT_EXTRACTIONS: usize = 2;
+
+/// Longer pages are cut before the extraction, the article is usually near the start
+const MAX_PAGE_BYTES: usize = 512 * 1024;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Extraction taking longer is abandoned, the page is probably pathological
+const EXTRACT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pages with more elements are not extracted
+const MAX_ELEMENTS: usize = 20_000;
+
+/// Readability-style extraction of linked articles, inlined by `full_content`.
+/// Fetches and extractions are limited in size, time and concurrency, and skipped
+/// for the opted out domains and non-public hosts, see [PublicClient].
+/// Articles that cannot be extracted are cached as `None`.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct FullText {
+    client: PublicClient,
+    permits: Arc<Semaphore>,
+    extractions: Arc<Semaphore>,
+    cache: Arc<moka::future::Cache<String, Option<Arc<str>>>>,
+    stats: Arc<CacheStats>,
+    /// Domains whose articles are not fetched, with their subdomains
+    opt_out: Arc<Vec<String>>,
+}
+
+impl FullText {
+    pub fn new(client: PublicClient, cache: &CacheConfig) -> FullText {
+        let stats = Arc::new(CacheStats::default());
+        FullText {
+            client,
+            permits: Arc::new(Semaphore::new(CONCURRENT_FETCHES)),
+            extractions: Arc::new(Semaphore::new(CONCURRENT_EXTRACTIONS)),
+            cache: Arc::new(
+                moka::future::CacheBuilder::new(cache.article_budget)
+                    .weigher(|_, article: &Option<Arc<str>>| {
+                        article.as_ref().map_or(0, |a| a.len()) as u32
+                    })
+                    .time_to_live(cache.article_ttl)
+                    .eviction_listener(stats.listener())
+                    .build(),
+            ),
+            stats,
+            opt_out: Arc::default(),
+        }
+    }
+
+    /// Domains whose articles are not fetched, see [FullText::read_opt_out]
+    pub fn with_opt_out(mut self, domains: Vec<String>) -> FullText {
+        self.opt_out = Arc::new(domains);
+        self
+    }
+
+    /// Domains taken from `FULL_CONTENT_OPT_OUT` secret, comma separated,
+    /// e.g. `nytimes.com,medium.com`
+    pub fn read_opt_out(reader: &mut Reader) -> Vec<String> {
+        let domains = reader.string("FULL_CONTENT_OPT_OUT").unwrap_or_default();
+        domains
+            .split(',')
+            .map(|domain| domain.trim().trim_start_matches("www.").to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect()
+    }
+
+    /// Articles of the URLs extracted before the deadline, by URL
+    pub async fn lookup<'a>(
+        &self,
+        urls: impl IntoIterator<Item = &'a str>,
+        deadline: Instant,
+    ) -> HashMap<String, Arc<str>> {
+        let lookups = urls.into_iter().map(|url| async move {
+            let article = timeout_at(deadline, self.get(url)).await.ok().flatten()?;
+            Some((url.to_string(), article))
+        });
+        join_all(lookups).await.into_iter().flatten().collect()
+    }
+
+    async fn get(&self, url: &str) -> Option<Arc<str>> {
+        self.stats.lookup();
+        self.cache
+            .get_with(url.to_string(), async {
+                self.stats.miss();
+                self.fetch(url)
+                    .await
+                    .inspect_err(|e| info!("no full content of {url}: {e:?}"))
+                    .ok()
+                    .map(Arc::from)
+            })
+            .await
+    }
+
+    fn opted_out(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        self.opt_out
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")))
+    }
+
+    async fn fetch(&self, url: &str) -> eyre::Result<String> {
+        let parsed = Url::parse(url).context("invalid URL")?;
+        let request = self.client.get(&parsed)?;
+        if self.opted_out(&parsed) {
+            bail!("domain opted out");
+        }
+        let permit = self.permits.acquire().await?;
+        let response = request
+            .header(header::ACCEPT, "text/html")
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/html"));
+        if !is_html {
+            bail!("not an HTML page");
+        }
+        let html = read_prefix(response, MAX_PAGE_BYTES).await?;
+        drop(permit);
+        let extraction = self.extractions.clone().acquire_owned().await?;
+        let url = url.to_string();
+        let extraction = tokio::task::spawn_blocking(move || {
+            let article = extract(&html, &url);
+            drop(extraction);
+            article
+        });
+        timeout(EXTRACT_TIMEOUT, extraction)
+            .await
+            .context("extraction timed out")??
+    }
+
+    pub fn cache_stats(&self) -> CacheSnapshot {
+        self.stats.snapshot(&self.cache)
+    }
+
+    pub fn purge(&self) {
+        self.cache.invalidate_all();
+    }
+}
+
+/// Main content of the page as sanitized HTML
+fn extract(html: &str, url: &str) -> eyre::Result<String> {
+    let config = Config {
+        max_elements_to_parse: MAX_ELEMENTS,
+        ..Default::default()
+    };
+    let article = Readability::new(html, Some(url), Some(config))?.parse()?;
+    if article.text_content.trim().is_empty() {
+        bail!("no content");
+    }
+    Ok(sanitize_html(&article.content))
+}
+
+/// Puts the article before the content of the entry, which keeps Reddit's links.
+/// `article` is third-party HTML, sanitized when extracted by [FullText]
+pub fn inline_article(entry: &mut Entry, article: &str) {
+    let content = entry.content.get_or_insert_with(|| Content {
+        content_type: Some("html".to_string()),
+        ..Default::default()
+    });
+    let original = content.value.take().unwrap_or_default();
+    content.value = Some(format!("{article}<hr />{original}"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_test() {
+        let paragraph = "Rust 1.77 brings C-string literals and async recursion. ".repeat(20);
+        let html = format!(
+            "<html><head><title>Announcing Rust 1.77</title></head><body>\
+             <nav><a href=\"/\">Home</a><a href=\"/blog\">Blog</a></nav>\
+             <script>track()</script>\
+             <article><h1>Announcing Rust 1.77</h1><p>{paragraph}</p><p>{paragraph}</p></article>\
+             <footer>Copyright</footer></body></html>"
+        );
+        let article = extract(
+            &html,
+            "https://blog.rust-lang.org/2024/03/21/Rust-1.77.0.html",
+        )
+        .unwrap();
+        assert!(article.contains("C-string literals"));
+        assert!(!article.contains("<script"));
+        assert!(!article.contains("Copyright"));
+    }
+
+    #[test]
+    fn opted_out_test() {
+        let secrets = HashMap::from([("FULL_CONTENT_OPT_OUT", "www.example.com, news.org")]);
+        let client = PublicClient::new(reqwest::Client::builder()).unwrap();
+        let domains = FullText::read_opt_out(&mut Reader::new(&secrets));
+        let full_text = FullText::new(client, &CacheConfig::default()).with_opt_out(domains);
+        let opted_out = |url: &str| full_text.opted_out(&Url::parse(url).unwrap());
+        assert!(opted_out("https://example.com/a"));
+        assert!(opted_out("https://blog.news.org/a"));
+        assert!(!opted_out("https://notexample.com/a"));
+    }
+}
@@ -0,0 +1,262 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use atom_syndication::{Content, Entry, Feed, Link, Person};
+use chrono::{DateTime, Utc};
+use eyre::{bail, Context};
+use futures::future::join_all;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tokio::time::{timeout_at, Instant};
+use tracing::{info, warn};
+
+use crate::error::UpstreamError;
+use crate::reddit::client::ArticleInfo;
+use crate::rss::feed::Upstream;
+use crate::rss::source::{FeedSource, ScoredListing};
+
+const API_URL: &str = "https://hacker-news.firebaseio.com/v0";
+
+/// Stories taken from the top of a list, the same amount as a page of the front page
+const STORIES_PER_LIST: usize = 30;
+
+/// Story lists of Hacker News
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HnList {
+    Front,
+    New,
+    Ask,
+    Show,
+}
+
+impl HnList {
+    fn endpoint(self) -> &'static str {
+        match self {
+            HnList::Front => "topstories",
+            HnList::New => "newstories",
+            HnList::Ask => "askstories",
+            HnList::Show => "showstories",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            HnList::Front => "Hacker News",
+            HnList::New => "Hacker News: new",
+            HnList::Ask => "Ask HN",
+            HnList::Show => "Show HN",
+        }
+    }
+
+    fn page(self) -> &'static str {
+        match self {
+            HnList::Front => "https://news.ycombinator.com/",
+            HnList::New => "https://news.ycombinator.com/newest",
+            HnList::Ask => "https://news.ycombinator.com/ask",
+            HnList::Show => "https://news.ycombinator.com/show",
+        }
+    }
+}
+
+impl FromStr for HnList {
+    type Err = String;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        match list {
+            "front" => Ok(HnList::Front),
+            "new" => Ok(HnList::New),
+            "ask" => Ok(HnList::Ask),
+            "show" => Ok(HnList::Show),
+            _ => Err(format!(
+                "Unknown Hacker News list {list}, expected front, new, ask or show"
+            )),
+        }
+    }
+}
+
+impl Display for HnList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let list = match self {
+            HnList::Front => "front",
+            HnList::New => "new",
+            HnList::Ask => "ask",
+            HnList::Show => "show",
+        };
+        write!(f, "{list}")
+    }
+}
+
+/// Item of the Firebase API, only stories are used
+#[derive(Deserialize, Debug)]
+struct Item {
+    id: u64,
+    #[serde(default)]
+    by: String,
+    #[serde(default)]
+    score: u64,
+    /// Number of comments
+    #[serde(default)]
+    descendants: u64,
+    /// Unix timestamp of the submission
+    time: i64,
+    #[serde(default)]
+    title: String,
+    /// Absent for text posts, e.g. Ask HN
+    url: Option<String>,
+    /// HTML text of text posts
+    text: Option<String>,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    dead: bool,
+}
+
+impl Item {
+    fn comments_url(&self) -> String {
+        format!("https://news.ycombinator.com/item?id={}", self.id)
+    }
+
+    fn info(&self) -> ArticleInfo {
+        ArticleInfo {
+            score: self.score,
+            num_comments: self.descendants,
+            created_utc: self.time as f64,
+            domain: self
+                .url
+                .as_deref()
+                .and_then(|url| Url::parse(url).ok())
+                .and_then(|url| {
+                    url.host_str()
+                        .map(|h| h.trim_start_matches("www.").to_string())
+                }),
+            url: self.url.clone(),
+            is_self: self.url.is_none(),
+            ..Default::default()
+        }
+    }
+
+    fn entry(&self) -> Entry {
+        let published = DateTime::from_timestamp(self.time, 0)
+            .unwrap_or_default()
+            .fixed_offset();
+        Entry {
+            id: format!("hn:{}", self.id),
+            title: self.title.clone().into(),
+            updated: published,
+            published: Some(published),
+            authors: vec![Person {
+                name: self.by.clone(),
+                uri: Some(format!("https://news.ycombinator.com/user?id={}", self.by)),
+                ..Default::default()
+            }],
+            links: vec![Link {
+                href: self.comments_url(),
+                ..Default::default()
+            }],
+            content: self.text.clone().map(|text| Content {
+                content_type: Some("html".to_string()),
+                value: Some(text),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Hacker News stories from the Firebase API, the API needs no credentials
+#[derive(Clone)]
+pub struct HackerNewsSource {
+    client: Client,
+}
+
+impl HackerNewsSource {
+    pub fn new(client: Client) -> HackerNewsSource {
+        HackerNewsSource { client }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> eyre::Result<T> {
+        let response = self
+            .client
+            .get(format!("{API_URL}/{path}.json"))
+            .send()
+            .await
+            .with_context(|| format!("cannot request {path}"))?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::from_response(&response))
+                .wrap_err(format!("cannot load {path}: {}", response.status()));
+        }
+        response
+            .json()
+            .await
+            .map_err(|_| UpstreamError::Parse)
+            .wrap_err_with(|| format!("cannot parse {path}"))
+    }
+}
+
+#[async_trait]
+impl FeedSource for HackerNewsSource {
+    /// Stories not fetched before the deadline are left out,
+    /// there is nothing to show for them without the item
+    async fn listing(&self, upstream: &Upstream, deadline: Instant) -> eyre::Result<ScoredListing> {
+        let Upstream::HackerNews(list) = upstream else {
+            bail!("{upstream} is not a Hacker News list");
+        };
+        info!("fetching story ids");
+        let ids: Vec<u64> = self.get(list.endpoint()).await?;
+        info!("fetching stories");
+        let items = join_all(ids.iter().take(STORIES_PER_LIST).map(|id| async move {
+            match timeout_at(deadline, self.get::<Option<Item>>(&format!("item/{id}"))).await {
+                Ok(Ok(item)) => item,
+                Ok(Err(e)) => {
+                    warn!("cannot load story {id}: {e:?}");
+                    None
+                }
+                Err(_) => None,
+            }
+        }))
+        .await;
+        let items = items
+            .into_iter()
+            .flatten()
+            .filter(|item| !item.deleted && !item.dead)
+            .collect::<Vec<_>>();
+        let feed = Feed {
+            id: format!("hn:{list}"),
+            title: list.title().into(),
+            updated: Utc::now().fixed_offset(),
+            links: vec![Link {
+                href: list.page().to_string(),
+                ..Default::default()
+            }],
+            entries: items.iter().map(Item::entry).collect(),
+            ..Default::default()
+        };
+        let scores = items.iter().map(|item| Some(item.info())).collect();
+        Ok(ScoredListing::new(feed, scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_test() {
+        let item: Item = serde_json::from_str(
+            r#"{"by": "dhouston", "descendants": 71, "id": 8863, "score": 111,
+            "time": 1175714200, "title": "My YC app: Dropbox", "type": "story",
+            "url": "http://www.getdropbox.com/u/2/screencast.html"}"#,
+        )
+        .unwrap();
+        let info = item.info();
+        assert_eq!(info.domain.as_deref(), Some("getdropbox.com"));
+        assert!(!info.is_self);
+        let entry = item.entry();
+        assert_eq!(entry.id, "hn:8863");
+        assert_eq!(
+            entry.links[0].href,
+            "https://news.ycombinator.com/item?id=8863"
+        );
+    }
+}
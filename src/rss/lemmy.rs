@@ -0,0 +1,234 @@
+use async_trait::async_trait;
+use atom_syndication::{Content, Entry, Feed, Link, Person};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use eyre::{bail, Context};
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::time::{timeout_at, Instant};
+use tracing::info;
+use url::Host;
+
+use crate::error::UpstreamError;
+use crate::http::PublicClient;
+use crate::reddit::client::ArticleInfo;
+use crate::rss::feed::Upstream;
+use crate::rss::source::{FeedSource, ScoredListing};
+
+/// Posts taken from the community, the maximum Lemmy returns at once
+const POSTS_PER_LISTING: &str = "50";
+
+/// Response of `/api/v3/post/list`
+#[derive(Deserialize, Debug)]
+struct PostList {
+    posts: Vec<PostView>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PostView {
+    post: Post,
+    creator: Creator,
+    counts: Counts,
+}
+
+#[derive(Deserialize, Debug)]
+struct Post {
+    id: u64,
+    name: String,
+    url: Option<String>,
+    /// Markdown text of the post
+    body: Option<String>,
+    /// e.g. `2024-03-29T10:00:00.123456Z`, older instances omit the offset
+    published: String,
+    /// Canonical URL of the post on its home instance
+    ap_id: String,
+    #[serde(default)]
+    nsfw: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Creator {
+    name: String,
+    actor_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Counts {
+    score: i64,
+    #[serde(default)]
+    comments: u64,
+}
+
+/// Validates the instance, so the service only talks to plain host names.
+/// IP addresses are rejected, the names are resolved to public addresses
+/// only by [LemmySource]
+pub fn instance_host(instance: &str) -> Result<String, String> {
+    let plain = instance.contains('.')
+        && instance
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    match Host::parse(instance) {
+        Ok(Host::Domain(host)) if plain => Ok(host),
+        _ => Err(format!("Invalid Lemmy instance {instance}")),
+    }
+}
+
+fn parse_published(published: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(published)
+        .map(|at| at.to_utc())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(published, "%Y-%m-%dT%H:%M:%S%.f").map(|at| at.and_utc())
+        })
+        .unwrap_or_default()
+}
+
+impl PostView {
+    fn info(&self) -> ArticleInfo {
+        ArticleInfo {
+            score: self.counts.score.max(0) as u64,
+            num_comments: self.counts.comments,
+            created_utc: parse_published(&self.post.published).timestamp() as f64,
+            domain: self
+                .post
+                .url
+                .as_deref()
+                .and_then(|url| Url::parse(url).ok())
+                .and_then(|url| {
+                    url.host_str()
+                        .map(|h| h.trim_start_matches("www.").to_string())
+                }),
+            over_18: self.post.nsfw,
+            url: self.post.url.clone(),
+            is_self: self.post.url.is_none(),
+            ..Default::default()
+        }
+    }
+
+    /// Links point to the post on the instance the feed was requested from,
+    /// so the reader can vote and comment with their account there
+    fn entry(&self, instance: &str) -> Entry {
+        let published = parse_published(&self.post.published).fixed_offset();
+        Entry {
+            id: format!("lemmy:{}", self.post.ap_id),
+            title: self.post.name.clone().into(),
+            updated: published,
+            published: Some(published),
+            authors: vec![Person {
+                name: self.creator.name.clone(),
+                uri: Some(self.creator.actor_id.clone()),
+                ..Default::default()
+            }],
+            links: vec![Link {
+                href: format!("https://{instance}/post/{}", self.post.id),
+                ..Default::default()
+            }],
+            content: self.post.body.clone().map(|body| Content {
+                content_type: Some("text".to_string()),
+                value: Some(body),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Lemmy communities from the instance's HTTP API, reading public posts needs no account.
+/// The instance comes from the reader, so only public hosts are requested, see [PublicClient]
+#[derive(Clone)]
+pub struct LemmySource {
+    client: PublicClient,
+}
+
+impl LemmySource {
+    pub fn new(client: PublicClient) -> LemmySource {
+        LemmySource { client }
+    }
+}
+
+#[async_trait]
+impl FeedSource for LemmySource {
+    /// Newest posts of the community, which may live on another instance (`community@instance`)
+    async fn listing(&self, upstream: &Upstream, deadline: Instant) -> eyre::Result<ScoredListing> {
+        let Upstream::Lemmy {
+            instance,
+            community,
+        } = upstream
+        else {
+            bail!("{upstream} is not a Lemmy community");
+        };
+        info!("fetching posts");
+        let url = Url::parse(&format!("https://{instance}/api/v3/post/list"))?;
+        let request = self
+            .client
+            .get(&url)?
+            .query(&[
+                ("community_name", community.as_str()),
+                ("sort", "New"),
+                ("limit", POSTS_PER_LISTING),
+            ])
+            .send();
+        let response = timeout_at(deadline, request)
+            .await
+            .map_err(|_| UpstreamError::Unavailable)
+            .wrap_err("Lemmy did not respond in time")?
+            .context("cannot request posts")?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::from_response(&response))
+                .wrap_err(format!("cannot load posts: {}", response.status()));
+        }
+        let list: PostList = response
+            .json()
+            .await
+            .map_err(|_| UpstreamError::Parse)
+            .wrap_err("cannot parse posts")?;
+        let feed = Feed {
+            id: format!("lemmy:{instance}/c/{community}"),
+            title: format!("!{community}").into(),
+            updated: Utc::now().fixed_offset(),
+            links: vec![Link {
+                href: format!("https://{instance}/c/{community}"),
+                ..Default::default()
+            }],
+            entries: list.posts.iter().map(|p| p.entry(instance)).collect(),
+            ..Default::default()
+        };
+        let scores = list.posts.iter().map(|p| Some(p.info())).collect();
+        Ok(ScoredListing::new(feed, scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_view_test() {
+        let list: PostList = serde_json::from_str(
+            r#"{"posts": [{
+                "post": {"id": 42, "name": "Rust 1.77 released", "url": "https://blog.rust-lang.org/",
+                    "published": "2024-03-21T12:00:00.123456", "ap_id": "https://lemmy.ml/post/42",
+                    "nsfw": false},
+                "creator": {"name": "ferris", "actor_id": "https://lemmy.ml/u/ferris"},
+                "community": {"name": "rust"},
+                "counts": {"score": 120, "comments": 4}
+            }]}"#,
+        )
+        .unwrap();
+        let post = &list.posts[0];
+        assert_eq!(post.info().score, 120);
+        assert_eq!(post.info().created_utc, 1711022400.0);
+        let entry = post.entry("programming.dev");
+        assert_eq!(entry.id, "lemmy:https://lemmy.ml/post/42");
+        assert_eq!(entry.links[0].href, "https://programming.dev/post/42");
+        assert_eq!(instance_host("Lemmy.ML").unwrap(), "lemmy.ml");
+        for invalid in [
+            "lemmy.ml/../x",
+            "127.0.0.1",
+            "10.0.0.5",
+            "lemmy",
+            "[::1]",
+            "",
+        ] {
+            assert!(instance_host(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::{Display, Formatter};
+
+use atom_syndication::{Entry, Link};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// Origins of Reddit links as they appear in upstream feeds
+const REDDIT_ORIGINS: [&str; 4] = [
+    "https://www.reddit.com",
+    "https://reddit.com",
+    "https://old.reddit.com",
+    "http://www.reddit.com",
+];
+
+/// Frontend Reddit links of the feed point to, `www`, `old` or `redlib:<host>`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LinkStyle {
+    #[default]
+    Www,
+    Old,
+    /// A self-hosted Redlib instance, e.g. `redlib.example.com`
+    Redlib(String),
+}
+
+impl TryFrom<String> for LinkStyle {
+    type Error = String;
+
+    fn try_from(style: String) -> Result<Self, Self::Error> {
+        match style.as_str() {
+            "www" => Ok(LinkStyle::Www),
+            "old" => Ok(LinkStyle::Old),
+            _ => {
+                let host = style
+                    .strip_prefix("redlib:")
+                    .ok_or_else(|| format!("unknown link style {style}"))?;
+                match Url::parse(&format!("https://{host}")) {
+                    Ok(url) if url.path() == "/" && url.host_str().is_some() => {
+                        Ok(LinkStyle::Redlib(host.to_string()))
+                    }
+                    _ => Err(format!("invalid Redlib host {host}")),
+                }
+            }
+        }
+    }
+}
+
+impl From<LinkStyle> for String {
+    fn from(style: LinkStyle) -> Self {
+        style.to_string()
+    }
+}
+
+impl Display for LinkStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkStyle::Www => write!(f, "www"),
+            LinkStyle::Old => write!(f, "old"),
+            LinkStyle::Redlib(host) => write!(f, "redlib:{host}"),
+        }
+    }
+}
+
+/// What the main link of an entry points to
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkTarget {
+    /// The comments page of the post
+    #[default]
+    Comments,
+    /// The submitted URL of link posts, with the comments page as a secondary link
+    External,
+}
+
+impl LinkTarget {
+    /// Makes `url` the main link of the entry, the previous links are kept
+    /// as replies links
+    pub fn retarget_entry(&self, entry: &mut Entry, url: &str) {
+        if *self == LinkTarget::Comments {
+            return;
+        }
+        for link in entry.links.iter_mut() {
+            link.rel = "replies".to_string();
+            link.mime_type = Some("text/html".to_string());
+        }
+        entry.links.insert(
+            0,
+            Link {
+                href: url.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+impl LinkStyle {
+    fn origin(&self) -> String {
+        match self {
+            LinkStyle::Www => "https://www.reddit.com".to_string(),
+            LinkStyle::Old => "https://old.reddit.com".to_string(),
+            LinkStyle::Redlib(host) => format!("https://{host}"),
+        }
+    }
+
+    /// Points a Reddit link to the chosen frontend, other links are left as is
+    pub fn rewrite(&self, url: &str) -> String {
+        for origin in REDDIT_ORIGINS {
+            if let Some(path) = url.strip_prefix(origin) {
+                if path.is_empty() || path.starts_with('/') {
+                    return format!("{}{path}", self.origin());
+                }
+            }
+        }
+        url.to_string()
+    }
+
+    /// Rewrites the links of the entry and the Reddit links inside its content
+    pub fn rewrite_entry(&self, entry: &mut Entry) {
+        if *self == LinkStyle::Www {
+            return;
+        }
+        for link in entry.links.iter_mut() {
+            link.href = self.rewrite(&link.href);
+        }
+        if let Some(value) = entry.content.as_mut().and_then(|c| c.value.as_mut()) {
+            let origin = self.origin();
+            for reddit in REDDIT_ORIGINS {
+                *value = value.replace(&format!("\"{reddit}/"), &format!("\"{origin}/"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_test() {
+        let redlib = LinkStyle::try_from("redlib:redlib.example.com".to_string()).unwrap();
+        assert_eq!(
+            redlib.rewrite("https://www.reddit.com/r/rust/comments/1/"),
+            "https://redlib.example.com/r/rust/comments/1/"
+        );
+        assert_eq!(
+            LinkStyle::Old.rewrite("https://reddit.com/r/rust"),
+            "https://old.reddit.com/r/rust"
+        );
+        assert_eq!(
+            LinkStyle::Old.rewrite("https://www.reddit.community/"),
+            "https://www.reddit.community/"
+        );
+        assert!(LinkStyle::try_from("redlib:a/b".to_string()).is_err());
+        assert!(LinkStyle::try_from("new".to_string()).is_err());
+    }
+}
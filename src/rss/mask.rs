@@ -0,0 +1,72 @@
+use atom_syndication::Entry;
+
+use crate::rss::digest::escape;
+
+/// Hides the content of an NSFW or spoiler entry behind a collapsed `<details>` element,
+/// with images replaced by links, as some readers show `<details>` expanded
+pub fn mask_entry(entry: &mut Entry, label: &str) {
+    let Some(html) = entry.content.as_mut().and_then(|c| c.value.as_mut()) else {
+        return;
+    };
+    *html = format!(
+        "<details><summary>{} - click to show</summary>{}</details>",
+        escape(label),
+        images_to_links(html)
+    );
+}
+
+/// Replaces `<img>` tags with links to their `src`
+fn images_to_links(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = find_tag(rest, "<img") {
+        result.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            // unterminated tag, dropped
+            return result;
+        };
+        let tag = &rest[start..start + end + 1];
+        if let Some(src) = attribute(tag, "src") {
+            result.push_str(&format!(r#"<a href="{src}">[image]</a>"#));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// `src` of the first `<img>` tag, still escaped
+pub fn first_image(html: &str) -> Option<&str> {
+    let start = find_tag(html, "<img")?;
+    let end = html[start..].find('>')?;
+    attribute(&html[start..start + end + 1], "src")
+}
+
+/// Position of the opening tag, case-insensitive
+fn find_tag(html: &str, tag: &str) -> Option<usize> {
+    html.to_ascii_lowercase().find(tag)
+}
+
+/// Value of a double-quoted attribute, still escaped
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let prefix = format!(" {name}=\"");
+    let start = tag.to_ascii_lowercase().find(&prefix)? + prefix.len();
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_to_links_test() {
+        assert_eq!(
+            images_to_links(
+                r#"<p>look</p><IMG alt="x" src="https://i.redd.it/a.jpg?w=1&amp;s=2" /> ok"#
+            ),
+            r#"<p>look</p><a href="https://i.redd.it/a.jpg?w=1&amp;s=2">[image]</a> ok"#
+        );
+        assert_eq!(images_to_links("no images"), "no images");
+    }
+}
@@ -1 +1,24 @@
+pub mod account;
+pub mod api;
+pub mod authors;
+pub mod comments;
+pub mod crosspost;
+pub mod digest;
 pub mod feed;
+pub mod filter;
+pub mod format;
+pub mod fulltext;
+pub mod hacker_news;
+pub mod lemmy;
+pub mod links;
+pub mod mask;
+pub mod moderation;
+pub mod opml;
+pub mod poll;
+pub mod preview;
+pub mod sanitize;
+pub mod search;
+pub mod source;
+pub mod sparkline;
+pub mod stream;
+pub mod template;
@@ -0,0 +1,82 @@
+use atom_syndication::{Content, Entry, Feed, Link, Person};
+use chrono::{DateTime, Utc};
+
+use crate::reddit::listing::{ModAction, Thing};
+use crate::rss::account::thing_entries;
+use crate::rss::comments::reddit_url;
+use crate::rss::feed::entry_id;
+
+/// Feed of the posts and comments waiting for moderator review
+pub fn render_modqueue(subreddit: &str, things: Vec<Thing>) -> Feed {
+    Feed {
+        id: format!("modqueue:{subreddit}"),
+        title: format!("Modqueue of {subreddit}").into(),
+        updated: Utc::now().fixed_offset(),
+        links: vec![Link {
+            href: format!("https://www.reddit.com/{subreddit}/about/modqueue/"),
+            ..Default::default()
+        }],
+        entries: thing_entries(things),
+        ..Default::default()
+    }
+}
+
+/// Feed of the recent moderator actions, one entry per action
+pub fn render_modlog(subreddit: &str, actions: Vec<ModAction>) -> Feed {
+    Feed {
+        id: format!("modlog:{subreddit}"),
+        title: format!("Modlog of {subreddit}").into(),
+        updated: Utc::now().fixed_offset(),
+        links: vec![Link {
+            href: format!("https://www.reddit.com/{subreddit}/about/log/"),
+            ..Default::default()
+        }],
+        entries: actions.into_iter().map(action_entry).collect(),
+        ..Default::default()
+    }
+}
+
+fn action_entry(action: ModAction) -> Entry {
+    let created = DateTime::from_timestamp(action.created_utc as i64, 0)
+        .unwrap_or_default()
+        .fixed_offset();
+    let mut title = format!("{} {}", action.moderator, action.action);
+    if let Some(target) = action
+        .target_title
+        .as_ref()
+        .or(action.target_author.as_ref())
+    {
+        title.push_str(&format!(": {target}"));
+    }
+    let details = [&action.details, &action.description]
+        .into_iter()
+        .flatten()
+        .filter(|s| !s.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" — ");
+    Entry {
+        id: entry_id(&action.id),
+        title: title.into(),
+        updated: created,
+        published: Some(created),
+        authors: vec![Person {
+            name: action.moderator,
+            ..Default::default()
+        }],
+        links: action
+            .target_permalink
+            .iter()
+            .map(|permalink| Link {
+                href: reddit_url(permalink),
+                ..Default::default()
+            })
+            .collect(),
+        content: (!details.is_empty()).then(|| Content {
+            content_type: Some("text".to_string()),
+            value: Some(details),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
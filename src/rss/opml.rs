@@ -0,0 +1,58 @@
+/// A feed to be listed in an OPML document
+pub struct OpmlFeed {
+    pub title: String,
+    pub url: String,
+}
+
+/// Renders an OPML 2.0 document, so readers can import all feeds in one step
+pub fn render_opml(title: &str, feeds: &[OpmlFeed]) -> String {
+    let outlines = feeds
+        .iter()
+        .map(|feed| {
+            format!(
+                r#"    <outline type="rss" text="{title}" title="{title}" xmlUrl="{url}"/>"#,
+                title = escape(&feed.title),
+                url = escape(&feed.url),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head>
+    <title>{}</title>
+  </head>
+  <body>
+{outlines}
+  </body>
+</opml>
+"#,
+        escape(title)
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_opml_test() {
+        let opml = render_opml(
+            "feeds",
+            &[OpmlFeed {
+                title: "r/rust".to_string(),
+                url: "https://example.com/feed/rust?min_score=1&token=a".to_string(),
+            }],
+        );
+        insta::assert_snapshot!(opml);
+    }
+}
@@ -0,0 +1,66 @@
+use atom_syndication::{Content, Entry};
+use chrono::DateTime;
+
+use crate::reddit::listing::PollData;
+use crate::rss::digest::escape;
+
+/// Appends the options of the poll with their votes, as far as Reddit shows them,
+/// to the content of the entry
+pub fn render_poll(entry: &mut Entry, poll: &PollData) {
+    let options = poll
+        .options
+        .iter()
+        .map(|option| match option.vote_count {
+            Some(votes) => format!("<li>{} ({votes} votes)</li>", escape(&option.text)),
+            None => format!("<li>{}</li>", escape(&option.text)),
+        })
+        .collect::<String>();
+    let ends = poll
+        .voting_end_timestamp
+        .and_then(DateTime::from_timestamp_millis)
+        .map(|end| format!(", voting ends {}", end.format("%Y-%m-%d %H:%M UTC")))
+        .unwrap_or_default();
+    let html = format!(
+        "<ul>{options}</ul><p>{} votes{ends}</p>",
+        poll.total_vote_count
+    );
+    let content = entry.content.get_or_insert_with(|| Content {
+        content_type: Some("html".to_string()),
+        ..Default::default()
+    });
+    content
+        .value
+        .get_or_insert_with(String::new)
+        .push_str(&html);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reddit::listing::PollOption;
+
+    #[test]
+    fn render_poll_test() {
+        let mut entry = Entry::default();
+        let poll = PollData {
+            options: vec![
+                PollOption {
+                    text: "Rust".to_string(),
+                    vote_count: Some(30),
+                },
+                PollOption {
+                    text: "C & C++".to_string(),
+                    vote_count: Some(12),
+                },
+            ],
+            total_vote_count: 42,
+            voting_end_timestamp: Some(1_711_929_600_000),
+        };
+        render_poll(&mut entry, &poll);
+        assert_eq!(
+            entry.content.unwrap().value.unwrap(),
+            "<ul><li>Rust (30 votes)</li><li>C &amp; C++ (12 votes)</li></ul>\
+             <p>42 votes, voting ends 2024-04-01 00:00 UTC</p>"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use atom_syndication::{Content, Entry};
+use eyre::{bail, Context};
+use futures::future::join_all;
+use reqwest::{header, Url};
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::time::{timeout_at, Instant};
+use tracing::{info, warn};
+
+use crate::cache::{CacheConfig, CacheSnapshot, CacheStats};
+use crate::http::PublicClient;
+use crate::rss::digest::{escape, unescape};
+
+/// Pages fetched at the same time, across all feeds
+const CONCURRENT_FETCHES: usize = 4;
+
+/// Only the start of a page is read, Open Graph tags are in the head
+const MAX_PAGE_BYTES: usize = 256 * 1024;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Product token matched against `User-agent` lines of robots.txt
+const ROBOTS_AGENT: &str = "reddit-rss";
+
+/// Open Graph metadata of a linked page
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Preview {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute URL of the image
+    pub image: Option<String>,
+}
+
+impl Preview {
+    fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none() && self.image.is_none()
+    }
+
+    /// Appends the preview as a quote to the content of the entry
+    pub fn render_into(&self, entry: &mut Entry) {
+        let mut html = String::from("<blockquote>");
+        if let Some(image) = &self.image {
+            html.push_str(&format!(r#"<img src="{}" />"#, escape(image)));
+        }
+        if let Some(title) = &self.title {
+            html.push_str(&format!("<p><strong>{}</strong></p>", escape(title)));
+        }
+        if let Some(description) = &self.description {
+            html.push_str(&format!("<p>{}</p>", escape(description)));
+        }
+        html.push_str("</blockquote>");
+        let content = entry.content.get_or_insert_with(|| Content {
+            content_type: Some("html".to_string()),
+            ..Default::default()
+        });
+        content
+            .value
+            .get_or_insert_with(String::new)
+            .push_str(&html);
+    }
+}
+
+/// Open Graph previews of linked pages, fetched politely: a few at a time,
+/// only where robots.txt allows, and cached along with the robots.txt files.
+/// Pages that cannot be previewed are cached as `None`. Only public hosts
+/// are fetched, see [PublicClient].
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Previews {
+    client: PublicClient,
+    permits: Arc<Semaphore>,
+    cache: Arc<moka::future::Cache<String, Option<Preview>>>,
+    stats: Arc<CacheStats>,
+    /// Rules of robots.txt by origin, `None` if there are none for us
+    robots: Arc<moka::future::Cache<String, Option<Arc<Robots>>>>,
+}
+
+impl Previews {
+    pub fn new(client: PublicClient, cache: &CacheConfig) -> Previews {
+        let stats = Arc::new(CacheStats::default());
+        Previews {
+            client,
+            permits: Arc::new(Semaphore::new(CONCURRENT_FETCHES)),
+            cache: Arc::new(
+                moka::future::CacheBuilder::new(cache.preview_capacity)
+                    .time_to_live(cache.preview_ttl)
+                    .eviction_listener(stats.listener())
+                    .build(),
+            ),
+            stats,
+            robots: Arc::new(
+                moka::future::CacheBuilder::new(cache.preview_capacity)
+                    .time_to_live(cache.preview_ttl)
+                    .build(),
+            ),
+        }
+    }
+
+    /// Previews of the URLs resolved before the deadline, by URL
+    pub async fn lookup<'a>(
+        &self,
+        urls: impl IntoIterator<Item = &'a str>,
+        deadline: Instant,
+    ) -> HashMap<String, Preview> {
+        let lookups = urls.into_iter().map(|url| async move {
+            let preview = timeout_at(deadline, self.get(url)).await.ok().flatten()?;
+            Some((url.to_string(), preview))
+        });
+        join_all(lookups).await.into_iter().flatten().collect()
+    }
+
+    async fn get(&self, url: &str) -> Option<Preview> {
+        self.stats.lookup();
+        self.cache
+            .get_with(url.to_string(), async {
+                self.stats.miss();
+                self.fetch(url)
+                    .await
+                    .inspect_err(|e| info!("no preview of {url}: {e:?}"))
+                    .ok()
+                    .filter(|preview| !preview.is_empty())
+            })
+            .await
+    }
+
+    async fn fetch(&self, url: &str) -> eyre::Result<Preview> {
+        let url = Url::parse(url).context("invalid URL")?;
+        let request = self.client.get(&url)?;
+        if !self.allowed(&url).await {
+            bail!("disallowed by robots.txt");
+        }
+        let _permit = self.permits.acquire().await?;
+        let response = request
+            .header(header::ACCEPT, "text/html")
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/html"));
+        if !is_html {
+            bail!("not an HTML page");
+        }
+        let html = read_prefix(response, MAX_PAGE_BYTES).await?;
+        Ok(parse_preview(&html, &url))
+    }
+
+    async fn allowed(&self, url: &Url) -> bool {
+        let origin = url.origin().ascii_serialization();
+        let robots = self
+            .robots
+            .get_with(origin.clone(), async {
+                let robots_url = format!("{origin}/robots.txt");
+                match self.fetch_robots(&robots_url).await {
+                    Ok(robots) => robots.map(Arc::new),
+                    Err(e) => {
+                        warn!("cannot fetch {robots_url}: {e:?}");
+                        Some(Arc::new(Robots::disallow_all()))
+                    }
+                }
+            })
+            .await;
+        robots.is_none_or(|robots| robots.allows(url.path()))
+    }
+
+    /// Missing robots.txt allows everything, an unreachable one nothing
+    async fn fetch_robots(&self, url: &str) -> eyre::Result<Option<Robots>> {
+        let response = self
+            .client
+            .get(&Url::parse(url)?)?
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?;
+        if response.status().is_client_error() {
+            return Ok(None);
+        }
+        let text = read_prefix(response.error_for_status()?, MAX_PAGE_BYTES).await?;
+        Ok(Robots::parse(&text, ROBOTS_AGENT))
+    }
+
+    pub fn cache_stats(&self) -> CacheSnapshot {
+        self.stats.snapshot(&self.cache)
+    }
+
+    pub fn purge(&self) {
+        self.cache.invalidate_all();
+    }
+}
+
+/// Reads the body up to `limit` bytes, the rest is not downloaded
+pub async fn read_prefix(mut response: reqwest::Response, limit: usize) -> eyre::Result<String> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= limit {
+            body.truncate(limit);
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// `Allow` and `Disallow` rules of robots.txt for one agent
+#[derive(Debug, Default, PartialEq)]
+struct Robots {
+    /// Path prefixes and whether they are allowed
+    rules: Vec<(String, bool)>,
+}
+
+impl Robots {
+    /// Rules of a site whose robots.txt cannot be read, e.g. answering 5xx
+    fn disallow_all() -> Robots {
+        Robots {
+            rules: vec![(String::from("/"), false)],
+        }
+    }
+
+    /// Rules of the group naming `agent`, or of the `*` group if none does.
+    /// `None` if neither exists
+    fn parse(text: &str, agent: &str) -> Option<Robots> {
+        let mut groups: Vec<(Vec<String>, Robots)> = vec![];
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if !in_agents {
+                        groups.push((vec![], Robots::default()));
+                    }
+                    in_agents = true;
+                    if let Some((agents, _)) = groups.last_mut() {
+                        agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                rule @ ("allow" | "disallow") => {
+                    in_agents = false;
+                    if let Some((_, robots)) = groups.last_mut() {
+                        // an empty `Disallow` allows everything
+                        if !value.is_empty() {
+                            robots.rules.push((value.to_string(), rule == "allow"));
+                        }
+                    }
+                }
+                _ => in_agents = false,
+            }
+        }
+        let agent = agent.to_ascii_lowercase();
+        let position = groups
+            .iter()
+            .position(|(agents, _)| agents.iter().any(|a| agent.starts_with(a.as_str())))
+            .or_else(|| {
+                groups
+                    .iter()
+                    .position(|(agents, _)| agents.contains(&"*".to_string()))
+            })?;
+        Some(groups.swap_remove(position).1)
+    }
+
+    /// The longest matching rule wins, `Allow` on ties
+    fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.trim_end_matches('*')))
+            .max_by_key(|(prefix, allow)| (prefix.len(), *allow))
+            .is_none_or(|(_, allow)| *allow)
+    }
+}
+
+/// Open Graph tags of the page, falling back to `<title>` and the `description` meta tag
+fn parse_preview(html: &str, url: &Url) -> Preview {
+    let head = match html.to_ascii_lowercase().find("</head>") {
+        Some(end) => &html[..end],
+        None => html,
+    };
+    let mut meta: HashMap<String, String> = HashMap::new();
+    let lower = head.to_ascii_lowercase();
+    let mut position = 0;
+    while let Some(start) = lower[position..].find("<meta") {
+        let start = position + start;
+        let Some(end) = head[start..].find('>') else {
+            break;
+        };
+        let attributes = attributes(&head[start + "<meta".len()..start + end]);
+        let key = attributes
+            .get("property")
+            .or_else(|| attributes.get("name"))
+            .map(|key| key.to_ascii_lowercase());
+        if let (Some(key), Some(content)) = (key, attributes.get("content")) {
+            meta.entry(key)
+                .or_insert_with(|| unescape(content).trim().to_string());
+        }
+        position = start + end;
+    }
+    let title = lower.find("<title").and_then(|start| {
+        let start = start + head[start..].find('>')? + 1;
+        let end = start + lower[start..].find("</title")?;
+        Some(unescape(&head[start..end]).trim().to_string())
+    });
+    let mut take = |keys: &[&str]| keys.iter().find_map(|key| meta.remove(*key));
+    Preview {
+        title: take(&["og:title", "twitter:title"]).or(title),
+        description: take(&["og:description", "twitter:description", "description"]),
+        image: take(&["og:image", "og:image:url", "twitter:image"])
+            .and_then(|image| url.join(&image).ok())
+            .map(|image| image.to_string()),
+    }
+    .non_empty_fields()
+}
+
+impl Preview {
+    fn non_empty_fields(mut self) -> Preview {
+        for field in [&mut self.title, &mut self.description, &mut self.image] {
+            if field.as_deref().is_some_and(str::is_empty) {
+                *field = None;
+            }
+        }
+        self
+    }
+}
+
+/// Attributes of a tag, names lowercased, values still escaped
+fn attributes(tag: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = tag.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_ascii_lowercase();
+        let value = rest[eq + 1..].trim_start();
+        let (value, remaining) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => match value[1..].find(quote) {
+                Some(end) => (&value[1..end + 1], &value[end + 2..]),
+                None => (&value[1..], ""),
+            },
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_whitespace() || c == '/')
+                    .unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        // boolean attributes before this one end up in its name
+        let name = name.rsplit(' ').next().unwrap_or_default();
+        attributes.insert(name.to_string(), value.to_string());
+        rest = remaining.trim_start();
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_preview_test() {
+        let html = r#"<html><head><title>Fallback</title>
+            <meta property="og:title" content="Rust 1.77 &amp; more">
+            <meta name='description' content='What is new' />
+            <meta property=og:image content="/img/cover.png">
+            </head><body><meta property="og:title" content="ignored"></body></html>"#;
+        let url = Url::parse("https://blog.example.com/posts/1").unwrap();
+        assert_eq!(
+            parse_preview(html, &url),
+            Preview {
+                title: Some("Rust 1.77 & more".to_string()),
+                description: Some("What is new".to_string()),
+                image: Some("https://blog.example.com/img/cover.png".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn robots_test() {
+        let text = "User-agent: *\nDisallow: /\n\n\
+                    User-agent: Reddit-RSS\nUser-agent: other\n\
+                    Disallow: /private\nAllow: /private/shared # comment\n";
+        let robots = Robots::parse(text, ROBOTS_AGENT).unwrap();
+        assert!(robots.allows("/posts/1"));
+        assert!(!robots.allows("/private/notes"));
+        assert!(robots.allows("/private/shared/1"));
+        let others = Robots::parse(text, "someone-else").unwrap();
+        assert!(!others.allows("/posts/1"));
+        assert_eq!(Robots::parse("Sitemap: /sitemap.xml", ROBOTS_AGENT), None);
+    }
+}
@@ -0,0 +1,148 @@
+use atom_syndication::Entry;
+use dom_query::Document;
+use reqwest::Url;
+
+/// Elements dropped with their content by [sanitize_html]: they run code,
+/// embed other documents, submit data or restyle the reader
+const REMOVED_ELEMENTS: &str = "script, style, link, meta, base, iframe, frame, frameset, \
+    object, embed, applet, form, input, button, select, textarea, svg, math, noscript, template";
+
+/// Attributes kept by [sanitize_html], the others, e.g. `on*` handlers and `style`, are dropped
+const ALLOWED_ATTRIBUTES: [&str; 9] = [
+    "href", "src", "alt", "title", "width", "height", "colspan", "rowspan", "datetime",
+];
+
+/// Query parameters only used to track the reader, `utm_*` are matched by prefix
+const TRACKING_PARAMS: [&str; 12] = [
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid", "igshid", "mc_cid",
+    "mc_eid", "_hsenc", "_hsmi",
+];
+
+fn is_tracking(param: &str) -> bool {
+    param.starts_with("utm_") || TRACKING_PARAMS.contains(&param)
+}
+
+/// Removes tracking parameters from the URL, URLs without them are returned unchanged
+pub fn strip_tracking(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    if !parsed.query_pairs().any(|(k, _)| is_tracking(&k)) {
+        return url.to_string();
+    }
+    let kept = parsed
+        .query_pairs()
+        .filter(|(k, _)| !is_tracking(k))
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect::<Vec<_>>();
+    if kept.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(kept);
+    }
+    parsed.to_string()
+}
+
+/// Strips tracking parameters from the `href` attributes of the HTML
+pub fn strip_tracking_html(html: &str) -> String {
+    const HREF: &str = "href=\"";
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(HREF) {
+        let (before, after) = rest.split_at(start + HREF.len());
+        result.push_str(before);
+        let Some(end) = after.find('"') else {
+            rest = after;
+            break;
+        };
+        let href = after[..end].replace("&amp;", "&");
+        result.push_str(&strip_tracking(&href).replace('&', "&amp;"));
+        rest = &after[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Third-party HTML made safe to embed in an entry: no scripts, embedded documents, forms,
+/// event handlers or styles, and links to `http`, `https`, `mailto` and relative URLs only
+pub fn sanitize_html(html: &str) -> String {
+    let document = Document::fragment(html);
+    document.select(REMOVED_ELEMENTS).remove();
+    for node in document.select("*").nodes() {
+        node.retain_attrs(&ALLOWED_ATTRIBUTES);
+        for name in ["href", "src"] {
+            if node.attr(name).is_some_and(|url| !is_safe_url(&url)) {
+                node.remove_attr(name);
+            }
+        }
+    }
+    // the fragment is wrapped into an `<html>` element
+    document.html_root().inner_html().to_string()
+}
+
+/// Browsers ignore whitespace and control characters in the scheme, e.g. `java\tscript:`
+fn is_safe_url(url: &str) -> bool {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>();
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            matches!(
+                scheme.to_ascii_lowercase().as_str(),
+                "http" | "https" | "mailto"
+            )
+        }
+        _ => true,
+    }
+}
+
+/// Strips tracking parameters from the links and the content of the entry
+pub fn sanitize_entry(entry: &mut Entry) {
+    for link in entry.links.iter_mut() {
+        link.href = strip_tracking(&link.href);
+    }
+    if let Some(value) = entry.content.as_mut().and_then(|c| c.value.as_mut()) {
+        *value = strip_tracking_html(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_tracking_test() {
+        assert_eq!(
+            strip_tracking("https://example.com/a?utm_source=reddit&id=1&fbclid=x"),
+            "https://example.com/a?id=1"
+        );
+        assert_eq!(
+            strip_tracking("https://example.com/a?utm_medium=rss"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            strip_tracking("https://example.com/a?q=a%20b"),
+            "https://example.com/a?q=a%20b"
+        );
+        assert_eq!(
+            strip_tracking_html(r#"<a href="https://example.com/?a=1&amp;utm_source=x">link</a>"#),
+            r#"<a href="https://example.com/?a=1">link</a>"#
+        );
+    }
+
+    #[test]
+    fn sanitize_html_test() {
+        let html = r#"<div onclick="steal()" class="x"><p style="color:red">Text</p>
+            <script>alert(1)</script><iframe src="https://evil.example"></iframe>
+            <a href="java&#9;script:alert(1)">bad</a><a href="/relative" target="_blank">ok</a>
+            <img src="https://example.com/a.png" onerror="steal()" alt="A"></div>"#;
+        let sanitized = sanitize_html(html);
+        assert!(sanitized.starts_with("<div><p>Text</p>"));
+        assert!(sanitized.contains(r#"<a href="/relative">ok</a>"#));
+        assert!(sanitized.contains(r#"<img src="https://example.com/a.png" alt="A">"#));
+        for unsafe_part in ["script", "iframe", "onclick", "onerror", "style", "class"] {
+            assert!(!sanitized.contains(unsafe_part), "{sanitized}");
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use atom_syndication::{Content, Entry, Feed, Link};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+
+use crate::archive::{ArchiveQuery, ArchivedPost};
+use crate::rss::digest::escape;
+use crate::rss::feed::entry_id;
+
+/// Feed of the archived posts found by the query, one entry per post
+pub fn render_search(
+    query: &ArchiveQuery,
+    results: Vec<(String, ArchivedPost)>,
+    now: DateTime<Utc>,
+) -> Feed {
+    let entries = results
+        .into_iter()
+        .map(|(id, post)| {
+            let published = DateTime::from_timestamp(post.published, 0)
+                .unwrap_or(now)
+                .fixed_offset();
+            Entry {
+                id: entry_id(&id),
+                title: post.title.into(),
+                links: vec![Link {
+                    href: post.link,
+                    ..Default::default()
+                }],
+                published: Some(published),
+                updated: published,
+                content: Some(Content {
+                    content_type: Some("html".to_string()),
+                    value: Some(format!(
+                        "<p>{}</p><p>{} points</p>",
+                        escape(&post.excerpt),
+                        post.score
+                    )),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        })
+        .collect_vec();
+    let scope = match &query.subreddit {
+        Some(subreddit) => format!(" in r/{subreddit}"),
+        None => String::new(),
+    };
+    Feed {
+        id: format!("archive-search:{}{scope}", query.q),
+        title: format!("Archived posts matching \"{}\"{scope}", query.q).into(),
+        updated: now.fixed_offset(),
+        entries,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,504 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use atom_syndication::{Entry, Feed, Link};
+use axum::body::Bytes;
+use chrono::Utc;
+use eyre::{bail, eyre, Context};
+use futures::future::join_all;
+use itertools::Itertools;
+use reqwest::{header, Client};
+use tokio::time::{timeout_at, Instant};
+use tracing::field::{self, Empty};
+use tracing::{debug, debug_span, info, warn, Instrument};
+
+use crate::cache::{CacheConfig, CacheReport, CacheStats};
+use crate::error::UpstreamError;
+use crate::metrics::{Histogram, MetricsReport};
+use crate::reddit::client::{ArticleInfo, RedditClient};
+use crate::rss::feed::{listing_entry, normalize_entry, post_fullname, Upstream};
+use crate::rss::hacker_news::HackerNewsSource;
+use crate::rss::lemmy::LemmySource;
+
+/// Reddit's bot-block page, served instead of the feed to requests it takes for a bot,
+/// with either `403` or `200` status
+fn is_block_page(body: &str) -> bool {
+    let body = body.to_lowercase();
+    body.contains("blocked by network security") || body.contains("whoa there, pardner")
+}
+
+/// Upstream feed with the info of every entry, in the same order,
+/// info that could not be fetched in time is missing
+pub struct ScoredListing {
+    pub feed: Feed,
+    pub scores: Vec<Option<ArticleInfo>>,
+    /// Atom document the feed was read from, for the verbatim feeds,
+    /// see [crate::rss::feed::FeedOptions::verbatim]. None for listings built otherwise
+    pub document: Option<Bytes>,
+}
+
+impl ScoredListing {
+    /// Listing built from something else than an Atom document
+    pub fn new(feed: Feed, scores: Vec<Option<ArticleInfo>>) -> ScoredListing {
+        ScoredListing {
+            feed,
+            scores,
+            document: None,
+        }
+    }
+}
+
+/// Backend the filtered feeds are built from: a listing of posts with their scores
+#[async_trait]
+pub trait FeedSource: Send + Sync {
+    /// Listing of the upstream, info not fetched before the deadline is left out
+    async fn listing(&self, upstream: &Upstream, deadline: Instant) -> eyre::Result<ScoredListing>;
+
+    /// Statistics of the caches of the source, by name
+    fn cache_stats(&self) -> CacheReport {
+        CacheReport::new()
+    }
+
+    /// Drops the cached entries of the source, e.g. to see changes on Reddit right away
+    fn purge_caches(&self) {}
+
+    /// Timings of the source by name
+    fn metrics(&self) -> MetricsReport {
+        MetricsReport::new()
+    }
+}
+
+/// Removed posts do not come back, but are forgotten sooner to make room
+const REMOVED_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Failed fetches are retried after this, so every poll does not hit a broken post again
+const FAILED_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Outcome of fetching the info of a post, negative outcomes are cached too
+#[derive(Clone, Debug)]
+pub enum ScoreEntry {
+    Found(Box<ArticleInfo>),
+    /// The post is gone, left out of the feeds. Posts removed but still listed
+    /// are [ScoreEntry::Found], see [crate::rss::feed::FeedOptions::hide_removed]
+    Removed,
+    /// The info cannot be fetched, the post is treated as if its info was missing
+    Failed,
+}
+
+/// Found info lives for the configured TTL, negative outcomes at most as long
+struct ScoreExpiry {
+    ttl: Duration,
+}
+
+impl moka::Expiry<String, ScoreEntry> for ScoreExpiry {
+    fn expire_after_create(
+        &self,
+        _: &String,
+        entry: &ScoreEntry,
+        _: std::time::Instant,
+    ) -> Option<Duration> {
+        Some(match entry {
+            ScoreEntry::Found(info) if info.is_removed() => REMOVED_TTL.min(self.ttl),
+            ScoreEntry::Found(_) => self.ttl,
+            ScoreEntry::Removed => REMOVED_TTL.min(self.ttl),
+            ScoreEntry::Failed => FAILED_TTL.min(self.ttl),
+        })
+    }
+}
+
+/// How the score fetch of an entry ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScoreOutcome {
+    Found,
+    Removed,
+    Failed,
+    /// The entry has no link to look the score up by
+    NoLink,
+    RateLimited,
+    /// The feed's deadline was reached first
+    Deadline,
+}
+
+impl ScoreOutcome {
+    const ALL: [ScoreOutcome; 6] = [
+        ScoreOutcome::Found,
+        ScoreOutcome::Removed,
+        ScoreOutcome::Failed,
+        ScoreOutcome::NoLink,
+        ScoreOutcome::RateLimited,
+        ScoreOutcome::Deadline,
+    ];
+}
+
+/// Durations of the score fetches, the most expensive phase of a feed generation,
+/// by whether they were cached and by outcome
+#[derive(Default, Debug)]
+struct ScoreMetrics {
+    cached: Histogram,
+    fetched: Histogram,
+    outcomes: [Histogram; ScoreOutcome::ALL.len()],
+}
+
+impl ScoreMetrics {
+    fn record(&self, fetched: bool, outcome: ScoreOutcome, elapsed: Duration) {
+        let cache = if fetched { &self.fetched } else { &self.cached };
+        cache.record(elapsed);
+        self.outcomes[outcome as usize].record(elapsed);
+    }
+
+    fn report(&self) -> MetricsReport {
+        let mut report = MetricsReport::from([
+            ("score_cached", self.cached.snapshot()),
+            ("score_fetched", self.fetched.snapshot()),
+        ]);
+        for outcome in ScoreOutcome::ALL {
+            let name = match outcome {
+                ScoreOutcome::Found => "score_found",
+                ScoreOutcome::Removed => "score_removed",
+                ScoreOutcome::Failed => "score_failed",
+                ScoreOutcome::NoLink => "score_no_link",
+                ScoreOutcome::RateLimited => "score_rate_limited",
+                ScoreOutcome::Deadline => "score_deadline",
+            };
+            report.insert(name, self.outcomes[outcome as usize].snapshot());
+        }
+        report
+    }
+}
+
+/// Reddit's public feeds, with the scores fetched from the API.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct RedditSource {
+    client: Client,
+    reddit_client: RedditClient,
+    score_cache: Arc<moka::future::Cache<String, ScoreEntry>>,
+    score_stats: Arc<CacheStats>,
+    score_metrics: Arc<ScoreMetrics>,
+}
+
+impl RedditSource {
+    pub fn new(client: Client, reddit_client: RedditClient, cache: &CacheConfig) -> RedditSource {
+        let score_stats = Arc::new(CacheStats::default());
+        RedditSource {
+            client,
+            reddit_client,
+            score_cache: Arc::new(
+                moka::future::CacheBuilder::new(cache.score_capacity)
+                    .expire_after(ScoreExpiry {
+                        ttl: cache.score_ttl,
+                    })
+                    .eviction_listener(score_stats.listener())
+                    .build(),
+            ),
+            score_stats,
+            score_metrics: Arc::default(),
+        }
+    }
+
+    /// Public feed of the upstream, with the info of every entry fetched from the API,
+    /// info not fetched before the deadline is missing
+    async fn rss_listing(
+        &self,
+        upstream: &Upstream,
+        deadline: Instant,
+    ) -> eyre::Result<ScoredListing> {
+        info!("fetching feed");
+        let url = upstream.rss_url(&self.reddit_client.endpoints().www)?;
+        let request = self
+            .reddit_client
+            .retry()
+            .send(Some(deadline), || self.client.get(url.clone()).send())
+            .await
+            .context("cannot send feed request")?;
+        let status = request.status();
+        let error = (status.is_client_error() || status.is_server_error())
+            .then(|| UpstreamError::from_response(&request));
+        let html = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html"));
+        let body = request.text().await;
+        if html && body.as_deref().is_ok_and(is_block_page) {
+            return Err(UpstreamError::Blocked)
+                .wrap_err(format!("feed request is blocked, status: {status:?}"));
+        }
+        if let Some(error) = error {
+            return Err(error).wrap_err(format!(
+                "cannot load feed: \t\nstatus: {status:?}\t\nbody: {body:?}"
+            ));
+        }
+        let feed = body.context("cannot parse feed")?;
+        let mut atom_feed = Feed::read_from(feed.as_bytes())
+            .map_err(|_| UpstreamError::Parse)
+            .wrap_err("Cannot parse feed")?;
+        for entry in atom_feed.entries.iter_mut() {
+            if let Some(fullname) = post_fullname(entry) {
+                entry.id = fullname;
+            }
+            normalize_entry(entry);
+        }
+
+        info!("fetching scores");
+        let score_fetch = atom_feed
+            .entries()
+            .iter()
+            .map(|e| self.timed_score(e, deadline))
+            .collect_vec();
+        let outcomes = join_all(score_fetch).await;
+        let (entries, scores): (Vec<_>, Vec<_>) = atom_feed
+            .entries
+            .into_iter()
+            .zip(outcomes)
+            .filter_map(|(entry, outcome)| match outcome {
+                Some(ScoreEntry::Found(info)) => Some((entry, Some(*info))),
+                Some(ScoreEntry::Removed) => None,
+                Some(ScoreEntry::Failed) | None => Some((entry, None)),
+            })
+            .unzip();
+        atom_feed.entries = entries;
+        let missing = scores.iter().filter(|s| s.is_none()).count();
+        if missing > 0 {
+            warn!("{missing} entries have no info, deadline is reached, fetch failed or no link");
+        }
+        Ok(ScoredListing {
+            feed: atom_feed,
+            scores,
+            document: Some(Bytes::from(feed)),
+        })
+    }
+
+    /// Listing of the upstream as read by the account, the info comes with the listing
+    async fn api_listing(&self, upstream: &Upstream) -> eyre::Result<ScoredListing> {
+        let (posts, id, title, href) = match upstream {
+            Upstream::Subreddit(subreddit) => {
+                let subreddit = subreddit.trim_start_matches("r/");
+                (
+                    self.reddit_client.get_subreddit_posts(subreddit).await?,
+                    format!("t5_{subreddit}"),
+                    format!("r/{subreddit}"),
+                    format!("https://www.reddit.com/r/{subreddit}/"),
+                )
+            }
+            Upstream::Search {
+                query,
+                subreddit,
+                sort,
+            } => {
+                let mut href = upstream.rss_url("https://www.reddit.com")?;
+                href.set_path(&href.path().replace("search.rss", "search/"));
+                (
+                    self.reddit_client
+                        .search_posts(query, subreddit.as_deref(), sort)
+                        .await?,
+                    upstream.to_string(),
+                    format!("reddit.com: search results - {query}"),
+                    href.to_string(),
+                )
+            }
+            Upstream::HackerNews(_) | Upstream::Lemmy { .. } => {
+                bail!("{upstream} is not a Reddit listing")
+            }
+        };
+        let scores = posts.iter().map(|p| Some(ArticleInfo::from(p))).collect();
+        let feed = Feed {
+            id,
+            title: title.into(),
+            updated: Utc::now().fixed_offset(),
+            links: vec![Link {
+                href,
+                ..Default::default()
+            }],
+            entries: posts.into_iter().map(listing_entry).collect(),
+            ..Default::default()
+        };
+        Ok(ScoredListing::new(feed, scores))
+    }
+
+    /// Only rate limiting fails the fetch, it is not specific to the post
+    /// so it is not cached, other failures are cached as [ScoreEntry::Failed]
+    async fn load_score(&self, mut url: String) -> eyre::Result<ScoreEntry> {
+        url = url.replace("https://www.reddit.com/", "");
+        match self.reddit_client.get_article_info(&url).await {
+            Ok(info) => Ok(ScoreEntry::Found(Box::new(info))),
+            Err(e) => match e.downcast_ref::<UpstreamError>() {
+                Some(UpstreamError::RateLimited { .. }) => {
+                    Err(e).context("Cannot load score from reddit")
+                }
+                Some(UpstreamError::NotFound) => Ok(ScoreEntry::Removed),
+                _ => {
+                    warn!("cannot load score of {url}: {e:?}");
+                    Ok(ScoreEntry::Failed)
+                }
+            },
+        }
+    }
+
+    /// Score of the entry in a `score` span of its own, timed into [ScoreMetrics].
+    /// The span is logged at debug level once the score is in.
+    /// Rate limited scores are unknown, like the ones past the deadline
+    async fn timed_score(&self, entry: &Entry, deadline: Instant) -> Option<ScoreEntry> {
+        let span = debug_span!(
+            "score",
+            post = %entry.id,
+            cache = Empty,
+            retries = Empty,
+            outcome = Empty,
+            duration_ms = Empty
+        );
+        let fetched = AtomicBool::new(false);
+        let start = Instant::now();
+        let result = timeout_at(deadline, self.get_score(entry, &fetched))
+            .instrument(span.clone())
+            .await;
+        let elapsed = start.elapsed();
+        let outcome = match &result {
+            Ok(Ok(Some(ScoreEntry::Found(_)))) => ScoreOutcome::Found,
+            Ok(Ok(Some(ScoreEntry::Removed))) => ScoreOutcome::Removed,
+            Ok(Ok(Some(ScoreEntry::Failed))) => ScoreOutcome::Failed,
+            Ok(Ok(None)) => ScoreOutcome::NoLink,
+            Ok(Err(_)) => ScoreOutcome::RateLimited,
+            Err(_) => ScoreOutcome::Deadline,
+        };
+        let fetched = fetched.load(Ordering::Relaxed);
+        span.record("cache", if fetched { "miss" } else { "hit" });
+        span.record("outcome", field::debug(outcome));
+        span.record("duration_ms", elapsed.as_millis() as u64);
+        span.in_scope(|| debug!("score fetched"));
+        self.score_metrics.record(fetched, outcome, elapsed);
+        match result {
+            Ok(Ok(score)) => score,
+            Ok(Err(e)) => {
+                warn!("score of {} is unknown: {e:?}", entry.id);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// `fetched` is set if the score was not cached
+    async fn get_score(
+        &self,
+        entry: &Entry,
+        fetched: &AtomicBool,
+    ) -> eyre::Result<Option<ScoreEntry>> {
+        match entry.links.first() {
+            Some(link) => {
+                let url = link.href.clone();
+                self.score_stats.lookup();
+                let score = self
+                    .score_cache
+                    .try_get_with(url.clone(), async {
+                        self.score_stats.miss();
+                        fetched.store(true, Ordering::Relaxed);
+                        self.load_score(url).await
+                    })
+                    .await
+                    .map_err(|e| match e.downcast_ref::<UpstreamError>() {
+                        // keeps the cause visible to the handlers
+                        Some(upstream) => eyre::Report::new(*upstream)
+                            .wrap_err(format!("cannot load score, {e:?}")),
+                        None => eyre!("cannot load score, {e:?}"),
+                    })?;
+                Ok(Some(score))
+            }
+            None => {
+                info!("Cannot find link in the entry\n{entry:?}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl FeedSource for RedditSource {
+    /// Subreddits whose public feed is forbidden, e.g. private ones the account
+    /// is approved on, are fetched as the account instead.
+    /// So are the listings Reddit's bot-block refuses to serve anonymously.
+    async fn listing(&self, upstream: &Upstream, deadline: Instant) -> eyre::Result<ScoredListing> {
+        let report = match self.rss_listing(upstream, deadline).await {
+            Ok(listing) => return Ok(listing),
+            Err(report) => report,
+        };
+        let subreddit = upstream
+            .archive_key()
+            .map(|subreddit| subreddit.trim_start_matches("r/"));
+        let result = match (report.downcast_ref::<UpstreamError>(), subreddit) {
+            (Some(UpstreamError::Blocked), _) => {
+                warn!("public feed is blocked, fetching {upstream} as the account");
+                self.api_listing(upstream).await
+            }
+            (Some(UpstreamError::Forbidden), Some(subreddit)) => {
+                info!("public feed is forbidden, fetching r/{subreddit} as the account");
+                self.api_listing(upstream).await
+            }
+            _ => Err(report),
+        };
+        match (result, subreddit) {
+            (Err(e), Some(subreddit)) => Err(self.reddit_client.explain(subreddit, e).await),
+            (result, _) => result,
+        }
+    }
+
+    fn cache_stats(&self) -> CacheReport {
+        CacheReport::from([
+            ("score", self.score_stats.snapshot(&self.score_cache)),
+            ("token", self.reddit_client.token_cache_stats()),
+        ])
+    }
+
+    fn metrics(&self) -> MetricsReport {
+        self.score_metrics.report()
+    }
+
+    /// The access token is kept, it does not go stale
+    fn purge_caches(&self) {
+        self.score_cache.invalidate_all();
+    }
+}
+
+/// Dispatches every upstream to the source serving it
+pub struct Sources {
+    reddit: RedditSource,
+    hacker_news: HackerNewsSource,
+    lemmy: LemmySource,
+}
+
+impl Sources {
+    pub fn new(reddit: RedditSource, hacker_news: HackerNewsSource, lemmy: LemmySource) -> Sources {
+        Sources {
+            reddit,
+            hacker_news,
+            lemmy,
+        }
+    }
+}
+
+#[async_trait]
+impl FeedSource for Sources {
+    async fn listing(&self, upstream: &Upstream, deadline: Instant) -> eyre::Result<ScoredListing> {
+        match upstream {
+            Upstream::Subreddit(_) | Upstream::Search { .. } => {
+                self.reddit.listing(upstream, deadline).await
+            }
+            Upstream::HackerNews(_) => self.hacker_news.listing(upstream, deadline).await,
+            Upstream::Lemmy { .. } => self.lemmy.listing(upstream, deadline).await,
+        }
+    }
+
+    fn cache_stats(&self) -> CacheReport {
+        self.reddit.cache_stats()
+    }
+
+    fn metrics(&self) -> MetricsReport {
+        self.reddit.metrics()
+    }
+
+    fn purge_caches(&self) {
+        self.reddit.purge_caches();
+    }
+}
@@ -0,0 +1,58 @@
+use atom_syndication::Entry;
+
+use crate::archive::ScoreSample;
+
+const WIDTH: f64 = 120.0;
+const HEIGHT: f64 = 24.0;
+
+/// Inline SVG line of the score over time, `None` with fewer than two samples
+fn sparkline_svg(samples: &[ScoreSample]) -> Option<String> {
+    let (first, last) = match samples {
+        [first, .., last] => (first, last),
+        _ => return None,
+    };
+    let duration = (last.at - first.at).max(1) as f64;
+    let min = samples.iter().map(|s| s.score).min()?;
+    let max = samples.iter().map(|s| s.score).max()?;
+    let points = samples
+        .iter()
+        .map(|sample| {
+            let x = (sample.at - first.at) as f64 / duration * WIDTH;
+            // flat histories are drawn in the middle
+            let y = match max - min {
+                0 => HEIGHT / 2.0,
+                range => HEIGHT - (sample.score - min) as f64 / range as f64 * HEIGHT,
+            };
+            format!("{x:.1},{y:.1}")
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}"><polyline fill="none" stroke="#ff4500" stroke-width="1.5" points="{points}" /></svg>"##
+    ))
+}
+
+/// Appends a sparkline of the score history with the first and latest scores
+pub fn render_sparkline(entry: &mut Entry, samples: &[ScoreSample]) {
+    let Some(svg) = sparkline_svg(samples) else {
+        return;
+    };
+    let Some(html) = entry.content.as_mut().and_then(|c| c.value.as_mut()) else {
+        return;
+    };
+    let (first, last) = (samples[0].score, samples[samples.len() - 1].score);
+    html.push_str(&format!("<p>{svg} {first} → {last} points</p>"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparkline_svg_test() {
+        let sample = |at, score| ScoreSample { at, score };
+        assert_eq!(sparkline_svg(&[sample(0, 10)]), None);
+        let svg = sparkline_svg(&[sample(0, 10), sample(50, 30), sample(100, 20)]).unwrap();
+        assert!(svg.contains(r#"points="0.0,24.0 60.0,0.0 120.0,12.0""#));
+    }
+}
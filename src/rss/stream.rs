@@ -0,0 +1,319 @@
+use std::ops::Range;
+
+use atom_syndication::Generator;
+use chrono::{DateTime, FixedOffset};
+use eyre::{bail, Context};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::rss::sanitize::{strip_tracking, strip_tracking_html};
+
+/// An `<entry>` being read, copied with the tracking parameters of its links stripped
+#[derive(Default)]
+struct EntryState {
+    /// The entry copied so far
+    output: Vec<u8>,
+    id: Option<String>,
+    /// Where the id text is in `output`
+    id_text: Option<Range<usize>>,
+}
+
+/// Copies the Atom feed through, dropping the `<entry>` elements whose `<id>` is rejected
+/// by `keep`, the id of the kept ones is replaced by the one `keep` returns and tracking
+/// parameters are stripped from their links and content. Unlike parsing
+/// into [atom_syndication::Feed] and writing it back, everything else is kept byte for byte:
+/// formatting, namespaces and elements unknown to the parser.
+///
+/// Only the current entry is held in memory besides the input and output.
+/// The feed-level elements set at serving time are rewritten by [rewrite_head].
+pub fn retain_entries(
+    xml: &[u8],
+    mut keep: impl FnMut(&str) -> Option<String>,
+) -> eyre::Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(xml);
+    let mut output = Vec::with_capacity(xml.len());
+    // start of the input not copied yet, to the output or to the current entry
+    let mut copied = 0;
+    let mut depth = 0usize;
+    let mut entry: Option<EntryState> = None;
+    let mut in_id = false;
+    let mut in_content = false;
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = reader.read_event().context("cannot read feed")?;
+        let end = reader.buffer_position() as usize;
+        match event {
+            Event::Start(e) => {
+                depth += 1;
+                match &mut entry {
+                    None if depth == 2 && e.local_name().as_ref() == b"entry" => {
+                        output.extend_from_slice(&xml[copied..start]);
+                        copied = start;
+                        entry = Some(EntryState::default());
+                    }
+                    Some(state) if depth == 3 => match e.local_name().as_ref() {
+                        b"id" => in_id = state.id.is_none(),
+                        b"content" => in_content = true,
+                        b"link" => {
+                            if let Some(link) = strip_link(&e)? {
+                                state.output.extend_from_slice(&xml[copied..start]);
+                                write_tag(&mut state.output, &link, b">");
+                                copied = end;
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+            Event::Empty(e) if depth == 2 && e.local_name().as_ref() == b"link" => {
+                if let Some(state) = &mut entry {
+                    if let Some(link) = strip_link(&e)? {
+                        state.output.extend_from_slice(&xml[copied..start]);
+                        write_tag(&mut state.output, &link, b"/>");
+                        copied = end;
+                    }
+                }
+            }
+            Event::Text(text) if in_id => {
+                if let Some(state) = &mut entry {
+                    let id = text.unescape().context("cannot read entry id")?;
+                    state.id.get_or_insert_with(String::new).push_str(&id);
+                    state.output.extend_from_slice(&xml[copied..start]);
+                    let id_start = state
+                        .id_text
+                        .as_ref()
+                        .map_or(state.output.len(), |range| range.start);
+                    state.output.extend_from_slice(&xml[start..end]);
+                    copied = end;
+                    state.id_text = Some(id_start..state.output.len());
+                }
+            }
+            Event::Text(text) if in_content && depth == 3 => {
+                if let Some(state) = &mut entry {
+                    let html = text.unescape().context("cannot read entry content")?;
+                    let stripped = strip_tracking_html(&html);
+                    if stripped != html {
+                        state.output.extend_from_slice(&xml[copied..start]);
+                        state
+                            .output
+                            .extend_from_slice(escape(stripped.as_str()).as_bytes());
+                        copied = end;
+                    }
+                }
+            }
+            Event::End(_) => {
+                in_id = false;
+                in_content = false;
+                if depth == 2 {
+                    if let Some(mut state) = entry.take() {
+                        let id = state.id.as_deref().map(str::trim);
+                        if let Some(id) = id.and_then(&mut keep) {
+                            state.output.extend_from_slice(&xml[copied..end]);
+                            if let Some(id_text) = state.id_text {
+                                state.output.splice(id_text, escape(id.as_str()).bytes());
+                            }
+                            output.append(&mut state.output);
+                        }
+                        copied = end;
+                    }
+                }
+                depth = depth.saturating_sub(1);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if entry.is_some() {
+        bail!("feed ends inside an entry");
+    }
+    output.extend_from_slice(&xml[copied..]);
+    Ok(output)
+}
+
+/// The link with the tracking parameters stripped from its `href`, None if it has none
+fn strip_link(link: &BytesStart) -> eyre::Result<Option<BytesStart<'static>>> {
+    let Some(href) = link.try_get_attribute("href").context("cannot read link")? else {
+        return Ok(None);
+    };
+    let href = href.unescape_value().context("cannot read link")?;
+    let stripped = strip_tracking(&href);
+    if stripped == href {
+        return Ok(None);
+    }
+    let mut rewritten = link.to_owned();
+    rewritten.clear_attributes();
+    for attribute in link.attributes() {
+        let attribute = attribute.context("cannot read link")?;
+        if attribute.key.as_ref() == b"href" {
+            rewritten.push_attribute(("href", stripped.as_str()));
+        } else {
+            rewritten.push_attribute(attribute);
+        }
+    }
+    Ok(Some(rewritten))
+}
+
+/// Writes the start tag, `end` is `>` or `/>` for an empty element
+fn write_tag(output: &mut Vec<u8>, tag: &BytesStart, end: &[u8]) {
+    output.push(b'<');
+    output.extend_from_slice(tag);
+    output.extend_from_slice(end);
+}
+
+/// Feed-level elements set when the feed is served, see [rewrite_head]
+pub struct FeedHead<'a> {
+    pub self_link: &'a str,
+    pub updated: DateTime<FixedOffset>,
+    pub generator: &'a Generator,
+}
+
+impl FeedHead<'_> {
+    fn to_xml(&self) -> String {
+        let generator = self.generator;
+        let mut attributes = String::new();
+        if let Some(uri) = &generator.uri {
+            attributes.push_str(&format!(r#" uri="{}""#, escape(uri.as_str())));
+        }
+        if let Some(version) = &generator.version {
+            attributes.push_str(&format!(r#" version="{}""#, escape(version.as_str())));
+        }
+        format!(
+            r#"<link rel="self" href="{}" type="application/atom+xml"/><updated>{}</updated><generator{attributes}>{}</generator>"#,
+            escape(self.self_link),
+            self.updated.to_rfc3339(),
+            escape(generator.value.as_str())
+        )
+    }
+}
+
+/// Whether the feed-level element is one set by [rewrite_head]
+fn is_replaced(element: &BytesStart) -> eyre::Result<bool> {
+    Ok(match element.local_name().as_ref() {
+        b"updated" | b"generator" => true,
+        b"link" => element
+            .try_get_attribute("rel")
+            .context("cannot read link")?
+            .is_some_and(|rel| rel.value.as_ref() == b"self"),
+        _ => false,
+    })
+}
+
+/// Replaces the feed-level `<link rel="self">`, `<updated>` and `<generator>` of the Atom feed
+/// by the ones of `head`, written before the first entry. Only the start of the feed is read:
+/// returns it rewritten, with the offset of the rest, to be sent as is.
+pub fn rewrite_head(xml: &[u8], head: &FeedHead) -> eyre::Result<(Vec<u8>, usize)> {
+    let mut reader = Reader::from_reader(xml);
+    let mut output = Vec::new();
+    let mut copied = 0;
+    let mut depth = 0usize;
+    // start of the replaced element being read
+    let mut replaced = None;
+    let rest = loop {
+        let start = reader.buffer_position() as usize;
+        let event = reader.read_event().context("cannot read feed")?;
+        let end = reader.buffer_position() as usize;
+        match event {
+            Event::Start(e) if depth == 1 && e.local_name().as_ref() == b"entry" => break start,
+            Event::Start(e) => {
+                depth += 1;
+                if depth == 2 && is_replaced(&e)? {
+                    replaced = Some(start);
+                }
+            }
+            Event::Empty(e) if depth == 1 && is_replaced(&e)? => {
+                output.extend_from_slice(&xml[copied..start]);
+                copied = end;
+            }
+            // the feed has no entries
+            Event::End(_) if depth == 1 => break start,
+            Event::End(_) => {
+                if depth == 2 {
+                    if let Some(replaced) = replaced.take() {
+                        output.extend_from_slice(&xml[copied..replaced]);
+                        copied = end;
+                    }
+                }
+                depth = depth.saturating_sub(1);
+            }
+            Event::Eof => bail!("feed is not closed"),
+            _ => {}
+        }
+    };
+    output.extend_from_slice(&xml[copied..rest]);
+    output.extend_from_slice(head.to_xml().as_bytes());
+    Ok((output, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain_entries_test() {
+        let xml = include_str!("../../tests/fixtures/listing.rss");
+        let filtered = retain_entries(xml.as_bytes(), |id| {
+            (id == "t3_aaaaaa").then(|| format!("urn:reddit:{id}"))
+        })
+        .unwrap();
+        let filtered = String::from_utf8(filtered).unwrap();
+
+        assert!(filtered.contains("<id>urn:reddit:t3_aaaaaa</id>"));
+        assert!(!filtered.contains("t3_bbbbbb"));
+        // the rest is untouched
+        let dropped_start = xml.find("<entry><author><name>/u/crab").unwrap();
+        let dropped_end = xml.rfind("</feed>").unwrap();
+        assert_eq!(
+            filtered.replace("urn:reddit:", ""),
+            format!("{}{}", &xml[..dropped_start], &xml[dropped_end..])
+        );
+    }
+
+    #[test]
+    fn retain_entries_tracking_test() {
+        let xml = r#"<feed><entry><id>t3_a</id><link href="https://example.com/a?utm_source=reddit&amp;id=1" /><content type="html">&lt;a href=&quot;https://example.com/b?fbclid=x&quot;&gt;b&lt;/a&gt;</content></entry></feed>"#;
+        let filtered = retain_entries(xml.as_bytes(), |id| Some(id.to_string())).unwrap();
+        assert_eq!(
+            String::from_utf8(filtered).unwrap(),
+            r#"<feed><entry><id>t3_a</id><link href="https://example.com/a?id=1"/><content type="html">&lt;a href=&quot;https://example.com/b&quot;&gt;b&lt;/a&gt;</content></entry></feed>"#
+        );
+    }
+
+    #[test]
+    fn rewrite_head_test() {
+        let xml = include_str!("../../tests/fixtures/listing.rss");
+        let generator = Generator {
+            value: "redditrss".to_string(),
+            uri: Some("https://github.com/hov1417/redditrss".to_string()),
+            ..Default::default()
+        };
+        let head = FeedHead {
+            self_link: "https://example.com/feed/rust?min_score=10&sticky=true",
+            updated: DateTime::parse_from_rfc3339("2024-03-30T00:00:00+00:00").unwrap(),
+            generator: &generator,
+        };
+        let (start, rest) = rewrite_head(xml.as_bytes(), &head).unwrap();
+        let start = String::from_utf8(start).unwrap();
+
+        assert!(xml[rest..].starts_with("<entry>"));
+        assert!(
+            !start.contains("https://www.reddit.com/r/rust/.rss"),
+            "{start}"
+        );
+        assert!(!start.contains("2024-03-29T12:00:00"), "{start}");
+        assert!(start.ends_with(concat!(
+            r#"<link rel="self" href="https://example.com/feed/rust?min_score=10&amp;sticky=true" type="application/atom+xml"/>"#,
+            r#"<updated>2024-03-30T00:00:00+00:00</updated>"#,
+            r#"<generator uri="https://github.com/hov1417/redditrss">redditrss</generator>"#
+        )));
+        // the other feed-level elements are kept
+        assert!(start.contains(r#"<link rel="alternate" href="https://www.reddit.com/r/rust/""#));
+
+        let (start, rest) = rewrite_head(b"<feed><title>t</title></feed>", &head).unwrap();
+        assert!(String::from_utf8(start)
+            .unwrap()
+            .starts_with("<feed><title>t</title><link"));
+        assert_eq!(rest, "<feed><title>t</title>".len());
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use atom_syndication::{Content, Entry};
+use chrono::DateTime;
+use handlebars::{html_escape, no_escape, Handlebars};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::reddit::client::ArticleInfo;
+
+/// Handlebars template of entry titles or contents, given as `title_template` and
+/// `content_template` query parameters, e.g. `[{{score}}] {{title}} ({{domain}})`.
+///
+/// Variables: `title`, `score`, `num_comments`, `flair`, `domain`, `author`,
+/// `url` (submitted URL of link posts), `link` (main link of the entry), `created`
+/// (RFC 3339), flags `nsfw`, `spoiler`, `is_self`, and `content` (HTML) in contents.
+/// Variables are HTML-escaped in contents, so they render as text there.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    /// The template as given, templates are compared and hashed by it
+    source: String,
+    registry: Arc<Handlebars<'static>>,
+}
+
+const NAME: &str = "entry";
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Template {}
+
+impl Hash for Template {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> Self {
+        template.source
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut registry = Handlebars::new();
+        // escaping depends on where the template is used, see `TemplateVars`
+        registry.register_escape_fn(no_escape);
+        registry
+            .register_template_string(NAME, source)
+            .map_err(|e| format!("invalid template: {}", e.reason()))?;
+        Ok(Template {
+            source: source.to_string(),
+            registry: Arc::new(registry),
+        })
+    }
+}
+
+impl Template {
+    fn render(&self, vars: &TemplateVars) -> Option<String> {
+        self.registry
+            .render(NAME, vars)
+            .inspect_err(|e| warn!("cannot render template {:?}: {e}", self.source))
+            .ok()
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateVars {
+    title: String,
+    score: u64,
+    num_comments: u64,
+    flair: String,
+    domain: String,
+    author: String,
+    url: String,
+    link: String,
+    created: String,
+    nsfw: bool,
+    spoiler: bool,
+    is_self: bool,
+    content: Option<String>,
+}
+
+impl TemplateVars {
+    fn new(entry: &Entry, info: &ArticleInfo) -> TemplateVars {
+        let text = |value: Option<&str>| value.unwrap_or_default().to_string();
+        TemplateVars {
+            title: entry.title.value.clone(),
+            score: info.score,
+            num_comments: info.num_comments,
+            flair: text(info.link_flair_text.as_deref()),
+            domain: text(info.domain.as_deref()),
+            author: text(entry.authors.first().map(|a| a.name.as_str())),
+            url: text(info.url.as_deref().filter(|_| !info.is_self)),
+            link: text(entry.links.first().map(|l| l.href.as_str())),
+            created: DateTime::from_timestamp(info.created_utc as i64, 0)
+                .map(|created| created.to_rfc3339())
+                .unwrap_or_default(),
+            nsfw: info.over_18,
+            spoiler: info.spoiler,
+            is_self: info.is_self,
+            content: None,
+        }
+    }
+
+    /// Variables of content templates, texts are escaped and the content is kept as HTML
+    fn html(mut self, entry: &Entry) -> TemplateVars {
+        for text in [
+            &mut self.title,
+            &mut self.flair,
+            &mut self.domain,
+            &mut self.author,
+            &mut self.url,
+            &mut self.link,
+        ] {
+            *text = html_escape(text);
+        }
+        self.content = entry.content.as_ref().and_then(|c| c.value.clone());
+        self
+    }
+}
+
+/// Renders title and content of the entry with the templates,
+/// an entry is left as is if its template fails
+pub fn render_entry(
+    entry: &mut Entry,
+    info: &ArticleInfo,
+    title: Option<&Template>,
+    content: Option<&Template>,
+) {
+    if title.is_none() && content.is_none() {
+        return;
+    }
+    let vars = TemplateVars::new(entry, info);
+    let rendered_title = title.and_then(|template| template.render(&vars));
+    if let Some(html) = content.and_then(|template| template.render(&vars.html(entry))) {
+        entry.content = Some(Content {
+            content_type: Some("html".to_string()),
+            value: Some(html),
+            ..entry.content.take().unwrap_or_default()
+        });
+    }
+    if let Some(rendered_title) = rendered_title {
+        entry.title.value = rendered_title;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_entry_test() {
+        let mut entry = Entry {
+            title: "Vec<T> & you".into(),
+            content: Some(Content {
+                value: Some("<p>body</p>".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let info = ArticleInfo {
+            score: 250,
+            num_comments: 12,
+            link_flair_text: Some("News".to_string()),
+            ..Default::default()
+        };
+        let title: Template = "[{{score}}] {{title}}{{#if flair}} ({{flair}}){{/if}}"
+            .parse()
+            .unwrap();
+        let content: Template = "<b>{{title}}</b> {{num_comments}} comments{{content}}"
+            .parse()
+            .unwrap();
+        render_entry(&mut entry, &info, Some(&title), Some(&content));
+        assert_eq!(entry.title.value, "[250] Vec<T> & you (News)");
+        assert_eq!(
+            entry.content.unwrap().value.unwrap(),
+            "<b>Vec&lt;T&gt; &amp; you</b> 12 comments<p>body</p>"
+        );
+
+        assert!("{{#if score}}".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, info_span, warn, Instrument};
+
+/// Background tasks still running this long after [Shutdown::stop] are aborted,
+/// well before the platform kills the process
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stops the background tasks on shutdown, see [Shutdown::stop].
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Shutdown {
+    stopping: Arc<watch::Sender<bool>>,
+    /// Running tasks, the finished ones are dropped on the next spawn
+    tasks: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            stopping: Arc::new(watch::Sender::new(false)),
+            tasks: Arc::default(),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Completes once [Shutdown::stop] is called
+    pub async fn stopping(&self) {
+        let mut stopping = self.stopping.subscribe();
+        // the sender lives as long as `self`
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+    }
+
+    /// Runs `task` in the background, [Shutdown::stop] waits for it to complete
+    pub fn spawn<Fut>(&self, task: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().unwrap();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Signals the background tasks to stop and waits for them,
+    /// jobs already running are allowed to finish within [STOP_TIMEOUT].
+    /// So are the tasks spawned while stopping, e.g. jobs dequeued meanwhile
+    pub async fn stop(&self) {
+        info!("stopping background tasks");
+        self.stopping.send_replace(true);
+        let deadline = tokio::time::Instant::now() + STOP_TIMEOUT;
+        loop {
+            let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+            if tasks.is_empty() {
+                break;
+            }
+            while !tasks.is_empty() {
+                if tokio::time::timeout_at(deadline, tasks.join_next())
+                    .await
+                    .is_err()
+                {
+                    warn!("aborting {} background tasks still running", tasks.len());
+                    tasks.shutdown().await;
+                }
+            }
+        }
+    }
+}
+
+/// Runs `job` every `period` in a background task until shutdown,
+/// the first run happens after one period.
+///
+/// If a run takes longer than the period, the next one is delayed instead of
+/// running several times in a row to catch up.
+pub fn spawn_periodic<F, Fut>(shutdown: &Shutdown, name: &'static str, period: Duration, mut job: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let stopping = shutdown.clone();
+    shutdown.spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // the first tick completes immediately
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = stopping.stopping() => break,
+            }
+            job().instrument(info_span!("background", job = name)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_test() {
+        let shutdown = Shutdown::default();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        spawn_periodic(&shutdown, "test", Duration::from_secs(60), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {}
+        });
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        shutdown.stop().await;
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_timeout_test() {
+        let shutdown = Shutdown::default();
+        shutdown.spawn(std::future::pending());
+        let (flushed, flag) = (Arc::new(AtomicU32::new(0)), shutdown.clone());
+        let counter = flushed.clone();
+        shutdown.spawn(async move {
+            flag.stopping().await;
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let started = tokio::time::Instant::now();
+        shutdown.stop().await;
+        assert_eq!(started.elapsed(), STOP_TIMEOUT);
+        assert_eq!(flushed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spawn_test() {
+        let shutdown = Shutdown::default();
+        for _ in 0..3 {
+            shutdown.spawn(async {});
+        }
+        tokio::task::yield_now().await;
+        // the finished tasks are dropped
+        shutdown.spawn(async {});
+        assert_eq!(shutdown.tasks.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spawn_while_stopping_test() {
+        let shutdown = Shutdown::default();
+        let (spawned, flag) = (Arc::new(AtomicU32::new(0)), shutdown.clone());
+        let counter = spawned.clone();
+        let late = shutdown.clone();
+        shutdown.spawn(async move {
+            flag.stopping().await;
+            late.spawn(async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        });
+        let started = tokio::time::Instant::now();
+        shutdown.stop().await;
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+        assert!(shutdown.tasks.lock().unwrap().is_empty());
+    }
+}
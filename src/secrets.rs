@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use eyre::Context;
+
+/// Source of the configuration and credentials, e.g. `REDDIT_CLIENT_ID`.
+///
+/// On Shuttle it is the deployment's secret store,
+/// standalone it is the environment plus an optional TOML file (see [EnvSecrets]),
+/// tests can use a TOML file alone (see [FileSecrets]) to not depend on the environment.
+pub trait Secrets: Send + Sync {
+    fn get(&self, key: &str) -> Option<String>;
+
+    /// Re-reads the secrets whose source can change while running,
+    /// Shuttle's secrets only change on redeploy
+    fn reload(&self) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "shuttle")]
+impl Secrets for shuttle_runtime::SecretStore {
+    fn get(&self, key: &str) -> Option<String> {
+        shuttle_runtime::SecretStore::get(self, key)
+    }
+}
+
+/// Secrets of a standalone deployment: environment variables,
+/// falling back to a [FileSecrets]
+#[derive(Default, Debug)]
+pub struct EnvSecrets {
+    file: Option<FileSecrets>,
+}
+
+impl EnvSecrets {
+    /// Environment variables only
+    pub fn new() -> EnvSecrets {
+        EnvSecrets::default()
+    }
+
+    /// Environment variables, falling back to the TOML file at `path`
+    pub fn with_file(path: &Path) -> eyre::Result<EnvSecrets> {
+        Ok(EnvSecrets {
+            file: Some(FileSecrets::open(path)?),
+        })
+    }
+}
+
+impl Secrets for EnvSecrets {
+    fn get(&self, key: &str) -> Option<String> {
+        std::env::var(key)
+            .ok()
+            .or_else(|| self.file.as_ref()?.get(key))
+    }
+
+    /// Re-reads the file, environment variables are read on every use anyway
+    fn reload(&self) -> eyre::Result<()> {
+        match &self.file {
+            Some(file) => file.reload(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Secrets in a TOML file of the same format as Shuttle's `Secrets.toml`
+#[derive(Default, Debug)]
+pub struct FileSecrets {
+    /// File the secrets are reloaded from, see [Secrets::reload]
+    path: Option<PathBuf>,
+    values: RwLock<BTreeMap<String, String>>,
+}
+
+impl FileSecrets {
+    pub fn open(path: &Path) -> eyre::Result<FileSecrets> {
+        Ok(FileSecrets {
+            path: Some(path.to_path_buf()),
+            values: RwLock::new(read_file(path)?),
+        })
+    }
+
+    /// Secrets of the TOML document, not reloadable
+    pub fn from_toml(data: &str) -> eyre::Result<FileSecrets> {
+        Ok(FileSecrets {
+            path: None,
+            values: RwLock::new(parse_toml(data)?),
+        })
+    }
+}
+
+impl Secrets for FileSecrets {
+    fn get(&self, key: &str) -> Option<String> {
+        self.values.read().unwrap().get(key).cloned()
+    }
+
+    /// A broken file is reported and the previous secrets are kept
+    fn reload(&self) -> eyre::Result<()> {
+        if let Some(path) = &self.path {
+            *self.values.write().unwrap() = read_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> eyre::Result<BTreeMap<String, String>> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read config file {}", path.display()))?;
+    parse_toml(&data).with_context(|| format!("cannot parse config file {}", path.display()))
+}
+
+fn parse_toml(data: &str) -> eyre::Result<BTreeMap<String, String>> {
+    let table: toml::Table = data.parse()?;
+    Ok(table
+        .into_iter()
+        .map(|(key, value)| match value {
+            toml::Value::String(value) => (key, value),
+            // e.g. `RATE_LIMIT_PER_MINUTE = 60`
+            value => (key, value.to_string()),
+        })
+        .collect())
+}
+
+/// Fixed secrets for tests
+#[cfg(any(test, feature = "test-util"))]
+impl Secrets for std::collections::HashMap<&str, &str> {
+    fn get(&self, key: &str) -> Option<String> {
+        std::collections::HashMap::get(self, key).map(|v| v.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_toml_test() {
+        let secrets = FileSecrets::from_toml(
+            r#"
+            REDDITRSS_TEST_TOKENS = "alice:abc"
+            REDDITRSS_TEST_LIMIT = 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            secrets.get("REDDITRSS_TEST_TOKENS").as_deref(),
+            Some("alice:abc")
+        );
+        assert_eq!(secrets.get("REDDITRSS_TEST_LIMIT").as_deref(), Some("60"));
+        assert_eq!(secrets.get("REDDITRSS_TEST_MISSING"), None);
+    }
+
+    #[test]
+    fn reload_test() {
+        let dir = tempfile::TempDir::with_prefix("redditrss-secrets-").unwrap();
+        let path = dir.path().join("secrets.toml");
+        std::fs::write(&path, r#"REDDITRSS_TEST_TOKENS = "alice:abc""#).unwrap();
+        let secrets = FileSecrets::open(&path).unwrap();
+
+        std::fs::write(&path, r#"REDDITRSS_TEST_TOKENS = "alice:def""#).unwrap();
+        secrets.reload().unwrap();
+        assert_eq!(
+            secrets.get("REDDITRSS_TEST_TOKENS").as_deref(),
+            Some("alice:def")
+        );
+
+        std::fs::write(&path, "REDDITRSS_TEST_TOKENS = ").unwrap();
+        assert!(secrets.reload().is_err());
+        assert_eq!(
+            secrets.get("REDDITRSS_TEST_TOKENS").as_deref(),
+            Some("alice:def")
+        );
+    }
+}
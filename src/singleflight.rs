@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+
+type SharedResult<V> = Shared<BoxFuture<'static, Result<V, Arc<eyre::Report>>>>;
+
+/// Deduplicates concurrent computations with the same key: while one is in flight,
+/// other callers with the same key wait for its result instead of starting their own.
+///
+/// Results are not kept after the computation finishes, caching is up to the caller.
+///
+/// Cheaply cloneable.
+pub struct SingleFlight<K, V> {
+    in_flight: Arc<Mutex<HashMap<K, SharedResult<V>>>>,
+}
+
+impl<K, V> Clone for SingleFlight<K, V> {
+    fn clone(&self) -> Self {
+        SingleFlight {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        SingleFlight {
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Runs `computation`, unless one with the same key is already in flight,
+    /// then waits for that one instead
+    pub async fn run<F>(&self, key: K, computation: F) -> Result<V, Arc<eyre::Report>>
+    where
+        F: Future<Output = eyre::Result<V>> + Send + 'static,
+    {
+        let shared = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            in_flight
+                .entry(key.clone())
+                .or_insert_with(|| computation.map(|r| r.map_err(Arc::new)).boxed().shared())
+                .clone()
+        };
+        let result = shared.clone().await;
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // a new computation with the same key might have started already
+        if in_flight
+            .get(&key)
+            .is_some_and(|current| current.ptr_eq(&shared))
+        {
+            in_flight.remove(&key);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn deduplicates_concurrent_calls_test() {
+        let flight = SingleFlight::<&str, usize>::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let call = || {
+            let runs = runs.clone();
+            flight.run("key", async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(runs.fetch_add(1, Ordering::SeqCst))
+            })
+        };
+        let results = futures::future::join_all([call(), call(), call()]).await;
+        assert!(results.into_iter().all(|r| r.unwrap() == 0));
+
+        // finished computations are not reused
+        assert_eq!(call().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use reqwest::{header, Client};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use tracing::{info, warn};
+
+use crate::config::Reader;
+use crate::profiles::FeedProfile;
+use crate::rss::feed::RssFeedProvider;
+use crate::store::Collection;
+use crate::version::generator;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Lifetime of the presigned upload URLs
+const SIGNATURE_TTL: Duration = Duration::from_secs(5 * 60);
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where and how often [Snapshots] are written
+#[derive(Clone, Debug)]
+pub struct SnapshotConfig {
+    pub bucket: Bucket,
+    pub credentials: Credentials,
+    pub prefix: String,
+    pub interval: Duration,
+}
+
+impl SnapshotConfig {
+    /// Taken from `SNAPSHOT_S3_ENDPOINT`, `SNAPSHOT_S3_BUCKET`, `SNAPSHOT_S3_REGION`,
+    /// `SNAPSHOT_S3_ACCESS_KEY`, `SNAPSHOT_S3_SECRET_KEY`, `SNAPSHOT_PREFIX` and
+    /// `SNAPSHOT_INTERVAL_SECS` secrets, `None` without an endpoint.
+    /// Buckets are addressed path-style, e.g. `https://<endpoint>/<bucket>/f/<id>.xml`
+    pub fn read(reader: &mut Reader) -> Option<SnapshotConfig> {
+        let endpoint = reader.url("SNAPSHOT_S3_ENDPOINT")?;
+        let (Some(name), Some(key), Some(secret)) = (
+            reader.string("SNAPSHOT_S3_BUCKET"),
+            reader.string("SNAPSHOT_S3_ACCESS_KEY"),
+            reader.string("SNAPSHOT_S3_SECRET_KEY"),
+        ) else {
+            reader.error("SNAPSHOT_S3_BUCKET, SNAPSHOT_S3_ACCESS_KEY and SNAPSHOT_S3_SECRET_KEY are required with SNAPSHOT_S3_ENDPOINT");
+            return None;
+        };
+        let region = reader
+            .string("SNAPSHOT_S3_REGION")
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, name, region)
+            .map_err(|e| reader.error(format!("invalid SNAPSHOT_S3_ENDPOINT: {e}")))
+            .ok()?;
+        Some(SnapshotConfig {
+            bucket,
+            credentials: Credentials::new(key, secret),
+            prefix: reader.string("SNAPSHOT_PREFIX").unwrap_or_default(),
+            interval: reader
+                .secs("SNAPSHOT_INTERVAL_SECS")
+                .unwrap_or(DEFAULT_INTERVAL),
+        })
+    }
+}
+
+/// Copies of the stored profiles' feeds in S3-compatible storage, under `<prefix>f/<id>.xml`
+/// like their `/f/<id>` URLs, so readers can be served from a CDN while the service is down.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Snapshots {
+    client: Client,
+    bucket: Arc<Bucket>,
+    credentials: Arc<Credentials>,
+    prefix: Arc<str>,
+    /// How often the snapshots are written
+    pub interval: Duration,
+}
+
+impl Snapshots {
+    pub fn new(
+        client: Client,
+        bucket: Bucket,
+        credentials: Credentials,
+        prefix: &str,
+        interval: Duration,
+    ) -> Snapshots {
+        Snapshots {
+            client,
+            bucket: Arc::new(bucket),
+            credentials: Arc::new(credentials),
+            prefix: prefix.into(),
+            interval,
+        }
+    }
+
+    pub fn from_config(config: &SnapshotConfig) -> eyre::Result<Snapshots> {
+        Ok(Snapshots::new(
+            Client::builder().timeout(UPLOAD_TIMEOUT).build()?,
+            config.bucket.clone(),
+            config.credentials.clone(),
+            &config.prefix,
+            config.interval,
+        ))
+    }
+
+    /// Writes the current feed of every stored profile, failed ones are kept as they were
+    pub async fn snapshot(&self, provider: &RssFeedProvider, profiles: &Collection<FeedProfile>) {
+        for (id, FeedProfile { definition, .. }) in profiles.list().await {
+            let feed = provider
+                .feed_filter(definition.upstream(), &definition.options)
+                .await;
+            let mut feed = match feed {
+                Ok(feed) => feed,
+                Err(e) => {
+                    warn!("cannot snapshot profile {id}: {e:?}");
+                    continue;
+                }
+            };
+            feed.updated = Utc::now().fixed_offset();
+            feed.generator = Some(generator());
+            let key = format!("{}f/{id}.xml", self.prefix);
+            match self.upload(&key, feed.to_string()).await {
+                Ok(()) => info!("wrote snapshot {key}"),
+                Err(e) => warn!("cannot write snapshot {key}: {e:?}"),
+            }
+        }
+    }
+
+    async fn upload(&self, key: &str, body: String) -> eyre::Result<()> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+        self.client
+            .put(url)
+            .header(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use eyre::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+/// Default directory for the stored data, relative to the working directory
+pub const DEFAULT_STORE_PATH: &str = ".redditrss";
+
+/// A minimal persistence layer: named collections of JSON documents,
+/// each collection is a single file in the store directory.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    pub fn new(root: impl Into<PathBuf>) -> Store {
+        Store { root: root.into() }
+    }
+
+    /// Loads the collection into memory, missing collection is treated as empty
+    pub async fn collection<T>(&self, name: &str) -> eyre::Result<Collection<T>>
+    where
+        T: Serialize + DeserializeOwned + Clone,
+    {
+        let path = self.root.join(format!("{name}.json"));
+        let items = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("cannot parse collection {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("cannot read {}", path.display()));
+            }
+        };
+        Ok(Collection {
+            path,
+            items: Arc::new(RwLock::new(items)),
+        })
+    }
+}
+
+/// Key-value collection kept in memory and written back as a whole on every change.
+///
+/// Cheaply cloneable.
+pub struct Collection<T> {
+    path: PathBuf,
+    items: Arc<RwLock<BTreeMap<String, T>>>,
+}
+
+impl<T> Clone for Collection<T> {
+    fn clone(&self) -> Self {
+        Collection {
+            path: self.path.clone(),
+            items: self.items.clone(),
+        }
+    }
+}
+
+impl<T> Collection<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    pub async fn get(&self, key: &str) -> Option<T> {
+        self.items.read().await.get(key).cloned()
+    }
+
+    pub async fn list(&self) -> Vec<(String, T)> {
+        self.items
+            .read()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    pub async fn contains(&self, key: &str) -> bool {
+        self.items.read().await.contains_key(key)
+    }
+
+    pub async fn insert(&self, key: String, value: T) -> eyre::Result<Option<T>> {
+        let mut items = self.items.write().await;
+        let old = items.insert(key, value);
+        self.persist(&items).await?;
+        Ok(old)
+    }
+
+    /// Replaces the value of the key with `update` of the current one, `None` removes it.
+    /// The write lock is held throughout, so concurrent updates of the key are not lost
+    pub async fn update(
+        &self,
+        key: &str,
+        update: impl FnOnce(Option<T>) -> Option<T>,
+    ) -> eyre::Result<()> {
+        let mut items = self.items.write().await;
+        let existed = items.contains_key(key);
+        match update(items.remove(key)) {
+            Some(value) => {
+                items.insert(key.to_string(), value);
+            }
+            None if !existed => return Ok(()),
+            None => {}
+        }
+        self.persist(&items).await
+    }
+
+    /// Runs `read` on the items in place, without cloning them like [Collection::list]
+    pub async fn read<R>(&self, read: impl FnOnce(&BTreeMap<String, T>) -> R) -> R {
+        read(&*self.items.read().await)
+    }
+
+    pub async fn remove(&self, key: &str) -> eyre::Result<Option<T>> {
+        let mut items = self.items.write().await;
+        let old = items.remove(key);
+        if old.is_some() {
+            self.persist(&items).await?;
+        }
+        Ok(old)
+    }
+
+    /// Writes to a temporary file first, so a crash mid-write does not corrupt the collection.
+    /// Called with the write lock held, which serializes concurrent writes.
+    async fn persist(&self, items: &BTreeMap<String, T>) -> eyre::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("cannot create {}", parent.display()))?;
+        }
+        let data = serde_json::to_vec(items).context("cannot serialize collection")?;
+        let tmp = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, data)
+            .await
+            .with_context(|| format!("cannot write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("cannot replace {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn collection_roundtrip_test() {
+        let root = std::env::temp_dir().join(format!("redditrss-store-{}", std::process::id()));
+        let store = Store::new(&root);
+        let collection = store.collection::<u64>("numbers").await.unwrap();
+        collection.insert("a".into(), 1).await.unwrap();
+        collection.insert("b".into(), 2).await.unwrap();
+        collection.remove("a").await.unwrap();
+
+        let increments =
+            (0..10).map(|_| collection.update("b", |n| Some(n.unwrap_or_default() + 1)));
+        futures::future::try_join_all(increments).await.unwrap();
+        collection.update("c", |_| None).await.unwrap();
+
+        let reloaded = store.collection::<u64>("numbers").await.unwrap();
+        assert_eq!(reloaded.list().await, vec![("b".to_string(), 12)]);
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+//! Mock Reddit server for end-to-end tests without real credentials,
+//! enabled with the `test-util` feature.
+//!
+//! The server answers the token endpoint out of the box,
+//! everything else is configured per path with [MockReddit::respond].
+//! Real responses can be recorded and replayed with [vcr::Cassette].
+
+pub mod vcr;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Method, StatusCode, Uri};
+use axum::response::Response;
+use axum::Router;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::archive::Archive;
+use crate::cache::CacheConfig;
+use crate::http::PublicClient;
+use crate::reddit::client::RedditClient;
+use crate::reddit::endpoints::Endpoints;
+use crate::reposts::Reposts;
+use crate::rss::feed::RssFeedProvider;
+use crate::rss::source::RedditSource;
+use crate::secrets::Secrets;
+use crate::store::Store;
+
+/// Reddit `.rss` listing of r/rust with two posts, `t3_aaaaaa` and `t3_bbbbbb`
+pub const LISTING_RSS: &str = include_str!("../../tests/fixtures/listing.rss");
+
+/// Path of the token endpoint on the mock server
+const TOKEN_PATH: &str = "/api/v1/access_token";
+
+/// Canned response of the mock server
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn json(body: serde_json::Value) -> MockResponse {
+        MockResponse {
+            status: StatusCode::OK,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn atom(body: &str) -> MockResponse {
+        MockResponse {
+            status: StatusCode::OK,
+            headers: vec![(
+                "content-type".to_string(),
+                "application/atom+xml; charset=UTF-8".to_string(),
+            )],
+            body: body.to_string(),
+        }
+    }
+
+    /// Empty response with the status, e.g. `403` for a private subreddit
+    pub fn status(status: StatusCode) -> MockResponse {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: String::new(),
+        }
+    }
+
+    /// Response of the comments endpoint, the first listing carries the post's info
+    pub fn article(score: u64) -> MockResponse {
+        MockResponse::json(json!([
+            {"kind": "Listing", "data": {"children": [
+                {"kind": "t3", "data": {"score": score, "created_utc": 1711706400.0}}
+            ]}},
+            {"kind": "Listing", "data": {"children": []}}
+        ]))
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: &str) -> MockResponse {
+        self.body = body.to_string();
+        self
+    }
+
+    /// Reddit's rate limit headers, see [RedditClient] for how they are handled
+    pub fn with_rate_limit(self, used: u32, remaining: u32, reset_secs: u32) -> MockResponse {
+        self.with_header("x-ratelimit-used", &used.to_string())
+            .with_header("x-ratelimit-remaining", &remaining.to_string())
+            .with_header("x-ratelimit-reset", &reset_secs.to_string())
+    }
+
+    /// `429` asking to come back in `retry_after` seconds
+    pub fn too_many_requests(retry_after: u32) -> MockResponse {
+        MockResponse::status(StatusCode::TOO_MANY_REQUESTS)
+            .with_header("retry-after", &retry_after.to_string())
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Responses by path, without the query
+    responses: Mutex<HashMap<String, MockResponse>>,
+    /// `METHOD /path?query` of every received request, in order
+    requests: Mutex<Vec<String>>,
+}
+
+/// Reddit stand-in serving both the API and the public site on a local port.
+/// The server runs until the test's runtime shuts down
+pub struct MockReddit {
+    url: String,
+    state: Arc<MockState>,
+}
+
+impl MockReddit {
+    pub async fn start() -> MockReddit {
+        let state = Arc::new(MockState::default());
+        let router = Router::new().fallback(respond).with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("cannot bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let mock = MockReddit { url, state };
+        mock.respond(
+            TOKEN_PATH,
+            MockResponse::json(json!({
+                "access_token": "mock-token",
+                "expires_in": 86400,
+                "scope": "*",
+                "token_type": "bearer"
+            })),
+        );
+        mock
+    }
+
+    /// Replaces the response for the path, e.g. `/r/rust/.rss`
+    pub fn respond(&self, path: &str, response: MockResponse) {
+        self.state
+            .responses
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), response);
+    }
+
+    /// Serves [LISTING_RSS] as `r/rust` with the scores of its two posts
+    pub fn serve_listing(&self, scores: [u64; 2]) {
+        self.respond("/r/rust/.rss", MockResponse::atom(LISTING_RSS));
+        self.respond(
+            "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+            MockResponse::article(scores[0]),
+        );
+        self.respond(
+            "/r/rust/comments/bbbbbb/lifetime_question/",
+            MockResponse::article(scores[1]),
+        );
+    }
+
+    /// Received requests as `METHOD /path?query`, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints {
+            api: self.url.clone(),
+            www: self.url.clone(),
+            token: format!("{}{TOKEN_PATH}", self.url),
+        }
+    }
+
+    /// Client talking to the mock server with made up credentials
+    pub fn client(&self) -> RedditClient {
+        RedditClient::new(Arc::new(MockSecrets), Client::new(), self.endpoints())
+    }
+
+    /// Feed provider reading Reddit from the mock server, keeping its state in `store`
+    pub async fn provider(&self, store: &Store) -> RssFeedProvider {
+        provider(self.client(), store).await
+    }
+}
+
+/// Feed provider reading Reddit through `reddit_client`, keeping its state in `store`
+pub async fn provider(reddit_client: RedditClient, store: &Store) -> RssFeedProvider {
+    RssFeedProvider::new(
+        Arc::new(RedditSource::new(
+            Client::new(),
+            reddit_client.clone(),
+            &CacheConfig::default(),
+        )),
+        reddit_client,
+        PublicClient::new(Client::builder()).unwrap(),
+        store.collection("qualified_feeds").await.unwrap(),
+        Archive::new(store.collection("archive").await.unwrap()),
+        Reposts::new(store.collection("reposts").await.unwrap()),
+        &CacheConfig::default(),
+    )
+}
+
+async fn respond(State(state): State<Arc<MockState>>, method: Method, uri: Uri) -> Response {
+    state
+        .requests
+        .lock()
+        .unwrap()
+        .push(format!("{method} {uri}"));
+    let response = state.responses.lock().unwrap().get(uri.path()).cloned();
+    let response = response.unwrap_or_else(|| MockResponse::status(StatusCode::NOT_FOUND));
+    let mut builder = Response::builder().status(response.status);
+    for (name, value) in response.headers {
+        builder = builder.header(name, value);
+    }
+    builder.body(Body::from(response.body)).unwrap()
+}
+
+/// Made up Reddit credentials, the mock server accepts any
+pub(crate) struct MockSecrets;
+
+impl Secrets for MockSecrets {
+    fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "REDDIT_CLIENT_ID" => Some("mock-client".to_string()),
+            "REDDIT_CLIENT_SECRET" => Some("mock-secret".to_string()),
+            "REDDIT_USERNAME" => Some("mock-user".to_string()),
+            "REDDIT_PASSWORD" => Some("mock-password".to_string()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Record/replay of Reddit's responses: a local proxy the client is pointed at
+//! (see [Endpoints]) records real responses into a cassette file,
+//! and replays them later, deterministically and without credentials.
+//!
+//! Cassettes are recorded when `VCR_RECORD` is set, the real credentials are then
+//! taken from the environment, e.g. `VCR_RECORD=1 REDDIT_CLIENT_ID=... cargo test`
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::Response;
+use axum::Router;
+use eyre::Context;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::reddit::client::RedditClient;
+use crate::reddit::endpoints::Endpoints;
+use crate::secrets::EnvSecrets;
+use crate::test_util::MockSecrets;
+
+/// Response headers kept in the cassette, others are not used by the client
+const RECORDED_HEADERS: [&str; 5] = [
+    "content-type",
+    "retry-after",
+    "x-ratelimit-used",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+];
+
+/// Proxy path prefixes of the two Reddit hosts
+const API_PREFIX: &str = "/oauth";
+const WWW_PREFIX: &str = "/www";
+
+/// A recorded request with its response
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Interaction {
+    pub method: String,
+    /// Proxy path and query, e.g. `/www/r/rust/.rss`
+    pub uri: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+struct CassetteState {
+    mode: Mode,
+    upstream: Endpoints,
+    client: Client,
+    interactions: Mutex<Vec<Interaction>>,
+    /// Replayed interactions, a request is answered by the first unused match
+    used: Mutex<Vec<bool>>,
+}
+
+/// Cassette file served by a local proxy, see the module docs
+pub struct Cassette {
+    path: PathBuf,
+    url: String,
+    state: Arc<CassetteState>,
+}
+
+impl Cassette {
+    /// Starts the proxy for the cassette at `path`, e.g. `tests/fixtures/listing_replay.json`
+    pub async fn start(path: impl AsRef<Path>) -> eyre::Result<Cassette> {
+        let path = path.as_ref().to_path_buf();
+        let mode = if std::env::var_os("VCR_RECORD").is_some() {
+            Mode::Record
+        } else {
+            Mode::Replay
+        };
+        let interactions: Vec<Interaction> = match mode {
+            Mode::Record => vec![],
+            Mode::Replay => {
+                let data = std::fs::read_to_string(&path)
+                    .with_context(|| format!("cannot read cassette {}", path.display()))?;
+                serde_json::from_str(&data)
+                    .with_context(|| format!("cannot parse cassette {}", path.display()))?
+            }
+        };
+        let state = Arc::new(CassetteState {
+            mode,
+            upstream: Endpoints::default(),
+            client: Client::new(),
+            used: Mutex::new(vec![false; interactions.len()]),
+            interactions: Mutex::new(interactions),
+        });
+        let router = Router::new().fallback(proxy).with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok(Cassette { path, url, state })
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints {
+            api: format!("{}{API_PREFIX}", self.url),
+            www: format!("{}{WWW_PREFIX}", self.url),
+            token: format!("{}{API_PREFIX}/api/v1/access_token", self.url),
+        }
+    }
+
+    /// Client going through the proxy, with the real credentials when recording
+    pub fn client(&self) -> RedditClient {
+        match self.state.mode {
+            Mode::Record => {
+                RedditClient::new(Arc::new(EnvSecrets::new()), Client::new(), self.endpoints())
+            }
+            Mode::Replay => {
+                RedditClient::new(Arc::new(MockSecrets), Client::new(), self.endpoints())
+            }
+        }
+    }
+
+    /// Writes the recorded interactions to the cassette file, does nothing when replaying
+    pub fn save(&self) -> eyre::Result<()> {
+        if self.state.mode == Mode::Replay {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let interactions = self.state.interactions.lock().unwrap();
+        std::fs::write(&self.path, serde_json::to_string_pretty(&*interactions)?)
+            .with_context(|| format!("cannot write cassette {}", self.path.display()))
+    }
+}
+
+async fn proxy(
+    State(state): State<Arc<CassetteState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let uri = uri.path_and_query().map_or("/", |p| p.as_str()).to_string();
+    let interaction = match state.mode {
+        Mode::Replay => replay(&state, method.as_str(), &uri),
+        Mode::Record => record(&state, method, &uri, headers, body).await,
+    };
+    let Some(interaction) = interaction else {
+        return Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(Body::from(format!("no recorded interaction for {uri}")))
+            .unwrap();
+    };
+    let mut builder = Response::builder().status(interaction.status);
+    for (name, value) in interaction.headers {
+        builder = builder.header(name, value);
+    }
+    builder.body(Body::from(interaction.body)).unwrap()
+}
+
+fn replay(state: &CassetteState, method: &str, uri: &str) -> Option<Interaction> {
+    let interactions = state.interactions.lock().unwrap();
+    let mut used = state.used.lock().unwrap();
+    let matching = |i: &Interaction| i.method == method && i.uri == uri;
+    // a request repeated more often than recorded gets the last response again
+    let index = interactions
+        .iter()
+        .enumerate()
+        .position(|(n, i)| !used[n] && matching(i))
+        .or_else(|| interactions.iter().rposition(matching))?;
+    used[index] = true;
+    Some(interactions[index].clone())
+}
+
+async fn record(
+    state: &CassetteState,
+    method: Method,
+    uri: &str,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Option<Interaction> {
+    let target = if let Some(rest) = uri.strip_prefix(API_PREFIX) {
+        format!("{}{rest}", state.upstream.api)
+    } else {
+        format!("{}{}", state.upstream.www, uri.strip_prefix(WWW_PREFIX)?)
+    };
+    let mut request = state.client.request(method.clone(), target).body(body);
+    for name in [
+        header::AUTHORIZATION,
+        header::CONTENT_TYPE,
+        header::USER_AGENT,
+    ] {
+        if let Some(value) = headers.get(&name) {
+            request = request.header(name, value);
+        }
+    }
+    let response = request.send().await.ok()?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| RECORDED_HEADERS.contains(&name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let mut body = response.text().await.ok()?;
+    if uri.ends_with("/access_token") {
+        body = scrub_token(&body);
+    }
+    let interaction = Interaction {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        status,
+        headers,
+        body,
+    };
+    state.interactions.lock().unwrap().push(interaction.clone());
+    Some(interaction)
+}
+
+/// The access token must not end up in a fixture
+fn scrub_token(body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(mut token) => {
+            token["access_token"] = "recorded-token".into();
+            token.to_string()
+        }
+        Err(_) => body.to_string(),
+    }
+}
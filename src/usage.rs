@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::Reader;
+use crate::error::AppError;
+use crate::store::{Collection, Store};
+
+/// How often the counters are written to the store, they are also written on shutdown
+pub const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Requests made with each client token by client name, counted in memory
+/// and persisted periodically. Enforces the daily quotas, see [Quotas].
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Usage {
+    clients: Arc<Mutex<BTreeMap<String, ClientUsage>>>,
+    store: Collection<ClientUsage>,
+    quotas: Arc<Quotas>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientUsage {
+    /// Requests since the usage is tracked
+    pub requests: u64,
+    /// Unix timestamp of the last request
+    pub last_seen: u64,
+    /// Day `today` counts the requests of, in days since the unix epoch (UTC)
+    pub day: u64,
+    pub today: u64,
+}
+
+/// Daily request quotas of the clients from `DAILY_QUOTAS` secret, e.g. `alice:500,bob:2000`,
+/// other clients get `DEFAULT_DAILY_QUOTA` secret if set, otherwise they are not limited.
+/// Days start at midnight UTC
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Quotas {
+    clients: HashMap<String, u64>,
+    default: Option<u64>,
+}
+
+impl Quotas {
+    pub fn read(reader: &mut Reader) -> Quotas {
+        Quotas {
+            clients: reader.pairs("DAILY_QUOTAS").into_iter().collect(),
+            default: reader.number("DEFAULT_DAILY_QUOTA"),
+        }
+    }
+
+    /// Requests the client may make a day, `None` if unlimited
+    pub fn quota(&self, client: &str) -> Option<u64> {
+        self.clients.get(client).copied().or(self.default)
+    }
+}
+
+impl Usage {
+    /// Counters are restored from `usage` collection of the store
+    pub async fn new(store: &Store, quotas: Quotas) -> eyre::Result<Usage> {
+        let store = store.collection("usage").await?;
+        let clients = store.list().await.into_iter().collect();
+        Ok(Usage {
+            clients: Arc::new(Mutex::new(clients)),
+            store,
+            quotas: Arc::new(quotas),
+        })
+    }
+
+    /// Counts a request of the client, requests over the daily quota are rejected
+    /// and not counted
+    pub fn record(&self, client: &str, now: u64) -> Result<(), AppError> {
+        let mut clients = self.clients.lock().unwrap();
+        let usage = clients.entry(client.to_string()).or_default();
+        let day = now / SECS_PER_DAY;
+        if usage.day != day {
+            usage.day = day;
+            usage.today = 0;
+        }
+        if let Some(quota) = self.quota(client) {
+            if usage.today >= quota {
+                return Err(AppError::QuotaExceeded {
+                    quota,
+                    retry_after: SECS_PER_DAY - now % SECS_PER_DAY,
+                });
+            }
+        }
+        usage.requests += 1;
+        usage.today += 1;
+        usage.last_seen = now;
+        Ok(())
+    }
+
+    pub fn quota(&self, client: &str) -> Option<u64> {
+        self.quotas.quota(client)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ClientUsage> {
+        self.clients.lock().unwrap().clone()
+    }
+
+    /// Writes the counters that changed since the last write
+    pub async fn persist(&self) {
+        for (client, usage) in self.snapshot() {
+            if self.store.get(&client).await.as_ref() == Some(&usage) {
+                continue;
+            }
+            if let Err(e) = self.store.insert(client, usage).await {
+                warn!("cannot persist usage: {e:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn quota_test() {
+        let store = Store::new(
+            std::env::temp_dir().join(format!("redditrss-usage-{}", rand::random::<u64>())),
+        );
+        let quotas = Quotas {
+            clients: HashMap::from([("alice".to_string(), 2)]),
+            default: None,
+        };
+        let usage = Usage::new(&store, quotas).await.unwrap();
+        let day = 20_000 * SECS_PER_DAY;
+        assert!(usage.record("alice", day + 10).is_ok());
+        assert!(usage.record("alice", day + 20).is_ok());
+        assert!(matches!(
+            usage.record("alice", day + 30),
+            Err(AppError::QuotaExceeded {
+                quota: 2,
+                retry_after
+            }) if retry_after == SECS_PER_DAY - 30
+        ));
+        assert!(usage.record("bob", day + 30).is_ok());
+        // quotas reset at midnight
+        assert!(usage.record("alice", day + SECS_PER_DAY).is_ok());
+
+        usage.persist().await;
+        let restored = Usage::new(&store, Quotas::default()).await.unwrap();
+        assert_eq!(
+            restored.snapshot()["alice"],
+            ClientUsage {
+                requests: 3,
+                last_seen: day + SECS_PER_DAY,
+                day: day / SECS_PER_DAY + 1,
+                today: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use atom_syndication::Generator;
+use chrono::DateTime;
+use serde::Serialize;
+
+/// Which build is running, to tell deployments apart when their behavior differs
+#[derive(Serialize, Debug)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Short SHA of the built commit, `unknown` if built outside a git checkout
+    pub git_sha: &'static str,
+    /// RFC 3339 timestamp of the build
+    pub built_at: String,
+    pub features: Vec<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    let built_at = env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .unwrap_or_default();
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        built_at: built_at.to_rfc3339(),
+        features: env!("BUILD_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .collect(),
+    }
+}
+
+/// Feed-level `<generator>` element carrying the build info
+pub fn generator() -> Generator {
+    let info = build_info();
+    let mut version = format!(
+        "{} ({}, built {})",
+        info.version, info.git_sha, info.built_at
+    );
+    if !info.features.is_empty() {
+        version.push_str(&format!(", features: {}", info.features.join(", ")));
+    }
+    Generator {
+        value: env!("CARGO_PKG_NAME").to_string(),
+        uri: Some("https://github.com/hov1417/redditrss".to_string()),
+        version: Some(version),
+    }
+}
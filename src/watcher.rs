@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use eyre::bail;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::profiles::FeedProfile;
+use crate::rss::feed::RssFeedProvider;
+use crate::store::{Collection, Store};
+use crate::webhooks::{Webhooks, POLL_INTERVAL as SINK_POLL_INTERVAL};
+
+/// The watcher looks for the profiles due for a poll this often
+pub const TICK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Profiles are not polled more often than this
+pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Adaptive polls of quiet subreddits are not further apart than this
+const MAX_ADAPTIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Adaptive intervals aim at this many new posts per poll
+const POSTS_PER_POLL: f64 = 2.0;
+
+/// Posting rate is learned from the posts published in this window, 3 days
+const RATE_WINDOW_SECS: i64 = 3 * 24 * 60 * 60;
+
+/// Posting rate of an archive covering less than this is extrapolated from this span,
+/// so a fresh archive does not look busier than it is
+const MIN_RATE_SPAN_SECS: i64 = 60 * 60;
+
+/// Posts per hour, from the publication times of the archived posts of a listing.
+/// `None` without posts in the window
+pub fn posting_rate(published: &[i64], now: i64) -> Option<f64> {
+    let recent = published
+        .iter()
+        .filter(|&&at| at >= now - RATE_WINDOW_SECS && at <= now);
+    let (count, oldest) = recent.fold((0, now), |(count, oldest), &at| (count + 1, oldest.min(at)));
+    if count == 0 {
+        return None;
+    }
+    let span = (now - oldest).max(MIN_RATE_SPAN_SECS);
+    Some(count as f64 * 3600.0 / span as f64)
+}
+
+/// Interval expecting [POSTS_PER_POLL] new posts at the posting rate,
+/// the sink interval if the rate is not known yet
+fn adaptive_interval(posts_per_hour: Option<f64>) -> Duration {
+    let Some(rate) = posts_per_hour.filter(|&rate| rate > 0.0) else {
+        return SINK_POLL_INTERVAL;
+    };
+    Duration::from_secs_f64(POSTS_PER_POLL / rate * 3600.0)
+        .clamp(MIN_INTERVAL, MAX_ADAPTIVE_INTERVAL)
+}
+
+/// Where the polling interval of a profile comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleSource {
+    /// Set through the admin API
+    Admin,
+    /// The profile is delivered to a notification sink
+    Sink,
+}
+
+/// Polling state of a watched profile
+#[derive(Clone, Debug, Serialize)]
+pub struct WatchedProfile {
+    pub id: String,
+    /// Current interval, learned from the posting rate unless set through the admin API
+    pub interval_secs: u64,
+    pub adaptive: bool,
+    /// Posting rate of the profile's subreddits as of the last poll,
+    /// known for adaptive schedules only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub posts_per_hour: Option<f64>,
+    pub source: ScheduleSource,
+    /// Unix timestamps, both missing until the first poll, which is on the next tick
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_poll: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_poll: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct PollState {
+    last_poll: Option<i64>,
+    last_error: Option<String>,
+    /// Posting rate as of the last poll, of adaptive schedules only
+    posts_per_hour: Option<f64>,
+}
+
+/// Polls the stored profiles on their own schedules, keeping their feeds warm
+/// in the cache and delivering them to the notification sinks.
+/// Unless an interval is set, busy subreddits are polled more often than quiet ones,
+/// see [posting_rate]. Polls are postponed while Reddit's rate budget is low,
+/// readers come first.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Watcher {
+    provider: RssFeedProvider,
+    webhooks: Webhooks,
+    profiles: Collection<FeedProfile>,
+    /// Schedules set through the admin API by profile id, `None` for adaptive ones
+    schedules: Collection<Option<u64>>,
+    /// Not persisted, profiles are polled on the first tick after a restart
+    state: Arc<Mutex<HashMap<String, PollState>>>,
+}
+
+impl Watcher {
+    pub async fn new(
+        provider: RssFeedProvider,
+        webhooks: Webhooks,
+        profiles: Collection<FeedProfile>,
+        store: &Store,
+    ) -> eyre::Result<Watcher> {
+        Ok(Watcher {
+            provider,
+            webhooks,
+            profiles,
+            schedules: store.collection("profile_schedules").await?,
+            state: Arc::default(),
+        })
+    }
+
+    /// Watched profiles by id, the admin set schedules override the sink ones.
+    /// The sink profiles are polled adaptively
+    pub async fn list(&self) -> Vec<WatchedProfile> {
+        let mut schedules = BTreeMap::new();
+        for profile in self.webhooks.sink_profiles() {
+            schedules.insert(profile, (None, ScheduleSource::Sink));
+        }
+        for (profile, interval) in self.schedules.list().await {
+            schedules.insert(profile, (interval, ScheduleSource::Admin));
+        }
+        let mut watched = Vec::with_capacity(schedules.len());
+        for (id, (fixed, source)) in schedules {
+            let PollState {
+                last_poll,
+                last_error,
+                posts_per_hour,
+            } = self
+                .state
+                .lock()
+                .unwrap()
+                .get(&id)
+                .cloned()
+                .unwrap_or_default();
+            let posts_per_hour = posts_per_hour.filter(|_| fixed.is_none());
+            let interval_secs =
+                fixed.unwrap_or_else(|| adaptive_interval(posts_per_hour).as_secs());
+            watched.push(WatchedProfile {
+                next_poll: last_poll.map(|last| last + interval_secs as i64),
+                id,
+                interval_secs,
+                adaptive: fixed.is_none(),
+                posts_per_hour,
+                source,
+                last_poll,
+                last_error,
+            });
+        }
+        watched
+    }
+
+    /// Loads the archived posts of the profile, so only computed on its polls
+    async fn posting_rate(&self, id: &str, now: i64) -> Option<f64> {
+        let FeedProfile { definition, .. } = self.profiles.get(id).await?;
+        let published = self
+            .provider
+            .archived_publications(&definition.upstream())
+            .await;
+        posting_rate(&published, now)
+    }
+
+    /// Polls the (existing) profile every `interval`, at least [MIN_INTERVAL],
+    /// or adaptively to its posting rate without one, from the next tick on
+    pub async fn set_interval(&self, id: &str, interval: Option<Duration>) -> eyre::Result<()> {
+        let secs = interval.map(|interval| interval.as_secs());
+        self.schedules.insert(id.to_string(), secs).await?;
+        match secs {
+            Some(secs) => info!("polling profile {id} every {secs}s"),
+            None => info!("polling profile {id} adaptively"),
+        }
+        Ok(())
+    }
+
+    /// Whether the admin set schedule was removed, profiles of the sinks
+    /// fall back to the adaptive sink schedule
+    pub async fn remove_interval(&self, id: &str) -> eyre::Result<bool> {
+        Ok(self.schedules.remove(id).await?.is_some())
+    }
+
+    /// Polls the profiles due, one by one. Stops at the first one
+    /// if the rate budget is low, the rest are polled on a later tick
+    pub async fn tick(&self) {
+        let now = Utc::now().timestamp();
+        for watched in self.list().await {
+            if watched.next_poll.is_some_and(|next| next > now) {
+                continue;
+            }
+            if self.provider.reddit_client().budget_low() {
+                info!("rate budget is low, postponing the profile polls");
+                return;
+            }
+            let error = self.poll(&watched.id).await.err().map(|e| {
+                warn!("cannot poll profile {}: {e:?}", watched.id);
+                format!("{e:#}")
+            });
+            let posts_per_hour = if watched.adaptive {
+                self.posting_rate(&watched.id, now).await
+            } else {
+                None
+            };
+            self.state.lock().unwrap().insert(
+                watched.id,
+                PollState {
+                    last_poll: Some(now),
+                    last_error: error,
+                    posts_per_hour,
+                },
+            );
+        }
+    }
+
+    async fn poll(&self, id: &str) -> eyre::Result<()> {
+        let Some(FeedProfile { definition, .. }) = self.profiles.get(id).await else {
+            bail!("profile does not exist");
+        };
+        self.provider
+            .refresh_feed(definition.upstream(), &definition.options)
+            .await?;
+        // served from the refreshed cache
+        self.webhooks.deliver_profile(id, &definition).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posting_rate_test() {
+        let now = 1_700_000_000;
+        let hour = 60 * 60;
+        assert_eq!(posting_rate(&[], now), None);
+        // too old to count
+        assert_eq!(posting_rate(&[now - RATE_WINDOW_SECS - 1], now), None);
+        // 12 posts over the last 6 hours
+        let busy = (0..12).map(|i| now - i * hour / 2).collect::<Vec<_>>();
+        assert_eq!(posting_rate(&busy, now), Some(12.0 / 5.5));
+        // a fresh archive is extrapolated from at least an hour
+        assert_eq!(posting_rate(&[now - 60, now - 30], now), Some(2.0));
+    }
+
+    #[test]
+    fn adaptive_interval_test() {
+        assert_eq!(adaptive_interval(None), SINK_POLL_INTERVAL);
+        assert_eq!(adaptive_interval(Some(0.0)), SINK_POLL_INTERVAL);
+        assert_eq!(adaptive_interval(Some(12.0)), Duration::from_secs(10 * 60));
+        assert_eq!(adaptive_interval(Some(1000.0)), MIN_INTERVAL);
+        assert_eq!(adaptive_interval(Some(0.1)), MAX_ADAPTIVE_INTERVAL);
+    }
+}
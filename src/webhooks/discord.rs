@@ -0,0 +1,98 @@
+use serde_json::{json, Value};
+
+use crate::rss::digest::unescape;
+use crate::rss::format::entry_url;
+use crate::rss::mask::first_image;
+use crate::webhooks::{entry_flair, Notification};
+
+/// Reddit's orange, the color bar of the embeds
+const EMBED_COLOR: u32 = 0xFF4500;
+
+/// Discord rejects embed titles longer than this
+const MAX_TITLE_CHARS: usize = 256;
+
+/// Execute-webhook payload with an embed of the entry: title, link, score, flair and thumbnail
+pub fn discord_payload(notification: &Notification) -> Value {
+    let Notification { feed, entry, score } = *notification;
+    let title = &entry.title.value;
+    let title = match title.char_indices().nth(MAX_TITLE_CHARS - 1) {
+        Some((i, _)) => format!("{}…", &title[..i]),
+        None => title.clone(),
+    };
+    let mut embed = json!({
+        "title": title,
+        "color": EMBED_COLOR,
+        "footer": { "text": feed },
+        "timestamp": entry.published.unwrap_or(entry.updated).to_rfc3339(),
+    });
+    if let Some(url) = entry_url(entry) {
+        embed["url"] = json!(url);
+    }
+    let mut fields = vec![];
+    if let Some(score) = score {
+        fields.push(json!({ "name": "Score", "value": score.to_string(), "inline": true }));
+    }
+    if let Some(flair) = entry_flair(entry) {
+        fields.push(json!({ "name": "Flair", "value": flair, "inline": true }));
+    }
+    if !fields.is_empty() {
+        embed["fields"] = json!(fields);
+    }
+    let thumbnail = entry
+        .content
+        .as_ref()
+        .and_then(|c| c.value.as_deref())
+        .and_then(first_image);
+    if let Some(thumbnail) = thumbnail {
+        embed["thumbnail"] = json!({ "url": unescape(thumbnail) });
+    }
+    json!({ "embeds": [embed] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rss::feed::post_categories;
+    use atom_syndication::{Content, Entry, Link};
+
+    #[test]
+    fn discord_payload_test() {
+        let entry = Entry {
+            title: "Announcing Rust 1.77".into(),
+            links: vec![Link {
+                href: "https://www.reddit.com/r/rust/comments/abc/announcing/".to_string(),
+                ..Default::default()
+            }],
+            categories: post_categories(Some("news"), Some("blog.rust-lang.org"), false, false),
+            content: Some(Content {
+                value: Some(
+                    r#"<a href="x"><img src="https://b.thumbs.redditmedia.com/a.jpg?w=1&amp;s=2" /></a>"#
+                        .to_string(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let notification = Notification {
+            feed: "r/rust",
+            entry: &entry,
+            score: Some(512),
+        };
+        let embed = &discord_payload(&notification)["embeds"][0];
+        assert_eq!(
+            embed["url"],
+            "https://www.reddit.com/r/rust/comments/abc/announcing/"
+        );
+        assert_eq!(
+            embed["fields"],
+            json!([
+                { "name": "Score", "value": "512", "inline": true },
+                { "name": "Flair", "value": "news", "inline": true },
+            ])
+        );
+        assert_eq!(
+            embed["thumbnail"]["url"],
+            "https://b.thumbs.redditmedia.com/a.jpg?w=1&s=2"
+        );
+    }
+}
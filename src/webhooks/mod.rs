@@ -0,0 +1,472 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use atom_syndication::Entry;
+use chrono::Utc;
+use eyre::{bail, eyre};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::config::Reader;
+use crate::profiles::ProfileDefinition;
+use crate::reddit::is_valid_name;
+use crate::rss::feed::{entry_fullname, FeedOptions, RssFeedProvider, Upstream};
+use crate::rss::format::json_feed_item;
+use crate::store::{Collection, Store};
+use crate::webhooks::discord::discord_payload;
+use crate::webhooks::ntfy::{Ntfy, NtfyConfig};
+use crate::webhooks::telegram::{Telegram, TelegramConfig};
+
+pub mod discord;
+pub mod ntfy;
+pub mod telegram;
+
+/// Feeds of the webhooks are checked this often
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Delivered entries are remembered this long after leaving the feed, 7 days
+const DELIVERED_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header with the hex HMAC-SHA256 of the body, keyed with the webhook's secret
+pub const SIGNATURE_HEADER: &str = "x-redditrss-signature";
+
+/// Default limits of the sinks, Telegram allows 20 messages a minute in groups
+const TELEGRAM_PER_HOUR: u32 = 60;
+const NTFY_PER_HOUR: u32 = 20;
+
+/// Length of generated webhook ids
+const WEBHOOK_ID_LENGTH: usize = 8;
+
+/// New entry of a watched feed
+#[derive(Clone, Copy)]
+pub struct Notification<'a> {
+    /// Upstream of the feed, e.g. `r/rust+programming`
+    pub feed: &'a str,
+    pub entry: &'a Entry,
+    /// Latest known score of the post
+    pub score: Option<u64>,
+}
+
+/// Destination of the new entries of a watched feed
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: &Notification<'_>) -> eyre::Result<()>;
+}
+
+/// Postpones the notifications over the quota of the sink to the next polls
+pub struct RateLimited<N> {
+    notifier: N,
+    limiter: DefaultDirectRateLimiter,
+}
+
+impl<N: Notifier> RateLimited<N> {
+    pub fn new(notifier: N, per_hour: NonZeroU32) -> RateLimited<N> {
+        RateLimited {
+            notifier,
+            limiter: RateLimiter::direct(Quota::per_hour(per_hour)),
+        }
+    }
+}
+
+#[async_trait]
+impl<N: Notifier> Notifier for RateLimited<N> {
+    async fn notify(&self, notification: &Notification<'_>) -> eyre::Result<()> {
+        if self.limiter.check().is_err() {
+            bail!("rate limited");
+        }
+        self.notifier.notify(notification).await
+    }
+}
+
+/// Stored profile delivered to a sink and its limit
+#[derive(Clone, Debug)]
+pub struct SinkConfig<T> {
+    pub notifier: T,
+    pub profile: String,
+    pub per_hour: NonZeroU32,
+}
+
+impl<T> SinkConfig<T> {
+    /// Profile is taken from `<ID>_PROFILE` secret and the limit from `<ID>_MAX_PER_HOUR`
+    fn read(reader: &mut Reader, id: &str, notifier: T, default_per_hour: u32) -> Option<Self> {
+        let prefix = id.to_uppercase();
+        let per_hour = reader
+            .number(&format!("{prefix}_MAX_PER_HOUR"))
+            .unwrap_or(NonZeroU32::new(default_per_hour).unwrap());
+        let Some(profile) = reader.string(&format!("{prefix}_PROFILE")) else {
+            reader.error(format!("{prefix}_PROFILE is required to deliver to {id}"));
+            return None;
+        };
+        Some(SinkConfig {
+            notifier,
+            profile,
+            per_hour,
+        })
+    }
+}
+
+/// Configured webhooks and the sinks of the stored profiles
+#[derive(Clone, Debug, Default)]
+pub struct WebhookConfig {
+    pub webhooks: Vec<Webhook>,
+    pub telegram: Option<SinkConfig<TelegramConfig>>,
+    pub ntfy: Option<SinkConfig<NtfyConfig>>,
+}
+
+impl WebhookConfig {
+    /// `WEBHOOKS` secret is a JSON array of [Webhook]s, e.g.
+    /// `[{"url": "https://example.com/hook", "subreddits": ["rust"], "min_score": 200}]`,
+    /// the sinks are configured with [TelegramConfig::read] and [NtfyConfig::read]
+    pub fn read(reader: &mut Reader) -> WebhookConfig {
+        let webhooks = reader.parse("WEBHOOKS", |webhooks| {
+            let webhooks: Vec<Webhook> = serde_json::from_str(webhooks)?;
+            for webhook in &webhooks {
+                webhook.validate().map_err(|e| eyre!(e))?;
+            }
+            eyre::Ok(webhooks)
+        });
+        let telegram = TelegramConfig::read(reader)
+            .and_then(|telegram| SinkConfig::read(reader, "telegram", telegram, TELEGRAM_PER_HOUR));
+        let ntfy = NtfyConfig::read(reader)
+            .and_then(|ntfy| SinkConfig::read(reader, "ntfy", ntfy, NTFY_PER_HOUR));
+        WebhookConfig {
+            webhooks: webhooks.unwrap_or_default(),
+            telegram,
+            ntfy,
+        }
+    }
+}
+
+/// Sink of the new entries of a stored profile
+struct ProfileSink {
+    /// Prefix of its secrets, in lower case, e.g. `telegram`
+    id: &'static str,
+    profile: String,
+    notifier: Box<dyn Notifier>,
+}
+
+impl ProfileSink {
+    fn new<T>(id: &'static str, config: &SinkConfig<T>, notifier: impl Notifier + 'static) -> Self {
+        ProfileSink {
+            id,
+            profile: config.profile.clone(),
+            notifier: Box::new(RateLimited::new(notifier, config.per_hour)),
+        }
+    }
+}
+
+/// Body of the deliveries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// `{"webhook": id, "feed": "r/rust", "entry": <JSON Feed item>}`
+    #[default]
+    Json,
+    /// Discord execute-webhook payload with an embed of the post, see [discord_payload]
+    Discord,
+}
+
+/// Posts newly qualifying for a feed, POSTed to `url` as JSON
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Subreddits merged into the feed
+    pub subreddits: Vec<String>,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Key of the [SIGNATURE_HEADER], deliveries are not signed without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(flatten)]
+    pub options: FeedOptions,
+}
+
+impl Webhook {
+    pub fn validate(&self) -> Result<(), String> {
+        match Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => return Err(format!("Invalid webhook URL: {:?}", self.url)),
+        }
+        if self.subreddits.is_empty() {
+            return Err("At least one subreddit is required".to_string());
+        }
+        match self.subreddits.iter().find(|s| !is_valid_name(s)) {
+            Some(invalid) => Err(format!("Invalid subreddit name: {invalid:?}")),
+            None => Ok(()),
+        }
+    }
+
+    fn upstream(&self) -> Upstream {
+        Upstream::Subreddit(format!("r/{}", self.subreddits.join("+")))
+    }
+}
+
+/// Watcher of the feeds of the webhooks, configured with [WebhookConfig] or registered
+/// through the admin API. Delivers the stored profiles to Telegram and ntfy too,
+/// when the [crate::watcher::Watcher] polls them.
+///
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub struct Webhooks {
+    provider: RssFeedProvider,
+    client: Client,
+    /// Registered through the admin API, by id
+    registered: Collection<Webhook>,
+    /// Configured ones, by `config-<index>` ids
+    configured: Arc<BTreeMap<String, Webhook>>,
+    /// Delivered entry ids with the (unix) time they were last in the feed, by webhook
+    /// or sink id
+    delivered: Collection<BTreeMap<String, i64>>,
+    sinks: Arc<Vec<ProfileSink>>,
+}
+
+impl Webhooks {
+    pub async fn new(
+        provider: RssFeedProvider,
+        config: &WebhookConfig,
+        store: &Store,
+    ) -> eyre::Result<Webhooks> {
+        let client = Client::builder().timeout(DELIVERY_TIMEOUT).build()?;
+        let mut sinks = vec![];
+        if let Some(telegram) = &config.telegram {
+            let notifier = Telegram::new(client.clone(), &telegram.notifier);
+            sinks.push(ProfileSink::new("telegram", telegram, notifier));
+        }
+        if let Some(ntfy) = &config.ntfy {
+            let notifier = Ntfy::new(client.clone(), &ntfy.notifier);
+            sinks.push(ProfileSink::new("ntfy", ntfy, notifier));
+        }
+        Ok(Webhooks {
+            provider,
+            sinks: Arc::new(sinks),
+            client,
+            registered: store.collection("webhooks").await?,
+            configured: Arc::new(
+                config
+                    .webhooks
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .map(|(i, webhook)| (format!("config-{i}"), webhook))
+                    .collect(),
+            ),
+            delivered: store.collection("webhook_deliveries").await?,
+        })
+    }
+
+    /// Configured webhooks first, then the registered ones
+    pub async fn list(&self) -> Vec<(String, Webhook)> {
+        let mut webhooks = self
+            .configured
+            .iter()
+            .map(|(id, webhook)| (id.clone(), webhook.clone()))
+            .collect::<Vec<_>>();
+        webhooks.extend(self.registered.list().await);
+        webhooks
+    }
+
+    /// Registers the (validated) webhook, returns its id
+    pub async fn register(&self, webhook: Webhook) -> eyre::Result<String> {
+        let id = loop {
+            let id = Alphanumeric.sample_string(&mut rand::thread_rng(), WEBHOOK_ID_LENGTH);
+            if !self.registered.contains(&id).await {
+                break id;
+            }
+        };
+        self.registered.insert(id.clone(), webhook).await?;
+        info!("registered webhook {id}");
+        Ok(id)
+    }
+
+    /// Whether the webhook was registered, configured ones cannot be removed
+    pub async fn unregister(&self, id: &str) -> eyre::Result<bool> {
+        let removed = self.registered.remove(id).await?.is_some();
+        if removed {
+            self.delivered.remove(id).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Delivers the new entries of every webhook's feed
+    pub async fn poll(&self) {
+        for (id, webhook) in self.list().await {
+            let notifier = WebhookNotifier {
+                client: &self.client,
+                id: &id,
+                webhook: &webhook,
+            };
+            let watched = self.watch(&id, webhook.upstream(), &webhook.options, &notifier);
+            if let Err(e) = watched.await {
+                warn!("cannot poll webhook {id}: {e:?}");
+            }
+        }
+    }
+
+    /// Profiles delivered to the sinks, polled by the [crate::watcher::Watcher]
+    pub fn sink_profiles(&self) -> Vec<String> {
+        self.sinks.iter().map(|sink| sink.profile.clone()).collect()
+    }
+
+    /// Delivers the new entries of the profile to the sinks it is delivered to
+    pub async fn deliver_profile(&self, profile: &str, definition: &ProfileDefinition) {
+        for sink in self.sinks.iter().filter(|sink| sink.profile == profile) {
+            let watched = self.watch(
+                sink.id,
+                definition.upstream(),
+                &definition.options,
+                sink.notifier.as_ref(),
+            );
+            if let Err(e) = watched.await {
+                warn!("cannot poll profile of {}: {e:?}", sink.id);
+            }
+        }
+    }
+
+    /// Notifies the entries of the feed not delivered to the watcher `id` yet
+    async fn watch(
+        &self,
+        id: &str,
+        upstream: Upstream,
+        options: &FeedOptions,
+        notifier: &dyn Notifier,
+    ) -> eyre::Result<()> {
+        let feed = self.provider.feed_filter(upstream.clone(), options).await?;
+        let now = Utc::now().timestamp();
+        let previously = self.delivered.get(id).await;
+        let mut delivered = previously.clone().unwrap_or_default();
+        let scores = self.provider.archived_scores(&upstream).await;
+        let feed_name = upstream.to_string();
+        for entry in pending(previously.as_ref(), &feed.entries) {
+            let notification = Notification {
+                feed: &feed_name,
+                entry,
+                score: scores.get(entry_fullname(&entry.id)).copied(),
+            };
+            match notifier.notify(&notification).await {
+                Ok(()) => {
+                    info!("delivered {} to {id}", entry.id);
+                    delivered.insert(entry_fullname(&entry.id).to_string(), now);
+                }
+                // retried on the next poll
+                Err(e) => warn!("cannot deliver {} to {id}: {e:?}", entry.id),
+            }
+        }
+        // entries still in the feed are kept, so they are not delivered again
+        for entry in &feed.entries {
+            let key = entry_fullname(&entry.id);
+            if previously.is_none() || delivered.contains_key(key) {
+                delivered.insert(key.to_string(), now);
+            }
+        }
+        delivered.retain(|_, seen| *seen >= now - DELIVERED_RETENTION_SECS);
+        if previously.as_ref() != Some(&delivered) {
+            self.delivered.insert(id.to_string(), delivered).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Delivers to a [Webhook], in its format
+struct WebhookNotifier<'a> {
+    client: &'a Client,
+    id: &'a str,
+    webhook: &'a Webhook,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier<'_> {
+    async fn notify(&self, notification: &Notification<'_>) -> eyre::Result<()> {
+        let body = match self.webhook.format {
+            WebhookFormat::Json => json!({
+                "webhook": self.id,
+                "feed": notification.feed,
+                "entry": json_feed_item(notification.entry),
+            }),
+            WebhookFormat::Discord => discord_payload(notification),
+        }
+        .to_string();
+        let mut request = self
+            .client
+            .post(&self.webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(secret) = &self.webhook.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body.as_bytes()));
+        }
+        request.body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Score, flair and feed of the post, e.g. `321 points · discussion · r/rust`
+fn summary(notification: &Notification) -> String {
+    let mut details = vec![];
+    if let Some(score) = notification.score {
+        details.push(format!("{score} points"));
+    }
+    if let Some(flair) = entry_flair(notification.entry) {
+        details.push(flair.to_string());
+    }
+    details.push(notification.feed.to_string());
+    details.join(" · ")
+}
+
+/// Flair of the post, from its categories
+fn entry_flair(entry: &Entry) -> Option<&str> {
+    entry
+        .categories
+        .iter()
+        .find(|c| c.scheme.as_deref().is_some_and(|s| s.ends_with("#flair")))
+        .map(|c| c.term.as_str())
+}
+
+/// Entries not delivered yet, by the fullname of their post. Nothing is pending
+/// on the first poll, when `delivered` is `None`, so registering a webhook
+/// does not replay the whole feed
+fn pending<'a>(
+    delivered: Option<&BTreeMap<String, i64>>,
+    entries: &'a [Entry],
+) -> impl Iterator<Item = &'a Entry> + 'a {
+    let delivered = delivered.cloned();
+    entries.iter().filter(move |entry| {
+        delivered
+            .as_ref()
+            .is_some_and(|delivered| !delivered.contains_key(entry_fullname(&entry.id)))
+    })
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_test() {
+        let entries = ["urn:reddit:t3_a", "urn:reddit:t3_b"].map(|id| Entry {
+            id: id.to_string(),
+            ..Default::default()
+        });
+        assert_eq!(pending(None, &entries).count(), 0);
+        let delivered = BTreeMap::from([("t3_a".to_string(), 0)]);
+        let ids = pending(Some(&delivered), &entries)
+            .map(|e| e.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["urn:reddit:t3_b"]);
+    }
+}
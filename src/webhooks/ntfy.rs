@@ -0,0 +1,129 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use eyre::bail;
+use reqwest::{Client, Url};
+use serde_json::{json, Value};
+
+use crate::config::Reader;
+use crate::rss::format::entry_url;
+use crate::webhooks::{summary, Notification, Notifier};
+
+/// Topic the [Ntfy] notifications are published to
+#[derive(Clone)]
+pub struct NtfyConfig {
+    server: Url,
+    topic: String,
+    token: Option<String>,
+}
+
+impl NtfyConfig {
+    /// Taken from `NTFY_TOPIC_URL` secret, e.g. `https://ntfy.sh/my-reddit`,
+    /// and `NTFY_TOKEN` of protected topics. `None` without a topic
+    pub fn read(reader: &mut Reader) -> Option<NtfyConfig> {
+        let (server, topic) = reader.parse("NTFY_TOPIC_URL", |topic_url| {
+            let mut server = Url::parse(topic_url)?;
+            let topic = match server.path_segments().and_then(|mut s| s.next_back()) {
+                Some(topic) if !topic.is_empty() => topic.to_string(),
+                _ => bail!("no topic in {topic_url}"),
+            };
+            server.set_path("/");
+            Ok((server, topic))
+        })?;
+        Some(NtfyConfig {
+            server,
+            topic,
+            token: reader.string("NTFY_TOKEN"),
+        })
+    }
+}
+
+impl fmt::Debug for NtfyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NtfyConfig")
+            .field("server", &self.server)
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Push notifications through an ntfy server, e.g. ntfy.sh
+pub struct Ntfy {
+    client: Client,
+    /// Root of the server, messages are published there as JSON
+    server: Url,
+    topic: String,
+    /// Access token of protected topics
+    token: Option<String>,
+}
+
+impl Ntfy {
+    pub fn new(client: Client, config: &NtfyConfig) -> Ntfy {
+        Ntfy {
+            client,
+            server: config.server.clone(),
+            topic: config.topic.clone(),
+            token: config.token.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Ntfy {
+    async fn notify(&self, notification: &Notification<'_>) -> eyre::Result<()> {
+        let mut request = self
+            .client
+            .post(self.server.clone())
+            .json(&ntfy_message(&self.topic, notification));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Title of the post, its [summary] as the message, opening the post on click
+fn ntfy_message(topic: &str, notification: &Notification) -> Value {
+    let mut message = json!({
+        "topic": topic,
+        "title": notification.entry.title.value,
+        "message": summary(notification),
+    });
+    if let Some(url) = entry_url(notification.entry) {
+        message["click"] = json!(url);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atom_syndication::{Entry, Link};
+
+    #[test]
+    fn ntfy_message_test() {
+        let entry = Entry {
+            title: "Rust 2024 is stable".into(),
+            links: vec![Link {
+                href: "https://www.reddit.com/r/rust/comments/abc/rust_2024/".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let notification = Notification {
+            feed: "r/rust",
+            entry: &entry,
+            score: Some(1500),
+        };
+        assert_eq!(
+            ntfy_message("my-reddit", &notification),
+            json!({
+                "topic": "my-reddit",
+                "title": "Rust 2024 is stable",
+                "message": "1500 points · r/rust",
+                "click": "https://www.reddit.com/r/rust/comments/abc/rust_2024/",
+            })
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::config::Reader;
+use crate::rss::digest::escape;
+use crate::rss::format::entry_url;
+use crate::webhooks::{summary, Notification, Notifier};
+
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// Chat the [Telegram] bot posts to
+#[derive(Clone)]
+pub struct TelegramConfig {
+    token: String,
+    chat_id: String,
+}
+
+impl TelegramConfig {
+    /// Taken from `TELEGRAM_BOT_TOKEN` and `TELEGRAM_CHAT_ID` secrets, `None` without a token
+    pub fn read(reader: &mut Reader) -> Option<TelegramConfig> {
+        let token = reader.string("TELEGRAM_BOT_TOKEN")?;
+        let Some(chat_id) = reader.string("TELEGRAM_CHAT_ID") else {
+            reader.error("TELEGRAM_CHAT_ID is required with TELEGRAM_BOT_TOKEN");
+            return None;
+        };
+        Some(TelegramConfig { token, chat_id })
+    }
+}
+
+impl fmt::Debug for TelegramConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelegramConfig")
+            .field("chat_id", &self.chat_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bot posting the new entries to a Telegram chat
+pub struct Telegram {
+    client: Client,
+    token: String,
+    chat_id: String,
+}
+
+impl Telegram {
+    pub fn new(client: Client, config: &TelegramConfig) -> Telegram {
+        Telegram {
+            client,
+            token: config.token.clone(),
+            chat_id: config.chat_id.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    async fn notify(&self, notification: &Notification<'_>) -> eyre::Result<()> {
+        let body = json!({
+            "chat_id": self.chat_id,
+            "text": telegram_message(notification),
+            "parse_mode": "HTML",
+        });
+        self.client
+            .post(format!("{TELEGRAM_API}/bot{}/sendMessage", self.token))
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            // the URL has the token
+            .map_err(|e| e.without_url())?;
+        Ok(())
+    }
+}
+
+/// Linked title, then the [summary] of the post
+fn telegram_message(notification: &Notification) -> String {
+    let entry = notification.entry;
+    let title = escape(&entry.title.value);
+    let mut message = match entry_url(entry) {
+        Some(url) => format!("<b><a href=\"{}\">{title}</a></b>", escape(url)),
+        None => format!("<b>{title}</b>"),
+    };
+    message.push('\n');
+    message.push_str(&escape(&summary(notification)));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rss::feed::post_categories;
+    use atom_syndication::{Entry, Link};
+
+    #[test]
+    fn telegram_message_test() {
+        let entry = Entry {
+            title: "Vec<T> & friends".into(),
+            links: vec![Link {
+                href: "https://www.reddit.com/r/rust/comments/abc/vec/".to_string(),
+                ..Default::default()
+            }],
+            categories: post_categories(Some("discussion"), None, false, false),
+            ..Default::default()
+        };
+        let notification = Notification {
+            feed: "r/rust",
+            entry: &entry,
+            score: Some(321),
+        };
+        assert_eq!(
+            telegram_message(&notification),
+            "<b><a href=\"https://www.reddit.com/r/rust/comments/abc/vec/\">Vec&lt;T&gt; &amp; friends</a></b>\n\
+             321 points · discussion · r/rust"
+        );
+    }
+}
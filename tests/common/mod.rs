@@ -0,0 +1,28 @@
+use std::ops::Deref;
+
+use redditrss::store::Store;
+use tempfile::TempDir;
+
+/// Store in a temporary directory, removed when dropped
+pub struct TempStore {
+    store: Store,
+    _dir: TempDir,
+}
+
+impl TempStore {
+    pub fn new() -> TempStore {
+        let dir = TempDir::with_prefix("redditrss-test-").unwrap();
+        TempStore {
+            store: Store::new(dir.path()),
+            _dir: dir,
+        }
+    }
+}
+
+impl Deref for TempStore {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        &self.store
+    }
+}
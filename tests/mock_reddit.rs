@@ -0,0 +1,683 @@
+mod common;
+
+use common::TempStore;
+use redditrss::cache::CacheConfig;
+use redditrss::error::{AppError, UpstreamError};
+use redditrss::http::PublicClient;
+use redditrss::jobs::{JobStatus, Jobs};
+use redditrss::profiles::{FeedProfile, ProfileDefinition};
+use redditrss::readiness::{Check, Readiness, REQUIRED_SECRETS};
+use redditrss::rss::feed::{FeedOptions, Upstream};
+use redditrss::rss::preview::Previews;
+use redditrss::scheduler::Shutdown;
+use redditrss::snapshots::Snapshots;
+use redditrss::test_util::{MockReddit, MockResponse, LISTING_RSS};
+use redditrss::watcher::{ScheduleSource, Watcher};
+use redditrss::webhooks::{WebhookConfig, Webhooks};
+use reqwest::Url;
+use rusty_s3::{Bucket, Credentials, UrlStyle};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn options(min_score: u64) -> FeedOptions {
+    serde_json::from_value(serde_json::json!({ "min_score": min_score })).unwrap()
+}
+
+#[tokio::test]
+async fn feed_filter_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options(100))
+        .await
+        .unwrap();
+
+    let ids = feed
+        .entries
+        .iter()
+        .map(|e| e.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["urn:reddit:t3_aaaaaa"]);
+    assert!(reddit
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("POST /api/v1/access_token")));
+    let metrics = provider.metrics();
+    assert_eq!(metrics["score_fetched"].count, 2);
+    assert_eq!(metrics["score_found"].count, 2);
+    assert_eq!(metrics["score_cached"].count, 0);
+}
+
+#[tokio::test]
+async fn verbatim_feed_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+    let upstream = || Upstream::Subreddit("r/rust".to_string());
+    let mut options = options(100);
+    options.verbatim = true;
+
+    let filtered = provider.filtered_feed(upstream(), &options).await.unwrap();
+
+    let document = String::from_utf8(filtered.document.unwrap().to_vec()).unwrap();
+    let dropped_start = LISTING_RSS.find("<entry><author><name>/u/crab").unwrap();
+    let dropped_end = LISTING_RSS.rfind("</feed>").unwrap();
+    let expected = format!(
+        "{}{}",
+        &LISTING_RSS[..dropped_start],
+        &LISTING_RSS[dropped_end..]
+    )
+    .replace("<id>t3_aaaaaa</id>", "<id>urn:reddit:t3_aaaaaa</id>");
+    assert_eq!(document, expected);
+
+    // rewriting the entries, the feed is rendered
+    options.mark_edited = true;
+    let filtered = provider.filtered_feed(upstream(), &options).await.unwrap();
+    assert!(filtered.document.is_none());
+}
+
+#[tokio::test]
+async fn cached_feed_expiry_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+    let upstream = || Upstream::Subreddit("r/rust".to_string());
+
+    let first = provider
+        .filtered_feed(upstream(), &options(100))
+        .await
+        .unwrap();
+    let cached = provider
+        .filtered_feed(upstream(), &options(100))
+        .await
+        .unwrap();
+
+    // the cached feed expires with the cache entry, not on every poll
+    assert_eq!(cached.generated, first.generated);
+    assert_eq!(
+        provider.expires(&cached) - cached.generated,
+        chrono::TimeDelta::minutes(10)
+    );
+}
+
+#[tokio::test]
+async fn removed_post_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::json(serde_json::json!([
+            {"kind": "Listing", "data": {"children": [
+                {"kind": "t3", "data": {"score": 250, "removed_by_category": "deleted"}}
+            ]}},
+            {"kind": "Listing", "data": {"children": []}}
+        ])),
+    );
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+
+    // removed posts are kept unless asked otherwise
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options(0))
+        .await
+        .unwrap();
+    assert_eq!(feed.entries.len(), 2);
+
+    let options = serde_json::from_value(serde_json::json!({
+        "min_score": 0,
+        "hide_removed": true,
+    }))
+    .unwrap();
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options)
+        .await
+        .unwrap();
+    let ids = feed
+        .entries
+        .iter()
+        .map(|e| e.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["urn:reddit:t3_bbbbbb"]);
+}
+
+#[tokio::test]
+async fn promote_late_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+    let options = serde_json::from_value(serde_json::json!({
+        "min_score": 100,
+        "promote_late": true,
+    }))
+    .unwrap();
+
+    // nothing is promoted on the first generation
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options)
+        .await
+        .unwrap();
+    assert_eq!(feed.entries.len(), 1);
+    assert_eq!(
+        feed.entries[0].updated.to_rfc3339(),
+        "2024-03-29T10:00:00+00:00"
+    );
+}
+
+#[tokio::test]
+async fn author_filter_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 250]);
+    let article = |author: &str| {
+        MockResponse::json(serde_json::json!([
+            {"kind": "Listing", "data": {"children": [
+                {"kind": "t3", "data": {"score": 250, "author": author}}
+            ]}},
+            {"kind": "Listing", "data": {"children": []}}
+        ]))
+    };
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        article("veteran"),
+    );
+    reddit.respond(
+        "/r/rust/comments/bbbbbb/lifetime_question/",
+        article("spammer"),
+    );
+    reddit.respond(
+        "/user/veteran/about",
+        MockResponse::json(serde_json::json!({"kind": "t2", "data": {
+            "total_karma": 5000, "created_utc": 1420070400.0
+        }})),
+    );
+    // `/user/spammer/about` is not found, like shadow-banned accounts
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+
+    let options = serde_json::from_value(serde_json::json!({
+        "min_score": 0,
+        "min_author_karma": 100,
+        "min_author_age_days": 30,
+    }))
+    .unwrap();
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options)
+        .await
+        .unwrap();
+
+    let ids = feed
+        .entries
+        .iter()
+        .map(|e| e.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["urn:reddit:t3_aaaaaa"]);
+}
+
+#[tokio::test]
+async fn link_preview_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let page = format!("{}/blog/post", reddit.endpoints().www);
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::json(serde_json::json!([
+            {"kind": "Listing", "data": {"children": [
+                {"kind": "t3", "data": {"score": 250, "url": page, "is_self": false}}
+            ]}},
+            {"kind": "Listing", "data": {"children": []}}
+        ])),
+    );
+    reddit.respond(
+        "/blog/post",
+        MockResponse {
+            body: r#"<head><meta property="og:title" content="Announcing Rust"></head>"#
+                .to_string(),
+            ..MockResponse::status(axum::http::StatusCode::OK)
+                .with_header("content-type", "text/html; charset=utf-8")
+        },
+    );
+    // the mock server is local, the public addresses are checked in `http`
+    let client = PublicClient::allowing_private(reqwest::Client::builder()).unwrap();
+    let store = TempStore::new();
+    let provider = reddit
+        .provider(&store)
+        .await
+        .with_previews(Previews::new(client, &CacheConfig::default()));
+
+    let options = serde_json::from_value(serde_json::json!({
+        "min_score": 100,
+        "link_preview": true,
+    }))
+    .unwrap();
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options)
+        .await
+        .unwrap();
+
+    let content = feed.entries[0]
+        .content
+        .as_ref()
+        .unwrap()
+        .value
+        .as_ref()
+        .unwrap();
+    assert!(content.ends_with("<blockquote><p><strong>Announcing Rust</strong></p></blockquote>"));
+    assert!(reddit.requests().contains(&"GET /robots.txt".to_string()));
+}
+
+#[tokio::test]
+async fn feed_job_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let store = TempStore::new();
+    let jobs = Jobs::new(reddit.provider(&store).await, &store)
+        .await
+        .unwrap();
+    let shutdown = Shutdown::default();
+    let (runner, stopping) = (jobs.clone(), shutdown.clone());
+    shutdown.spawn(async move { runner.run(stopping).await });
+
+    let definition = ProfileDefinition {
+        subreddits: vec!["rust".to_string()],
+        max_age: None,
+        options: options(100),
+    };
+    let id = jobs.submit("reader", definition).await.unwrap();
+    let job = loop {
+        let job = jobs.get(&id).await.unwrap();
+        if !matches!(job.status, JobStatus::Queued | JobStatus::Running) {
+            break job;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    };
+    shutdown.stop().await;
+
+    assert_eq!(job.status, JobStatus::Done);
+    assert_eq!(job.owner, "reader");
+    let ids = jobs
+        .feed(&id)
+        .await
+        .unwrap()
+        .entries
+        .iter()
+        .map(|e| e.id.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["urn:reddit:t3_aaaaaa"]);
+}
+
+#[tokio::test]
+async fn job_limit_test() {
+    let reddit = MockReddit::start().await;
+    let store = TempStore::new();
+    let jobs = Jobs::new(reddit.provider(&store).await, &store)
+        .await
+        .unwrap();
+    let definition = ProfileDefinition {
+        subreddits: vec!["rust".to_string()],
+        max_age: None,
+        options: options(100),
+    };
+    // not running, the jobs stay queued
+    for _ in 0..5 {
+        jobs.submit("reader", definition.clone()).await.unwrap();
+    }
+    assert!(matches!(
+        jobs.submit("reader", definition.clone()).await,
+        Err(AppError::TooManyJobs { limit: 5 })
+    ));
+    assert!(jobs.submit("other", definition).await.is_ok());
+}
+
+#[tokio::test]
+async fn forbidden_listing_test() {
+    let reddit = MockReddit::start().await;
+    reddit.respond(
+        "/r/secret/.rss",
+        MockResponse::status(axum::http::StatusCode::FORBIDDEN),
+    );
+    reddit.respond(
+        "/r/secret/hot",
+        MockResponse::status(axum::http::StatusCode::FORBIDDEN),
+    );
+    reddit.respond(
+        "/r/secret/about",
+        MockResponse::status(axum::http::StatusCode::FORBIDDEN)
+            .with_header("content-type", "application/json"),
+    );
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+
+    let report = provider
+        .feed_filter(Upstream::Subreddit("r/secret".to_string()), &options(0))
+        .await
+        .unwrap_err();
+
+    assert!(report.downcast_ref::<UpstreamError>().is_some());
+}
+
+#[tokio::test]
+async fn blocked_listing_test() {
+    let reddit = MockReddit::start().await;
+    reddit.respond(
+        "/search.rss",
+        MockResponse::status(axum::http::StatusCode::FORBIDDEN)
+            .with_header("content-type", "text/html; charset=utf-8")
+            .with_body("<html><body>You've been blocked by network security.</body></html>"),
+    );
+    reddit.respond(
+        "/search",
+        MockResponse::json(serde_json::json!({"kind": "Listing", "data": {"children": [
+            {"kind": "t3", "data": {
+                "name": "t3_cccccc",
+                "title": "Async closures are stable",
+                "permalink": "/r/rust/comments/cccccc/async_closures_are_stable/",
+                "author": "ferris",
+                "score": 321,
+                "created_utc": 1711706400.0,
+                "subreddit_name_prefixed": "r/rust"
+            }}
+        ]}})),
+    );
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+    let upstream = Upstream::Search {
+        query: "async closures".to_string(),
+        subreddit: None,
+        sort: "new".to_string(),
+    };
+
+    let feed = provider.feed_filter(upstream, &options(100)).await.unwrap();
+
+    assert_eq!(feed.entries().len(), 1);
+    assert_eq!(
+        feed.entries()[0].title().as_str(),
+        "Async closures are stable"
+    );
+    assert!(reddit
+        .requests()
+        .iter()
+        .any(|r| r.starts_with("GET /search?q=async+closures")));
+}
+
+#[tokio::test]
+async fn rate_limit_test() {
+    let reddit = MockReddit::start().await;
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::article(10).with_rate_limit(599, 0, 120),
+    );
+    let client = reddit.client();
+
+    client
+        .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+        .await
+        .unwrap();
+    let report = client
+        .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        report.downcast_ref::<UpstreamError>(),
+        Some(UpstreamError::RateLimited {
+            retry_after: Some(_)
+        })
+    ));
+}
+
+#[tokio::test]
+async fn rate_limited_not_retried_test() {
+    let reddit = MockReddit::start().await;
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::too_many_requests(1),
+    );
+    let client = reddit.client();
+
+    // answered right away instead of waiting out the retry-after
+    let report = client
+        .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        report.downcast_ref::<UpstreamError>(),
+        Some(UpstreamError::RateLimited {
+            retry_after: Some(1)
+        })
+    ));
+    let sent = reddit.requests();
+    assert_eq!(sent.iter().filter(|r| r.contains("aaaaaa")).count(), 1);
+}
+
+#[tokio::test]
+async fn rate_limited_score_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    reddit.respond(
+        "/r/rust/comments/bbbbbb/lifetime_question/",
+        MockResponse::too_many_requests(120),
+    );
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+
+    // the throttled score is unknown, the rest of the feed is served
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options(100))
+        .await
+        .unwrap();
+    let ids = feed
+        .entries
+        .iter()
+        .map(|e| e.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["urn:reddit:t3_aaaaaa"]);
+}
+
+#[tokio::test]
+async fn invalid_rate_limit_header_test() {
+    let reddit = MockReddit::start().await;
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::article(10)
+            .with_header("x-ratelimit-used", "many")
+            .with_header("x-ratelimit-remaining", "0")
+            .with_header("x-ratelimit-reset", "-5"),
+    );
+    // ignored instead of failing, the throttle falls back to a second
+    reddit
+        .client()
+        .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+        .await
+        .unwrap();
+
+    // throttled for a default time without a retry-after
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::status(axum::http::StatusCode::TOO_MANY_REQUESTS),
+    );
+    let client = reddit.client();
+    for retry_after in [None, Some(60)] {
+        let report = client
+            .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            report.downcast_ref::<UpstreamError>(),
+            Some(&UpstreamError::RateLimited { retry_after: r }) if r == retry_after
+        ));
+    }
+}
+
+#[tokio::test]
+async fn throttle_restored_test() {
+    let reddit = MockReddit::start().await;
+    reddit.respond(
+        "/r/rust/comments/aaaaaa/announcing_rust_1770/",
+        MockResponse::too_many_requests(120),
+    );
+    let store = TempStore::new();
+    let client = reddit
+        .client()
+        .with_throttle_store(store.collection("throttle").await.unwrap())
+        .await;
+    assert!(client
+        .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+        .await
+        .is_err());
+    client.flush_throttle().await.unwrap();
+
+    let restarted = reddit
+        .client()
+        .with_throttle_store(store.collection("throttle").await.unwrap())
+        .await;
+    let report = restarted
+        .get_article_info("r/rust/comments/aaaaaa/announcing_rust_1770/")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        report.downcast_ref::<UpstreamError>(),
+        Some(UpstreamError::RateLimited {
+            retry_after: Some(_)
+        })
+    ));
+    let sent = reddit.requests();
+    assert_eq!(sent.iter().filter(|r| r.contains("aaaaaa")).count(), 1);
+}
+
+#[tokio::test]
+async fn watcher_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+    let profiles = store.collection("profiles").await.unwrap();
+    let profile = FeedProfile {
+        owner: "alice".to_string(),
+        definition: ProfileDefinition {
+            subreddits: vec!["rust".to_string()],
+            max_age: None,
+            options: options(100),
+        },
+    };
+    profiles.insert("p1".to_string(), profile).await.unwrap();
+    let webhooks = Webhooks::new(provider.clone(), &WebhookConfig::default(), &store)
+        .await
+        .unwrap();
+    let watcher = Watcher::new(provider, webhooks, profiles, &store)
+        .await
+        .unwrap();
+    assert!(watcher.list().await.is_empty());
+
+    watcher
+        .set_interval("p1", Some(std::time::Duration::from_secs(300)))
+        .await
+        .unwrap();
+    watcher.tick().await;
+    // not due again until the interval passes
+    watcher.tick().await;
+
+    let watched = watcher.list().await;
+    assert_eq!(watched.len(), 1);
+    assert_eq!(watched[0].source, ScheduleSource::Admin);
+    assert_eq!(watched[0].last_error, None);
+    let last_poll = watched[0].last_poll.unwrap();
+    assert_eq!(watched[0].next_poll, Some(last_poll + 300));
+    let polls = reddit.requests();
+    assert_eq!(
+        polls.iter().filter(|r| r.contains("/r/rust/.rss")).count(),
+        1
+    );
+
+    // the posting rate is learned on the next poll, not on listing
+    watcher.set_interval("p1", None).await.unwrap();
+    let watched = watcher.list().await;
+    assert!(watched[0].adaptive);
+    assert_eq!(watched[0].posts_per_hour, None);
+}
+
+#[tokio::test]
+async fn snapshot_test() {
+    let reddit = MockReddit::start().await;
+    reddit.serve_listing([250, 3]);
+    reddit.respond(
+        "/feeds/f/abcd1234.xml",
+        MockResponse::status(axum::http::StatusCode::OK),
+    );
+    let store = TempStore::new();
+    let provider = reddit.provider(&store).await;
+    let profiles = store.collection("profiles").await.unwrap();
+    let definition = ProfileDefinition {
+        subreddits: vec!["rust".to_string()],
+        max_age: None,
+        options: options(100),
+    };
+    let profile = FeedProfile {
+        owner: "alice".to_string(),
+        definition,
+    };
+    profiles
+        .insert("abcd1234".to_string(), profile)
+        .await
+        .unwrap();
+    let endpoint = Url::parse(&reddit.endpoints().www).unwrap();
+    let snapshots = Snapshots::new(
+        reqwest::Client::new(),
+        Bucket::new(endpoint, UrlStyle::Path, "feeds", "us-east-1").unwrap(),
+        Credentials::new("key", "secret"),
+        "",
+        std::time::Duration::from_secs(60),
+    );
+
+    snapshots.snapshot(&provider, &profiles).await;
+
+    let uploads = reddit
+        .requests()
+        .into_iter()
+        .filter(|request| request.starts_with("PUT "))
+        .collect::<Vec<_>>();
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0].starts_with("PUT /feeds/f/abcd1234.xml?X-Amz-Algorithm="));
+}
+
+#[tokio::test]
+async fn self_test_test() {
+    let reddit = MockReddit::start().await;
+    let secrets = REQUIRED_SECRETS.map(|key| (key, "mock-user"));
+    let readiness = Readiness::new(Arc::new(HashMap::from(secrets)), reddit.client());
+
+    // `/user/mock-user/about` is not served yet
+    let test = readiness.self_test().await;
+    assert!(!test.ready);
+    assert_eq!(
+        test.checks[1],
+        Check {
+            name: "token",
+            error: None
+        }
+    );
+    assert_eq!(test.checks[2].name, "api");
+    assert!(!readiness.is_ready());
+
+    reddit.respond(
+        "/user/mock-user/about",
+        MockResponse::json(serde_json::json!({"kind": "t2", "data": {"total_karma": 1}})),
+    );
+    assert!(readiness.self_test().await.ready);
+    assert!(readiness.is_ready());
+
+    let missing = Readiness::new(Arc::new(HashMap::from([secrets[0]])), reddit.client());
+    assert_eq!(
+        missing.self_test().await.checks,
+        [Check {
+            name: "secrets",
+            error: Some(
+                "missing REDDIT_CLIENT_SECRET, REDDIT_USERNAME, REDDIT_PASSWORD".to_string()
+            ),
+        }]
+    );
+}
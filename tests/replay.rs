@@ -0,0 +1,37 @@
+mod common;
+
+use common::TempStore;
+use redditrss::error::UpstreamError;
+use redditrss::reddit::client::SubredditStatus;
+use redditrss::rss::feed::{FeedOptions, Upstream};
+use redditrss::test_util::provider;
+use redditrss::test_util::vcr::Cassette;
+
+/// Hand-written fixture in the cassette format, not a recording of real responses:
+/// it covers a listing and a private subreddit only. Running with `VCR_RECORD` replaces it
+/// with a recording, see [redditrss::test_util::vcr]
+const FIXTURE: &str = "tests/fixtures/listing_replay.json";
+
+#[tokio::test]
+async fn replay_listing_test() {
+    let cassette = Cassette::start(FIXTURE).await.unwrap();
+    let store = TempStore::new();
+    let provider = provider(cassette.client(), &store).await;
+    let options: FeedOptions = serde_json::from_str(r#"{"min_score": 100}"#).unwrap();
+
+    let feed = provider
+        .feed_filter(Upstream::Subreddit("r/rust".to_string()), &options)
+        .await
+        .unwrap();
+    let status = cassette.client().probe_subreddit("secret").await.unwrap();
+    cassette.save().unwrap();
+
+    let ids = feed
+        .entries
+        .iter()
+        .map(|e| e.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["urn:reddit:t3_aaaaaa"]);
+    assert_eq!(status, SubredditStatus::Private);
+    assert_eq!(status.error(), Some(UpstreamError::Private));
+}